@@ -0,0 +1,155 @@
+//! 合规归档：每次烧录在 `reports.jsonl` 中追加一条记录，实际写入的镜像以 gzip 压缩的
+//! Intel HEX 按 SHA-256 存放在 `images/` 下，记录通过摘要引用镜像
+//!
+//! 每条记录还带有上一行的 SHA-256，删改任一历史记录都会使之后的链接失效。
+
+use std::collections::BTreeMap;
+use std::fs::{self, OpenOptions};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+use flate2::Compression;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::stm32_uart::{self, Result};
+
+const REPORTS_FILE: &str = "reports.jsonl";
+const IMAGES_DIR: &str = "images";
+
+/// 一次烧录的归档记录
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ArchiveEntry {
+    pub finished_at: String,
+    pub station: String,
+    pub job: String,
+    pub ok: bool,
+    pub bytes_written: u64,
+    pub error: Option<String>,
+    /// 写入镜像（Intel HEX 文本）的 SHA-256
+    pub image_sha256: String,
+    /// 同一文本的 CRC-32，解压后先比较它以快速发现损坏
+    pub image_crc32: u32,
+    /// 相对归档目录的镜像文件路径
+    pub image_file: String,
+    /// 上一行记录的 SHA-256，第一条为 None
+    pub previous: Option<String>,
+}
+
+/// 已保存的镜像
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StoredImage {
+    pub sha256: String,
+    pub crc32: u32,
+    pub file: String,
+}
+
+fn sha256_hex(data: &[u8]) -> String {
+    Sha256::digest(data)
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+/// 归档目录
+#[derive(Debug, Clone)]
+pub struct ImageArchive {
+    dir: PathBuf,
+}
+
+impl ImageArchive {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// 保存镜像，内容相同的镜像只存一份
+    pub fn store_image(&self, image: &BTreeMap<u32, u8>) -> Result<StoredImage> {
+        let hex = stm32_uart::image_to_hex(image)?;
+        let sha256 = sha256_hex(hex.as_bytes());
+        let file = format!("{IMAGES_DIR}/{sha256}.hex.gz");
+        let path = self.dir.join(&file);
+        if !path.exists() {
+            fs::create_dir_all(self.dir.join(IMAGES_DIR))?;
+            // 先写临时文件再改名，中途断电不会留下残缺的镜像
+            let partial = path.with_extension("partial");
+            let mut encoder = GzEncoder::new(fs::File::create(&partial)?, Compression::best());
+            encoder.write_all(hex.as_bytes())?;
+            encoder.finish()?.sync_all()?;
+            fs::rename(&partial, &path)?;
+        }
+        Ok(StoredImage {
+            sha256,
+            crc32: crc32fast::hash(hex.as_bytes()),
+            file,
+        })
+    }
+
+    /// 追加一条记录，`previous` 由最后一行计算
+    pub fn append(&self, mut entry: ArchiveEntry) -> Result<ArchiveEntry> {
+        fs::create_dir_all(&self.dir)?;
+        let path = self.dir.join(REPORTS_FILE);
+        let existing = fs::read_to_string(&path).unwrap_or_default();
+        entry.previous = existing.lines().last().map(|l| sha256_hex(l.as_bytes()));
+
+        let mut file = OpenOptions::new().create(true).append(true).open(&path)?;
+        writeln!(file, "{}", serde_json::to_string(&entry)?)?;
+        file.sync_all()?;
+        Ok(entry)
+    }
+
+    /// 检查记录链和每条记录引用的镜像，返回发现的问题（空表示完好）
+    pub fn verify(&self) -> Result<Vec<String>> {
+        let text = fs::read_to_string(self.dir.join(REPORTS_FILE))?;
+        let mut problems = Vec::new();
+        let mut previous: Option<String> = None;
+        for (i, line) in text.lines().enumerate() {
+            let n = i + 1;
+            match serde_json::from_str::<ArchiveEntry>(line) {
+                Ok(entry) => {
+                    if entry.previous != previous {
+                        problems.push(format!("entry {n}: link to the previous entry is broken"));
+                    }
+                    if let Err(problem) = self.check_image(&entry) {
+                        problems.push(format!("entry {n}: {problem}"));
+                    }
+                }
+                Err(e) => problems.push(format!("entry {n}: unreadable ({e})")),
+            }
+            previous = Some(sha256_hex(line.as_bytes()));
+        }
+        Ok(problems)
+    }
+
+    fn check_image(&self, entry: &ArchiveEntry) -> std::result::Result<(), String> {
+        let file = fs::File::open(self.dir.join(&entry.image_file))
+            .map_err(|e| format!("image {} missing ({e})", entry.image_file))?;
+        let mut hex = Vec::new();
+        GzDecoder::new(file)
+            .read_to_end(&mut hex)
+            .map_err(|e| format!("image {} is corrupt ({e})", entry.image_file))?;
+        if crc32fast::hash(&hex) != entry.image_crc32 {
+            return Err(format!("image {} fails its CRC-32", entry.image_file));
+        }
+        if sha256_hex(&hex) != entry.image_sha256 {
+            return Err(format!(
+                "image {} does not match its SHA-256",
+                entry.image_file
+            ));
+        }
+        Ok(())
+    }
+
+    /// 取回记录引用的镜像，用于复现某块板写入的内容
+    pub fn load_image(&self, entry: &ArchiveEntry) -> Result<String> {
+        let mut hex = String::new();
+        GzDecoder::new(fs::File::open(self.dir.join(&entry.image_file))?)
+            .read_to_string(&mut hex)?;
+        Ok(hex)
+    }
+}
@@ -0,0 +1,64 @@
+//! 同步接口的异步包装：在 tokio 的阻塞线程池中执行 `stm32_uart` 的操作，
+//! GUI 的异步命令 await 它们时不占用运行时的工作线程，多个串口上的操作可以同时进行
+//!
+//! 这不是异步串口传输：串口 I/O 和 Boot 序列的延时仍在阻塞线程池的线程上同步执行，
+//! 每个进行中的操作占用一个池线程。协议实现只有 `stm32_uart` 一份，这里的函数与同名的
+//! 同步函数行为一致。`open_session_async` 只包装连接，之后的分步操作是同步的，
+//! 异步调用方需自行放到阻塞线程池中执行。需要在 tokio 运行时中调用。
+
+use std::path::Path;
+
+use crate::stm32_uart::{
+    self, BootloaderSession, FlashOptions, FlashReport, IdentifyResult, ImageFormat, ImageSource,
+    ProgressSink, Result,
+};
+
+/// 在阻塞线程池中执行 `job`；`job` 中的 panic 原样传回调用方
+async fn blocking<T: Send + 'static>(job: impl FnOnce() -> T + Send + 'static) -> T {
+    match tokio::task::spawn_blocking(job).await {
+        Ok(value) => value,
+        Err(e) if e.is_panic() => std::panic::resume_unwind(e.into_panic()),
+        Err(e) => panic!("blocking task did not complete: {e}"),
+    }
+}
+
+/// `stm32_uart::identify` 的异步版本
+pub async fn identify_async(port_name: &str, options: &FlashOptions) -> IdentifyResult {
+    let port_name = port_name.to_string();
+    let options = options.clone();
+    blocking(move || stm32_uart::identify(&port_name, &options)).await
+}
+
+/// `stm32_uart::flash_hex` 的异步版本
+pub async fn flash_hex_async(
+    port_name: &str,
+    hex_path: &Path,
+    options: &FlashOptions,
+    sink: impl ProgressSink + Send + 'static,
+) -> Result<FlashReport> {
+    let source = ImageSource::with_format(hex_path, ImageFormat::IntelHex, None);
+    flash_sources_async(port_name, &[source], options, sink).await
+}
+
+/// `stm32_uart::flash_sources` 的异步版本
+pub async fn flash_sources_async(
+    port_name: &str,
+    sources: &[ImageSource],
+    options: &FlashOptions,
+    sink: impl ProgressSink + Send + 'static,
+) -> Result<FlashReport> {
+    let port_name = port_name.to_string();
+    let sources = sources.to_vec();
+    let options = options.clone();
+    blocking(move || stm32_uart::flash_sources(&port_name, &sources, &options, &sink)).await
+}
+
+/// `BootloaderSession::open` 的异步版本；之后的分步操作仍是同步的
+pub async fn open_session_async(
+    port_name: &str,
+    options: &FlashOptions,
+) -> Result<BootloaderSession> {
+    let port_name = port_name.to_string();
+    let options = options.clone();
+    blocking(move || BootloaderSession::open(&port_name, &options)).await
+}
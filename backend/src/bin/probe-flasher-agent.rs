@@ -0,0 +1,143 @@
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use clap::Parser;
+use probe_flasher::i18n::{self, Lang, Msg, tr};
+use probe_flasher::job::{JobFile, JobRunner, StationConfig, StationStatus};
+use probe_flasher::logging;
+use probe_flasher::stm32_uart::StdoutLogger;
+
+/// 无人值守的产线烧录：按任务文件和工位配置循环烧录，并通过 HTTP 提供工位状态
+#[derive(Parser)]
+#[command(name = "probe-flasher-agent", version)]
+#[command(
+    after_help = "示例: probe-flasher-agent --job app-v1.2.json --station line1.json\n状态: curl http://127.0.0.1:8787/status"
+)]
+struct Cli {
+    /// 任务文件（JSON：镜像和烧录参数）
+    #[arg(long)]
+    job: PathBuf,
+
+    /// 工位配置（JSON：串口、Boot 模式、治具触发、状态接口地址）
+    #[arg(long)]
+    station: PathBuf,
+
+    /// 覆盖工位配置中的状态接口监听地址
+    #[arg(long)]
+    listen: Option<String>,
+
+    /// 覆盖工位配置中的合规归档目录
+    #[arg(long, value_name = "DIR")]
+    archive: Option<PathBuf>,
+
+    /// 只烧录一次后退出，退出码表示成功与否
+    #[arg(long)]
+    once: bool,
+
+    /// 输出语言；默认读取 PROBE_FLASHER_LANG 或系统 locale
+    #[arg(long, value_enum)]
+    lang: Option<Lang>,
+}
+
+/// 对 `GET /status` 返回 JSON 状态，其余请求返回 404
+fn serve_status(stream: TcpStream, status: &Mutex<StationStatus>) -> std::io::Result<()> {
+    let mut request = String::new();
+    BufReader::new(&stream).read_line(&mut request)?;
+    let path = request.split_whitespace().nth(1).unwrap_or("");
+
+    let (code, body) = match path {
+        "/status" | "/" => {
+            let status = status.lock().unwrap().clone();
+            ("200 OK", serde_json::to_string(&status).unwrap_or_default())
+        }
+        _ => ("404 Not Found", r#"{"error":"not found"}"#.to_string()),
+    };
+    let mut stream = stream;
+    write!(
+        stream,
+        "HTTP/1.1 {code}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    )
+}
+
+fn spawn_status_server(addr: &str, status: Arc<Mutex<StationStatus>>) -> std::io::Result<()> {
+    let listener = TcpListener::bind(addr)?;
+    println!("{}", tr(Msg::StatusEndpoint, &[&listener.local_addr()?]));
+    std::thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let _ = stream.set_read_timeout(Some(Duration::from_secs(2)));
+            let _ = serve_status(stream, &status);
+        }
+    });
+    Ok(())
+}
+
+fn main() {
+    let cli = Cli::parse();
+    i18n::set_lang(cli.lang.unwrap_or_else(Lang::from_env));
+    logging::init(|level, msg| StdoutLogger.line(level, msg));
+    let logger = StdoutLogger;
+
+    let loaded = JobFile::load(&cli.job).and_then(|job| {
+        let station = StationConfig::load(&cli.station)?;
+        if !cli.once {
+            station.check_continuous()?;
+        }
+        Ok((job, station))
+    });
+    let (job, mut station) = match loaded {
+        Ok(loaded) => loaded,
+        Err(e) => {
+            eprintln!("{}", tr(Msg::Failed, &[&"Agent", &e]));
+            std::process::exit(2);
+        }
+    };
+
+    if cli.archive.is_some() {
+        station.archive_dir = cli.archive.clone();
+    }
+    let listen = cli.listen.clone().or_else(|| station.status_listen.clone());
+    println!(
+        "{}",
+        tr(
+            Msg::AgentStarted,
+            &[&station.name, &job.name, &station.port]
+        )
+    );
+    let runner = JobRunner::new(job, station);
+    if let Some(addr) = listen
+        && let Err(e) = spawn_status_server(&addr, runner.status())
+    {
+        eprintln!("{}", tr(Msg::Failed, &[&"Agent", &e]));
+        std::process::exit(2);
+    }
+
+    loop {
+        match runner.run_once(&logger) {
+            Ok(report) => {
+                println!("{}", tr(Msg::Succeeded, &[&"Flash"]));
+                println!(
+                    "{}",
+                    tr(
+                        Msg::ReportBytesWrittenIn,
+                        &[&report.bytes_written, &report.timeline.total().as_millis()]
+                    )
+                );
+                if cli.once {
+                    return;
+                }
+            }
+            Err(e) => {
+                eprintln!("{}", tr(Msg::Failed, &[&"Flash", &e]));
+                if cli.once {
+                    std::process::exit(1);
+                }
+                // 串口拔出等错误后稍等再进入下一轮，避免空转
+                std::thread::sleep(Duration::from_secs(1));
+            }
+        }
+    }
+}
@@ -1,8 +1,29 @@
 use std::path::PathBuf;
+use std::sync::LazyLock;
 use std::time::Duration;
 
-use clap::{Parser, Subcommand, builder::styling};
-use probe_flasher::stm32_uart::{self, BootLineConfig, BootMode, FlashOptions, StdoutLogger};
+use clap::{Args, Parser, Subcommand, ValueEnum, builder::styling};
+use probe_flasher::config::{CONFIG_FILE, ProjectConfig};
+use probe_flasher::devicedb::{self, CycleTracking, DeviceDb};
+use probe_flasher::diag::CheckStatus;
+use probe_flasher::i18n::{self, Lang, Msg, tr};
+use probe_flasher::power::{PowerCycle, PowerSwitch};
+use probe_flasher::stm32_dfu::{self, DfuSelector};
+use probe_flasher::stm32_uart::{
+    self, BootLineConfig, BootMode, BootTiming, BootloaderSession, BoundaryPolicy, CancelToken,
+    ControlLine, DumpFormat, Edge, EraseMode, ExternalMemory, FixtureTrigger, FlashEvent,
+    FlashOptions, FlashReport, ImageFormat, ImageSource, JsonEventLogger, Level, Padding,
+    ProgressGranularity, ProgressSink, RetryPolicy, StatusLine, StdoutLogger, Timeouts,
+    VerifyStrategy, VersionLocator,
+};
+use probe_flasher::stub::{self, StubConfig};
+use probe_flasher::{chipdb, diag, logging, optbytes};
+
+/// Ctrl-C 设置的取消标志，所有连接参数都带上它
+static CANCEL: LazyLock<CancelToken> = LazyLock::new(CancelToken::default);
+
+/// `flash --events-json` 的事件输出，日志和进度共用以保证阶段事件的顺序
+static JSON_EVENTS: LazyLock<JsonEventLogger> = LazyLock::new(JsonEventLogger::default);
 
 const STYLES: styling::Styles = styling::Styles::styled()
     .header(styling::AnsiColor::Yellow.on_default().bold())
@@ -17,8 +38,14 @@ const STYLES: styling::Styles = styling::Styles::styled()
 struct Cli {
     #[command(subcommand)]
     command: Commands,
+
+    /// 输出语言；默认读取 PROBE_FLASHER_LANG 或系统 locale
+    #[arg(long, global = true, value_enum)]
+    lang: Option<Lang>,
 }
 
+// 命令行只解析一次，Flash 参数较多不必装箱
+#[allow(clippy::large_enum_variant)]
 #[derive(Subcommand)]
 enum Commands {
     /// 列出可用的串口
@@ -27,119 +54,1715 @@ enum Commands {
     /// 识别选定串口的 STM32 Bootloader
     #[command(after_help = "示例: probe-flasher identify --port COM5")]
     Identify {
-        /// 串口名称
-        #[arg(short, long)]
-        port: String,
+        #[command(flatten)]
+        conn: ConnectArgs,
+    },
 
-        /// 波特率
-        #[arg(short, long, default_value = "115200")]
-        baud: u32,
+    /// 测试链路质量：反复执行只读命令，统计 ACK 往返时间、超时和重试
+    #[command(after_help = "示例: probe-flasher bench --port COM5 --rounds 200")]
+    Bench {
+        #[command(flatten)]
+        conn: ConnectArgs,
 
-        /// Boot 进入模式
-        #[arg(short = 'm', long, value_enum, default_value = "dtr-low-rts-high")]
-        boot_mode: BootMode,
+        /// 测试轮数
+        #[arg(short, long, default_value_t = 100)]
+        rounds: u32,
     },
 
     /// 通过 UART Bootloader 烧录 .hex 文件到 STM32
     Flash {
-        /// 串口名称
+        #[command(flatten)]
+        conn: ConnectArgs,
+
+        /// .hex / .elf / .srec 文件路径（按扩展名识别，ELF 按可加载段的物理地址写入）；
+        /// 可重复给出，多个文件合并后一次擦除、写入
+        #[arg(
+            short = 'f',
+            long,
+            required_unless_present = "bin",
+            conflicts_with = "bin"
+        )]
+        hex: Vec<PathBuf>,
+
+        /// 覆盖按扩展名识别的固件格式
+        #[arg(long, value_enum, conflicts_with = "bin")]
+        format: Option<ImageFormat>,
+
+        /// 原始二进制固件路径，从 --address 开始写入
+        #[arg(long)]
+        bin: Option<PathBuf>,
+
+        /// 二进制固件（--bin）的写入地址，默认 0x08000000
+        #[arg(long, value_parser = parse_number, conflicts_with = "hex")]
+        address: Option<u32>,
+
+        #[command(flatten)]
+        version: VersionArgs,
+
+        /// 烧录后跳过自动复位（如果 GO 命令不起作用）
+        #[arg(long)]
+        no_reset: bool,
+
+        /// 烧录后保持在 Bootloader，后续命令可用 --boot-mode none 直接连接
+        #[arg(long, conflicts_with = "no_reset")]
+        stay_in_bootloader: bool,
+
+        /// 固件链接在 0x00000000 镜像区时重定位到 0x08000000
+        #[arg(long)]
+        rebase: bool,
+
+        /// 把读入的固件整体平移（可为负），如 0x08000000 或 -0x4000；越出地址空间或与其他文件重叠时报错
+        #[arg(long, value_parser = parse_offset, allow_hyphen_values = true, default_value = "0")]
+        offset: i64,
+
+        /// 烧录后 GO 命令的跳转地址 [默认: probe-flasher.toml，或 HEX 文件中的入口地址，都没有时为 0x08000000]
+        #[arg(long, value_parser = parse_number)]
+        go_address: Option<u32>,
+
+        /// 烧录完成后设置读保护（RDP Level 1）
+        #[arg(long)]
+        lock: bool,
+
+        /// 擦除前解除写保护（出厂开启 WRP 的器件）
+        #[arg(long)]
+        unprotect: bool,
+
+        /// 烧录成功后对这些扇区开启写保护，逗号分隔，如 0,1,2
+        #[arg(long, value_delimiter = ',', value_parser = parse_sector)]
+        protect_after: Option<Vec<u8>>,
+
+        /// 烧录成功后修改选项字节，可重复，如 --option-byte nboot1=0 --option-byte wdg_sw=0
+        #[arg(long = "option-byte", value_name = "NAME=VALUE", value_parser = parse_option_byte)]
+        option_bytes: Vec<(String, u32)>,
+
+        /// 写入后全量回读校验，同 --verify-method read-back
+        #[arg(long)]
+        verify: bool,
+
+        /// 校验策略：none 不校验，crc 由目标计算 CRC（需 Get Checksum 命令），
+        /// read-back 逐块回读，sampled:N 只回读均匀分布的 N% 数据块
+        #[arg(long, value_name = "STRATEGY", value_parser = parse_verify)]
+        verify_method: Option<VerifyStrategy>,
+
+        /// 擦除范围：mass 全片擦除，pages 只擦除固件覆盖的页 / 扇区，bank 只擦除 --bank 选择的 Bank
+        #[arg(long, value_enum, default_value = "mass")]
+        erase: EraseMode,
+
+        /// 双 Bank 器件的目标 Bank，链接在 Bank 1 地址的固件平移到该 Bank 写入
+        #[arg(long, value_parser = clap::value_parser!(u8).range(1..=2), required_if_eq("erase", "bank"))]
+        bank: Option<u8>,
+
+        /// 写入后翻转 Bank 交换选项位（BFB2），下次复位从另一个 Bank 启动
+        #[arg(long)]
+        swap_banks: bool,
+
+        /// 全新芯片：先不执行 Boot 序列直接同步，Flash 为空的芯片上电即在 Bootloader 中；
+        /// 带空检查的系列（F04x/F07x/F09x/G0）烧录后重新加载选项字节，使芯片从新固件启动
+        #[arg(long)]
+        virgin: bool,
+
+        /// 等待串口（端口名或 USB 序列号）出现后再开始烧录
+        #[arg(long)]
+        wait: bool,
+
+        /// 等待超时（秒），默认一直等待
+        #[arg(long, requires = "wait")]
+        wait_timeout: Option<u64>,
+
+        #[command(flatten)]
+        progress: ProgressArgs,
+
+        #[command(flatten)]
+        fixture: FixtureArgs,
+
+        #[command(flatten)]
+        padding: PaddingArgs,
+
+        /// 写入每帧后不等待串口发送完成（高波特率下减少每块开销）
+        #[arg(long)]
+        no_flush: bool,
+
+        /// 不跳过全为 0xFF 的数据块（默认擦除后直接跳过，不发送）
+        #[arg(long)]
+        no_skip_blank: bool,
+
+        /// 每个写入帧的字节数（32–256），慢速链路上整块写入出错时调小
+        #[arg(long, default_value_t = 256, value_parser = clap::value_parser!(u16).range(32..=256))]
+        chunk_size: u16,
+
+        /// 不在本地设备数据库中记录擦写次数
+        #[arg(long)]
+        no_track: bool,
+
+        /// 设备擦除次数超过该值时告警
+        #[arg(long, default_value_t = devicedb::DEFAULT_CYCLE_WARN, conflicts_with = "no_track")]
+        cycle_warn: u64,
+
+        /// 以 JSON Lines 在 stdout 输出阶段、进度、日志和结果事件，供外部工具渲染进度
+        #[arg(long)]
+        events_json: bool,
+
+        /// 经 Bootloader 扩展命令编程的外部 Flash：起始地址:大小:写入命令[:擦除命令]，
+        /// 如 0x90000000:0x800000:0xA0:0xA1，可重复
+        #[arg(long, value_parser = parse_external_memory)]
+        external_memory: Vec<ExternalMemory>,
+
+        /// 先把该 RAM 烧录程序（.bin，开头为向量表）写入 SRAM 并运行，再经其擦写固件
+        #[arg(long)]
+        stub: Option<PathBuf>,
+
+        /// RAM 烧录程序的加载地址
+        #[arg(long, value_parser = parse_number, default_value = "0x20000000", requires = "stub")]
+        stub_address: u32,
+
+        /// RAM 烧录程序启动后切换到的波特率
+        #[arg(long, requires = "stub")]
+        stub_baud: Option<u32>,
+    },
+
+    /// 只读校验：回读 Flash 与固件逐段比较，不擦除、不写入
+    #[command(after_help = "示例: probe-flasher verify --port COM5 --hex app.hex")]
+    Verify {
+        #[command(flatten)]
+        conn: ConnectArgs,
+
+        /// .hex / .elf / .srec 文件路径；可重复给出，多个文件合并后比较
+        #[arg(
+            short = 'f',
+            long,
+            required_unless_present = "bin",
+            conflicts_with = "bin"
+        )]
+        hex: Vec<PathBuf>,
+
+        /// 覆盖按扩展名识别的固件格式
+        #[arg(long, value_enum, conflicts_with = "bin")]
+        format: Option<ImageFormat>,
+
+        /// 原始二进制固件路径，与 --address 起的 Flash 比较
+        #[arg(long)]
+        bin: Option<PathBuf>,
+
+        /// 二进制固件（--bin）的起始地址，默认 0x08000000
+        #[arg(long, value_parser = parse_number, conflicts_with = "hex")]
+        address: Option<u32>,
+
+        /// 校验策略：read-back 逐块回读，crc 先由目标计算 CRC，不一致的段再回读，
+        /// sampled:N 只回读均匀分布的 N% 数据块
+        #[arg(long, value_name = "STRATEGY", value_parser = parse_verify, default_value = "read-back")]
+        verify_method: VerifyStrategy,
+
+        /// 完成后不复位运行程序
+        #[arg(long)]
+        no_reset: bool,
+    },
+
+    /// 用 READ MEMORY 读出 Flash 内容保存为 .bin / .hex 文件
+    #[command(
+        after_help = "示例: probe-flasher read --port COM5 --address 0x08000000 --length 0x20000 --out dump.bin"
+    )]
+    Read {
+        #[command(flatten)]
+        conn: ConnectArgs,
+
+        /// 起始地址
+        #[arg(short, long, value_parser = parse_number, default_value = "0x08000000")]
+        address: u32,
+
+        /// 读取字节数
+        #[arg(short, long, value_parser = parse_number)]
+        length: u32,
+
+        /// 输出文件路径
         #[arg(short, long)]
-        port: String,
+        out: PathBuf,
 
-        /// .hex 文件路径
+        /// 输出格式，默认按扩展名推断（.hex / .ihex 为 Intel HEX，其余为二进制）
+        #[arg(long, value_enum)]
+        format: Option<DumpFormat>,
+    },
+
+    /// 查看 .hex 固件的地址范围和版本信息
+    #[command(after_help = "示例: probe-flasher inspect --hex app.hex --version-pattern VERSION=")]
+    Inspect {
+        /// .hex / .elf / .srec / .bin 文件路径（.bin 从 0x08000000 起）
         #[arg(short = 'f', long)]
         hex: PathBuf,
 
+        /// 覆盖按扩展名识别的固件格式
+        #[arg(long, value_enum)]
+        format: Option<ImageFormat>,
+
+        #[command(flatten)]
+        version: VersionArgs,
+    },
+
+    /// 设置读保护（RDP Level 1）
+    #[command(after_help = "示例: probe-flasher lock --port COM5")]
+    Lock {
+        #[command(flatten)]
+        conn: ConnectArgs,
+
+        /// 完成后不复位运行程序
+        #[arg(long)]
+        no_reset: bool,
+
+        /// 完成后保持在 Bootloader
+        #[arg(long, conflicts_with = "no_reset")]
+        stay_in_bootloader: bool,
+    },
+
+    /// 备份 / 恢复选项字节
+    #[command(subcommand)]
+    OptionBytes(OptionBytesCommand),
+
+    /// 经 USB DFU（DfuSe）烧录，芯片以 BOOT0=1 上电并接在 USB 上
+    #[command(subcommand)]
+    Dfu(DfuCommand),
+
+    /// 串口控制线
+    #[command(subcommand)]
+    Lines(LinesCommand),
+
+    /// 释放 BOOT0 并复位，退出 Bootloader 运行用户程序
+    #[command(after_help = "示例: probe-flasher reset --port COM5 --boot-mode rts-low-dtr-high")]
+    Reset {
+        #[command(flatten)]
+        conn: ConnectArgs,
+    },
+
+    /// 列出本地设备数据库中各芯片的擦写次数
+    #[command(after_help = "示例: probe-flasher devices")]
+    Devices,
+
+    /// 输出诊断信息，或打包为 zip 用于问题报告
+    #[command(
+        after_help = "示例: probe-flasher doctor --port COM5 --probe\n      probe-flasher doctor --bundle"
+    )]
+    Doctor {
+        /// 打包版本、系统信息、串口列表、上次运行的日志和协议跟踪
+        #[arg(long, conflicts_with = "port")]
+        bundle: bool,
+
+        /// 要检查的串口（权限、DTR/RTS、偶校验）及连接参数
+        #[command(flatten)]
+        conn: ConnectArgs,
+
+        /// 尝试进入 Bootloader 并同步
+        #[arg(long, requires = "port")]
+        probe: bool,
+
+        /// 诊断包输出路径，默认 probe-flasher-diag-<时间>.zip
+        #[arg(short, long, requires = "bundle")]
+        output: Option<PathBuf>,
+    },
+
+    /// 解除读保护（会擦除整个 Flash）
+    #[command(after_help = "示例: probe-flasher unlock --port COM5 --yes")]
+    Unlock {
+        #[command(flatten)]
+        conn: ConnectArgs,
+
+        /// 不再询问确认（用于脚本）
+        #[arg(short, long)]
+        yes: bool,
+    },
+
+    /// 首次使用向导：选择串口、自动探测 Boot 模式、测试同步并生成 probe-flasher.toml
+    #[command(after_help = "示例: probe-flasher init")]
+    Init {
         /// 波特率
-        #[arg(short, long, default_value = "115200")]
+        #[arg(short, long, default_value_t = 115200)]
         baud: u32,
 
-        /// Boot 进入模式
-        #[arg(short = 'm', long, value_enum, default_value = "dtr-low-rts-high")]
-        boot_mode: BootMode,
+        /// 覆盖已有的 probe-flasher.toml 而不询问
+        #[arg(long)]
+        force: bool,
+    },
+}
 
-        /// 烧录后跳过自动复位（如果 GO 命令不起作用）
+impl Commands {
+    /// 命令行未给出的参数取配置文件中的值
+    fn apply_config(&mut self, config: &ProjectConfig) {
+        if let Commands::Flash { go_address, .. } = self {
+            *go_address = go_address.or(config.go_address);
+        }
+        if let Some(conn) = self.conn_mut() {
+            conn.apply_config(config);
+        }
+    }
+
+    /// 带连接参数的子命令
+    fn conn_mut(&mut self) -> Option<&mut ConnectArgs> {
+        match self {
+            Commands::Identify { conn }
+            | Commands::Bench { conn, .. }
+            | Commands::Flash { conn, .. }
+            | Commands::Read { conn, .. }
+            | Commands::Verify { conn, .. }
+            | Commands::Lock { conn, .. }
+            | Commands::OptionBytes(OptionBytesCommand::Show { conn, .. })
+            | Commands::OptionBytes(OptionBytesCommand::Set { conn, .. })
+            | Commands::OptionBytes(OptionBytesCommand::Backup { conn, .. })
+            | Commands::OptionBytes(OptionBytesCommand::Restore { conn, .. })
+            | Commands::Reset { conn }
+            | Commands::Unlock { conn, .. } => Some(conn),
+            _ => None,
+        }
+    }
+}
+
+/// 连接 Bootloader 所需的公共参数
+#[derive(Args)]
+struct ConnectArgs {
+    /// 串口名称，默认读取 probe-flasher.toml
+    #[arg(short, long)]
+    port: Option<String>,
+
+    /// 波特率，auto 依次尝试 115200 / 57600 / 38400 / 19200 / 9600 [默认: probe-flasher.toml 或 115200]
+    #[arg(short, long, value_parser = parse_baud)]
+    baud: Option<Baud>,
+
+    /// Boot 进入模式 [默认: probe-flasher.toml 或 dtr-low-rts-high]
+    #[arg(short = 'm', long, value_enum)]
+    boot_mode: Option<BootMode>,
+
+    /// BOOT0 有效电平（默认由 Boot 模式决定）
+    #[arg(long, value_enum)]
+    boot_level: Option<Level>,
+
+    /// 复位有效电平（默认由 Boot 模式决定）
+    #[arg(long, value_enum)]
+    reset_level: Option<Level>,
+
+    /// 无响应时自动探测波特率
+    #[arg(long)]
+    auto_baud: bool,
+
+    /// 同步后切换到更高的波特率（如 460800 / 921600），失败时回退到 --baud
+    #[arg(long, value_name = "BAUD")]
+    high_baud: Option<u32>,
+
+    /// 同步无响应时重新执行 Boot 序列的总轮数
+    #[arg(long, default_value_t = 1)]
+    boot_cycles: u32,
+
+    /// 第一次重试前的等待（毫秒），之后每轮翻倍
+    #[arg(long, default_value_t = 200)]
+    boot_backoff_ms: u64,
+
+    /// 等待命令应答的超时（毫秒），未指定时按 Bootloader 协议版本取默认值
+    #[arg(long)]
+    read_timeout_ms: Option<u64>,
+
+    /// 等待擦除完成的超时（毫秒），未指定时按芯片的整片擦除耗时估算
+    #[arg(long)]
+    erase_timeout_ms: Option<u64>,
+
+    /// 每次发送 0x7F 后等待同步应答的超时（毫秒），未指定时按 Bootloader 协议版本取默认值
+    #[arg(long)]
+    sync_timeout_ms: Option<u64>,
+
+    /// READ MEMORY 读回一块数据的超时（毫秒），回读校验和读出固件时使用；未指定时按协议版本取默认值
+    #[arg(long)]
+    read_chunk_timeout_ms: Option<u64>,
+
+    /// 0x7F 同步尝试次数
+    #[arg(long, default_value_t = FlashOptions::default().sync_retry.attempts, value_parser = clap::value_parser!(u32).range(1..))]
+    sync_attempts: u32,
+
+    /// 两次同步尝试之间的额外等待（毫秒）
+    #[arg(long, default_value_t = FlashOptions::default().sync_retry.backoff.as_millis() as u64)]
+    sync_backoff_ms: u64,
+
+    /// 写入 / 擦除命令被 NACK 时的尝试次数（含第一次），写入用完后再减半块大小
+    #[arg(long, default_value_t = FlashOptions::default().command_retry.attempts, value_parser = clap::value_parser!(u32).range(1..))]
+    command_attempts: u32,
+
+    /// 命令重试前的等待（毫秒）
+    #[arg(long, default_value_t = FlashOptions::default().command_retry.backoff.as_millis() as u64)]
+    command_backoff_ms: u64,
+
+    /// 释放复位后、发出复位脉冲前的稳定时间（毫秒）
+    #[arg(long, default_value_t = BootTiming::default().settle.as_millis() as u64)]
+    boot_settle_ms: u64,
+
+    /// 复位脉冲宽度（毫秒）
+    #[arg(long, default_value_t = BootTiming::default().reset_pulse.as_millis() as u64)]
+    reset_pulse_ms: u64,
+
+    /// 复位释放后等待 Bootloader 启动的时间（毫秒）
+    #[arg(long, default_value_t = BootTiming::default().boot_delay.as_millis() as u64)]
+    boot_delay_ms: u64,
+
+    /// 不使用也不记住该设备上次成功的连接参数
+    #[arg(long)]
+    no_remember: bool,
+
+    #[command(flatten)]
+    power: PowerArgs,
+}
+
+/// 目标电源控制参数（BOOT0 只在上电时采样的板子）
+#[derive(Args)]
+struct PowerArgs {
+    /// 驱动电源继电器的辅助串口，连接前先断电重启目标
+    #[arg(long, conflicts_with = "power_hook")]
+    power_port: Option<String>,
+
+    /// 辅助串口上驱动继电器的控制线
+    #[arg(long, value_enum, default_value = "dtr")]
+    power_line: ControlLine,
+
+    /// 控制线处于该电平时断电
+    #[arg(long, value_enum, default_value = "high")]
+    power_off_level: Level,
+
+    /// 控制电源的 shell 命令，环境变量 PROBE_FLASHER_POWER 为 off / on
+    #[arg(long)]
+    power_hook: Option<String>,
+
+    /// 断电保持时间（毫秒）
+    #[arg(long, default_value_t = 500)]
+    power_off_ms: u64,
+
+    /// 完成后也断电重启（释放 BOOT0），代替 GO / 复位
+    #[arg(long)]
+    power_cycle_after: bool,
+}
+
+impl PowerArgs {
+    fn power_cycle(&self) -> Option<PowerCycle> {
+        let switch = match (&self.power_port, &self.power_hook) {
+            (Some(port), _) => PowerSwitch::SerialLine {
+                port: port.clone(),
+                line: self.power_line,
+                off_level: self.power_off_level,
+            },
+            (None, Some(command)) => PowerSwitch::Hook {
+                command: command.clone(),
+            },
+            (None, None) => return None,
+        };
+        Some(PowerCycle {
+            off_time: Duration::from_millis(self.power_off_ms),
+            after: self.power_cycle_after,
+            ..PowerCycle::new(switch)
+        })
+    }
+}
+
+impl ConnectArgs {
+    /// 命令行未给出的参数取配置文件中的值
+    fn apply_config(&mut self, config: &ProjectConfig) {
+        self.port = self.port.take().or_else(|| config.port.clone());
+        self.baud = self.baud.or(config.baud.map(Baud::Fixed));
+        self.boot_mode = self.boot_mode.or(config.boot_mode);
+        self.boot_level = self.boot_level.or(config.boot_level);
+        self.reset_level = self.reset_level.or(config.reset_level);
+    }
+
+    fn port(&self) -> &str {
+        self.port.as_deref().unwrap_or_default()
+    }
+
+    fn boot_mode(&self) -> BootMode {
+        self.boot_mode.unwrap_or(BootMode::DtrLowRtsHigh)
+    }
+
+    fn options(&self) -> FlashOptions {
+        let mut lines = BootLineConfig::for_mode(self.boot_mode());
+        if let Some(level) = self.boot_level {
+            lines.boot_level = level;
+        }
+        if let Some(level) = self.reset_level {
+            lines.reset_assert_level = level;
+        }
+
+        FlashOptions {
+            baud_rate: match self.baud {
+                Some(Baud::Fixed(baud)) => baud,
+                Some(Baud::Auto) => stm32_uart::BAUD_LADDER[0],
+                None => 115200,
+            },
+            boot_mode: self.boot_mode(),
+            lines,
+            auto_baud: self.auto_baud || matches!(self.baud, Some(Baud::Auto)),
+            high_baud: self.high_baud,
+            boot_cycles: self.boot_cycles,
+            boot_backoff: Duration::from_millis(self.boot_backoff_ms),
+            timeouts: Timeouts {
+                sync: self.sync_timeout_ms.map(Duration::from_millis),
+                ack: self.read_timeout_ms.map(Duration::from_millis),
+                erase: self.erase_timeout_ms.map(Duration::from_millis),
+                read_chunk: self.read_chunk_timeout_ms.map(Duration::from_millis),
+            },
+            sync_retry: RetryPolicy::new(
+                self.sync_attempts,
+                Duration::from_millis(self.sync_backoff_ms),
+            ),
+            command_retry: RetryPolicy::new(
+                self.command_attempts,
+                Duration::from_millis(self.command_backoff_ms),
+            ),
+            boot_timing: BootTiming {
+                settle: Duration::from_millis(self.boot_settle_ms),
+                reset_pulse: Duration::from_millis(self.reset_pulse_ms),
+                boot_delay: Duration::from_millis(self.boot_delay_ms),
+            },
+            power_cycle: self.power.power_cycle(),
+            remember_connection: (!self.no_remember).then(devicedb::default_connections_path),
+            cancel: CANCEL.clone(),
+            ..FlashOptions::default()
+        }
+    }
+}
+
+#[derive(Subcommand)]
+enum OptionBytesCommand {
+    /// 读出并解码选项字节（RDP、BOR、nBOOT、看门狗、写保护等）
+    #[command(after_help = "示例: probe-flasher option-bytes show --port COM5")]
+    Show {
+        #[command(flatten)]
+        conn: ConnectArgs,
+
+        /// 完成后不复位运行程序
         #[arg(long)]
         no_reset: bool,
     },
+
+    /// 按字段名修改选项字节（芯片会自动复位），字段名见 show 的输出
+    #[command(after_help = "示例: probe-flasher option-bytes set --port COM5 nboot1=0 bor_lev=0x1")]
+    Set {
+        #[command(flatten)]
+        conn: ConnectArgs,
+
+        /// 要修改的字段，NAME=VALUE，值可带 0x 前缀
+        #[arg(required = true, value_name = "NAME=VALUE", value_parser = parse_option_byte)]
+        changes: Vec<(String, u32)>,
+
+        /// 完成后不复位运行程序
+        #[arg(long)]
+        no_reset: bool,
+    },
+
+    /// 读出选项字节保存为 .hex 文件
+    #[command(after_help = "示例: probe-flasher option-bytes backup --port COM5 --out ob.hex")]
+    Backup {
+        #[command(flatten)]
+        conn: ConnectArgs,
+
+        /// 输出文件路径
+        #[arg(short, long)]
+        out: PathBuf,
+    },
+
+    /// 把备份文件原样写回选项字节（芯片会自动复位）
+    #[command(after_help = "示例: probe-flasher option-bytes restore --port COM5 --file ob.hex")]
+    Restore {
+        #[command(flatten)]
+        conn: ConnectArgs,
+
+        /// 备份文件路径
+        #[arg(short, long)]
+        file: PathBuf,
+
+        /// 完成后不复位运行程序
+        #[arg(long)]
+        no_reset: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum DfuCommand {
+    /// 列出处于 DFU 模式的 USB 设备及其内存区域
+    #[command(after_help = "示例: probe-flasher dfu list")]
+    List,
+
+    /// 经 USB DFU 擦除、写入并校验固件
+    #[command(after_help = "示例: probe-flasher dfu flash --hex app.hex --verify")]
+    Flash {
+        #[command(flatten)]
+        device: DfuArgs,
+
+        /// .hex / .elf / .srec 文件路径（按扩展名识别）
+        #[arg(
+            short = 'f',
+            long,
+            required_unless_present = "bin",
+            conflicts_with = "bin"
+        )]
+        hex: Option<PathBuf>,
+
+        /// 覆盖按扩展名识别的固件格式
+        #[arg(long, value_enum, conflicts_with = "bin")]
+        format: Option<ImageFormat>,
+
+        /// 原始二进制固件路径，从 --address 开始写入
+        #[arg(long)]
+        bin: Option<PathBuf>,
+
+        /// 二进制固件（--bin）的写入地址，默认 0x08000000
+        #[arg(long, value_parser = parse_number, conflicts_with = "hex")]
+        address: Option<u32>,
+
+        /// 擦除范围：pages 只擦除固件覆盖的扇区（按设备报告的布局），mass 全片擦除；DFU 不支持 bank
+        #[arg(long, value_enum, default_value = "pages")]
+        erase: EraseMode,
+
+        /// 写入后全量回读校验
+        #[arg(long)]
+        verify: bool,
+
+        /// 烧录后留在 DFU 模式，不启动用户程序
+        #[arg(long)]
+        no_reset: bool,
+
+        /// 退出 DFU 时的跳转地址 [默认: HEX 文件中的入口地址，没有时为 0x08000000]
+        #[arg(long, value_parser = parse_number)]
+        go_address: Option<u32>,
+
+        #[command(flatten)]
+        progress: ProgressArgs,
+
+        #[command(flatten)]
+        padding: PaddingArgs,
+    },
+}
+
+/// 选择 DFU 设备的参数
+#[derive(Args)]
+struct DfuArgs {
+    /// USB VID，默认为 ST 系统存储器 Bootloader
+    #[arg(long, value_parser = parse_usb_id, default_value = "0x0483")]
+    vid: u16,
+
+    /// USB PID
+    #[arg(long, value_parser = parse_usb_id, default_value = "0xDF11")]
+    pid: u16,
+
+    /// 多个设备时按 USB 序列号选择
+    #[arg(long)]
+    serial: Option<String>,
+
+    /// 内存区域的 alt setting，0 通常为内部 Flash
+    #[arg(long, default_value_t = 0)]
+    alt: u8,
+
+    /// USB 控制传输及 GETSTATUS 轮询的超时（毫秒），默认 800
+    #[arg(long)]
+    timeout_ms: Option<u64>,
+
+    /// 等待擦除完成的超时（毫秒），默认 25000
+    #[arg(long)]
+    erase_timeout_ms: Option<u64>,
+}
+
+impl DfuArgs {
+    fn selector(&self) -> DfuSelector {
+        DfuSelector {
+            vid: self.vid,
+            pid: self.pid,
+            serial: self.serial.clone(),
+            alt: self.alt,
+        }
+    }
+
+    fn timeouts(&self) -> Timeouts {
+        Timeouts {
+            ack: self.timeout_ms.map(Duration::from_millis),
+            erase: self.erase_timeout_ms.map(Duration::from_millis),
+            ..Timeouts::default()
+        }
+    }
+}
+
+#[derive(Subcommand)]
+enum LinesCommand {
+    /// 读取 CTS / DSR / CD / RI 状态输入线（如检测接在 DSR 上的目标电源）
+    #[command(after_help = "示例: probe-flasher lines status --port COM5")]
+    Status {
+        /// 串口名称
+        #[arg(short, long)]
+        port: String,
+    },
+}
+
+/// 固件版本字符串的定位参数
+#[derive(Args)]
+struct VersionArgs {
+    /// 版本字符串前缀，如 "VERSION="
+    #[arg(long, conflicts_with = "version_offset")]
+    version_pattern: Option<String>,
+
+    /// 版本字符串相对固件起始地址的偏移
+    #[arg(long, value_parser = parse_number)]
+    version_offset: Option<u32>,
+}
+
+impl VersionArgs {
+    fn locator(&self) -> Option<VersionLocator> {
+        match (&self.version_pattern, self.version_offset) {
+            (Some(pattern), _) => Some(VersionLocator::Pattern(pattern.clone())),
+            (None, Some(offset)) => Some(VersionLocator::Offset(offset)),
+            (None, None) => None,
+        }
+    }
+}
+
+/// 进度上报粒度参数
+#[derive(Args)]
+struct ProgressArgs {
+    /// 每累计多少字节上报一次进度，0 表示每块都上报
+    #[arg(long, default_value_t = ProgressGranularity::default().every_bytes)]
+    progress_every: usize,
+
+    /// 每秒最多上报多少次进度，0 表示不限制
+    #[arg(long, default_value_t = ProgressGranularity::default().max_per_sec)]
+    progress_rate: u32,
+}
+
+impl ProgressArgs {
+    fn granularity(&self) -> ProgressGranularity {
+        ProgressGranularity {
+            every_bytes: self.progress_every,
+            max_per_sec: self.progress_rate,
+        }
+    }
+}
+
+/// 镜像对齐填充参数
+#[derive(Args)]
+struct PaddingArgs {
+    /// 编程粒度（字节），H7 为 32
+    #[arg(long, default_value_t = Padding::default().granularity, value_parser = clap::value_parser!(u32).range(1..=256))]
+    align: u32,
+
+    /// 数据段起止不对齐时：split 原样分段写入，pad 用 0xFF 补齐到粒度边界
+    #[arg(long, value_enum, default_value = "split")]
+    block_boundary: BoundaryPolicy,
+
+    /// 每段末尾不足 4 字节的部分用 0xFF 补齐
+    #[arg(long)]
+    pad_tail: bool,
+}
+
+impl PaddingArgs {
+    fn padding(&self) -> Padding {
+        Padding {
+            granularity: self.align,
+            boundaries: self.block_boundary,
+            tail_word: self.pad_tail,
+        }
+    }
+}
+
+/// 治具触发参数
+#[derive(Args)]
+struct FixtureArgs {
+    /// 等待该状态线的边沿后再开始烧录（治具启动按钮）
+    #[arg(long, value_enum)]
+    trigger: Option<StatusLine>,
+
+    /// 触发边沿
+    #[arg(long, value_enum, default_value = "rising", requires = "trigger")]
+    trigger_edge: Edge,
+
+    /// 触发消抖时间（毫秒）
+    #[arg(long, default_value_t = 50, requires = "trigger")]
+    debounce_ms: u64,
+
+    /// 完成后让 DTR 有效该毫秒数，通知治具
+    #[arg(long)]
+    done_pulse: Option<u64>,
+
+    /// 完成后继续等待下一次触发
+    #[arg(long = "loop", requires = "trigger")]
+    repeat: bool,
+}
+
+impl FixtureArgs {
+    fn trigger(&self) -> FixtureTrigger {
+        FixtureTrigger {
+            line: self.trigger.unwrap_or(StatusLine::Cts),
+            edge: self.trigger_edge,
+            debounce: Duration::from_millis(self.debounce_ms),
+            done_pulse: self.done_pulse.map(Duration::from_millis),
+        }
+    }
+}
+
+/// 解析十进制或 0x 前缀的十六进制数
+fn parse_number(s: &str) -> Result<u32, String> {
+    let parsed = match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(&hex.replace('_', ""), 16),
+        None => s.replace('_', "").parse(),
+    };
+    parsed.map_err(|e| format!("invalid number '{s}': {e}"))
+}
+
+/// `--hex`（可多个）或 `--bin` / `--address` 给出的固件文件
+fn firmware_sources(
+    hex: &[PathBuf],
+    format: Option<ImageFormat>,
+    bin: Option<PathBuf>,
+    address: Option<u32>,
+) -> Vec<ImageSource> {
+    match bin {
+        Some(path) => vec![ImageSource::with_format(path, ImageFormat::Binary, address)],
+        None => hex
+            .iter()
+            .map(|path| match format {
+                Some(format) => ImageSource::with_format(path, format, None),
+                None => ImageSource::from_path(path, None),
+            })
+            .collect(),
+    }
+}
+
+/// 解析 USB VID / PID，格式同 `parse_number`
+fn parse_usb_id(s: &str) -> Result<u16, String> {
+    let value = parse_number(s)?;
+    u16::try_from(value).map_err(|_| format!("USB id '{s}' is out of range"))
+}
+
+/// 解析带可选符号的偏移量，绝对值按 `parse_number` 解析
+fn parse_offset(s: &str) -> Result<i64, String> {
+    match s.strip_prefix('-') {
+        Some(rest) => parse_number(rest).map(|n| -i64::from(n)),
+        None => parse_number(s.strip_prefix('+').unwrap_or(s)).map(i64::from),
+    }
+}
+
+/// `--baud` 的取值
+#[derive(Debug, Clone, Copy)]
+enum Baud {
+    /// 按 `BAUD_LADDER` 逐个尝试同步，报告芯片响应的波特率
+    Auto,
+    Fixed(u32),
+}
+
+fn parse_baud(s: &str) -> Result<Baud, String> {
+    if s.eq_ignore_ascii_case("auto") {
+        return Ok(Baud::Auto);
+    }
+    match parse_number(s)? {
+        0 => Err("baud rate must be greater than zero".to_string()),
+        baud => Ok(Baud::Fixed(baud)),
+    }
+}
+
+/// 解析写保护扇区号（0–255）
+fn parse_sector(s: &str) -> Result<u8, String> {
+    let n = parse_number(s)?;
+    u8::try_from(n).map_err(|_| format!("sector {n} is out of range (0-255)"))
+}
+
+fn parse_verify(s: &str) -> Result<VerifyStrategy, String> {
+    s.parse()
+}
+
+fn parse_option_byte(s: &str) -> Result<(String, u32), String> {
+    optbytes::parse_assignment(s).map_err(|e| e.to_string())
+}
+
+/// 解析外部存储器描述：起始地址:大小:写入命令[:擦除命令]
+fn parse_external_memory(s: &str) -> Result<ExternalMemory, String> {
+    let fields: Vec<&str> = s.split(':').collect();
+    let &[start, size, write, ref rest @ ..] = fields.as_slice() else {
+        return Err(format!(
+            "expected START:SIZE:WRITE_CMD[:ERASE_CMD], got '{s}'"
+        ));
+    };
+    let command = |s: &str| {
+        let n = parse_number(s)?;
+        u8::try_from(n).map_err(|_| format!("command 0x{n:X} does not fit in one byte"))
+    };
+    let erase_command = match rest {
+        [] => None,
+        [erase] => Some(command(erase)?),
+        _ => return Err(format!("too many fields in '{s}'")),
+    };
+    let memory = ExternalMemory {
+        start: parse_number(start)?,
+        size: parse_number(size)?,
+        write_command: command(write)?,
+        erase_command,
+    };
+    if memory.size == 0 || memory.start.checked_add(memory.size - 1).is_none() {
+        return Err(format!("external memory range in '{s}' is empty or wraps"));
+    }
+    Ok(memory)
+}
+
+/// 在 stderr 输出提示并读取一行输入；stdin 关闭时返回空串
+fn prompt(message: &str) -> String {
+    eprint!("{message}");
+    let mut answer = String::new();
+    let _ = std::io::stdin().read_line(&mut answer);
+    answer.trim().to_string()
+}
+
+/// 首次使用向导，返回写入的配置文件路径
+fn run_init(baud: u32, force: bool) -> Result<PathBuf, String> {
+    let path = std::env::current_dir()
+        .map_err(|e| e.to_string())?
+        .join(CONFIG_FILE);
+    if path.exists()
+        && !force
+        && !prompt(&tr(Msg::InitOverwrite, &[&path.display()])).eq_ignore_ascii_case("y")
+    {
+        return Err(tr(Msg::InitKept, &[&path.display()]));
+    }
+
+    // 1. 选择串口，USB 适配器优先列出
+    let mut ports = stm32_uart::list_ports().map_err(|e| e.to_string())?;
+    ports.sort_by_key(|p| p.vid.is_none());
+    let port = match ports.len() {
+        0 => return Err(tr(Msg::InitNoPorts, &[])),
+        1 => ports.remove(0),
+        _ => {
+            for (i, p) in ports.iter().enumerate() {
+                eprintln!("  {}) {}", i + 1, p.label);
+            }
+            let answer = prompt(&tr(Msg::InitSelectPort, &[&ports.len()]));
+            let index = if answer.is_empty() {
+                0
+            } else {
+                answer
+                    .parse::<usize>()
+                    .ok()
+                    .filter(|n| (1..=ports.len()).contains(n))
+                    .ok_or_else(|| tr(Msg::InitInvalidChoice, &[&answer]))?
+                    - 1
+            };
+            ports.remove(index)
+        }
+    };
+    println!("{}", tr(Msg::InitPort, &[&port.label]));
+
+    // 2. 自动探测 Boot 模式，全部无应答时退回手动操作 BOOT0 / RESET
+    let options = FlashOptions {
+        baud_rate: baud,
+        ..FlashOptions::default()
+    };
+    let boot_mode =
+        match stm32_uart::probe_boot_mode(&port.port_name, &options).map_err(|e| e.to_string())? {
+            Some(mode) => mode,
+            None => {
+                prompt(&tr(Msg::InitManualBoot, &[]));
+                BootMode::None
+            }
+        };
+    let mode_name = boot_mode
+        .to_possible_value()
+        .map(|v| v.get_name().to_string())
+        .unwrap_or_default();
+    println!("{}", tr(Msg::InitBootMode, &[&mode_name]));
+
+    // 3. 用选定的参数完整连接一次
+    let options = FlashOptions {
+        boot_mode,
+        lines: BootLineConfig::for_mode(boot_mode),
+        ..options
+    };
+    let result = stm32_uart::identify(&port.port_name, &options);
+    if !result.ok {
+        return Err(result.error.unwrap_or_default());
+    }
+    let chip = result
+        .product_id
+        .and_then(chipdb::lookup)
+        .map(|c| c.name.to_string())
+        .unwrap_or_else(|| format!("0x{:03X}", result.product_id.unwrap_or(0)));
+    println!("{}", tr(Msg::InitSynced, &[&chip]));
+
+    // 4. 写入配置
+    let config = ProjectConfig {
+        port: Some(port.port_name),
+        baud: Some(baud),
+        boot_mode: Some(boot_mode),
+        ..ProjectConfig::default()
+    };
+    config.save(&path).map_err(|e| e.to_string())?;
+    Ok(path)
+}
+
+/// 解除读保护前要求输入 yes 确认；stdin 关闭时视为拒绝
+fn confirm_unlock() -> bool {
+    eprintln!("{}", tr(Msg::UnlockWarning, &[]));
+    eprint!("{}", tr(Msg::ConfirmUnlock, &[]));
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer).is_ok() && answer.trim().eq_ignore_ascii_case("yes")
+}
+
+/// 打印写入字节数和各阶段耗时
+fn print_report(report: &FlashReport) {
+    println!("{}", tr(Msg::ReportBytesWritten, &[&report.bytes_written]));
+    for (phase, elapsed) in report.timeline.entries() {
+        println!("  {:<11} {:>7.2} s", phase.name(), elapsed.as_secs_f32());
+    }
+    println!(
+        "  {:<11} {:>7.2} s",
+        tr(Msg::ReportTotal, &[]),
+        report.timeline.total().as_secs_f32()
+    );
+    // USB DFU 等不经过串口命令的传输没有链路统计
+    if let Some(link) = &report.link {
+        println!("{}", tr(Msg::ReportLinkQuality, &[link]));
+    }
 }
 
 fn main() {
-    tracing_subscriber::fmt()
-        .with_env_filter(
-            tracing_subscriber::EnvFilter::from_default_env()
-                .add_directive(tracing::Level::INFO.into()),
-        )
-        .init();
-
-    let cli = Cli::parse();
+    let mut cli = Cli::parse();
+    i18n::set_lang(cli.lang.unwrap_or_else(Lang::from_env));
+    let events_json = matches!(
+        cli.command,
+        Commands::Flash {
+            events_json: true,
+            ..
+        }
+    );
+    logging::init(move |level, msg| {
+        if events_json {
+            JSON_EVENTS.line(level, msg);
+        } else {
+            StdoutLogger.line(level, msg);
+        }
+    });
     let logger = StdoutLogger;
 
+    // 第一次 Ctrl-C 在下一个协议步骤之间中止并释放串口，再按一次直接退出
+    let _ = ctrlc::set_handler(|| {
+        if CANCEL.is_cancelled() {
+            std::process::exit(130);
+        }
+        CANCEL.cancel();
+        eprintln!("\n{}", tr(Msg::Cancelling, &[]));
+    });
+
+    if cli.command.conn_mut().is_some() {
+        match ProjectConfig::load_nearest() {
+            Ok(config) => cli.command.apply_config(&config),
+            Err(e) => {
+                eprintln!("{}", tr(Msg::Failed, &[&"Config", &e]));
+                return;
+            }
+        }
+        if cli
+            .command
+            .conn_mut()
+            .is_some_and(|conn| conn.port.is_none())
+        {
+            eprintln!("{}", tr(Msg::NoPortGiven, &[]));
+            return;
+        }
+    }
+
     match cli.command {
         Commands::ListPorts => match stm32_uart::list_ports() {
             Ok(ports) => {
                 if ports.is_empty() {
-                    println!("No serial ports found.");
+                    println!("{}", tr(Msg::NoSerialPorts, &[]));
                 } else {
-                    println!("Available ports:");
+                    println!("{}", tr(Msg::AvailablePorts, &[]));
                     for p in ports {
                         let marker = if p.vid.is_some() { "*" } else { " " };
                         println!("{} {}", marker, p.label);
                     }
                 }
             }
-            Err(e) => eprintln!("Error listing ports: {e}"),
+            Err(e) => eprintln!("{}", tr(Msg::ListPortsFailed, &[&e])),
         },
 
-        Commands::Identify {
-            port,
-            baud,
-            boot_mode,
-        } => {
+        Commands::Dfu(DfuCommand::List) => match stm32_dfu::list_devices() {
+            Ok(devices) => {
+                if devices.is_empty() {
+                    println!("{}", tr(Msg::NoDfuDevices, &[]));
+                } else {
+                    println!("{}", tr(Msg::DfuDevices, &[]));
+                    for device in devices {
+                        println!("  {}", device.label());
+                        for (alt, name) in &device.alts {
+                            println!("    alt {alt}: {name}");
+                        }
+                    }
+                }
+            }
+            Err(e) => eprintln!("{}", tr(Msg::ListDfuFailed, &[&e])),
+        },
+
+        Commands::Dfu(DfuCommand::Flash {
+            device,
+            hex,
+            format,
+            bin,
+            address,
+            erase,
+            verify,
+            no_reset,
+            go_address,
+            progress,
+            padding,
+        }) => {
+            let sources = firmware_sources(hex.as_slice(), format, bin, address);
             let opts = FlashOptions {
-                baud_rate: baud,
-                boot_mode,
-                lines: BootLineConfig::default(),
-                verify: false,
-                reset_after: false,
-                read_timeout: Duration::from_millis(800),
+                reset_after: !no_reset,
+                go_address,
+                verify: if verify {
+                    VerifyStrategy::ReadBackFull
+                } else {
+                    VerifyStrategy::None
+                },
+                erase_mode: erase,
+                progress: progress.granularity(),
+                padding: padding.padding(),
+                timeouts: device.timeouts(),
+                cancel: CANCEL.clone(),
+                ..FlashOptions::default()
             };
+            match stm32_dfu::flash_dfu(&device.selector(), &sources, &opts, &logger) {
+                Ok(report) => {
+                    println!("{}", tr(Msg::Succeeded, &[&"Flash"]));
+                    print_report(&report);
+                }
+                Err(e) => eprintln!("{}", tr(Msg::Failed, &[&"Flash", &e])),
+            }
+        }
 
-            let result = stm32_uart::identify(&port, &opts, &logger);
+        Commands::Identify { conn } => {
+            let opts = conn.options();
+
+            let result = stm32_uart::identify(conn.port(), &opts);
             if result.ok {
-                println!("Identify OK");
+                println!("{}", tr(Msg::IdentifyOk, &[]));
                 if let Some(ver) = result.bootloader_version {
-                    println!("  Bootloader version: 0x{ver:02X}");
+                    println!(
+                        "{}",
+                        tr(Msg::IdBootloaderVersion, &[&format!("0x{ver:02X}")])
+                    );
+                }
+                if let Some(protocol) = result.protocol {
+                    let timing = protocol.timing(result.product_id.and_then(chipdb::lookup));
+                    println!(
+                        "{}",
+                        tr(
+                            Msg::IdProtocol,
+                            &[
+                                &protocol.label(),
+                                &format!("{:?}", protocol.variant),
+                                &timing.erase().as_secs(),
+                            ]
+                        )
+                    );
                 }
                 if let Some(pid) = result.product_id {
-                    println!("  Product ID: 0x{pid:04X}");
+                    println!("{}", tr(Msg::IdProductId, &[&format!("0x{pid:04X}")]));
+                    match chipdb::lookup(pid) {
+                        Some(chip) => {
+                            println!("{}", tr(Msg::IdChip, &[&chip.describe()]));
+                            for quirk in chip.quirks() {
+                                println!("    - {}", quirk.describe());
+                            }
+                        }
+                        None => println!("{}", tr(Msg::IdChipUnknown, &[])),
+                    }
+                }
+                if let Some(size) = result.flash_size {
+                    println!("{}", tr(Msg::IdFlashSize, &[&(size / 1024)]));
+                }
+                if result.product_id_raw.len() > 2 {
+                    println!(
+                        "{}",
+                        tr(
+                            Msg::IdProductIdRaw,
+                            &[&format!("{:02X?}", result.product_id_raw)]
+                        )
+                    );
+                }
+                match result.read_protected {
+                    Some(true) => println!("{}", tr(Msg::IdReadProtected, &[])),
+                    Some(false) => println!("{}", tr(Msg::IdReadUnprotected, &[])),
+                    None => {}
+                }
+                if let Some(info) = result.version_info {
+                    println!(
+                        "{}",
+                        tr(
+                            Msg::IdRdpCounters,
+                            &[
+                                &info.read_protection_disabled,
+                                &info.read_protection_enabled
+                            ]
+                        )
+                    );
+                }
+                println!("{}", tr(Msg::IdSupportedCommands, &[]));
+                for &cmd in &result.supported_commands {
+                    println!("    0x{cmd:02X} {}", stm32_uart::command_name(cmd));
+                }
+                println!(
+                    "{}",
+                    tr(
+                        Msg::IdCapabilities,
+                        &[&result.capabilities.summary().join(", ")]
+                    )
+                );
+                if let Some(map) = result.product_id.and_then(chipdb::chip_memory_map) {
+                    println!("{}", tr(Msg::IdMemoryMap, &[&map.chip]));
+                    for region in &map.regions {
+                        println!(
+                            "{}",
+                            tr(
+                                Msg::IdMemoryRegion,
+                                &[
+                                    &format!("0x{:08X}", region.start),
+                                    &format!("{:>8}", region.size),
+                                    &region.name
+                                ]
+                            )
+                        );
+                    }
                 }
-                println!("  Supported commands: {:02X?}", result.supported_commands);
             } else {
-                eprintln!("Identify FAILED: {}", result.error.unwrap_or_default());
+                eprintln!(
+                    "{}",
+                    tr(
+                        Msg::Failed,
+                        &[&"Identify", &result.error.unwrap_or_default()]
+                    )
+                );
             }
         }
 
         Commands::Flash {
-            port,
+            conn,
             hex,
-            baud,
-            boot_mode,
+            format,
+            bin,
+            address,
+            version,
             no_reset,
+            stay_in_bootloader,
+            rebase,
+            offset,
+            go_address,
+            lock,
+            unprotect,
+            protect_after,
+            option_bytes,
+            verify,
+            verify_method,
+            erase,
+            bank,
+            swap_banks,
+            virgin,
+            wait,
+            wait_timeout,
+            progress,
+            fixture,
+            padding,
+            no_flush,
+            no_skip_blank,
+            chunk_size,
+            no_track,
+            cycle_warn,
+            events_json,
+            external_memory,
+            stub,
+            stub_address,
+            stub_baud,
         } => {
+            let json_logger = &*JSON_EVENTS;
+            let logger: &dyn ProgressSink = if events_json { json_logger } else { &logger };
+            // 事件模式下结果也作为事件输出，stdout 只有 JSON 行
+            let report_result = |result: Result<FlashReport, stm32_uart::Error>| {
+                if events_json {
+                    json_logger.emit(&FlashEvent::result("flash", result.as_ref()));
+                    return;
+                }
+                match result {
+                    Ok(report) => {
+                        println!("{}", tr(Msg::Succeeded, &[&"Flash"]));
+                        print_report(&report);
+                    }
+                    Err(e) => eprintln!("{}", tr(Msg::Failed, &[&"Flash", &e])),
+                }
+            };
+
+            let opts = FlashOptions {
+                reset_after: !no_reset,
+                stay_in_bootloader,
+                rebase_alias: rebase,
+                offset,
+                go_address,
+                lock_after: lock,
+                unprotect_before: unprotect,
+                protect_after,
+                option_bytes,
+                verify: verify_method.unwrap_or(if verify {
+                    VerifyStrategy::ReadBackFull
+                } else {
+                    VerifyStrategy::None
+                }),
+                erase_mode: erase,
+                bank,
+                swap_banks,
+                virgin_probe: virgin,
+                version_locator: version.locator(),
+                progress: progress.granularity(),
+                padding: padding.padding(),
+                flush_frames: !no_flush,
+                skip_blank: !no_skip_blank,
+                write_chunk: chunk_size.into(),
+                cycle_tracking: (!no_track).then(|| CycleTracking {
+                    warn_threshold: cycle_warn,
+                    ..CycleTracking::default()
+                }),
+                external_memory,
+                ..conn.options()
+            };
+
+            let port = if wait {
+                let timeout = wait_timeout.map(Duration::from_secs);
+                match stm32_uart::wait_for_port(conn.port(), timeout) {
+                    Ok(port) => port,
+                    Err(e) => {
+                        report_result(Err(e));
+                        return;
+                    }
+                }
+            } else {
+                conn.port().to_string()
+            };
+
+            // 多个 --hex 合并为一次会话，按各自地址一起擦除、写入
+            let sources = firmware_sources(&hex, format, bin, address);
+
+            let stub = match stub
+                .map(|path| StubConfig::load(&path, stub_address, stub_baud))
+                .transpose()
+            {
+                Ok(stub) => stub,
+                Err(e) => {
+                    report_result(Err(e));
+                    return;
+                }
+            };
+
+            let trigger = fixture.trigger();
+            loop {
+                if fixture.trigger.is_some()
+                    && let Err(e) = stm32_uart::wait_for_trigger(&port, &trigger, &opts)
+                {
+                    report_result(Err(e));
+                    return;
+                }
+
+                report_result(match &stub {
+                    Some(stub) => stub::flash_with_stub(&port, &sources, stub, &opts, logger),
+                    None => stm32_uart::flash_sources(&port, &sources, &opts, logger),
+                });
+                if let Err(e) = stm32_uart::signal_done(&port, &trigger, &opts) {
+                    eprintln!("{}", tr(Msg::Failed, &[&"Flash", &e]));
+                }
+                if !fixture.repeat || CANCEL.is_cancelled() {
+                    break;
+                }
+            }
+        }
+
+        Commands::Bench { conn, rounds } => {
+            let result = BootloaderSession::open(conn.port(), &conn.options())
+                .and_then(|mut session| session.bench(rounds, &logger));
+            match result {
+                Ok(stats) => {
+                    println!(
+                        "  {:<20} {:>6} {:>9} {:>9} {:>6} {:>8} {:>7}",
+                        "command", "count", "mean ms", "max ms", "NACK", "timeout", "garbled"
+                    );
+                    print!("{}", stats.table());
+                    println!("  Link quality: {stats}");
+                }
+                Err(e) => eprintln!("{}", tr(Msg::Failed, &[&"Bench", &e])),
+            }
+        }
+
+        Commands::Verify {
+            conn,
+            hex,
+            format,
+            bin,
+            address,
+            verify_method,
+            no_reset,
+        } => {
+            let sources = firmware_sources(&hex, format, bin, address);
+            let opts = FlashOptions {
+                reset_after: !no_reset,
+                verify: verify_method,
+                ..conn.options()
+            };
+            match stm32_uart::verify_sources(conn.port(), &sources, &opts, &logger) {
+                Ok(diffs) => {
+                    for diff in &diffs {
+                        println!("  {diff}");
+                    }
+                    if diffs.iter().all(|d| d.matches()) {
+                        println!("{}", tr(Msg::Succeeded, &[&"Verify"]));
+                    } else {
+                        eprintln!(
+                            "{}",
+                            tr(Msg::Failed, &[&"Verify", &"flash differs from the image"])
+                        );
+                    }
+                }
+                Err(e) => eprintln!("{}", tr(Msg::Failed, &[&"Verify", &e])),
+            }
+        }
+
+        Commands::Read {
+            conn,
+            address,
+            length,
+            out,
+            format,
+        } => {
+            let format = format.unwrap_or_else(|| DumpFormat::from_path(&out));
+            match stm32_uart::dump_flash(
+                conn.port(),
+                address,
+                length as usize,
+                &out,
+                format,
+                &conn.options(),
+                &logger,
+            ) {
+                Ok(()) => println!("{}", tr(Msg::Succeeded, &[&"Read"])),
+                Err(e) => eprintln!("{}", tr(Msg::Failed, &[&"Read", &e])),
+            }
+        }
+
+        Commands::Inspect {
+            hex,
+            format,
+            version,
+        } => {
+            let source = match format {
+                Some(format) => ImageSource::with_format(&hex, format, None),
+                None => ImageSource::from_path(&hex, None),
+            };
+            let image = match source.load() {
+                Ok(image) => image,
+                Err(e) => {
+                    eprintln!("{}", tr(Msg::Failed, &[&"Inspect", &e]));
+                    return;
+                }
+            };
+
+            let info = stm32_uart::inspect_image(&image, version.locator().as_ref());
+            println!("Image: {}", hex.display());
+            println!("  Size: {} bytes", info.size);
+            println!("  Range: 0x{:08X} - 0x{:08X}", info.start, info.end);
+            println!("  Segments:");
+            for (base, len) in &info.segments {
+                println!("    0x{base:08X}  {len} bytes");
+            }
+            if let Some(v) = info.version {
+                println!("  Version: {v}");
+            } else if version.locator().is_some() {
+                println!("  Version: (not found)");
+            }
+            for issue in &info.vector_issues {
+                println!("  Warning: {}", issue.message());
+            }
+        }
+
+        Commands::Lock {
+            conn,
+            no_reset,
+            stay_in_bootloader,
+        } => {
+            let opts = FlashOptions {
+                reset_after: !no_reset,
+                stay_in_bootloader,
+                ..conn.options()
+            };
+
+            match stm32_uart::lock(conn.port(), &opts) {
+                Ok(()) => println!("{}", tr(Msg::Succeeded, &[&"Lock"])),
+                Err(e) => eprintln!("{}", tr(Msg::Failed, &[&"Lock", &e])),
+            }
+        }
+
+        Commands::OptionBytes(OptionBytesCommand::Show { conn, no_reset }) => {
+            let opts = FlashOptions {
+                reset_after: !no_reset,
+                ..conn.options()
+            };
+            match stm32_uart::read_option_bytes(conn.port(), &opts) {
+                Ok(ob) => print!("{ob}"),
+                Err(e) => eprintln!("{}", tr(Msg::Failed, &[&"Read option bytes", &e])),
+            }
+        }
+
+        Commands::OptionBytes(OptionBytesCommand::Set {
+            conn,
+            changes,
+            no_reset,
+        }) => {
+            let opts = FlashOptions {
+                reset_after: !no_reset,
+                ..conn.options()
+            };
+            match stm32_uart::program_option_bytes(conn.port(), &changes, &opts) {
+                Ok(ob) => {
+                    println!("{}", tr(Msg::OptionBytesProgrammed, &[]));
+                    print!("{ob}");
+                }
+                Err(e) => eprintln!("{}", tr(Msg::Failed, &[&"Program option bytes", &e])),
+            }
+        }
+
+        Commands::OptionBytes(OptionBytesCommand::Backup { conn, out }) => {
+            match stm32_uart::backup_option_bytes(conn.port(), &out, &conn.options()) {
+                Ok(()) => println!("{}", tr(Msg::OptionBytesSaved, &[&out.display()])),
+                Err(e) => eprintln!("{}", tr(Msg::Failed, &[&"Backup", &e])),
+            }
+        }
+
+        Commands::OptionBytes(OptionBytesCommand::Restore {
+            conn,
+            file,
+            no_reset,
+        }) => {
             let opts = FlashOptions {
-                baud_rate: baud,
-                boot_mode,
-                lines: BootLineConfig::default(),
-                verify: false,
                 reset_after: !no_reset,
-                read_timeout: Duration::from_millis(800),
+                ..conn.options()
             };
 
-            match stm32_uart::flash_hex(&port, &hex, &opts, &logger) {
-                Ok(()) => println!("Flash completed successfully!"),
-                Err(e) => eprintln!("Flash FAILED: {e}"),
+            match stm32_uart::restore_option_bytes(conn.port(), &file, &opts) {
+                Ok(()) => println!("{}", tr(Msg::OptionBytesRestored, &[])),
+                Err(e) => eprintln!("{}", tr(Msg::Failed, &[&"Restore", &e])),
+            }
+        }
+
+        Commands::Lines(LinesCommand::Status { port }) => {
+            match stm32_uart::modem_status(&port, &FlashOptions::default()) {
+                Ok(status) => {
+                    let level = |on: bool| tr(if on { Msg::LineOn } else { Msg::LineOff }, &[]);
+                    println!("{}", tr(Msg::LinesHeader, &[&port]));
+                    println!("  CTS: {}", level(status.cts));
+                    println!("  DSR: {}", level(status.dsr));
+                    println!("  CD:  {}", level(status.cd));
+                    println!("  RI:  {}", level(status.ri));
+                }
+                Err(e) => eprintln!("{}", tr(Msg::Failed, &[&"Lines", &e])),
+            }
+        }
+
+        Commands::Reset { conn } => match stm32_uart::reset(conn.port(), &conn.options()) {
+            Ok(()) => println!("{}", tr(Msg::Succeeded, &[&"Reset"])),
+            Err(e) => eprintln!("{}", tr(Msg::Failed, &[&"Reset", &e])),
+        },
+
+        Commands::Doctor {
+            bundle,
+            output,
+            mut conn,
+            probe,
+        } => {
+            let version = env!("CARGO_PKG_VERSION");
+            if !bundle {
+                print!("{}", diag::system_info(version));
+                println!("\nPorts:");
+                print!("{}", diag::port_inventory());
+
+                // 配置文件读取失败由 config 检查报告，这里只补全命令行未给出的参数
+                if let Ok(config) = ProjectConfig::load_nearest() {
+                    conn.apply_config(&config);
+                }
+                println!("\nChecks:");
+                let checks = diag::run_checks(conn.port.as_deref(), &conn.options(), probe);
+                for check in &checks {
+                    let status = match check.status {
+                        CheckStatus::Pass => "PASS",
+                        CheckStatus::Warn => "WARN",
+                        CheckStatus::Fail => "FAIL",
+                        CheckStatus::Skip => "SKIP",
+                    };
+                    println!("  [{status}] {}: {}", check.name, check.detail);
+                    if let Some(fix) = &check.fix {
+                        println!("         fix: {fix}");
+                    }
+                }
+                println!("\nLogs of the last run: {}", diag::session_dir().display());
+                return;
+            }
+
+            let path = output.unwrap_or_else(|| {
+                let stamp = chrono::Local::now().format("%Y%m%d-%H%M%S");
+                PathBuf::from(format!("probe-flasher-diag-{stamp}.zip"))
+            });
+            let settings = format!("{:#?}", FlashOptions::default());
+            match diag::create_bundle(&path, version, &settings) {
+                Ok(()) => println!("{}", tr(Msg::BundleWritten, &[&path.display()])),
+                Err(e) => eprintln!("{}", tr(Msg::Failed, &[&"Doctor", &e])),
+            }
+        }
+
+        Commands::Devices => {
+            let path = devicedb::default_db_path();
+            match DeviceDb::load(&path) {
+                Ok(db) if db.devices.is_empty() => {
+                    println!("{}", tr(Msg::NoDevicesRecorded, &[&path.display()]))
+                }
+                Ok(db) => {
+                    println!("{}", tr(Msg::DevicesHeader, &[&path.display()]));
+                    for (uid, record) in &db.devices {
+                        let pid = record
+                            .product_id
+                            .map_or("-".to_string(), |p| format!("0x{p:03X}"));
+                        println!(
+                            "{}",
+                            tr(
+                                Msg::DeviceRecord,
+                                &[
+                                    uid,
+                                    &pid,
+                                    &record.erase_count,
+                                    &record.flash_count,
+                                    &record.last_seen
+                                ]
+                            )
+                        );
+                    }
+                }
+                Err(e) => eprintln!("{}", tr(Msg::Failed, &[&"Devices", &e])),
+            }
+        }
+
+        Commands::Init { baud, force } => match run_init(baud, force) {
+            Ok(path) => println!("{}", tr(Msg::InitSaved, &[&path.display()])),
+            Err(e) => eprintln!("{}", tr(Msg::Failed, &[&"Init", &e])),
+        },
+
+        Commands::Unlock { conn, yes } => {
+            if !yes && !confirm_unlock() {
+                eprintln!(
+                    "{}",
+                    tr(Msg::Failed, &[&"Unlock", &tr(Msg::UnlockCancelled, &[])])
+                );
+                return;
+            }
+            let opts = conn.options();
+
+            match stm32_uart::unlock(conn.port(), &opts) {
+                Ok(()) => println!("{}", tr(Msg::Succeeded, &[&"Unlock"])),
+                Err(e) => eprintln!("{}", tr(Msg::Failed, &[&"Unlock", &e])),
             }
         }
     }
+
+    let _ = diag::save_session();
 }
@@ -0,0 +1,445 @@
+//! 芯片数据库：按 Bootloader 返回的产品 ID 查找产品线名称、存储器布局和 Bootloader 特性
+//!
+//! Flash 大小按该产品线的最大容量登记，实际型号可能更小；连接后可读 `flash_size_reg` 得到实际容量。
+
+use std::time::Duration;
+
+use serde::Serialize;
+
+use crate::stm32_uart::FLASH_BASE;
+
+const SRAM_BASE: u32 = 0x2000_0000;
+
+/// F2 / F4 的扇区布局：4 × 16 KB、1 × 64 KB，其余为 128 KB
+const fn f4_sectors(count_128k: u32) -> [(u32, u32); 3] {
+    [(4, 16 * 1024), (1, 64 * 1024), (count_128k, 128 * 1024)]
+}
+
+/// 一款芯片（产品线）的存储器布局
+#[derive(Debug)]
+pub struct ChipInfo {
+    pub pid: u16,
+    pub name: &'static str,
+    /// 每个 Bank 的擦除单元布局（数量, 大小），Bank 依次紧接在 FLASH_BASE 之后
+    pub flash_banks: &'static [&'static [(u32, u32)]],
+    /// 系统存储器（Bootloader）起始地址和大小
+    pub system_memory: (u32, u32),
+    pub option_bytes: (u32, u32),
+    /// 96 位唯一 ID 的地址
+    pub unique_id: u32,
+    /// Flash 容量寄存器（16 位，单位 KB）的地址，读出同一产品线中具体型号的实际容量
+    pub flash_size_reg: u32,
+    pub otp: Option<(u32, u32)>,
+    pub sram_size: u32,
+    /// 最小编程单位（字节）：F0 / F1 / F3 / F4 为半字，L4 / G0 为双字，H7 为 256 位 Flash 字
+    pub write_unit: u32,
+}
+
+macro_rules! chips {
+    ($($pid:literal $name:literal flash $banks:expr, system $sys:expr, ob $ob:expr, uid $uid:expr, fsize $fsize:expr, otp $otp:expr, sram $sram:expr $(, write $unit:expr)?;)*) => {
+        const CHIPS: &[ChipInfo] = &[
+            $(ChipInfo {
+                pid: $pid,
+                name: $name,
+                flash_banks: $banks,
+                system_memory: $sys,
+                option_bytes: $ob,
+                unique_id: $uid,
+                flash_size_reg: $fsize,
+                otp: $otp,
+                sram_size: $sram * 1024,
+                write_unit: chips!(@unit $($unit)?),
+            },)*
+        ];
+    };
+    (@unit) => { 2 };
+    (@unit $unit:expr) => { $unit };
+}
+
+/// 影响烧录方式的 Bootloader / Flash 特性（AN2606、各系列参考手册）
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case", tag = "kind", content = "value")]
+pub enum ChipQuirk {
+    /// 编程单位大于半字，数据段需按该字节数对齐补 0xFF（见 `--align`）
+    WriteUnit(u32),
+    /// 大扇区 Flash，全片擦除期间 Bootloader 长时间不应答
+    SlowMassErase,
+    /// 双 Bank，扇区 / 页号跨 Bank 连续编号
+    DualBank,
+    /// Flash 为空时上电直接进入 Bootloader，该判断只在上电或重新加载选项字节时更新
+    EmptyCheck,
+}
+
+impl ChipQuirk {
+    pub fn describe(&self) -> String {
+        match self {
+            ChipQuirk::WriteUnit(n) => {
+                format!("programs in {n}-byte units; pad images with --align {n}")
+            }
+            ChipQuirk::SlowMassErase => {
+                "mass erase of large sectors takes tens of seconds".to_string()
+            }
+            ChipQuirk::DualBank => "dual bank; sector numbers continue across banks".to_string(),
+            ChipQuirk::EmptyCheck => {
+                "boots the bootloader while flash is empty; option bytes are reloaded after the first programming".to_string()
+            }
+        }
+    }
+}
+
+/// 带 Flash 空检查的产品线（AN2606）：F04x、F07x、F09x、G07x/08x
+const EMPTY_CHECK: &[u16] = &[0x445, 0x448, 0x442, 0x460];
+
+const F1_OB: (u32, u32) = (0x1FFF_F800, 16);
+const F4_OB: (u32, u32) = (0x1FFF_C000, 16);
+const F4_SYS: (u32, u32) = (0x1FFF_0000, 30 * 1024);
+const F4_OTP: Option<(u32, u32)> = Some((0x1FFF_7800, 528));
+const F1_UID: u32 = 0x1FFF_F7E8;
+const F0_UID: u32 = 0x1FFF_F7AC;
+const F4_UID: u32 = 0x1FFF_7A10;
+const F3_SYS: (u32, u32) = (0x1FFF_D800, 8 * 1024);
+const L4_SYS: (u32, u32) = (0x1FFF_0000, 28 * 1024);
+const L4_OB: (u32, u32) = (0x1FFF_7800, 40);
+const L4_UID: u32 = 0x1FFF_7590;
+const F1_FSIZE: u32 = 0x1FFF_F7E0;
+const F0_FSIZE: u32 = 0x1FFF_F7CC;
+const F4_FSIZE: u32 = 0x1FFF_7A22;
+const L4_FSIZE: u32 = 0x1FFF_75E0;
+
+chips! {
+    0x412 "STM32F10xxx low-density" flash &[&[(32, 1024)]], system (0x1FFF_F000, 2048), ob F1_OB, uid F1_UID, fsize F1_FSIZE, otp None, sram 10;
+    0x410 "STM32F10xxx medium-density" flash &[&[(128, 1024)]], system (0x1FFF_F000, 2048), ob F1_OB, uid F1_UID, fsize F1_FSIZE, otp None, sram 20;
+    0x414 "STM32F10xxx high-density" flash &[&[(256, 2048)]], system (0x1FFF_F000, 2048), ob F1_OB, uid F1_UID, fsize F1_FSIZE, otp None, sram 64;
+    0x430 "STM32F10xxx XL-density" flash &[&[(256, 2048)], &[(256, 2048)]], system (0x1FFF_E000, 6 * 1024), ob F1_OB, uid F1_UID, fsize F1_FSIZE, otp None, sram 96;
+    0x418 "STM32F105/107 connectivity line" flash &[&[(128, 2048)]], system (0x1FFF_B000, 18 * 1024), ob F1_OB, uid F1_UID, fsize F1_FSIZE, otp None, sram 64;
+    0x420 "STM32F100 value line medium-density" flash &[&[(128, 1024)]], system (0x1FFF_F000, 2048), ob F1_OB, uid F1_UID, fsize F1_FSIZE, otp None, sram 8;
+    0x428 "STM32F100 value line high-density" flash &[&[(256, 2048)]], system (0x1FFF_F000, 2048), ob F1_OB, uid F1_UID, fsize F1_FSIZE, otp None, sram 32;
+    0x444 "STM32F03x" flash &[&[(32, 1024)]], system (0x1FFF_EC00, 3 * 1024), ob F1_OB, uid F0_UID, fsize F0_FSIZE, otp None, sram 4;
+    0x445 "STM32F04x" flash &[&[(32, 1024)]], system (0x1FFF_C400, 12 * 1024), ob F1_OB, uid F0_UID, fsize F0_FSIZE, otp None, sram 6;
+    0x440 "STM32F05x / F030x8" flash &[&[(64, 1024)]], system (0x1FFF_EC00, 3 * 1024), ob F1_OB, uid F0_UID, fsize F0_FSIZE, otp None, sram 8;
+    0x448 "STM32F07x" flash &[&[(64, 2048)]], system (0x1FFF_C800, 12 * 1024), ob F1_OB, uid F0_UID, fsize F0_FSIZE, otp None, sram 16;
+    0x442 "STM32F09x / F030xC" flash &[&[(128, 2048)]], system (0x1FFF_D800, 8 * 1024), ob F1_OB, uid F0_UID, fsize F0_FSIZE, otp None, sram 32;
+    0x439 "STM32F301/F302x4/6/8" flash &[&[(32, 2048)]], system F3_SYS, ob F1_OB, uid F0_UID, fsize F0_FSIZE, otp None, sram 16;
+    0x438 "STM32F303x4/6/8 / F334" flash &[&[(32, 2048)]], system F3_SYS, ob F1_OB, uid F0_UID, fsize F0_FSIZE, otp None, sram 12;
+    0x422 "STM32F302xB/C / F303xB/C" flash &[&[(128, 2048)]], system F3_SYS, ob F1_OB, uid F0_UID, fsize F0_FSIZE, otp None, sram 40;
+    0x432 "STM32F37x" flash &[&[(128, 2048)]], system F3_SYS, ob F1_OB, uid F0_UID, fsize F0_FSIZE, otp None, sram 32;
+    0x446 "STM32F302xD/E / F303xD/E" flash &[&[(256, 2048)]], system F3_SYS, ob F1_OB, uid F0_UID, fsize F0_FSIZE, otp None, sram 64;
+    0x411 "STM32F2xx" flash &[&f4_sectors(7)], system F4_SYS, ob F4_OB, uid F4_UID, fsize F4_FSIZE, otp F4_OTP, sram 128;
+    0x413 "STM32F405/407/415/417" flash &[&f4_sectors(7)], system F4_SYS, ob F4_OB, uid F4_UID, fsize F4_FSIZE, otp F4_OTP, sram 128;
+    0x419 "STM32F42x/43x" flash &[&f4_sectors(7), &f4_sectors(7)], system F4_SYS, ob F4_OB, uid F4_UID, fsize F4_FSIZE, otp F4_OTP, sram 192;
+    0x423 "STM32F401xB/C" flash &[&f4_sectors(1)], system F4_SYS, ob F4_OB, uid F4_UID, fsize F4_FSIZE, otp F4_OTP, sram 64;
+    0x433 "STM32F401xD/E" flash &[&f4_sectors(3)], system F4_SYS, ob F4_OB, uid F4_UID, fsize F4_FSIZE, otp F4_OTP, sram 96;
+    0x431 "STM32F411" flash &[&f4_sectors(3)], system F4_SYS, ob F4_OB, uid F4_UID, fsize F4_FSIZE, otp F4_OTP, sram 128;
+    0x421 "STM32F446" flash &[&f4_sectors(3)], system F4_SYS, ob F4_OB, uid F4_UID, fsize F4_FSIZE, otp F4_OTP, sram 128;
+    0x458 "STM32F410" flash &[&f4_sectors(0)], system F4_SYS, ob F4_OB, uid F4_UID, fsize F4_FSIZE, otp F4_OTP, sram 32;
+    0x441 "STM32F412" flash &[&f4_sectors(7)], system F4_SYS, ob F4_OB, uid F4_UID, fsize F4_FSIZE, otp F4_OTP, sram 256;
+    0x463 "STM32F413/423" flash &[&f4_sectors(11)], system F4_SYS, ob F4_OB, uid F4_UID, fsize F4_FSIZE, otp F4_OTP, sram 320;
+    0x434 "STM32F469/479" flash &[&f4_sectors(7), &f4_sectors(7)], system F4_SYS, ob F4_OB, uid F4_UID, fsize F4_FSIZE, otp F4_OTP, sram 384;
+    0x460 "STM32G07x/08x" flash &[&[(64, 2048)]], system L4_SYS, ob L4_OB, uid L4_UID, fsize L4_FSIZE, otp Some((0x1FFF_7000, 1024)), sram 36, write 8;
+    0x415 "STM32L47x/48x" flash &[&[(256, 2048)], &[(256, 2048)]], system L4_SYS, ob L4_OB, uid L4_UID, fsize L4_FSIZE, otp Some((0x1FFF_7000, 1024)), sram 96, write 8;
+    0x450 "STM32H74x/75x" flash &[&[(8, 128 * 1024)], &[(8, 128 * 1024)]], system (0x1FF0_0000, 128 * 1024), ob (0x5200_201C, 128), uid 0x1FF1_E800, fsize 0x1FF1_E880, otp None, sram 128, write 32;
+}
+
+impl ChipInfo {
+    /// 各 Bank 合计的 Flash 容量（字节）
+    pub fn flash_size(&self) -> u32 {
+        self.flash_banks
+            .iter()
+            .flat_map(|bank| bank.iter())
+            .map(|&(count, size)| count * size)
+            .sum()
+    }
+
+    /// 如 "STM32F10xxx medium-density, 128 KB flash"
+    pub fn describe(&self) -> String {
+        format!("{}, {} KB flash", self.name, self.flash_size() / 1024)
+    }
+
+    /// 由存储器布局和编程单位推出的 Bootloader / Flash 特性
+    pub fn quirks(&self) -> Vec<ChipQuirk> {
+        let mut quirks = Vec::new();
+        if self.write_unit > 2 {
+            quirks.push(ChipQuirk::WriteUnit(self.write_unit));
+        }
+        if self
+            .flash_banks
+            .iter()
+            .flat_map(|b| b.iter())
+            .any(|&(_, size)| size > 2048)
+        {
+            quirks.push(ChipQuirk::SlowMassErase);
+        }
+        if self.flash_banks.len() > 1 {
+            quirks.push(ChipQuirk::DualBank);
+        }
+        if EMPTY_CHECK.contains(&self.pid) {
+            quirks.push(ChipQuirk::EmptyCheck);
+        }
+        quirks
+    }
+
+    /// 全片擦除的预计耗时（数据手册典型值）：页式 Flash 每个 Bank 约 40 ms，
+    /// F2 / F4 按扇区大小累加（x32 并行度）
+    pub fn mass_erase_time(&self) -> Duration {
+        let ms: u32 = self
+            .flash_banks
+            .iter()
+            .map(|bank| {
+                if bank.iter().all(|&(_, size)| size <= 2048) {
+                    40
+                } else {
+                    bank.iter()
+                        .map(|&(count, size)| count * sector_erase_ms(size))
+                        .sum()
+                }
+            })
+            .sum();
+        Duration::from_millis(ms as u64)
+    }
+
+    /// 默认的擦除超时：整片擦除预计耗时的 4 倍，至少 10 秒，给擦除更慢的兼容芯片留出余量
+    pub fn erase_timeout(&self) -> Duration {
+        (self.mass_erase_time() * 4).max(Duration::from_secs(10))
+    }
+
+    /// Flash 的全部擦除单元，按地址顺序
+    pub fn erase_units(&self) -> impl Iterator<Item = EraseUnit> + '_ {
+        self.flash_banks
+            .iter()
+            .flat_map(|bank| bank.iter())
+            .flat_map(|&(count, size)| std::iter::repeat_n(size, count as usize))
+            .scan(FLASH_BASE, |start, size| {
+                let unit_start = *start;
+                *start += size;
+                Some((unit_start, size))
+            })
+            .enumerate()
+            .map(|(index, (start, size))| EraseUnit {
+                index: index as u16,
+                start,
+                size,
+            })
+    }
+
+    /// 第 `bank` 个 Bank（从 1 开始）的起始地址和大小
+    pub fn bank_range(&self, bank: u8) -> Option<(u32, u32)> {
+        let index = usize::from(bank).checked_sub(1)?;
+        let size = |b: &[(u32, u32)]| b.iter().map(|&(count, size)| count * size).sum::<u32>();
+        let layout = self.flash_banks.get(index)?;
+        let start = FLASH_BASE
+            + self.flash_banks[..index]
+                .iter()
+                .map(|b| size(b))
+                .sum::<u32>();
+        Some((start, size(layout)))
+    }
+
+    /// 第 `bank` 个 Bank 的全部擦除单元，Bank 不存在时为空
+    pub fn bank_units(&self, bank: u8) -> Vec<EraseUnit> {
+        let Some((start, size)) = self.bank_range(bank) else {
+            return Vec::new();
+        };
+        self.erase_units_covering(start, size as usize)
+    }
+
+    /// 与 [address, address + len) 相交的擦除单元，落在 Flash 之外的部分忽略
+    pub fn erase_units_covering(&self, address: u32, len: usize) -> Vec<EraseUnit> {
+        let end = address as u64 + len as u64;
+        self.erase_units()
+            .filter(|unit| (unit.start as u64) < end && unit.end() > address as u64)
+            .collect()
+    }
+}
+
+/// 一个擦除单元（页或扇区）
+///
+/// 编号跨 Bank 连续，与 Erase / Extended Erase 命令使用的页号、扇区号一致。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EraseUnit {
+    pub index: u16,
+    pub start: u32,
+    pub size: u32,
+}
+
+impl EraseUnit {
+    pub fn end(&self) -> u64 {
+        self.start as u64 + self.size as u64
+    }
+
+    /// 擦除该单元的预计耗时：页约 20 ms，扇区按大小
+    pub fn erase_time(&self) -> Duration {
+        let ms = if self.size <= 2048 {
+            20
+        } else {
+            sector_erase_ms(self.size)
+        };
+        Duration::from_millis(ms as u64)
+    }
+}
+
+fn sector_erase_ms(size: u32) -> u32 {
+    match size / 1024 {
+        0..=16 => 250,
+        17..=64 => 500,
+        _ => 1000,
+    }
+}
+
+pub fn lookup(pid: u16) -> Option<&'static ChipInfo> {
+    CHIPS.iter().find(|c| c.pid == pid)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RegionKind {
+    Flash,
+    SystemMemory,
+    OptionBytes,
+    Otp,
+    Sram,
+}
+
+impl RegionKind {
+    /// 能否通过 Bootloader 的 WRITE 命令写入（系统存储器只读）
+    pub fn writable(self) -> bool {
+        self != RegionKind::SystemMemory
+    }
+}
+
+/// 一段命名的存储区域
+#[derive(Debug, Clone, Serialize)]
+pub struct MemoryRegion {
+    pub name: String,
+    pub kind: RegionKind,
+    pub start: u32,
+    pub size: u32,
+    /// Flash 的擦除单元（页 / 扇区）大小，按地址顺序；其他区域为空
+    pub sectors: Vec<u32>,
+}
+
+impl MemoryRegion {
+    fn new(name: &str, kind: RegionKind, (start, size): (u32, u32)) -> Self {
+        Self {
+            name: name.to_string(),
+            kind,
+            start,
+            size,
+            sectors: Vec::new(),
+        }
+    }
+
+    pub fn end(&self) -> u64 {
+        self.start as u64 + self.size as u64
+    }
+
+    pub fn contains(&self, address: u32, len: usize) -> bool {
+        address >= self.start && address as u64 + len as u64 <= self.end()
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MemoryMap {
+    pub chip: &'static str,
+    pub regions: Vec<MemoryRegion>,
+}
+
+impl MemoryMap {
+    /// Flash 区域的结束地址（不含）
+    pub fn flash_end(&self) -> Option<u64> {
+        self.regions
+            .iter()
+            .filter(|r| r.kind == RegionKind::Flash)
+            .map(MemoryRegion::end)
+            .max()
+    }
+
+    /// 按 Flash 容量寄存器读出的实际容量截短 Flash 区域，超出部分的 Bank 和扇区被移除
+    pub fn limit_flash(&mut self, size: u32) {
+        let mut left = size;
+        self.regions.retain_mut(|region| {
+            if region.kind != RegionKind::Flash {
+                return true;
+            }
+            if left == 0 {
+                return false;
+            }
+            if region.size > left {
+                let mut offset = 0;
+                region.sectors.retain(|&sector| {
+                    let keep = offset < left;
+                    offset += sector;
+                    keep
+                });
+                region.size = left;
+            }
+            left -= region.size;
+            true
+        });
+    }
+
+    pub fn region_at(&self, address: u32) -> Option<&MemoryRegion> {
+        self.regions.iter().find(|r| r.contains(address, 1))
+    }
+
+    /// [address, address + len) 是否完全由满足 `accept` 的区域覆盖（可跨越相邻的 Bank）
+    pub fn covers(&self, address: u32, len: usize, accept: impl Fn(&MemoryRegion) -> bool) -> bool {
+        let end = address as u64 + len as u64;
+        let mut cur = address as u64;
+        while cur < end {
+            match u32::try_from(cur).ok().and_then(|a| self.region_at(a)) {
+                Some(region) if accept(region) => cur = region.end(),
+                _ => return false,
+            }
+        }
+        true
+    }
+}
+
+/// 按产品 ID 生成存储器映射：各 Flash Bank、系统存储器、选项字节、OTP 和 SRAM
+pub fn chip_memory_map(pid: u16) -> Option<MemoryMap> {
+    let chip = lookup(pid)?;
+    let mut regions = Vec::new();
+
+    let mut start = FLASH_BASE;
+    for (i, bank) in chip.flash_banks.iter().enumerate() {
+        let sectors: Vec<u32> = bank
+            .iter()
+            .flat_map(|&(count, size)| std::iter::repeat_n(size, count as usize))
+            .collect();
+        let size = sectors.iter().sum();
+        let name = if chip.flash_banks.len() > 1 {
+            format!("Flash Bank {}", i + 1)
+        } else {
+            "Flash".to_string()
+        };
+        regions.push(MemoryRegion {
+            sectors,
+            ..MemoryRegion::new(&name, RegionKind::Flash, (start, size))
+        });
+        start += size;
+    }
+
+    regions.push(MemoryRegion::new(
+        "System memory",
+        RegionKind::SystemMemory,
+        chip.system_memory,
+    ));
+    regions.push(MemoryRegion::new(
+        "Option bytes",
+        RegionKind::OptionBytes,
+        chip.option_bytes,
+    ));
+    if let Some(otp) = chip.otp {
+        regions.push(MemoryRegion::new("OTP", RegionKind::Otp, otp));
+    }
+    regions.push(MemoryRegion::new(
+        "SRAM",
+        RegionKind::Sram,
+        (SRAM_BASE, chip.sram_size),
+    ));
+
+    Some(MemoryMap {
+        chip: chip.name,
+        regions,
+    })
+}
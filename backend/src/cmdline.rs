@@ -0,0 +1,312 @@
+//! 把烧录参数还原成等价的 `probe-flasher flash` 命令行，GUI 中调通的设置可以直接搬进脚本
+//!
+//! CLI 和 GUI 最终都构造同一个 `FlashOptions`，这里只输出与 CLI 默认值不同的参数。
+
+use std::borrow::Cow;
+
+use clap::ValueEnum;
+
+use crate::devicedb;
+use crate::power::PowerSwitch;
+use crate::stm32_uart::{
+    BootLineConfig, BootTiming, EraseMode, FLASH_BASE, FlashOptions, ImageFormat, ImageSource,
+    MAX_WRITE_CHUNK, Padding, ProgressGranularity, VerifyStrategy, VersionLocator, format_offset,
+};
+
+/// CLI 中未给出 `--baud` 时的波特率
+const CLI_BAUD: u32 = 115_200;
+
+fn value_name(value: &impl ValueEnum) -> String {
+    value
+        .to_possible_value()
+        .map(|v| v.get_name().to_string())
+        .unwrap_or_default()
+}
+
+/// 等价的 `probe-flasher flash` 参数（不含程序名）；多个文件中有需要平移地址的、
+/// 或各文件的格式不能用同一个 `--format` 覆盖时，命令行无法表达，返回 None
+pub fn flash_args(
+    port: &str,
+    sources: &[ImageSource],
+    options: &FlashOptions,
+) -> Option<Vec<String>> {
+    let mut args = vec!["flash".to_string(), "--port".to_string(), port.to_string()];
+    push_sources(&mut args, sources)?;
+    push_connect(&mut args, options);
+    push_flash(&mut args, options);
+    Some(args)
+}
+
+/// 同 `flash_args`，按 POSIX shell 规则加引号后拼成一行
+pub fn flash_command(
+    port: &str,
+    sources: &[ImageSource],
+    options: &FlashOptions,
+) -> Option<String> {
+    let args = flash_args(port, sources, options)?;
+    Some(
+        std::iter::once(Cow::Borrowed("probe-flasher"))
+            .chain(args.iter().map(|arg| quote(arg)))
+            .collect::<Vec<_>>()
+            .join(" "),
+    )
+}
+
+/// 只含安全字符的参数原样返回，否则用单引号包起来
+pub fn quote(arg: &str) -> Cow<'_, str> {
+    let safe = |c: char| c.is_ascii_alphanumeric() || "-_./:=,@+%".contains(c);
+    if !arg.is_empty() && arg.chars().all(safe) {
+        return Cow::Borrowed(arg);
+    }
+    Cow::Owned(format!("'{}'", arg.replace('\'', r"'\''")))
+}
+
+fn push(args: &mut Vec<String>, flag: &str, value: impl ToString) {
+    args.push(flag.to_string());
+    args.push(value.to_string());
+}
+
+fn push_sources(args: &mut Vec<String>, sources: &[ImageSource]) -> Option<()> {
+    if let [source] = sources
+        && source.format == ImageFormat::Binary
+    {
+        args.push("--bin".to_string());
+        args.push(source.path.display().to_string());
+        let address = source.base_address.unwrap_or(FLASH_BASE);
+        if address != FLASH_BASE {
+            push(args, "--address", format!("0x{address:08X}"));
+        }
+        return Some(());
+    }
+
+    if sources.is_empty() || sources.iter().any(|s| s.base_address.is_some()) {
+        return None;
+    }
+    // --format 对所有 --hex 生效，只有全部文件按同一格式读取时才能覆盖扩展名
+    let by_extension = sources
+        .iter()
+        .all(|s| s.format == ImageFormat::from_path(&s.path));
+    let format = sources[0].format;
+    if !by_extension && sources.iter().any(|s| s.format != format) {
+        return None;
+    }
+    for source in sources {
+        args.push("--hex".to_string());
+        args.push(source.path.display().to_string());
+    }
+    if !by_extension {
+        push(args, "--format", value_name(&format));
+    }
+    Some(())
+}
+
+fn push_connect(args: &mut Vec<String>, options: &FlashOptions) {
+    let defaults = FlashOptions::default();
+    if options.baud_rate != CLI_BAUD {
+        push(args, "--baud", options.baud_rate);
+    }
+    push(args, "--boot-mode", value_name(&options.boot_mode));
+    let named = BootLineConfig::for_mode(options.boot_mode);
+    if options.lines.boot_level != named.boot_level {
+        push(args, "--boot-level", value_name(&options.lines.boot_level));
+    }
+    if options.lines.reset_assert_level != named.reset_assert_level {
+        push(
+            args,
+            "--reset-level",
+            value_name(&options.lines.reset_assert_level),
+        );
+    }
+    if options.auto_baud {
+        args.push("--auto-baud".to_string());
+    }
+    if let Some(baud) = options.high_baud {
+        push(args, "--high-baud", baud);
+    }
+    if options.boot_cycles != defaults.boot_cycles {
+        push(args, "--boot-cycles", options.boot_cycles);
+    }
+    if options.boot_backoff != defaults.boot_backoff {
+        push(args, "--boot-backoff-ms", options.boot_backoff.as_millis());
+    }
+    let timeouts = options.timeouts;
+    if let Some(ack) = timeouts.ack {
+        push(args, "--read-timeout-ms", ack.as_millis());
+    }
+    if let Some(erase) = timeouts.erase {
+        push(args, "--erase-timeout-ms", erase.as_millis());
+    }
+    if let Some(sync) = timeouts.sync {
+        push(args, "--sync-timeout-ms", sync.as_millis());
+    }
+    if let Some(read_chunk) = timeouts.read_chunk {
+        push(args, "--read-chunk-timeout-ms", read_chunk.as_millis());
+    }
+    if options.sync_retry.attempts != defaults.sync_retry.attempts {
+        push(args, "--sync-attempts", options.sync_retry.attempts);
+    }
+    if options.sync_retry.backoff != defaults.sync_retry.backoff {
+        push(
+            args,
+            "--sync-backoff-ms",
+            options.sync_retry.backoff.as_millis(),
+        );
+    }
+    if options.command_retry.attempts != defaults.command_retry.attempts {
+        push(args, "--command-attempts", options.command_retry.attempts);
+    }
+    if options.command_retry.backoff != defaults.command_retry.backoff {
+        push(
+            args,
+            "--command-backoff-ms",
+            options.command_retry.backoff.as_millis(),
+        );
+    }
+    let timing = BootTiming::default();
+    if options.boot_timing.settle != timing.settle {
+        push(
+            args,
+            "--boot-settle-ms",
+            options.boot_timing.settle.as_millis(),
+        );
+    }
+    if options.boot_timing.reset_pulse != timing.reset_pulse {
+        push(
+            args,
+            "--reset-pulse-ms",
+            options.boot_timing.reset_pulse.as_millis(),
+        );
+    }
+    if options.boot_timing.boot_delay != timing.boot_delay {
+        push(
+            args,
+            "--boot-delay-ms",
+            options.boot_timing.boot_delay.as_millis(),
+        );
+    }
+    if options.remember_connection.is_none() {
+        args.push("--no-remember".to_string());
+    }
+
+    if let Some(power) = &options.power_cycle {
+        match &power.switch {
+            PowerSwitch::SerialLine {
+                port,
+                line,
+                off_level,
+            } => {
+                push(args, "--power-port", port);
+                push(args, "--power-line", value_name(line));
+                push(args, "--power-off-level", value_name(off_level));
+            }
+            PowerSwitch::Hook { command } => push(args, "--power-hook", command),
+        }
+        push(args, "--power-off-ms", power.off_time.as_millis());
+        if power.after {
+            args.push("--power-cycle-after".to_string());
+        }
+    }
+}
+
+fn push_flash(args: &mut Vec<String>, options: &FlashOptions) {
+    if options.stay_in_bootloader {
+        args.push("--stay-in-bootloader".to_string());
+    } else if !options.reset_after {
+        args.push("--no-reset".to_string());
+    }
+    if options.rebase_alias {
+        args.push("--rebase".to_string());
+    }
+    if options.offset != 0 {
+        push(args, "--offset", format_offset(options.offset));
+    }
+    if let Some(address) = options.go_address {
+        push(args, "--go-address", format!("0x{address:08X}"));
+    }
+    match &options.version_locator {
+        Some(VersionLocator::Pattern(pattern)) => push(args, "--version-pattern", pattern),
+        Some(VersionLocator::Offset(offset)) => {
+            push(args, "--version-offset", format!("0x{offset:X}"))
+        }
+        None => {}
+    }
+    if options.lock_after {
+        args.push("--lock".to_string());
+    }
+    if options.unprotect_before {
+        args.push("--unprotect".to_string());
+    }
+    if let Some(sectors) = &options.protect_after {
+        let sectors: Vec<String> = sectors.iter().map(u8::to_string).collect();
+        push(args, "--protect-after", sectors.join(","));
+    }
+    for (name, value) in &options.option_bytes {
+        push(args, "--option-byte", format!("{name}=0x{value:X}"));
+    }
+    match options.verify {
+        VerifyStrategy::None => {}
+        VerifyStrategy::ReadBackFull => args.push("--verify".to_string()),
+        strategy => push(args, "--verify-method", strategy),
+    }
+    if options.erase_mode != EraseMode::default() {
+        push(args, "--erase", value_name(&options.erase_mode));
+    }
+    if let Some(bank) = options.bank {
+        push(args, "--bank", bank);
+    }
+    if options.swap_banks {
+        args.push("--swap-banks".to_string());
+    }
+    if options.virgin_probe {
+        args.push("--virgin".to_string());
+    }
+
+    let progress = ProgressGranularity::default();
+    if options.progress.every_bytes != progress.every_bytes {
+        push(args, "--progress-every", options.progress.every_bytes);
+    }
+    if options.progress.max_per_sec != progress.max_per_sec {
+        push(args, "--progress-rate", options.progress.max_per_sec);
+    }
+    let padding = Padding::default();
+    if options.padding.granularity != padding.granularity {
+        push(args, "--align", options.padding.granularity);
+    }
+    if options.padding.boundaries != padding.boundaries {
+        push(
+            args,
+            "--block-boundary",
+            value_name(&options.padding.boundaries),
+        );
+    }
+    if options.padding.tail_word {
+        args.push("--pad-tail".to_string());
+    }
+
+    if !options.flush_frames {
+        args.push("--no-flush".to_string());
+    }
+    if !options.skip_blank {
+        args.push("--no-skip-blank".to_string());
+    }
+    if options.write_chunk != MAX_WRITE_CHUNK {
+        push(args, "--chunk-size", options.write_chunk);
+    }
+    match &options.cycle_tracking {
+        None => args.push("--no-track".to_string()),
+        Some(tracking) if tracking.warn_threshold != devicedb::DEFAULT_CYCLE_WARN => {
+            push(args, "--cycle-warn", tracking.warn_threshold)
+        }
+        Some(_) => {}
+    }
+    for ext in &options.external_memory {
+        let mut spec = format!(
+            "0x{:08X}:0x{:X}:0x{:02X}",
+            ext.start, ext.size, ext.write_command
+        );
+        if let Some(erase) = ext.erase_command {
+            spec.push_str(&format!(":0x{erase:02X}"));
+        }
+        push(args, "--external-memory", spec);
+    }
+}
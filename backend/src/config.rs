@@ -0,0 +1,57 @@
+//! 项目配置文件 probe-flasher.toml：保存串口、波特率和 Boot 模式，
+//! 命令行未给出对应参数时作为默认值，由 `probe-flasher init` 生成
+
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::stm32_uart::{BootMode, Level, Result, write_file_atomic};
+
+pub const CONFIG_FILE: &str = "probe-flasher.toml";
+
+/// 配置文件（项目配置、作业、工位、布局）无法解析或内容无效，经 `stm32_uart::Error::Config` 上报
+#[derive(thiserror::Error, Debug)]
+#[error("config file error: {0}")]
+pub struct ConfigError(pub String);
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProjectConfig {
+    /// 串口名称或 USB 序列号
+    pub port: Option<String>,
+    pub baud: Option<u32>,
+    pub boot_mode: Option<BootMode>,
+    pub boot_level: Option<Level>,
+    pub reset_level: Option<Level>,
+    /// 烧录后的跳转地址，应用位于自定义 Bootloader 之后（如 0x08004000）时设置
+    pub go_address: Option<u32>,
+}
+
+impl ProjectConfig {
+    /// 从当前目录向上查找配置文件
+    pub fn find() -> Option<PathBuf> {
+        let cwd = std::env::current_dir().ok()?;
+        cwd.ancestors()
+            .map(|dir| dir.join(CONFIG_FILE))
+            .find(|path| path.is_file())
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)?;
+        toml::from_str(&text).map_err(|e| ConfigError(format!("{}: {e}", path.display())).into())
+    }
+
+    /// 查找并读取配置文件，不存在时为空配置
+    pub fn load_nearest() -> Result<Self> {
+        match Self::find() {
+            Some(path) => Self::load(&path),
+            None => Ok(Self::default()),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let text = toml::to_string_pretty(self).map_err(|e| ConfigError(e.to_string()))?;
+        write_file_atomic(path, |file| {
+            Ok(std::io::Write::write_all(file, text.as_bytes())?)
+        })
+    }
+}
@@ -0,0 +1,249 @@
+//! 本地设备数据库：按 96 位唯一 ID 记录每块芯片的擦除 / 烧录次数
+
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+
+use crate::stm32_uart::{self, BootLineConfig, BootMode, BootTiming, FlashOptions, Result};
+
+const DB_FILE: &str = "devices.json";
+const CONNECTIONS_FILE: &str = "connections.json";
+
+/// 默认的擦写次数告警阈值
+pub const DEFAULT_CYCLE_WARN: u64 = 1000;
+
+/// 持久数据目录：PROBE_FLASHER_DATA_DIR，否则为系统的用户数据目录
+pub fn data_dir() -> PathBuf {
+    if let Some(dir) = std::env::var_os("PROBE_FLASHER_DATA_DIR") {
+        return PathBuf::from(dir);
+    }
+    let base = if cfg!(windows) {
+        std::env::var_os("APPDATA").map(PathBuf::from)
+    } else {
+        std::env::var_os("XDG_DATA_HOME")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|h| PathBuf::from(h).join(".local/share")))
+    };
+    base.unwrap_or_else(std::env::temp_dir)
+        .join("probe-flasher")
+}
+
+pub fn default_db_path() -> PathBuf {
+    data_dir().join(DB_FILE)
+}
+
+pub fn default_connections_path() -> PathBuf {
+    data_dir().join(CONNECTIONS_FILE)
+}
+
+/// 读取 JSON 文件，文件不存在时为默认值
+fn load_json<T: DeserializeOwned + Default>(path: &Path) -> Result<T> {
+    match std::fs::read(path) {
+        Ok(bytes) => Ok(serde_json::from_slice(&bytes)?),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(T::default()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn save_json<T: Serialize>(value: &T, path: &Path) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let json = serde_json::to_vec_pretty(value)?;
+    stm32_uart::write_file_atomic(path, |file| Ok(std::io::Write::write_all(file, &json)?))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Cycle {
+    Erase,
+    Flash,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DeviceRecord {
+    pub product_id: Option<u16>,
+    pub erase_count: u64,
+    pub flash_count: u64,
+    pub first_seen: String,
+    pub last_seen: String,
+}
+
+/// 擦写次数跟踪配置
+#[derive(Debug, Clone)]
+pub struct CycleTracking {
+    pub db_path: PathBuf,
+    /// 擦除次数超过该值时告警
+    pub warn_threshold: u64,
+}
+
+impl Default for CycleTracking {
+    fn default() -> Self {
+        Self {
+            db_path: default_db_path(),
+            warn_threshold: DEFAULT_CYCLE_WARN,
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct DeviceDb {
+    /// 唯一 ID 的十六进制字符串 -> 记录
+    pub devices: BTreeMap<String, DeviceRecord>,
+}
+
+impl DeviceDb {
+    /// 读取数据库，文件不存在时为空
+    pub fn load(path: &Path) -> Result<Self> {
+        load_json(path)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        save_json(self, path)
+    }
+
+    /// 记一次擦除或烧录，返回更新后的记录
+    pub fn record(&mut self, uid: &str, product_id: Option<u16>, cycle: Cycle) -> &DeviceRecord {
+        let now = chrono::Local::now().to_rfc3339();
+        let record = self
+            .devices
+            .entry(uid.to_string())
+            .or_insert_with(|| DeviceRecord {
+                first_seen: now.clone(),
+                ..DeviceRecord::default()
+            });
+        record.product_id = product_id.or(record.product_id);
+        record.last_seen = now;
+        match cycle {
+            Cycle::Erase => record.erase_count += 1,
+            Cycle::Flash => record.flash_count += 1,
+        }
+        record
+    }
+}
+
+/// 某个设备上次成功连接时使用的参数
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConnectionProfile {
+    pub baud_rate: u32,
+    pub boot_mode: BootMode,
+    pub lines: BootLineConfig,
+    pub boot_timing: BootTiming,
+    pub last_used: String,
+}
+
+impl ConnectionProfile {
+    pub fn from_options(options: &FlashOptions) -> Self {
+        Self {
+            baud_rate: options.baud_rate,
+            boot_mode: options.boot_mode,
+            lines: options.lines,
+            boot_timing: options.boot_timing,
+            last_used: chrono::Local::now().to_rfc3339(),
+        }
+    }
+
+    /// 参数是否与 `options` 相同（不比较使用时间）
+    pub fn same_as(&self, options: &FlashOptions) -> bool {
+        self.baud_rate == options.baud_rate
+            && self.boot_mode == options.boot_mode
+            && self.lines == options.lines
+            && self.boot_timing == options.boot_timing
+    }
+
+    /// 用记住的参数覆盖 `options` 中的连接参数
+    pub fn apply(&self, options: &FlashOptions) -> FlashOptions {
+        FlashOptions {
+            baud_rate: self.baud_rate,
+            boot_mode: self.boot_mode,
+            lines: self.lines,
+            boot_timing: self.boot_timing,
+            ..options.clone()
+        }
+    }
+}
+
+/// 按设备（USB VID:PID 加序列号）记住的连接参数
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct ConnectionDb {
+    pub profiles: BTreeMap<String, ConnectionProfile>,
+}
+
+impl ConnectionDb {
+    pub fn load(path: &Path) -> Result<Self> {
+        load_json(path)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        save_json(self, path)
+    }
+}
+
+/// 配置导出文件的格式版本，结构不兼容时递增
+pub const CONFIG_FORMAT: u32 = 1;
+
+/// 一台工位的完整配置，用于在另一台机器上复制完全相同的设置
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct ConfigExport {
+    pub format: u32,
+    pub exported_at: String,
+    /// 前端的设置（高级参数、连接表单中的波特率 / Boot 序列、语言等），结构由前端定义
+    pub settings: serde_json::Value,
+    /// 按设备记住的连接参数
+    pub connections: ConnectionDb,
+}
+
+impl ConfigExport {
+    /// 汇总前端设置和本机的连接参数记录
+    pub fn collect(settings: serde_json::Value, connections_path: &Path) -> Result<Self> {
+        Ok(Self {
+            format: CONFIG_FORMAT,
+            exported_at: chrono::Local::now().to_rfc3339(),
+            settings,
+            connections: ConnectionDb::load(connections_path)?,
+        })
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        save_json(self, path)
+    }
+
+    /// 读取导出文件，格式版本比当前新时报错
+    pub fn load(path: &Path) -> Result<Self> {
+        let export: Self = serde_json::from_slice(&std::fs::read(path)?)?;
+        if export.format > CONFIG_FORMAT {
+            return Err(stm32_uart::Error::UnsupportedConfig(export.format));
+        }
+        Ok(export)
+    }
+
+    /// 用导出的连接参数记录替换本机记录
+    pub fn install_connections(&self, connections_path: &Path) -> Result<()> {
+        self.connections.save(connections_path)
+    }
+}
+
+/// 串口对应设备的键：`VID:PID:序列号[:ifaceN]`；没有序列号的适配器（如 CH340）退化为 `VID:PID@端口名`，
+/// 非 USB 串口返回 None
+pub fn connection_key(port_name: &str) -> Option<String> {
+    let port = stm32_uart::list_ports()
+        .ok()?
+        .into_iter()
+        .find(|p| p.port_name == port_name)?;
+    let (vid, pid) = (port.vid?, port.pid?);
+    let mut key = match port.serial {
+        Some(serial) => format!("{vid:04X}:{pid:04X}:{serial}"),
+        None => format!("{vid:04X}:{pid:04X}@{port_name}"),
+    };
+    if let Some(iface) = port.interface {
+        key.push_str(&format!(":iface{iface}"));
+    }
+    Some(key)
+}
+
+/// 唯一 ID 的十六进制表示（按读出的字节顺序）
+pub fn uid_string(uid: &[u8]) -> String {
+    uid.iter().map(|b| format!("{b:02X}")).collect()
+}
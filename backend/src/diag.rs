@@ -0,0 +1,408 @@
+//! 诊断信息：最近日志、协议跟踪，以及用于问题报告的诊断包
+
+use std::{
+    collections::VecDeque,
+    io::Write,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::Duration,
+};
+
+use serde::Serialize;
+use serialport::{Parity, SerialPort};
+use zip::write::SimpleFileOptions;
+
+use crate::config::ProjectConfig;
+use crate::stm32_uart::{self, BootMode, Error, FlashOptions, Result};
+
+const MAX_LINES: usize = 500;
+
+const LOG_FILE: &str = "last-session.log";
+const TRACE_FILE: &str = "last-trace.log";
+
+static LOGS: Mutex<VecDeque<String>> = Mutex::new(VecDeque::new());
+static TRACE: Mutex<VecDeque<String>> = Mutex::new(VecDeque::new());
+
+fn push(buf: &Mutex<VecDeque<String>>, line: String) {
+    let mut buf = buf.lock().unwrap();
+    if buf.len() == MAX_LINES {
+        buf.pop_front();
+    }
+    buf.push_back(line);
+}
+
+fn timestamp() -> String {
+    chrono::Local::now().format("%H:%M:%S%.3f").to_string()
+}
+
+/// 记录一行日志，保留最近 500 行
+pub fn record_log(level: &str, msg: &str) {
+    push(&LOGS, format!("{} [{level}] {msg}", timestamp()));
+}
+
+/// 记录一帧协议交互，保留最近 500 帧
+pub fn record_trace(frame: &str) {
+    push(&TRACE, format!("{} {frame}", timestamp()));
+}
+
+pub fn recent_logs() -> Vec<String> {
+    LOGS.lock().unwrap().iter().cloned().collect()
+}
+
+pub fn last_trace() -> Vec<String> {
+    TRACE.lock().unwrap().iter().cloned().collect()
+}
+
+/// CLI 每次运行结束后保存日志和跟踪的目录，供下次 `doctor` 读取
+pub fn session_dir() -> PathBuf {
+    std::env::temp_dir().join("probe-flasher")
+}
+
+/// 保存本次运行的日志和协议跟踪，覆盖上一次的记录；没有任何记录时保留上一次的
+pub fn save_session() -> Result<()> {
+    let (logs, trace) = (recent_logs(), last_trace());
+    if logs.is_empty() && trace.is_empty() {
+        return Ok(());
+    }
+
+    let dir = session_dir();
+    std::fs::create_dir_all(&dir)?;
+    std::fs::write(dir.join(LOG_FILE), logs.join("\n"))?;
+    std::fs::write(dir.join(TRACE_FILE), trace.join("\n"))?;
+    Ok(())
+}
+
+/// 本进程内有记录时使用内存中的内容，否则读取上次保存的会话
+fn current_or_saved(lines: Vec<String>, file: &str) -> String {
+    if !lines.is_empty() {
+        return lines.join("\n");
+    }
+    std::fs::read_to_string(session_dir().join(file)).unwrap_or_else(|_| "(no records)".to_string())
+}
+
+pub fn system_info(app_version: &str) -> String {
+    format!(
+        "probe-flasher {app_version}\nos: {} ({})\narch: {}\ngenerated: {}\n",
+        std::env::consts::OS,
+        std::env::consts::FAMILY,
+        std::env::consts::ARCH,
+        chrono::Local::now().to_rfc3339(),
+    )
+}
+
+pub fn port_inventory() -> String {
+    match stm32_uart::list_ports() {
+        Ok(ports) if ports.is_empty() => "(no serial ports)\n".to_string(),
+        Ok(ports) => ports
+            .iter()
+            .map(|p| {
+                format!(
+                    "{}  vid={} pid={} serial={} iface={}\n",
+                    p.label,
+                    p.vid.map_or("-".to_string(), |v| format!("{v:04X}")),
+                    p.pid.map_or("-".to_string(), |v| format!("{v:04X}")),
+                    p.serial.as_deref().unwrap_or("-"),
+                    p.interface.map_or("-".to_string(), |n| n.to_string()),
+                )
+            })
+            .collect(),
+        Err(e) => format!("failed to list ports: {e}\n"),
+    }
+}
+
+/// 把版本、系统信息、串口列表、最近日志、协议跟踪和设置打包为 zip
+pub fn create_bundle(path: &Path, app_version: &str, settings: &str) -> Result<()> {
+    let files = [
+        ("system.txt", system_info(app_version)),
+        ("ports.txt", port_inventory()),
+        ("logs.txt", current_or_saved(recent_logs(), LOG_FILE)),
+        ("trace.txt", current_or_saved(last_trace(), TRACE_FILE)),
+        ("settings.txt", settings.to_string()),
+    ];
+
+    stm32_uart::write_file_atomic(path, |file| {
+        let mut zip = zip::ZipWriter::new(file);
+        for (name, content) in files {
+            zip.start_file(name, SimpleFileOptions::default())?;
+            zip.write_all(content.as_bytes())?;
+        }
+        zip.finish()?;
+        Ok(())
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CheckStatus {
+    Pass,
+    Warn,
+    Fail,
+    Skip,
+}
+
+/// 一项环境检查的结果，失败时附带建议的修复方法
+#[derive(Debug, Clone, Serialize)]
+pub struct CheckResult {
+    pub name: &'static str,
+    pub status: CheckStatus,
+    pub detail: String,
+    pub fix: Option<String>,
+}
+
+impl CheckResult {
+    fn new(name: &'static str, status: CheckStatus, detail: impl Into<String>) -> Self {
+        Self {
+            name,
+            status,
+            detail: detail.into(),
+            fix: None,
+        }
+    }
+
+    fn fix(mut self, fix: impl Into<String>) -> Self {
+        self.fix = Some(fix.into());
+        self
+    }
+}
+
+/// 常见 USB 转串口芯片的厂商 ID
+const KNOWN_ADAPTERS: &[(u16, &str)] = &[
+    (0x1A86, "WCH CH34x"),
+    (0x10C4, "Silicon Labs CP210x"),
+    (0x0403, "FTDI"),
+    (0x067B, "Prolific PL2303"),
+    (0x0483, "STMicroelectronics"),
+];
+
+fn check_ports() -> CheckResult {
+    let ports = match stm32_uart::list_ports() {
+        Ok(ports) => ports,
+        Err(e) => {
+            return CheckResult::new("serial ports", CheckStatus::Fail, e.to_string())
+                .fix("check that the OS serial subsystem is accessible");
+        }
+    };
+    if ports.is_empty() {
+        return CheckResult::new("serial ports", CheckStatus::Fail, "no serial ports found")
+            .fix("plug in the USB-serial adapter and check the cable");
+    }
+
+    let adapters: Vec<&str> = ports
+        .iter()
+        .filter_map(|p| {
+            KNOWN_ADAPTERS
+                .iter()
+                .find(|(vid, _)| p.vid == Some(*vid))
+                .map(|(_, name)| *name)
+        })
+        .collect();
+    if adapters.is_empty() {
+        CheckResult::new(
+            "drivers",
+            CheckStatus::Warn,
+            format!("{} port(s), no known USB-serial adapter", ports.len()),
+        )
+        .fix("install the adapter driver (CH34x / CP210x / FTDI / PL2303) if the adapter is not listed")
+    } else {
+        CheckResult::new(
+            "drivers",
+            CheckStatus::Pass,
+            format!("{} port(s), adapters: {}", ports.len(), adapters.join(", ")),
+        )
+    }
+}
+
+/// 直接打开串口（不做错误归类），区分权限问题和端口不存在
+fn check_open(
+    port_name: &str,
+    options: &FlashOptions,
+) -> (CheckResult, Option<Box<dyn SerialPort>>) {
+    let name = match stm32_uart::resolve_port(port_name) {
+        Ok(name) => name,
+        Err(e) => {
+            return (
+                CheckResult::new("open port", CheckStatus::Fail, e.to_string())
+                    .fix("run `probe-flasher list-ports` and pick one of the listed ports"),
+                None,
+            );
+        }
+    };
+    let result = serialport::new(&name, options.baud_rate)
+        .timeout(options.timeouts.ack())
+        .parity(Parity::Even)
+        .open();
+    match result {
+        Ok(port) => (
+            CheckResult::new("open port", CheckStatus::Pass, format!("{name} opened")),
+            Some(port),
+        ),
+        Err(e) => {
+            let denied = matches!(
+                e.kind,
+                serialport::ErrorKind::Io(std::io::ErrorKind::PermissionDenied)
+            ) || e.description.contains("ermission denied");
+            let check = CheckResult::new("open port", CheckStatus::Fail, e.to_string());
+            let check = if denied {
+                check.fix(if cfg!(target_os = "linux") {
+                    "add yourself to the dialout group (`sudo usermod -aG dialout $USER`) and log in again"
+                } else {
+                    "close other programs using the port and check its access permissions"
+                })
+            } else {
+                check.fix("close other programs using the port, or replug the adapter")
+            };
+            (check, None)
+        }
+    }
+}
+
+/// 校验偶校验和 DTR/RTS 是否被适配器接受
+fn check_adapter(port: &mut dyn SerialPort, options: &FlashOptions) -> Vec<CheckResult> {
+    let parity = match port.parity() {
+        Ok(Parity::Even) => CheckResult::new("parity", CheckStatus::Pass, "8E1 accepted"),
+        Ok(other) => CheckResult::new(
+            "parity",
+            CheckStatus::Fail,
+            format!("adapter reports {other:?}"),
+        )
+        .fix("the bootloader needs even parity; use an adapter that supports 8E1"),
+        Err(e) => CheckResult::new("parity", CheckStatus::Warn, e.to_string()),
+    };
+
+    let lines = match (
+        port.write_data_terminal_ready(false),
+        port.write_request_to_send(false),
+    ) {
+        (Ok(()), Ok(())) => {
+            CheckResult::new("control lines", CheckStatus::Pass, "DTR/RTS can be driven")
+        }
+        (Err(e), _) | (_, Err(e)) if options.boot_mode != BootMode::None => CheckResult::new(
+            "control lines",
+            CheckStatus::Fail,
+            e.to_string(),
+        )
+        .fix("the adapter cannot drive DTR/RTS; use --boot-mode none and set BOOT0/RESET by hand"),
+        (Err(e), _) | (_, Err(e)) => CheckResult::new(
+            "control lines",
+            CheckStatus::Warn,
+            format!("{e} (not needed with boot mode none)"),
+        ),
+    };
+
+    vec![parity, lines]
+}
+
+/// 读取 probe-flasher.toml（若有），再检查合成后的连接参数
+fn check_config(options: &FlashOptions) -> CheckResult {
+    let file = match ProjectConfig::load_nearest() {
+        Ok(_) => ProjectConfig::find().map_or_else(
+            || "no probe-flasher.toml".to_string(),
+            |path| path.display().to_string(),
+        ),
+        Err(e) => {
+            return CheckResult::new("config", CheckStatus::Fail, e.to_string()).fix(
+                "fix or delete probe-flasher.toml, or regenerate it with `probe-flasher init`",
+            );
+        }
+    };
+    let mut problems = Vec::new();
+    if options.baud_rate > 115_200 {
+        problems.push(format!(
+            "baud rate {} is above 115200, which many bootloaders do not support",
+            options.baud_rate
+        ));
+    }
+    if options.boot_mode == BootMode::None && options.reset_after {
+        problems.push("boot mode none cannot reset the chip after flashing".to_string());
+    }
+    let timeouts = options.timeouts;
+    if [
+        timeouts.sync(),
+        timeouts.ack(),
+        timeouts.erase(),
+        timeouts.read_chunk(),
+    ]
+    .iter()
+    .any(Duration::is_zero)
+        || options.sync_retry.attempts == 0
+        || options.command_retry.attempts == 0
+    {
+        problems.push("timeouts and retry attempts must be greater than zero".to_string());
+    }
+
+    if problems.is_empty() {
+        CheckResult::new(
+            "config",
+            CheckStatus::Pass,
+            format!(
+                "{:?} at {} baud ({file})",
+                options.boot_mode, options.baud_rate
+            ),
+        )
+    } else {
+        CheckResult::new("config", CheckStatus::Warn, problems.join("; "))
+            .fix("adjust --baud / --boot-mode, or reset the advanced settings")
+    }
+}
+
+fn check_sync(port_name: &str, options: &FlashOptions) -> CheckResult {
+    let result = stm32_uart::open_and_connect(port_name, options).and_then(|(mut port, baud)| {
+        Ok((
+            stm32_uart::get_info(&mut *port, options.timeouts.ack())?,
+            baud,
+        ))
+    });
+    match result {
+        Ok(((version, _), baud)) => CheckResult::new(
+            "sync probe",
+            CheckStatus::Pass,
+            format!("bootloader v{}.{} answered at {baud} baud", version >> 4, version & 0xF),
+        ),
+        Err(e @ Error::BootEntry(_)) => CheckResult::new("sync probe", CheckStatus::Fail, e.to_string())
+            .fix("the adapter rejected DTR/RTS changes; use --boot-mode none and set BOOT0/RESET by hand"),
+        Err(Error::NoSync { attempts, last }) => {
+            let check = CheckResult::new(
+                "sync probe",
+                CheckStatus::Fail,
+                format!("no answer to {attempts} sync attempt(s): {last}"),
+            );
+            match *last {
+                Error::UnexpectedResponse(_) => {
+                    check.fix("baud rate or parity mismatch; try --auto-baud or a lower --baud")
+                }
+                _ => check.fix(
+                    "check TX/RX crossing and GND, and that BOOT0 is high during reset (try another --boot-mode)",
+                ),
+            }
+        }
+        Err(e) => CheckResult::new("sync probe", CheckStatus::Fail, e.to_string()),
+    }
+}
+
+/// 依次检查串口与驱动、端口权限、适配器能力、配置，`probe` 时再做一次同步探测
+pub fn run_checks(
+    port_name: Option<&str>,
+    options: &FlashOptions,
+    probe: bool,
+) -> Vec<CheckResult> {
+    let mut checks = vec![check_ports(), check_config(options)];
+    let Some(port_name) = port_name else {
+        checks.push(CheckResult::new(
+            "open port",
+            CheckStatus::Skip,
+            "no --port given",
+        ));
+        return checks;
+    };
+
+    let (open, port) = check_open(port_name, options);
+    checks.push(open);
+    if let Some(mut port) = port {
+        checks.extend(check_adapter(&mut *port, options));
+        drop(port);
+        if probe {
+            checks.push(check_sync(port_name, options));
+        }
+    }
+    checks
+}
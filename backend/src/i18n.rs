@@ -0,0 +1,265 @@
+//! 中英文消息目录：后端日志和 CLI 输出按消息码查表，GUI 通过同一份目录翻译
+
+use std::{
+    fmt::Display,
+    sync::atomic::{AtomicU8, Ordering},
+};
+
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum, serde::Serialize, serde::Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Lang {
+    En,
+    Zh,
+}
+
+impl Lang {
+    /// 依次读取 PROBE_FLASHER_LANG、LC_ALL、LC_MESSAGES、LANG；都未设置时保持中文
+    pub fn from_env() -> Self {
+        ["PROBE_FLASHER_LANG", "LC_ALL", "LC_MESSAGES", "LANG"]
+            .iter()
+            .filter_map(|key| std::env::var(key).ok())
+            .find(|v| !v.is_empty())
+            .map_or(Lang::Zh, |v| {
+                if v.to_ascii_lowercase().starts_with("zh") {
+                    Lang::Zh
+                } else {
+                    Lang::En
+                }
+            })
+    }
+}
+
+static LANG: AtomicU8 = AtomicU8::new(Lang::Zh as u8);
+
+pub fn set_lang(lang: Lang) {
+    LANG.store(lang as u8, Ordering::Relaxed);
+}
+
+pub fn lang() -> Lang {
+    if LANG.load(Ordering::Relaxed) == Lang::En as u8 {
+        Lang::En
+    } else {
+        Lang::Zh
+    }
+}
+
+macro_rules! catalog {
+    ($($name:ident => $en:literal, $zh:literal;)*) => {
+        /// 消息码，名称即对外稳定的 code
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+        pub enum Msg {
+            $($name,)*
+        }
+
+        impl Msg {
+            pub const ALL: &[Msg] = &[$(Msg::$name,)*];
+
+            pub fn code(self) -> &'static str {
+                match self {
+                    $(Msg::$name => stringify!($name),)*
+                }
+            }
+
+            /// 消息模板，`{0}`、`{1}` 为参数占位
+            pub fn template(self, lang: Lang) -> &'static str {
+                match (self, lang) {
+                    $(
+                        (Msg::$name, Lang::En) => $en,
+                        (Msg::$name, Lang::Zh) => $zh,
+                    )*
+                }
+            }
+        }
+    };
+}
+
+catalog! {
+    WaitingForDevice => "Waiting for device {0}...", "等待设备 {0} 出现...";
+    DeviceDetected => "Detected {0}", "已检测到 {0}";
+    WaitingForTrigger => "Waiting for {0} {1} edge on {2}...", "等待 {2} 的 {0} {1} 触发...";
+    Triggered => "Start triggered by {0}", "{0} 已触发，开始烧录";
+    SignallingDone => "Signalling completion on DTR ({0} ms)", "通过 DTR 发送完成信号（{0} ms）";
+    NoResetLine => "Boot mode is none, cannot reset automatically; please reset the chip manually", "Boot 模式为 none，无法自动复位，请手动复位芯片";
+    AlreadySynced => "Bootloader is already synchronized", "Bootloader 已处于同步状态";
+    BootCycleRetry => "Boot cycle {0}/{1} got no answer, retrying in {2} ms", "第 {0}/{1} 轮进入 Bootloader 无响应，{2} ms 后重试";
+    AutoBaudStart => "No response at {0} baud, probing other baud rates...", "波特率 {0} 无响应，开始自动探测...";
+    TryingBaud => "Trying {0} baud...", "尝试波特率 {0}...";
+    BaudAnswered => "Bootloader answered at {0} baud", "Bootloader 在波特率 {0} 下响应";
+    RaisingBaud => "Switching to {0} baud: re-entering the bootloader...", "切换到波特率 {0}：重新进入 Bootloader...";
+    BaudRaiseFailed => "No response at {0} baud, falling back to {1}", "波特率 {0} 无响应，回退到 {1}";
+    BaudUnsupported => "The adapter does not support {0} baud ({1}); staying at {2}", "串口适配器不支持波特率 {0}（{1}），保持 {2}";
+    BaudRaiseNeedsReset => "Boot mode none cannot reset the chip to resync; staying at {0} baud", "Boot 模式为 none 时无法复位芯片重新同步，保持波特率 {0}";
+    UsbReenumerating => "USB serial port dropped after reset, waiting for {0} to re-enumerate...", "USB 串口复位后已断开，等待 {0} 重新枚举...";
+    WaitingForReset => "Waiting for the chip to reset...", "等待芯片复位...";
+    TryingRemembered => "Trying the last working settings for this device ({0} baud, {1})", "先尝试该设备上次成功的连接参数（{0} 波特率，{1}）";
+    RememberedFailed => "Last working settings got no answer, using the configured settings", "上次的连接参数无响应，改用当前配置";
+    ConnectionSaveFailed => "Could not save connection settings: {0}", "保存连接参数失败: {0}";
+    Connecting => "Connecting to bootloader...", "正在连接 Bootloader...";
+    QueryingCommands => "Querying supported commands...", "正在查询支持的命令...";
+    SettingRdp => "Setting readout protection...", "正在设置读保护...";
+    RdpActive => "Readout protection is active", "读保护已生效";
+    UnlockWarning => "Removing readout protection triggers a mass erase; all flash contents will be lost", "解除读保护会触发全片擦除，Flash 内容将全部丢失";
+    ConfirmUnlock => "Type 'yes' to erase the device and remove protection: ", "输入 yes 确认擦除设备并解除读保护：";
+    UnlockCancelled => "cancelled, device left unchanged", "已取消，设备未改动";
+    Cancelling => "Cancelling after the current step, press Ctrl-C again to quit immediately", "当前步骤完成后取消，再按一次 Ctrl-C 立即退出";
+    Unlocking => "Removing readout protection and erasing flash...", "正在解除读保护并擦除 Flash...";
+    Unlocked => "Readout protection removed, device is writable", "读保护已解除，设备可写";
+    CrcVerifyUnsupported => "Bootloader has no Get Checksum command, verifying by read-back", "Bootloader 不支持 Get Checksum，改用回读校验";
+    NoPortGiven => "No serial port given; pass --port or run `probe-flasher init`", "未指定串口：请加 --port，或先运行 `probe-flasher init`";
+    InitOverwrite => "{0} already exists, overwrite? [y/N] ", "{0} 已存在，是否覆盖？[y/N] ";
+    InitKept => "{0} left unchanged", "{0} 未改动";
+    InitNoPorts => "no serial ports found; check the USB-UART adapter and its driver", "未找到串口，请检查 USB 转串口适配器和驱动";
+    InitSelectPort => "Select port [1-{0}, default 1]: ", "选择串口 [1-{0}，默认 1]：";
+    InitInvalidChoice => "invalid choice '{0}'", "无效的选择 '{0}'";
+    InitPort => "Port: {0}", "串口：{0}";
+    InitManualBoot => "No boot mode answered. Hold BOOT0 high, press RESET, then press Enter: ", "所有 Boot 模式均无应答。请将 BOOT0 拉高并按下 RESET，然后按回车：";
+    InitBootMode => "Boot mode: {0}", "Boot 模式：{0}";
+    InitSynced => "Bootloader answered, chip: {0}", "Bootloader 已应答，芯片：{0}";
+    InitSaved => "Saved {0}; commands now use these settings by default", "已保存 {0}，之后的命令默认使用这些参数";
+    ProbingBootMode => "Trying boot mode {0}...", "正在尝试 Boot 模式 {0}...";
+    AgentStarted => "Station '{0}': running job '{1}' on {2}", "工位 '{0}'：在 {2} 上运行任务 '{1}'";
+    Archived => "Archived image {0} in {1}", "已归档镜像 {0} 到 {1}";
+    ArchiveFailed => "Failed to archive the flashed image: {0}", "归档写入的镜像失败: {0}";
+    StatusEndpoint => "Status endpoint: http://{0}/status", "状态接口：http://{0}/status";
+    RemovingWrp => "Removing write protection...", "正在解除写保护...";
+    ApplyingWrp => "Write-protecting sectors {0}...", "正在对扇区 {0} 设置写保护...";
+    ReadingOptionBytes => "Reading option bytes at {0}...", "正在读取选项字节 {0}...";
+    OptionBytesSaved => "Option bytes saved to {0}", "选项字节已保存到 {0}";
+    WritingOptionBytes => "Writing option bytes...", "正在写入选项字节...";
+    OptionBytesRestored => "Option bytes restored", "选项字节已恢复";
+    SettingOptionByte => "Option byte {0}: {1} -> {2}", "选项字节 {0}：{1} -> {2}";
+    OptionBytesUnchanged => "Option bytes already match, nothing to write", "选项字节已是目标值，无需写入";
+    OptionBytesProgrammed => "Option bytes programmed", "选项字节已写入";
+    ErasingBank => "Erasing bank {0} ({1}, {2} KB)...", "正在擦除 Bank {0}（{1}，{2} KB）...";
+    PlacedInBank => "Image placed in bank {0} at {1}", "镜像已平移到 Bank {0}（{1}）";
+    VirginDevice => "Device answered without the boot sequence and its flash is empty (virgin device)", "未执行 Boot 序列芯片即已应答且 Flash 为空（全新芯片）";
+    AlreadyInBootloader => "Device answered without the boot sequence (already in bootloader)", "未执行 Boot 序列芯片即已应答（已在 Bootloader 中）";
+    ReloadingOptionBytes => "Reloading option bytes so the device boots the new firmware...", "正在重新加载选项字节，使芯片从新固件启动...";
+    SwappingBanks => "Swapping banks: next boot from bank {0}", "切换 Bank：下次从 Bank {0} 启动";
+    LevelHigh => "high", "高电平";
+    LevelLow => "low", "低电平";
+    WiringStepPrompt => "DTR is now {0} and RTS is {1}.", "当前 DTR 为{0}、RTS 为{1}。";
+    WiringDetected => "Wiring check: boot mode {0} (BOOT0 active {1}, reset active {2})", "接线检查：Boot 模式 {0}（BOOT0 有效电平为{1}，复位有效电平为{2}）";
+    WiringInconclusive => "The observations do not match any DTR/RTS wiring; check that RTS/DTR reach NRST/BOOT0 and try again", "观察结果不符合任何 DTR / RTS 接法，请检查 RTS / DTR 是否接到 NRST / BOOT0 后重试";
+    WiringRemembered => "Saved as the connection settings for this device", "已保存为该设备的连接参数";
+    PowerOff => "Cutting target power", "断开目标电源";
+    PowerOn => "Restoring target power", "恢复目标电源";
+    Resetting => "Resetting to run the application...", "正在复位以运行用户程序...";
+    AppStarted => "Application started", "程序已启动";
+    StartingApp => "Starting application ({0})...", "正在启动用户程序 ({0})...";
+    GoFailed => "GO command failed: {0}, trying hardware reset", "GO 命令失败: {0}, 尝试硬件复位";
+    GoAddressIgnored => "Hardware reset always boots from {0}; ignoring jump address {1}", "硬件复位总是从 {0} 启动，忽略跳转地址 {1}";
+    GoFromEntryPoint => "Using entry point {0} from the firmware file: jumping to the vector table at {1}", "使用固件文件中的入口地址 {0}：跳转到 {1} 处的向量表";
+    EntryPointWithoutVectorTable => "Entry point {0} from the firmware file matches no vector table in the image; jumping to {1}", "固件文件中的入口地址 {0} 在固件中找不到对应的向量表，跳转到 {1}";
+    GoAddressOutsideImage => "Jump address {0} is outside the image data", "跳转地址 {0} 不在固件数据范围内";
+    StayInBootloader => "Chip stays in the bootloader; connect next time with --boot-mode none", "芯片保持在 Bootloader 模式，后续命令可使用 --boot-mode none 直接连接";
+    Rebased => "Image linked at alias address {0}, relocated to {1}", "固件链接在镜像地址 {0}，已重定位到 {1}";
+    Offset => "Image shifted by {0}, now at {1}..={2}", "固件整体平移 {0}，现位于 {1}..={2}";
+    WritePlan => "Write plan: {0} bytes, {1} of them 0xFF ({2} pages blank), {3}, ~{4} s at {5} baud", "写入计划：{0} 字节，其中 {1} 字节为 0xFF（{2} 个页只含 0xFF），{3}，{5} 波特率下预计约 {4} 秒";
+    VectorTableSuspicious => "WARNING: the vector table does not look like firmware for {0}; it may have been linked for a different device or address", "警告：向量表与 {0} 不符，固件可能是为其他型号或其他地址链接的";
+    VectorStackOutsideRam => "initial stack pointer {0} is not in RAM", "初始栈指针 {0} 不在 RAM 中";
+    VectorResetOutsideFlash => "reset vector {0} is not in flash", "复位向量 {0} 不在 Flash 中";
+    VectorResetNotThumb => "reset vector {0} does not have the Thumb bit set", "复位向量 {0} 未置 Thumb 位";
+    VectorResetOutsideImage => "reset vector {0} points outside the firmware image", "复位向量 {0} 指向固件之外";
+    PlanMassErase => "mass erase", "全片擦除";
+    PlanPageErase => "erase {0} pages/sectors", "擦除 {0} 个页 / 扇区";
+    LoadedSource => "Loaded {0}: {1} bytes", "已加载 {0}：{1} 字节";
+    FirmwareVersion => "Firmware version: {0}", "固件版本：{0}";
+    VersionNotFound => "No version string found in the image", "未在固件中找到版本字符串";
+    LinkQuality => "Link quality: {0}", "链路质量：{0}";
+    SkippedBlank => "Skipped {0} bytes of erased-value (0xFF) data", "跳过了 {0} 字节全 0xFF 的数据（擦除后已是该值）";
+    ProtocolTiming => "Bootloader protocol {0}: reply timeout {1} ms, erase timeout {2} s", "Bootloader 协议 {0}：应答超时 {1} ms，擦除超时 {2} 秒";
+    LoadingStub => "Loading RAM stub ({0} bytes) at {1}...", "正在把 RAM 烧录程序（{0} 字节）写入 {1}...";
+    StubStarted => "RAM stub v{0} running (up to {1} bytes per frame)", "RAM 烧录程序 v{0} 已启动（每帧最多 {1} 字节）";
+    StubBaudSwitched => "Switched the RAM stub link to {0} baud", "RAM 烧录程序链路已切换到 {0} 波特";
+    StubErasing => "Erasing {0} block(s) through the RAM stub...", "正在通过 RAM 烧录程序擦除 {0} 个数据段...";
+    DfuOpened => "USB DFU device {0}: {1}, {2}-byte transfers", "USB DFU 设备 {0}：{1}，每次传输 {2} 字节";
+    DfuErasing => "Erasing {0} page(s)/sector(s) over USB DFU...", "正在经 USB DFU 擦除 {0} 个页 / 扇区...";
+    DfuMassErasing => "Mass erasing over USB DFU...", "正在经 USB DFU 全片擦除...";
+    ErasingExternal => "Erasing external memory at {0} ({1} bytes)...", "正在擦除外部存储器 {0}（{1} 字节）...";
+    ErasingPages => "Erasing {0} pages/sectors covered by the image (expected ~{1} s)...", "正在擦除固件覆盖的 {0} 个页 / 扇区（预计约 {1} 秒）...";
+    Erasing => "Erasing (expected ~{0} s)...", "正在擦除（预计约 {0} 秒）...";
+    Padded => "Padded image with {0} bytes of 0xFF ({1}-byte granularity)", "已补入 {0} 字节 0xFF（编程粒度 {1} 字节）";
+    ChunkShrunk => "Write at {0} rejected repeatedly, retrying with {1}-byte chunks", "{0} 处写入多次被拒绝，改用 {1} 字节分块重试";
+    Writing => "Writing...", "正在写入...";
+    CheckingRdp => "Command rejected, checking readout protection...", "命令被拒绝，正在检查读保护状态...";
+    ReadingFlash => "Reading {1} bytes from {0}...", "正在读取 {0} 起 {1} 字节...";
+    SavedTo => "Saved to {0}", "已保存到 {0}";
+    Verifying => "Verifying...", "正在回读校验...";
+    Verified => "Verified {0} bytes", "校验通过（{0} 字节）";
+    VerifiedSampled => "Verified {0} of {1} bytes by sampling", "抽样校验通过（{1} 字节中回读 {0} 字节）";
+    RegionsDiffer => "{0} of {1} region(s) differ from the image", "{1} 个数据段中有 {0} 个与固件不一致";
+    CycleThresholdExceeded => "Device {0} has been erased {1} times, above the warning threshold {2}", "设备 {0} 已擦除 {1} 次，超过告警阈值 {2}";
+    InjectSpecInvalid => "Ignoring {0}: {1}", "忽略 {0}：{1}";
+    CycleTrackingFailed => "Failed to record the flash cycle: {0}", "记录擦写次数失败: {0}";
+    FlashDone => "Flash completed!", "烧录完成！";
+    FlashFailed => "Flash failed: {0}", "烧录失败: {0}";
+    BundleWritten => "Diagnostic bundle written to {0}", "诊断包已写入 {0}";
+    ReportBytesWritten => "  Bytes written: {0}", "  已写入字节: {0}";
+    ReportBytesWrittenIn => "  Bytes written: {0} ({1} ms)", "  已写入字节: {0}（{1} ms）";
+    ReportTotal => "total", "合计";
+    ReportLinkQuality => "  Link quality: {0}", "  链路质量: {0}";
+    NoSerialPorts => "No serial ports found.", "未找到串口。";
+    AvailablePorts => "Available ports:", "可用串口：";
+    ListPortsFailed => "Error listing ports: {0}", "列出串口失败：{0}";
+    NoDfuDevices => "No DFU devices found.", "未找到 DFU 设备。";
+    DfuDevices => "DFU devices:", "DFU 设备：";
+    ListDfuFailed => "Error listing DFU devices: {0}", "列出 DFU 设备失败：{0}";
+    IdentifyOk => "Identify OK", "识别成功";
+    IdBootloaderVersion => "  Bootloader version: {0}", "  Bootloader 版本: {0}";
+    IdProtocol => "  Protocol: {0} ({1}), default erase timeout {2} s", "  协议: {0}（{1}），默认擦除超时 {2} 秒";
+    ProtocolEraseTimeout => "{0} (erase timeout {1} s)", "{0}（擦除超时 {1} 秒）";
+    IdProductId => "  Product ID: {0}", "  产品 ID: {0}";
+    IdProductIdRaw => "  Product ID (raw): {0}", "  产品 ID（原始）: {0}";
+    IdChip => "  Chip: {0}", "  芯片: {0}";
+    IdChipUnknown => "  Chip: unknown (not in the built-in database)", "  芯片: 未知（不在内置数据库中）";
+    IdFlashSize => "  Flash size: {0} KB (flash size register)", "  Flash 容量: {0} KB（容量寄存器）";
+    IdReadProtected => "  Read protection: ACTIVE (reads/writes will NACK; run `probe-flasher unlock`)", "  读保护: 已开启（读写会被 NACK，运行 `probe-flasher unlock` 解除）";
+    IdReadUnprotected => "  Read protection: inactive", "  读保护: 未开启";
+    IdRdpCounters => "  RDP counters: disabled {0}, enabled {1}", "  RDP 计数: 解除 {0} 次，开启 {1} 次";
+    IdSupportedCommands => "  Supported commands:", "  支持的命令:";
+    IdCapabilities => "  Capabilities: {0}", "  能力: {0}";
+    IdMemoryMap => "  Memory map ({0}):", "  存储器映射（{0}）:";
+    IdMemoryRegion => "    {0}  {1} bytes  {2}", "    {0}  {1} 字节  {2}";
+    LinesHeader => "Lines ({0}):", "控制线（{0}）：";
+    LineOn => "on", "有效";
+    LineOff => "off", "无效";
+    NoDevicesRecorded => "No devices recorded in {0}", "{0} 中没有设备记录";
+    DevicesHeader => "Devices ({0}):", "设备（{0}）：";
+    DeviceRecord => "  {0}  pid={1}  erases={2}  flashes={3}  last={4}", "  {0}  pid={1}  擦除={2}  烧录={3}  最近={4}";
+    OptBaud => "Baud rate", "波特率";
+    OptBootMode => "Boot mode", "Boot 模式";
+    OptResetAfter => "Reset and run after flashing", "烧录后复位运行";
+    OptAutoBaud => "Probe the baud rate when there is no response", "无响应时自动探测波特率";
+    OptRebaseAlias => "Relocate firmware linked at 0 to 0x08000000", "0 地址固件重定位到 0x08000000";
+    OptGoAddress => "Jump address", "跳转地址";
+    OptVersionPattern => "Version string prefix", "版本字符串前缀";
+    OptDfuSerial => "USB serial number", "USB 序列号";
+    OptDfuAlt => "Memory alt setting", "内存区域 alt setting";
+    OptLoadAddress => "Load address for .bin files", ".bin 文件的写入地址";
+    Succeeded => "{0} completed successfully!", "{0} 成功完成！";
+    Failed => "{0} FAILED: {1}", "{0} 失败：{1}";
+}
+
+/// 按当前语言取出消息并填入参数
+pub fn tr(msg: Msg, args: &[&dyn Display]) -> String {
+    tr_in(lang(), msg, args)
+}
+
+pub fn tr_in(lang: Lang, msg: Msg, args: &[&dyn Display]) -> String {
+    let mut out = msg.template(lang).to_string();
+    for (i, arg) in args.iter().enumerate() {
+        out = out.replace(&format!("{{{i}}}"), &arg.to_string());
+    }
+    out
+}
+
+/// 整份目录（code, 模板），供 GUI 按消息码翻译
+pub fn catalog(lang: Lang) -> Vec<(&'static str, &'static str)> {
+    Msg::ALL
+        .iter()
+        .map(|&m| (m.code(), m.template(lang)))
+        .collect()
+}
@@ -0,0 +1,134 @@
+//! 故障注入（仅调试构建生效）：让烧录流程在指定位置失败，用于系统地检查界面的错误提示和重试流程
+//!
+//! 通过环境变量 `PROBE_FLASHER_INJECT` 或 `arm` 设置，多个故障用逗号分隔，每个只触发一次：
+//! - `sync-timeout`：下一次同步超时
+//! - `nack@50`：写入进度达到 50% 时设备返回 NACK
+//! - `port-lost@50`：写入进度达到 50% 时串口消失
+
+use std::{fmt, io, str::FromStr, sync::Mutex};
+
+use tracing::warn;
+
+use crate::diag;
+use crate::i18n::{Msg, tr};
+use crate::stm32_uart::{Error, Result};
+
+pub const ENV_VAR: &str = "PROBE_FLASHER_INJECT";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    SyncTimeout,
+    /// 写入进度达到该百分比时返回 NACK
+    Nack(u8),
+    /// 写入进度达到该百分比时串口消失
+    PortLost(u8),
+}
+
+impl FromStr for Fault {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let s = s.trim();
+        if s == "sync-timeout" {
+            return Ok(Fault::SyncTimeout);
+        }
+        let (kind, percent) = s
+            .split_once('@')
+            .ok_or_else(|| format!("unknown fault '{s}'"))?;
+        let percent: u8 = percent
+            .parse()
+            .ok()
+            .filter(|p| *p <= 100)
+            .ok_or_else(|| format!("invalid percentage in '{s}'"))?;
+        match kind {
+            "nack" => Ok(Fault::Nack(percent)),
+            "port-lost" => Ok(Fault::PortLost(percent)),
+            _ => Err(format!("unknown fault '{s}'")),
+        }
+    }
+}
+
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Fault::SyncTimeout => write!(f, "sync-timeout"),
+            Fault::Nack(p) => write!(f, "nack@{p}"),
+            Fault::PortLost(p) => write!(f, "port-lost@{p}"),
+        }
+    }
+}
+
+/// 解析逗号分隔的故障列表
+pub fn parse(spec: &str) -> std::result::Result<Vec<Fault>, String> {
+    spec.split(',')
+        .filter(|s| !s.trim().is_empty())
+        .map(str::parse)
+        .collect()
+}
+
+/// None 表示尚未读取环境变量
+static ARMED: Mutex<Option<Vec<Fault>>> = Mutex::new(None);
+
+fn with_armed<T>(f: impl FnOnce(&mut Vec<Fault>) -> T) -> T {
+    let mut armed = ARMED.lock().unwrap();
+    let faults = armed.get_or_insert_with(|| {
+        let Ok(spec) = std::env::var(ENV_VAR) else {
+            return Vec::new();
+        };
+        parse(&spec).unwrap_or_else(|e| {
+            warn!("{}", tr(Msg::InjectSpecInvalid, &[&ENV_VAR, &e]));
+            Vec::new()
+        })
+    });
+    f(faults)
+}
+
+/// 替换待触发的故障；发布构建中不生效，返回 false
+pub fn arm(faults: Vec<Fault>) -> bool {
+    if !cfg!(debug_assertions) {
+        return false;
+    }
+    with_armed(|armed| *armed = faults);
+    true
+}
+
+/// 尚未触发的故障
+pub fn armed() -> Vec<Fault> {
+    if !cfg!(debug_assertions) {
+        return Vec::new();
+    }
+    with_armed(|armed| armed.clone())
+}
+
+/// 取出第一个满足条件的故障
+fn take(matches: impl Fn(Fault) -> bool) -> Option<Fault> {
+    if !cfg!(debug_assertions) {
+        return None;
+    }
+    let fault = with_armed(|armed| {
+        let index = armed.iter().position(|&f| matches(f))?;
+        Some(armed.remove(index))
+    })?;
+    diag::record_trace(&format!("inject {fault}"));
+    Some(fault)
+}
+
+pub(crate) fn on_sync() -> Result<()> {
+    match take(|f| f == Fault::SyncTimeout) {
+        Some(_) => Err(Error::Timeout),
+        None => Ok(()),
+    }
+}
+
+/// 写入 `total` 字节中的第 `done` 字节前调用
+pub(crate) fn on_write(done: usize, total: usize) -> Result<()> {
+    let reached = |p: u8| done * 100 >= total * p as usize;
+    match take(|f| matches!(f, Fault::Nack(p) | Fault::PortLost(p) if reached(p))) {
+        Some(Fault::Nack(_)) => Err(Error::Nack),
+        Some(_) => Err(Error::Io(io::Error::new(
+            io::ErrorKind::BrokenPipe,
+            "port disappeared (injected)",
+        ))),
+        None => Ok(()),
+    }
+}
@@ -0,0 +1,359 @@
+//! 烧录任务：任务文件描述烧什么，工位配置描述怎么连接和何时开始，
+//! `JobRunner` 按二者循环执行并维护可查询的工位状态
+
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+use serde::{Deserialize, Serialize};
+
+use tracing::{info, warn};
+
+use crate::archive::{ArchiveEntry, ImageArchive};
+use crate::config::ConfigError;
+use crate::i18n::{Msg, tr};
+use crate::stm32_uart::{
+    self, BootLineConfig, BootMode, Edge, EraseMode, FixtureTrigger, FlashOptions, FlashReport,
+    ImageFormat, ImageSource, ProgressSink, Result, StatusLine, VerifyStrategy,
+};
+
+/// 任务文件中的一个镜像
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JobImage {
+    /// 相对路径按任务文件所在目录解析；格式按扩展名识别
+    pub path: PathBuf,
+    /// 覆盖按扩展名识别的格式：hex / bin / elf / srec
+    #[serde(default)]
+    pub format: Option<ImageFormat>,
+    /// 二进制的写入地址，或 HEX 整体平移到的地址
+    #[serde(default)]
+    pub base_address: Option<u32>,
+}
+
+/// 任务文件：要烧录的镜像和烧录参数，与工位无关
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct JobFile {
+    pub name: String,
+    pub images: Vec<JobImage>,
+    /// 写入后的校验策略，如 "read-back"、"crc"、"sampled:10"
+    pub verify: VerifyStrategy,
+    /// 只擦除镜像覆盖的页 / 扇区
+    pub erase_pages: bool,
+    /// 烧录完成后设置读保护
+    pub lock: bool,
+    pub go_address: Option<u32>,
+    pub reset_after: bool,
+}
+
+impl Default for JobFile {
+    fn default() -> Self {
+        Self {
+            name: String::new(),
+            images: Vec::new(),
+            verify: VerifyStrategy::ReadBackFull,
+            erase_pages: false,
+            lock: false,
+            go_address: None,
+            reset_after: true,
+        }
+    }
+}
+
+impl JobFile {
+    /// 读取任务文件，镜像的相对路径改为相对任务文件所在目录
+    pub fn load(path: &Path) -> Result<Self> {
+        let mut job: Self = serde_json::from_slice(&std::fs::read(path)?)?;
+        let dir = path.parent().unwrap_or(Path::new(""));
+        for image in &mut job.images {
+            if image.path.is_relative() {
+                image.path = dir.join(&image.path);
+            }
+        }
+        if job.images.is_empty() {
+            return Err(ConfigError(format!("{}: job has no images", path.display())).into());
+        }
+        Ok(job)
+    }
+
+    fn sources(&self) -> Vec<ImageSource> {
+        self.images
+            .iter()
+            .map(|image| ImageSource {
+                format: image
+                    .format
+                    .unwrap_or_else(|| ImageFormat::from_path(&image.path)),
+                path: image.path.clone(),
+                base_address: image.base_address,
+            })
+            .collect()
+    }
+}
+
+/// 治具触发：等待状态线边沿后开始，完成后可选输出 DTR 脉冲
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StationTrigger {
+    pub line: StatusLine,
+    #[serde(default = "default_edge")]
+    pub edge: Edge,
+    #[serde(default)]
+    pub done_pulse_ms: Option<u64>,
+}
+
+fn default_edge() -> Edge {
+    Edge::Rising
+}
+
+/// 工位配置：串口、连接参数、触发方式和状态接口，同一任务可在不同工位上运行
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StationConfig {
+    pub name: String,
+    pub port: String,
+    pub baud_rate: u32,
+    pub boot_mode: BootMode,
+    /// 没有触发配置时一次烧录结束后立即开始下一次（适合配合 `wait_port` 换板）
+    pub trigger: Option<StationTrigger>,
+    /// 每次烧录前等待串口出现（USB 转串口随目标板插拔的治具）
+    pub wait_port: bool,
+    /// 状态接口监听地址，如 `127.0.0.1:8787`
+    pub status_listen: Option<String>,
+    /// 合规归档目录：保存每次实际写入的镜像并追加记录，None 时不归档
+    pub archive_dir: Option<PathBuf>,
+}
+
+impl Default for StationConfig {
+    fn default() -> Self {
+        Self {
+            name: String::new(),
+            port: String::new(),
+            baud_rate: 115200,
+            boot_mode: BootMode::RtsLowDtrHigh,
+            trigger: None,
+            wait_port: false,
+            status_listen: None,
+            archive_dir: None,
+        }
+    }
+}
+
+impl StationConfig {
+    pub fn load(path: &Path) -> Result<Self> {
+        Ok(serde_json::from_slice(&std::fs::read(path)?)?)
+    }
+
+    /// 连续运行需要触发或换板检测来区分两块板，否则会反复烧录同一块板
+    pub fn check_continuous(&self) -> Result<()> {
+        if self.trigger.is_none() && !self.wait_port {
+            return Err(ConfigError(format!(
+                "station '{}' has neither a trigger nor wait_port, continuous runs would reflash the same board",
+                self.name
+            ))
+            .into());
+        }
+        Ok(())
+    }
+
+    fn fixture_trigger(&self) -> Option<FixtureTrigger> {
+        self.trigger.as_ref().map(|t| FixtureTrigger {
+            line: t.line,
+            edge: t.edge,
+            done_pulse: t.done_pulse_ms.map(std::time::Duration::from_millis),
+            ..FixtureTrigger::default()
+        })
+    }
+}
+
+/// 工位当前在做什么
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum StationState {
+    Idle,
+    WaitingForPort,
+    WaitingForTrigger,
+    Flashing,
+    /// 等待烧好的板子拔下，避免同一块板被重复烧录
+    WaitingForRemoval,
+}
+
+/// 最近一次烧录的结果
+#[derive(Debug, Clone, Serialize)]
+pub struct RunResult {
+    pub ok: bool,
+    pub finished_at: String,
+    pub bytes_written: u64,
+    pub duration_ms: u64,
+    pub error: Option<String>,
+}
+
+/// 通过状态接口对外提供的工位状态
+#[derive(Debug, Clone, Serialize)]
+pub struct StationStatus {
+    pub station: String,
+    pub job: String,
+    pub state: StationState,
+    pub passed: u64,
+    pub failed: u64,
+    pub last: Option<RunResult>,
+}
+
+/// 按任务和工位配置执行烧录，状态可在其他线程中读取；Agent 和 GUI 的任务队列共用
+pub struct JobRunner {
+    job: JobFile,
+    station: StationConfig,
+    /// 任务和工位未涉及的参数（超时、重试、取消等）
+    base: FlashOptions,
+    status: Arc<Mutex<StationStatus>>,
+}
+
+impl JobRunner {
+    pub fn new(job: JobFile, station: StationConfig) -> Self {
+        let status = StationStatus {
+            station: station.name.clone(),
+            job: job.name.clone(),
+            state: StationState::Idle,
+            passed: 0,
+            failed: 0,
+            last: None,
+        };
+        Self {
+            job,
+            station,
+            base: FlashOptions::default(),
+            status: Arc::new(Mutex::new(status)),
+        }
+    }
+
+    /// 以 `base` 为基础合成烧录参数，如 GUI 高级设置中的超时和取消标志
+    pub fn with_options(mut self, base: FlashOptions) -> Self {
+        self.base = base;
+        self
+    }
+
+    /// 共享的状态句柄
+    pub fn status(&self) -> Arc<Mutex<StationStatus>> {
+        Arc::clone(&self.status)
+    }
+
+    /// 任务和工位合成的烧录参数
+    pub fn options(&self) -> FlashOptions {
+        FlashOptions {
+            baud_rate: self.station.baud_rate,
+            boot_mode: self.station.boot_mode,
+            lines: BootLineConfig::for_mode(self.station.boot_mode),
+            verify: self.job.verify,
+            erase_mode: if self.job.erase_pages {
+                EraseMode::Pages
+            } else {
+                EraseMode::Mass
+            },
+            lock_after: self.job.lock,
+            go_address: self.job.go_address,
+            reset_after: self.job.reset_after,
+            ..self.base.clone()
+        }
+    }
+
+    fn set_state(&self, state: StationState) {
+        self.status.lock().unwrap().state = state;
+    }
+
+    /// 等待串口和触发，烧录一次并更新计数；烧录失败记入状态后照常返回错误
+    pub fn run_once(&self, sink: &dyn ProgressSink) -> Result<FlashReport> {
+        let options = self.options();
+        let port = if self.station.wait_port {
+            self.set_state(StationState::WaitingForPort);
+            stm32_uart::wait_for_port(&self.station.port, None)?
+        } else {
+            self.station.port.clone()
+        };
+
+        let trigger = self.station.fixture_trigger();
+        if let Some(trigger) = &trigger {
+            self.set_state(StationState::WaitingForTrigger);
+            stm32_uart::wait_for_trigger(&port, trigger, &options)?;
+        }
+
+        self.set_state(StationState::Flashing);
+        let started = Instant::now();
+        let sources = self.job.sources();
+        let (result, image) = match stm32_uart::merge_sources(&sources, &options) {
+            Ok(image) => {
+                let result = stm32_uart::sources_entry_point(&sources).and_then(|entry| {
+                    stm32_uart::flash_merged(&port, &image, entry, &options, sink)
+                });
+                (result, Some(image))
+            }
+            Err(e) => (Err(e), None),
+        };
+
+        let run = RunResult {
+            ok: result.is_ok(),
+            finished_at: chrono::Local::now().to_rfc3339(),
+            bytes_written: result.as_ref().map_or(0, |r| r.bytes_written),
+            duration_ms: started.elapsed().as_millis() as u64,
+            error: result.as_ref().err().map(|e| e.to_string()),
+        };
+        // 失败的烧录同样归档：审计时需要知道这块板可能收到了什么
+        if let (Some(dir), Some(image)) = (&self.station.archive_dir, &image) {
+            match self.archive(&ImageArchive::new(dir), image, &run) {
+                Ok(entry) => info!(
+                    "{}",
+                    tr(Msg::Archived, &[&entry.image_file, &dir.display()])
+                ),
+                Err(e) => warn!("{}", tr(Msg::ArchiveFailed, &[&e])),
+            }
+        }
+
+        let mut status = self.status.lock().unwrap();
+        status.state = StationState::Idle;
+        match &result {
+            Ok(_) => status.passed += 1,
+            Err(_) => status.failed += 1,
+        }
+        status.last = Some(run);
+        drop(status);
+
+        if let Some(trigger) = &trigger {
+            stm32_uart::signal_done(&port, trigger, &options)?;
+        }
+        if self.station.wait_port {
+            self.set_state(StationState::WaitingForRemoval);
+            wait_for_removal(&port);
+            self.set_state(StationState::Idle);
+        }
+        result
+    }
+
+    fn archive(
+        &self,
+        archive: &ImageArchive,
+        image: &BTreeMap<u32, u8>,
+        run: &RunResult,
+    ) -> Result<ArchiveEntry> {
+        let stored = archive.store_image(image)?;
+        archive.append(ArchiveEntry {
+            finished_at: run.finished_at.clone(),
+            station: self.station.name.clone(),
+            job: self.job.name.clone(),
+            ok: run.ok,
+            bytes_written: run.bytes_written,
+            error: run.error.clone(),
+            image_sha256: stored.sha256,
+            image_crc32: stored.crc32,
+            image_file: stored.file,
+            previous: None,
+        })
+    }
+}
+
+/// 轮询串口列表直到端口消失
+fn wait_for_removal(port_name: &str) {
+    while stm32_uart::list_ports().is_ok_and(|ports| ports.iter().any(|p| p.port_name == port_name))
+    {
+        std::thread::sleep(std::time::Duration::from_millis(200));
+    }
+}
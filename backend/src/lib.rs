@@ -1 +1,24 @@
+pub mod archive;
+pub mod async_uart;
+pub mod chipdb;
+pub mod cmdline;
+pub mod config;
+pub mod devicedb;
+pub mod diag;
+pub mod i18n;
+pub mod inject;
+pub mod job;
+pub mod linkstats;
+pub mod logging;
+pub mod monitor;
+pub mod optbytes;
+pub mod power;
+pub mod provision;
+pub mod rxbuf;
+pub mod sim;
+pub mod stm32_dfu;
 pub mod stm32_uart;
+pub mod stub;
+pub mod targets;
+pub mod transport;
+pub mod wiring;
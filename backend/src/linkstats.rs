@@ -0,0 +1,188 @@
+//! 链路质量统计：记录每条命令的 ACK 往返时间、超时和重试次数，给出 0–100 的链路评分
+//!
+//! 记录在会话的串口（`BufferedPort`）上，GUI 和产线工位同时烧录多个串口时互不干扰；
+//! 芯片复位后重新打开串口时沿用原来的统计。超时、乱码应答和重试说明
+//! 线缆 / 适配器不稳定，计入评分；NACK 是目标拒绝了命令（读保护、地址无效等），单独统计。
+
+use std::{collections::BTreeMap, fmt, time::Duration};
+
+use crate::stm32_uart::{Error, Result, command_name};
+use crate::transport::Transport;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CommandStats {
+    pub count: u32,
+    pub nacks: u32,
+    pub timeouts: u32,
+    /// 应答既不是 ACK 也不是 NACK
+    pub garbled: u32,
+    /// 收到应答（ACK / NACK）的往返时间合计和最大值
+    pub total: Duration,
+    pub max: Duration,
+}
+
+impl CommandStats {
+    fn answered(&self) -> u32 {
+        self.count - self.timeouts - self.garbled
+    }
+
+    /// 平均 ACK 往返时间，没有应答时为 None
+    pub fn mean(&self) -> Option<Duration> {
+        let answered = self.answered();
+        (answered > 0).then(|| self.total / answered)
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LinkStats {
+    /// 命令码 -> 统计
+    pub commands: BTreeMap<u8, CommandStats>,
+    /// 同步时多发送的 0x7F 次数
+    pub sync_retries: u32,
+    /// 写入 / 擦除命令被 NACK 后重发的次数
+    pub command_retries: u32,
+}
+
+/// 平均往返时间超过该值开始扣分；USB 串口适配器的延迟定时器通常为 1–16 ms
+const SLOW_ACK: Duration = Duration::from_millis(20);
+
+impl LinkStats {
+    fn all(&self) -> CommandStats {
+        self.commands
+            .values()
+            .fold(CommandStats::default(), |mut sum, c| {
+                sum.count += c.count;
+                sum.nacks += c.nacks;
+                sum.timeouts += c.timeouts;
+                sum.garbled += c.garbled;
+                sum.total += c.total;
+                sum.max = sum.max.max(c.max);
+                sum
+            })
+    }
+
+    pub fn mean(&self) -> Option<Duration> {
+        self.all().mean()
+    }
+
+    pub fn max(&self) -> Duration {
+        self.all().max
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.commands.is_empty()
+    }
+
+    /// 链路评分：超时、乱码和重试占全部交互的比例每 1% 扣 3 分（最多 70），
+    /// 平均往返时间超过 20 ms 后每 2 ms 扣 1 分（最多 30）
+    pub fn score(&self) -> u8 {
+        let all = self.all();
+        let retries = self.sync_retries + self.command_retries;
+        let exchanges = all.count + retries;
+        if exchanges == 0 {
+            return 100;
+        }
+        let faults = all.timeouts + all.garbled + retries;
+        let fault_penalty = (faults as u64 * 300 / exchanges as u64).min(70);
+        let slow_penalty = all.mean().map_or(0, |mean| {
+            (mean.saturating_sub(SLOW_ACK).as_millis() as u64 / 2).min(30)
+        });
+        (100 - fault_penalty - slow_penalty) as u8
+    }
+
+    pub fn rating(&self) -> &'static str {
+        match self.score() {
+            80.. => "good",
+            50..80 => "marginal",
+            _ => "poor",
+        }
+    }
+
+    /// 每条命令一行的统计表
+    pub fn table(&self) -> String {
+        let mut out = String::new();
+        for (&cmd, stats) in &self.commands {
+            let mean = stats
+                .mean()
+                .map_or("-".to_string(), |m| format!("{:.2}", m.as_secs_f64() * 1e3));
+            out.push_str(&format!(
+                "  {:<20} {:>6} {:>9} {:>9.2} {:>6} {:>8} {:>7}\n",
+                format!("{} (0x{cmd:02X})", command_name(cmd)),
+                stats.count,
+                mean,
+                stats.max.as_secs_f64() * 1e3,
+                stats.nacks,
+                stats.timeouts,
+                stats.garbled
+            ));
+        }
+        out
+    }
+}
+
+impl fmt::Display for LinkStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let all = self.all();
+        write!(f, "{}/100 ({})", self.score(), self.rating())?;
+        if let Some(mean) = all.mean() {
+            write!(
+                f,
+                ", ACK mean {:.2} ms, max {:.2} ms",
+                mean.as_secs_f64() * 1e3,
+                all.max.as_secs_f64() * 1e3
+            )?;
+        }
+        write!(
+            f,
+            ", {} timeout(s), {} garbled, {} retr{}, {} NACK(s)",
+            all.timeouts,
+            all.garbled,
+            self.sync_retries + self.command_retries,
+            if self.sync_retries + self.command_retries == 1 {
+                "y"
+            } else {
+                "ies"
+            },
+            all.nacks
+        )
+    }
+}
+
+/// 端口上自上次打开以来的统计，不记录统计的传输为空
+pub(crate) fn of(port: &mut dyn Transport) -> LinkStats {
+    port.link_stats().map(|s| s.clone()).unwrap_or_default()
+}
+
+/// 记录一条命令的应答；`elapsed` 为发出命令到收到应答（或超时）的时间
+pub(crate) fn record(port: &mut dyn Transport, cmd: u8, elapsed: Duration, result: &Result<()>) {
+    // 串口本身出错时没有应答可统计
+    if let Err(Error::Io(_) | Error::Serial(_)) = result {
+        return;
+    }
+    let Some(s) = port.link_stats() else { return };
+    let stats = s.commands.entry(cmd).or_default();
+    stats.count += 1;
+    match result {
+        Err(Error::Timeout) => stats.timeouts += 1,
+        Err(Error::UnexpectedResponse(_)) => stats.garbled += 1,
+        _ => {
+            if result.is_err() {
+                stats.nacks += 1;
+            }
+            stats.total += elapsed;
+            stats.max = stats.max.max(elapsed);
+        }
+    }
+}
+
+pub(crate) fn record_sync_retry(port: &mut dyn Transport) {
+    if let Some(s) = port.link_stats() {
+        s.sync_retries += 1;
+    }
+}
+
+pub(crate) fn record_command_retry(port: &mut dyn Transport) {
+    if let Some(s) = port.link_stats() {
+        s.command_retries += 1;
+    }
+}
@@ -0,0 +1,79 @@
+//! 协议代码用 `tracing` 输出日志：连接、擦除和每个写入块各有一个 span（`connect` / `erase` /
+//! `write_block`），提示信息为 info / warn 事件
+//!
+//! `LineLayer` 把这些事件逐行交给终端、JSON 事件流或 GUI；设置 `RUST_LOG`（如
+//! `probe_flasher=debug`）后，`init` 另在 stderr 输出带字段和 span 耗时的结构化日志。
+
+use std::fmt::{self, Write};
+
+use tracing::{
+    Event, Level, Subscriber,
+    field::{Field, Visit},
+};
+use tracing_subscriber::{EnvFilter, Layer, fmt::format::FmtSpan, layer::Context, prelude::*};
+
+/// 只转发本 crate 的 info 及以上事件，`line` 收到级别名（"error" / "warn" / "info"）和消息
+pub struct LineLayer<F> {
+    line: F,
+}
+
+impl<F> LineLayer<F>
+where
+    F: Fn(&'static str, &str) + Send + Sync + 'static,
+{
+    pub fn new(line: F) -> Self {
+        Self { line }
+    }
+}
+
+impl<S, F> Layer<S> for LineLayer<F>
+where
+    S: Subscriber,
+    F: Fn(&'static str, &str) + Send + Sync + 'static,
+{
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        let meta = event.metadata();
+        if !meta.target().starts_with(env!("CARGO_CRATE_NAME")) || *meta.level() > Level::INFO {
+            return;
+        }
+        let mut message = Message(String::new());
+        event.record(&mut message);
+        let level = match *meta.level() {
+            Level::ERROR => "error",
+            Level::WARN => "warn",
+            _ => "info",
+        };
+        (self.line)(level, &message.0);
+    }
+}
+
+struct Message(String);
+
+impl Visit for Message {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == "message" {
+            let _ = write!(self.0, "{value:?}");
+        }
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "message" {
+            self.0.push_str(value);
+        }
+    }
+}
+
+/// 安装全局 subscriber：日志行交给 `line`，`RUST_LOG` 存在时另输出结构化日志到 stderr；
+/// 已安装过时不做任何事
+pub fn init(line: impl Fn(&'static str, &str) + Send + Sync + 'static) {
+    let structured = EnvFilter::try_from_default_env().ok().map(|filter| {
+        tracing_subscriber::fmt::layer()
+            .with_writer(std::io::stderr)
+            .with_span_events(FmtSpan::CLOSE)
+            .with_filter(filter)
+    });
+    let _ = tracing_subscriber::registry()
+        .with(LineLayer::new(line))
+        .with(structured)
+        .try_init();
+}
@@ -0,0 +1,177 @@
+//! 串口监视器：可同时打开多个串口（8N1），每个串口一个读线程，收到的数据连同端口名
+//! 交给同一个回调；发送和关闭按端口名路由，多板测试台可以在一个实例里看所有 UART
+
+use std::{
+    collections::HashMap,
+    io::{ErrorKind, Read, Write},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread::JoinHandle,
+    time::Duration,
+};
+
+use serde::Serialize;
+use serialport::SerialPort;
+
+use crate::stm32_uart::{self, Error, Result};
+
+/// 读线程检查停止标志的间隔
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum MonitorEvent {
+    Data {
+        port: String,
+        data: Vec<u8>,
+    },
+    /// 串口已关闭；读出错（如 USB 拔出）时带错误信息
+    Closed {
+        port: String,
+        error: Option<String>,
+    },
+}
+
+impl MonitorEvent {
+    pub fn port(&self) -> &str {
+        match self {
+            MonitorEvent::Data { port, .. } | MonitorEvent::Closed { port, .. } => port,
+        }
+    }
+}
+
+type Sink = Arc<dyn Fn(MonitorEvent) + Send + Sync>;
+
+struct Monitor {
+    writer: Box<dyn SerialPort>,
+    stop: Arc<AtomicBool>,
+    reader: JoinHandle<()>,
+}
+
+/// 按端口名管理的一组监视器
+pub struct MonitorHub {
+    monitors: Arc<Mutex<HashMap<String, Monitor>>>,
+    sink: Sink,
+}
+
+impl MonitorHub {
+    pub fn new(sink: impl Fn(MonitorEvent) + Send + Sync + 'static) -> Self {
+        Self {
+            monitors: Arc::default(),
+            sink: Arc::new(sink),
+        }
+    }
+
+    /// 打开串口并启动读线程；同一串口不能重复打开
+    pub fn open(&self, port_name: &str, baud_rate: u32) -> Result<()> {
+        let mut monitors = self.monitors.lock().unwrap();
+        if monitors.contains_key(port_name) {
+            return Err(Error::MonitorBusy(port_name.to_string()));
+        }
+        let device = stm32_uart::resolve_port(port_name)?;
+        let writer = serialport::new(&device, baud_rate)
+            .timeout(POLL_INTERVAL)
+            .open()
+            .map_err(|e| stm32_uart::open_error(&device, e))?;
+        let mut reader = writer.try_clone()?;
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let handle = {
+            let port = port_name.to_string();
+            let stop = stop.clone();
+            let sink = self.sink.clone();
+            let monitors = Arc::downgrade(&self.monitors);
+            std::thread::spawn(move || {
+                let mut buf = [0u8; 1024];
+                let error = loop {
+                    if stop.load(Ordering::Relaxed) {
+                        return;
+                    }
+                    match reader.read(&mut buf) {
+                        Ok(0) => {}
+                        Ok(n) => sink(MonitorEvent::Data {
+                            port: port.clone(),
+                            data: buf[..n].to_vec(),
+                        }),
+                        Err(e) if e.kind() == ErrorKind::TimedOut => {}
+                        Err(e) => break e,
+                    }
+                };
+                // 串口出错后自行注销；此时已被 close 移除的不再重复通知
+                let removed = monitors.upgrade().and_then(|monitors| {
+                    let mut monitors = monitors.lock().unwrap();
+                    let own = monitors
+                        .get(&port)
+                        .is_some_and(|m| Arc::ptr_eq(&m.stop, &stop));
+                    own.then(|| monitors.remove(&port))
+                });
+                if removed.is_some() {
+                    sink(MonitorEvent::Closed {
+                        port,
+                        error: Some(error.to_string()),
+                    });
+                }
+            })
+        };
+
+        monitors.insert(
+            port_name.to_string(),
+            Monitor {
+                writer,
+                stop,
+                reader: handle,
+            },
+        );
+        Ok(())
+    }
+
+    /// 向指定串口发送数据
+    pub fn send(&self, port_name: &str, data: &[u8]) -> Result<()> {
+        let mut monitors = self.monitors.lock().unwrap();
+        let monitor = monitors
+            .get_mut(port_name)
+            .ok_or_else(|| Error::NotMonitored(port_name.to_string()))?;
+        monitor.writer.write_all(data)?;
+        monitor.writer.flush()?;
+        Ok(())
+    }
+
+    /// 停止读线程并关闭串口；该串口未打开时返回 false
+    pub fn close(&self, port_name: &str) -> bool {
+        let Some(monitor) = self.monitors.lock().unwrap().remove(port_name) else {
+            return false;
+        };
+        monitor.stop.store(true, Ordering::Relaxed);
+        let _ = monitor.reader.join();
+        (self.sink)(MonitorEvent::Closed {
+            port: port_name.to_string(),
+            error: None,
+        });
+        true
+    }
+
+    pub fn close_all(&self) {
+        for port in self.ports() {
+            self.close(&port);
+        }
+    }
+
+    /// 已打开的串口，按名称排序
+    pub fn ports(&self) -> Vec<String> {
+        let mut ports: Vec<String> = self.monitors.lock().unwrap().keys().cloned().collect();
+        ports.sort();
+        ports
+    }
+
+    pub fn is_open(&self, port_name: &str) -> bool {
+        self.monitors.lock().unwrap().contains_key(port_name)
+    }
+}
+
+impl Drop for MonitorHub {
+    fn drop(&mut self) {
+        self.close_all();
+    }
+}
@@ -0,0 +1,314 @@
+//! 选项字节解码与修改：按系列把 RDP、BOR、nBOOT、看门狗和写保护等位域拆成具名字段，
+//! 修改后重新计算反码，再整块写回（写入后芯片自动复位）
+//!
+//! 字段都是选项字节区中某个小端 32 位字的位域：
+//! - F0 / F1 / F3：16 字节，每个字节后跟其反码（RDP、USER、Data0/1、WRP0–3）
+//! - F2 / F4：0x1FFFC000 的 USER / RDP，0x1FFFC008 的 nWRP，无反码
+//! - L4 / G0：每个 64 位双字的高 32 位是低 32 位的反码（OPTR、PCROP、WRP1A/B）
+
+use std::fmt;
+
+use serde::Serialize;
+
+use crate::chipdb::ChipInfo;
+use crate::stm32_uart::{Error, Result};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ObFamily {
+    F1,
+    F4,
+    L4,
+    G0,
+}
+
+/// 选项字节中的一个位域
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct ObField {
+    pub name: &'static str,
+    /// 所在 32 位字相对选项字节区起始的偏移
+    pub offset: usize,
+    pub shift: u8,
+    pub width: u8,
+    pub description: &'static str,
+}
+
+macro_rules! fields {
+    ($($name:literal @ $offset:literal [$shift:literal; $width:literal] $desc:literal,)*) => {
+        &[$(ObField {
+            name: $name,
+            offset: $offset,
+            shift: $shift,
+            width: $width,
+            description: $desc,
+        },)*]
+    };
+}
+
+const F1_FIELDS: &[ObField] = fields! {
+    "rdp" @ 0 [0; 8] "readout protection (0xA5 / 0xAA = level 0, 0xCC = level 2, other = level 1)",
+    "wdg_sw" @ 0 [16; 1] "1 = software watchdog, 0 = hardware watchdog",
+    "nrst_stop" @ 0 [17; 1] "0 = reset when entering Stop",
+    "nrst_stdby" @ 0 [18; 1] "0 = reset when entering Standby",
+    "nboot0" @ 0 [19; 1] "F04x / F09x: BOOT0 value when nboot_sel = 0",
+    "nboot1" @ 0 [20; 1] "F0 / F3: inverted BOOT1",
+    "vdda_monitor" @ 0 [21; 1] "F0 / F3: VDDA power supply supervisor",
+    "ram_parity_check" @ 0 [22; 1] "F0 / F3: 0 = SRAM parity check enabled",
+    "nboot_sel" @ 0 [23; 1] "F04x / F09x: 0 = boot from nboot0 instead of the BOOT0 pin",
+    "data0" @ 4 [0; 8] "user data byte 0",
+    "data1" @ 4 [16; 8] "user data byte 1",
+    "wrp0" @ 8 [0; 8] "write protection, bit = 0 protects its page group",
+    "wrp1" @ 8 [16; 8] "write protection, bit = 0 protects its page group",
+    "wrp2" @ 12 [0; 8] "write protection, bit = 0 protects its page group",
+    "wrp3" @ 12 [16; 8] "write protection, bit = 0 protects its page group",
+};
+
+const F4_FIELDS: &[ObField] = fields! {
+    "bor_lev" @ 0 [2; 2] "brown-out reset level (3 = off, 2 = level 1, 1 = level 2, 0 = level 3)",
+    "bfb2" @ 0 [4; 1] "F42x/43x/469/479: 1 = boot from bank 2 when it holds a valid vector table",
+    "wdg_sw" @ 0 [5; 1] "1 = software watchdog, 0 = hardware watchdog",
+    "nrst_stop" @ 0 [6; 1] "0 = reset when entering Stop",
+    "nrst_stdby" @ 0 [7; 1] "0 = reset when entering Standby",
+    "rdp" @ 0 [8; 8] "readout protection (0xAA = level 0, 0xCC = level 2, other = level 1)",
+    "nwrp" @ 8 [0; 12] "write protection per sector, bit = 0 protects the sector",
+    "sprmod" @ 8 [15; 1] "1 = nwrp bits select PCROP instead of write protection",
+};
+
+const L4_FIELDS: &[ObField] = fields! {
+    "rdp" @ 0 [0; 8] "readout protection (0xAA = level 0, 0xCC = level 2, other = level 1)",
+    "bor_lev" @ 0 [8; 3] "brown-out reset level (0 = 1.7 V ... 4 = 2.8 V)",
+    "nrst_stop" @ 0 [12; 1] "0 = reset when entering Stop",
+    "nrst_stdby" @ 0 [13; 1] "0 = reset when entering Standby",
+    "nrst_shdw" @ 0 [14; 1] "0 = reset when entering Shutdown",
+    "iwdg_sw" @ 0 [16; 1] "1 = software independent watchdog",
+    "iwdg_stop" @ 0 [17; 1] "0 = independent watchdog frozen in Stop",
+    "iwdg_stdby" @ 0 [18; 1] "0 = independent watchdog frozen in Standby",
+    "wwdg_sw" @ 0 [19; 1] "1 = software window watchdog",
+    "bfb2" @ 0 [20; 1] "1 = boot from bank 2 when it holds a valid vector table",
+    "dualbank" @ 0 [21; 1] "1 = dual-bank flash (1 MB parts)",
+    "nboot1" @ 0 [23; 1] "inverted BOOT1",
+    "sram2_pe" @ 0 [24; 1] "0 = SRAM2 parity check enabled",
+    "sram2_rst" @ 0 [25; 1] "0 = SRAM2 erased on system reset",
+    "nswboot0" @ 0 [26; 1] "0 = boot from nboot0 instead of the BOOT0 pin",
+    "nboot0" @ 0 [27; 1] "BOOT0 value when nswboot0 = 0",
+    "wrp1a_strt" @ 24 [0; 8] "first page of bank 1 write-protected area A",
+    "wrp1a_end" @ 24 [16; 8] "last page of bank 1 write-protected area A",
+    "wrp1b_strt" @ 32 [0; 8] "first page of bank 1 write-protected area B",
+    "wrp1b_end" @ 32 [16; 8] "last page of bank 1 write-protected area B",
+};
+
+const G0_FIELDS: &[ObField] = fields! {
+    "rdp" @ 0 [0; 8] "readout protection (0xAA = level 0, 0xCC = level 2, other = level 1)",
+    "boren" @ 0 [8; 1] "1 = brown-out reset enabled",
+    "borf_lev" @ 0 [9; 2] "brown-out reset falling threshold",
+    "borr_lev" @ 0 [11; 2] "brown-out reset rising threshold",
+    "nrst_stop" @ 0 [13; 1] "0 = reset when entering Stop",
+    "nrst_stdby" @ 0 [14; 1] "0 = reset when entering Standby",
+    "nrst_shdw" @ 0 [15; 1] "0 = reset when entering Shutdown",
+    "iwdg_sw" @ 0 [16; 1] "1 = software independent watchdog",
+    "iwdg_stop" @ 0 [17; 1] "0 = independent watchdog frozen in Stop",
+    "iwdg_stdby" @ 0 [18; 1] "0 = independent watchdog frozen in Standby",
+    "wwdg_sw" @ 0 [19; 1] "1 = software window watchdog",
+    "ram_parity_check" @ 0 [22; 1] "0 = SRAM parity check enabled",
+    "nboot_sel" @ 0 [24; 1] "1 = boot from nboot0 instead of the BOOT0 pin",
+    "nboot1" @ 0 [25; 1] "inverted BOOT1",
+    "nboot0" @ 0 [26; 1] "BOOT0 value when nboot_sel = 1",
+    "nrst_mode" @ 0 [27; 2] "PF2-NRST pin mode",
+    "irhen" @ 0 [29; 1] "1 = internal reset drives the NRST pin",
+    "wrp1a_strt" @ 24 [0; 6] "first page of write-protected area A",
+    "wrp1a_end" @ 24 [16; 6] "last page of write-protected area A",
+    "wrp1b_strt" @ 32 [0; 6] "first page of write-protected area B",
+    "wrp1b_end" @ 32 [16; 6] "last page of write-protected area B",
+};
+
+impl ObFamily {
+    /// 按芯片数据库中的选项字节地址判断系列；H7 等未收录布局的返回 None
+    pub fn for_chip(chip: &ChipInfo) -> Option<Self> {
+        match chip.option_bytes {
+            (0x1FFF_F800, 16) => Some(ObFamily::F1),
+            (0x1FFF_C000, 16) => Some(ObFamily::F4),
+            (0x1FFF_7800, 40) if chip.pid == 0x460 => Some(ObFamily::G0),
+            (0x1FFF_7800, 40) => Some(ObFamily::L4),
+            _ => None,
+        }
+    }
+
+    pub fn fields(self) -> &'static [ObField] {
+        match self {
+            ObFamily::F1 => F1_FIELDS,
+            ObFamily::F4 => F4_FIELDS,
+            ObFamily::L4 => L4_FIELDS,
+            ObFamily::G0 => G0_FIELDS,
+        }
+    }
+
+    pub fn field(self, name: &str) -> Option<&'static ObField> {
+        self.fields().iter().find(|f| f.name == name)
+    }
+
+    fn len(self) -> usize {
+        match self {
+            ObFamily::F1 | ObFamily::F4 => 16,
+            ObFamily::L4 | ObFamily::G0 => 40,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum RdpLevel {
+    Level0,
+    Level1,
+    /// 永久保护，调试接口和 Bootloader 都不再可用
+    Level2,
+}
+
+/// 修改 RDP 时写入的值
+pub const RDP_LEVEL0: u32 = 0xAA;
+const RDP_LEVEL2: u32 = 0xCC;
+
+/// 一次读出的选项字节
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OptionBytes {
+    pub family: ObFamily,
+    pub address: u32,
+    pub raw: Vec<u8>,
+}
+
+impl OptionBytes {
+    pub fn new(family: ObFamily, address: u32, raw: Vec<u8>) -> Result<Self> {
+        if raw.len() != family.len() {
+            return Err(Error::OptionByte(format!(
+                "expected {} bytes, got {}",
+                family.len(),
+                raw.len()
+            )));
+        }
+        Ok(Self {
+            family,
+            address,
+            raw,
+        })
+    }
+
+    fn word(&self, offset: usize) -> u32 {
+        u32::from_le_bytes(self.raw[offset..offset + 4].try_into().unwrap())
+    }
+
+    fn field(&self, name: &str) -> Result<&'static ObField> {
+        self.family
+            .field(name)
+            .ok_or_else(|| Error::OptionByte(format!("no field '{name}' on {:?}", self.family)))
+    }
+
+    pub fn get(&self, name: &str) -> Result<u32> {
+        let field = self.field(name)?;
+        Ok(extract(self.word(field.offset), field))
+    }
+
+    /// 修改一个字段并更新反码；不允许设置永久生效的 RDP Level 2
+    pub fn set(&mut self, name: &str, value: u32) -> Result<()> {
+        let field = self.field(name)?;
+        let mask = mask(field);
+        if value > mask {
+            return Err(Error::OptionByte(format!(
+                "{name} is {} bit(s) wide, 0x{value:X} does not fit",
+                field.width
+            )));
+        }
+        if name == "rdp" && value == RDP_LEVEL2 {
+            return Err(Error::OptionByte(
+                "RDP level 2 is permanent and is not set by this tool".to_string(),
+            ));
+        }
+        let word = self.word(field.offset) & !(mask << field.shift) | value << field.shift;
+        self.raw[field.offset..field.offset + 4].copy_from_slice(&word.to_le_bytes());
+        self.fix_complements();
+        Ok(())
+    }
+
+    /// 按 `name=value` 形式修改，值可带 0x 前缀
+    pub fn apply(&mut self, assignment: &str) -> Result<()> {
+        let (name, value) = parse_assignment(assignment)?;
+        self.set(&name, value)
+    }
+
+    fn fix_complements(&mut self) {
+        match self.family {
+            ObFamily::F1 => {
+                for pair in self.raw.chunks_exact_mut(2) {
+                    pair[1] = !pair[0];
+                }
+            }
+            ObFamily::L4 | ObFamily::G0 => {
+                for pair in self.raw.chunks_exact_mut(8) {
+                    for i in 0..4 {
+                        pair[4 + i] = !pair[i];
+                    }
+                }
+            }
+            ObFamily::F4 => {}
+        }
+    }
+
+    pub fn rdp(&self) -> RdpLevel {
+        match self.get("rdp").unwrap_or_default() {
+            0xA5 | 0xAA => RdpLevel::Level0,
+            0xCC => RdpLevel::Level2,
+            _ => RdpLevel::Level1,
+        }
+    }
+
+    /// 所有字段及其当前值
+    pub fn fields(&self) -> Vec<(&'static ObField, u32)> {
+        self.family
+            .fields()
+            .iter()
+            .map(|f| (f, extract(self.word(f.offset), f)))
+            .collect()
+    }
+}
+
+impl fmt::Display for OptionBytes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{:?} option bytes at 0x{:08X}, RDP {:?}",
+            self.family,
+            self.address,
+            self.rdp()
+        )?;
+        for (field, value) in self.fields() {
+            let width = (field.width as usize).div_ceil(4);
+            writeln!(
+                f,
+                "  {:<18} 0x{value:0width$X}  {}",
+                field.name, field.description
+            )?;
+        }
+        Ok(())
+    }
+}
+
+fn mask(field: &ObField) -> u32 {
+    (1u64 << field.width) as u32 - 1
+}
+
+fn extract(word: u32, field: &ObField) -> u32 {
+    word >> field.shift & mask(field)
+}
+
+/// 解析 `name=value`，值可带 0x 前缀
+pub fn parse_assignment(s: &str) -> Result<(String, u32)> {
+    let invalid = || Error::OptionByte(format!("expected name=value, got '{s}'"));
+    let (name, value) = s.split_once('=').ok_or_else(invalid)?;
+    let value = value.trim();
+    let value = match value
+        .strip_prefix("0x")
+        .or_else(|| value.strip_prefix("0X"))
+    {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => value.parse(),
+    }
+    .map_err(|_| invalid())?;
+    Ok((name.trim().to_ascii_lowercase(), value))
+}
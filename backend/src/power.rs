@@ -0,0 +1,98 @@
+//! 目标电源控制：烧录前后断电重启目标，用于 BOOT0 只在上电时采样的板子
+//!
+//! 继电器接在辅助串口的 DTR / RTS 上，其他电源开关由外部脚本控制。
+
+use std::process::Command;
+use std::time::Duration;
+
+use tracing::info;
+
+use crate::i18n::{Msg, tr};
+use crate::stm32_uart::{self, ControlLine, Error, Level, Result};
+
+#[derive(Debug, Clone)]
+pub enum PowerSwitch {
+    /// 辅助串口的控制线处于 `off_level` 时断电
+    ///
+    /// 串口关闭后控制线回到空闲状态，继电器应接成空闲时目标上电。
+    SerialLine {
+        port: String,
+        line: ControlLine,
+        off_level: Level,
+    },
+    /// 通过 shell 执行的命令，环境变量 PROBE_FLASHER_POWER 为 off 或 on
+    Hook { command: String },
+}
+
+/// 断电重启配置
+#[derive(Debug, Clone)]
+pub struct PowerCycle {
+    pub switch: PowerSwitch,
+    /// 断电保持时间
+    pub off_time: Duration,
+    /// 恢复上电后等待芯片启动的时间
+    pub on_delay: Duration,
+    /// 连接 Bootloader 前断电重启（BOOT0 保持有效）
+    pub before: bool,
+    /// 完成后断电重启（BOOT0 释放）代替 GO / 复位
+    pub after: bool,
+}
+
+impl PowerCycle {
+    pub fn new(switch: PowerSwitch) -> Self {
+        Self {
+            switch,
+            off_time: Duration::from_millis(500),
+            on_delay: Duration::from_millis(100),
+            before: true,
+            after: false,
+        }
+    }
+
+    /// 断电 `off_time` 后恢复上电
+    pub fn cycle(&self) -> Result<()> {
+        match &self.switch {
+            PowerSwitch::SerialLine {
+                port,
+                line,
+                off_level,
+            } => {
+                let mut port = stm32_uart::open_port(port, 9600, Duration::from_millis(100))?;
+                info!("{}", tr(Msg::PowerOff, &[]));
+                stm32_uart::set_line(&mut *port, *line, *off_level)?;
+                std::thread::sleep(self.off_time);
+                info!("{}", tr(Msg::PowerOn, &[]));
+                stm32_uart::set_line(&mut *port, *line, !*off_level)?;
+            }
+            PowerSwitch::Hook { command } => {
+                info!("{}", tr(Msg::PowerOff, &[]));
+                run_hook(command, "off")?;
+                std::thread::sleep(self.off_time);
+                info!("{}", tr(Msg::PowerOn, &[]));
+                run_hook(command, "on")?;
+            }
+        }
+        std::thread::sleep(self.on_delay);
+        Ok(())
+    }
+}
+
+fn run_hook(command: &str, state: &str) -> Result<()> {
+    let mut cmd = if cfg!(windows) {
+        let mut cmd = Command::new("cmd");
+        cmd.args(["/C", command]);
+        cmd
+    } else {
+        let mut cmd = Command::new("sh");
+        cmd.args(["-c", command]);
+        cmd
+    };
+    let status = cmd.env("PROBE_FLASHER_POWER", state).status()?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(Error::PowerHook(format!(
+            "'{command}' ({state}) exited with {status}"
+        )))
+    }
+}
@@ -0,0 +1,364 @@
+//! 生产数据镜像：按配置的偏移把序列号、校准值等键值数据编码成一小段 Flash 镜像，
+//! 保存为 .hex / .bin 后与应用固件一起交给 `flash_sources` 在同一次连接中烧录
+//!
+//! 多字节数值一律小端；布局可以写在 TOML 里：
+//!
+//! ```toml
+//! base = 0x0800F800
+//! size = 64
+//! crc_offset = 60
+//! fields = [
+//!     { key = "serial", offset = 0, kind = "str", len = 16 },
+//!     { key = "adc_gain", offset = 16, kind = "f32" },
+//! ]
+//! ```
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::Path,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::ConfigError;
+use crate::stm32_uart::{
+    DumpFormat, Error, ImageFormat, ImageSource, Result, addressed, image_to_hex, write_file_atomic,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FieldKind {
+    U8,
+    U16,
+    U32,
+    U64,
+    I32,
+    F32,
+    /// UTF-8 字符串，不足 `len` 补 0
+    Str,
+    /// 原始字节，不足 `len` 补 0
+    Bytes,
+}
+
+impl FieldKind {
+    fn width(self, len: usize) -> usize {
+        match self {
+            FieldKind::U8 => 1,
+            FieldKind::U16 => 2,
+            FieldKind::U32 | FieldKind::I32 | FieldKind::F32 => 4,
+            FieldKind::U64 => 8,
+            FieldKind::Str | FieldKind::Bytes => len,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Field {
+    pub key: String,
+    /// 相对 `Layout::base` 的偏移
+    pub offset: u32,
+    pub kind: FieldKind,
+    /// Str / Bytes 占用的字节数，数值类型忽略
+    #[serde(default)]
+    pub len: usize,
+}
+
+fn default_fill() -> u8 {
+    0xFF
+}
+
+/// 生产数据区的布局
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Layout {
+    /// 数据区起始地址，通常是 Flash 末尾的一页
+    pub base: u32,
+    /// 数据区大小，未被字段占用的字节填充 `fill`
+    pub size: u32,
+    #[serde(default = "default_fill")]
+    pub fill: u8,
+    /// 在该偏移写入它之前所有字节的 CRC-32（小端），固件可据此判断数据区是否有效
+    #[serde(default)]
+    pub crc_offset: Option<u32>,
+    pub fields: Vec<Field>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Uint(u64),
+    Int(i64),
+    Float(f64),
+    Str(String),
+    Bytes(Vec<u8>),
+}
+
+impl From<u64> for Value {
+    fn from(v: u64) -> Self {
+        Value::Uint(v)
+    }
+}
+
+impl From<u32> for Value {
+    fn from(v: u32) -> Self {
+        Value::Uint(v.into())
+    }
+}
+
+impl From<i64> for Value {
+    fn from(v: i64) -> Self {
+        Value::Int(v)
+    }
+}
+
+impl From<f64> for Value {
+    fn from(v: f64) -> Self {
+        Value::Float(v)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(v: &str) -> Self {
+        Value::Str(v.to_string())
+    }
+}
+
+impl From<String> for Value {
+    fn from(v: String) -> Self {
+        Value::Str(v)
+    }
+}
+
+impl From<Vec<u8>> for Value {
+    fn from(v: Vec<u8>) -> Self {
+        Value::Bytes(v)
+    }
+}
+
+impl Value {
+    /// 按字段类型解析文本（CSV、命令行中的 key=value）：整数可带 0x 前缀，Bytes 为十六进制串
+    pub fn parse(kind: FieldKind, text: &str) -> Result<Self> {
+        let text = text.trim();
+        let invalid = || Error::Provision(format!("invalid {kind:?} value '{text}'"));
+        let uint = |s: &str| match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+            Some(hex) => u64::from_str_radix(hex, 16).ok(),
+            None => s.parse().ok(),
+        };
+        Ok(match kind {
+            FieldKind::U8 | FieldKind::U16 | FieldKind::U32 | FieldKind::U64 => {
+                Value::Uint(uint(text).ok_or_else(invalid)?)
+            }
+            FieldKind::I32 => match text.strip_prefix('-') {
+                Some(rest) => Value::Int(
+                    uint(rest)
+                        .and_then(|v| i64::try_from(v).ok())
+                        .map(|v| -v)
+                        .ok_or_else(invalid)?,
+                ),
+                None => Value::Uint(uint(text).ok_or_else(invalid)?),
+            },
+            FieldKind::F32 => Value::Float(text.parse().map_err(|_| invalid())?),
+            FieldKind::Str => Value::Str(text.to_string()),
+            FieldKind::Bytes => {
+                let hex: String = text.chars().filter(|c| !c.is_whitespace()).collect();
+                if !hex.is_ascii() || !hex.len().is_multiple_of(2) {
+                    return Err(invalid());
+                }
+                let bytes = (0..hex.len())
+                    .step_by(2)
+                    .map(|i| u8::from_str_radix(&hex[i..i + 2], 16))
+                    .collect::<std::result::Result<_, _>>()
+                    .map_err(|_| invalid())?;
+                Value::Bytes(bytes)
+            }
+        })
+    }
+
+    fn encode(&self, field: &Field) -> Result<Vec<u8>> {
+        let mismatch = || {
+            Error::Provision(format!(
+                "value {self:?} does not fit field '{}' ({:?})",
+                field.key, field.kind
+            ))
+        };
+        let uint = |max: u64| match *self {
+            Value::Uint(v) if v <= max => Some(v),
+            Value::Int(v) if v >= 0 && v as u64 <= max => Some(v as u64),
+            _ => None,
+        };
+        let bytes = match field.kind {
+            FieldKind::U8 => vec![uint(u8::MAX.into()).ok_or_else(mismatch)? as u8],
+            FieldKind::U16 => (uint(u16::MAX.into()).ok_or_else(mismatch)? as u16)
+                .to_le_bytes()
+                .to_vec(),
+            FieldKind::U32 => (uint(u32::MAX.into()).ok_or_else(mismatch)? as u32)
+                .to_le_bytes()
+                .to_vec(),
+            FieldKind::U64 => uint(u64::MAX).ok_or_else(mismatch)?.to_le_bytes().to_vec(),
+            FieldKind::I32 => {
+                let v = match *self {
+                    Value::Int(v) => i32::try_from(v).ok(),
+                    Value::Uint(v) => i32::try_from(v).ok(),
+                    _ => None,
+                };
+                v.ok_or_else(mismatch)?.to_le_bytes().to_vec()
+            }
+            FieldKind::F32 => {
+                let v = match *self {
+                    Value::Float(v) => v as f32,
+                    Value::Int(v) => v as f32,
+                    Value::Uint(v) => v as f32,
+                    _ => return Err(mismatch()),
+                };
+                v.to_le_bytes().to_vec()
+            }
+            FieldKind::Str | FieldKind::Bytes => {
+                let data = match (field.kind, self) {
+                    (FieldKind::Str, Value::Str(s)) => s.as_bytes(),
+                    (FieldKind::Bytes, Value::Bytes(b)) => b.as_slice(),
+                    _ => return Err(mismatch()),
+                };
+                if data.len() > field.len {
+                    return Err(Error::Provision(format!(
+                        "'{}' is {} bytes, field holds {}",
+                        field.key,
+                        data.len(),
+                        field.len
+                    )));
+                }
+                let mut padded = data.to_vec();
+                padded.resize(field.len, 0);
+                padded
+            }
+        };
+        Ok(bytes)
+    }
+}
+
+impl Layout {
+    /// 检查字段都在数据区内且互不重叠
+    pub fn validate(&self) -> Result<()> {
+        let mut spans: Vec<(u64, u64, &str)> = self
+            .fields
+            .iter()
+            .map(|f| {
+                let start = u64::from(f.offset);
+                (start, start + f.kind.width(f.len) as u64, f.key.as_str())
+            })
+            .collect();
+        if let Some(offset) = self.crc_offset {
+            spans.push((u64::from(offset), u64::from(offset) + 4, "crc"));
+        }
+        spans.sort();
+        for &(start, end, key) in &spans {
+            if start == end || end > u64::from(self.size) {
+                return Err(Error::Provision(format!(
+                    "field '{key}' at offset {start} does not fit the {}-byte area",
+                    self.size
+                )));
+            }
+        }
+        if let Some(pair) = spans.windows(2).find(|w| w[1].0 < w[0].1) {
+            return Err(Error::Provision(format!(
+                "fields '{}' and '{}' overlap",
+                pair[0].2, pair[1].2
+            )));
+        }
+        if u64::from(self.base) + u64::from(self.size) > 1 << 32 {
+            return Err(Error::Provision("area runs past 0xFFFFFFFF".to_string()));
+        }
+        Ok(())
+    }
+
+    /// 用一台设备的数据生成镜像；布局中的每个字段都必须给出，多余的键视为拼写错误
+    pub fn build(&self, values: &HashMap<String, Value>) -> Result<ProvisionImage> {
+        self.validate()?;
+        if let Some(key) = values
+            .keys()
+            .find(|key| !self.fields.iter().any(|f| &f.key == *key))
+        {
+            return Err(Error::Provision(format!("unknown field '{key}'")));
+        }
+
+        let mut data = vec![self.fill; self.size as usize];
+        for field in &self.fields {
+            let value = values
+                .get(&field.key)
+                .ok_or_else(|| Error::Provision(format!("missing value for '{}'", field.key)))?;
+            let bytes = value.encode(field)?;
+            let start = field.offset as usize;
+            data[start..start + bytes.len()].copy_from_slice(&bytes);
+        }
+        if let Some(offset) = self.crc_offset {
+            let offset = offset as usize;
+            let crc = crc32fast::hash(&data[..offset]);
+            data[offset..offset + 4].copy_from_slice(&crc.to_le_bytes());
+        }
+        Ok(ProvisionImage {
+            base: self.base,
+            data,
+        })
+    }
+
+    /// 同 `build`，值为文本，按各字段类型解析
+    pub fn build_from_text(&self, values: &HashMap<String, String>) -> Result<ProvisionImage> {
+        let parsed = values
+            .iter()
+            .map(|(key, text)| {
+                let field = self
+                    .fields
+                    .iter()
+                    .find(|f| &f.key == key)
+                    .ok_or_else(|| Error::Provision(format!("unknown field '{key}'")))?;
+                Ok((key.clone(), Value::parse(field.kind, text)?))
+            })
+            .collect::<Result<_>>()?;
+        self.build(&parsed)
+    }
+}
+
+/// 一台设备的生产数据镜像
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProvisionImage {
+    pub base: u32,
+    pub data: Vec<u8>,
+}
+
+impl ProvisionImage {
+    /// 地址 -> 字节，与 `merge_sources` 的结果形式相同
+    pub fn to_image(&self) -> Result<BTreeMap<u32, u8>> {
+        Ok(addressed(self.base, &self.data)?.collect())
+    }
+
+    pub fn to_hex(&self) -> Result<String> {
+        image_to_hex(&self.to_image()?)
+    }
+
+    /// 原子地保存为 .bin 或 .hex，返回可直接交给 `flash_sources` 的镜像描述
+    pub fn save(&self, path: &Path, format: DumpFormat) -> Result<ImageSource> {
+        let bytes = match format {
+            DumpFormat::Bin => self.data.clone(),
+            DumpFormat::Hex => self.to_hex()?.into_bytes(),
+        };
+        write_file_atomic(path, |file| Ok(std::io::Write::write_all(file, &bytes)?))?;
+        Ok(match format {
+            DumpFormat::Bin => ImageSource {
+                path: path.to_path_buf(),
+                format: ImageFormat::Binary,
+                base_address: Some(self.base),
+            },
+            DumpFormat::Hex => ImageSource {
+                path: path.to_path_buf(),
+                format: ImageFormat::IntelHex,
+                base_address: None,
+            },
+        })
+    }
+}
+
+/// 从 TOML 文件读取布局
+pub fn load_layout(path: &Path) -> Result<Layout> {
+    let text = std::fs::read_to_string(path)?;
+    let layout: Layout =
+        toml::from_str(&text).map_err(|e| ConfigError(format!("{}: {e}", path.display())))?;
+    layout.validate()?;
+    Ok(layout)
+}
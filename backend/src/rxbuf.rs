@@ -0,0 +1,118 @@
+//! 带接收队列的串口包装：每次系统调用尽量多读，逐字节的协议解析从内存队列中取数据
+//!
+//! 读不到数据时按调用方设置的超时阻塞在底层串口上，而不是反复以短超时轮询，
+//! 长时间全片擦除等待 ACK 时不再占满一个 CPU 核。
+
+use std::{collections::VecDeque, io, time::Duration};
+
+use crate::linkstats::LinkStats;
+use crate::stm32_uart::{Result, StatusLine};
+use crate::transport::Transport;
+
+/// 单次从底层串口读取的最大字节数
+const RX_CHUNK: usize = 4096;
+
+pub struct BufferedPort {
+    inner: Box<dyn Transport>,
+    rx: VecDeque<u8>,
+    scratch: Box<[u8]>,
+    /// 最近下发给底层的读超时
+    timeout: Option<Duration>,
+    stats: LinkStats,
+}
+
+impl BufferedPort {
+    pub fn new(inner: Box<dyn Transport>) -> Self {
+        Self {
+            inner,
+            rx: VecDeque::new(),
+            scratch: vec![0u8; RX_CHUNK].into_boxed_slice(),
+            timeout: None,
+            stats: LinkStats::default(),
+        }
+    }
+
+    /// 已从底层读入、尚未取走的字节数
+    pub fn queued(&self) -> usize {
+        self.rx.len()
+    }
+
+    /// 队列为空时从底层串口批量读取一次
+    fn fill(&mut self) -> io::Result<()> {
+        if !self.rx.is_empty() {
+            return Ok(());
+        }
+        let n = self.inner.read(&mut self.scratch)?;
+        self.rx.extend(&self.scratch[..n]);
+        Ok(())
+    }
+}
+
+impl io::Read for BufferedPort {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        self.fill()?;
+        let n = buf.len().min(self.rx.len());
+        for (dst, src) in buf.iter_mut().zip(self.rx.drain(..n)) {
+            *dst = src;
+        }
+        Ok(n)
+    }
+}
+
+impl io::Write for BufferedPort {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl Transport for BufferedPort {
+    // 超时不变时不下发，读循环每次都会按剩余时间设置
+    fn set_timeout(&mut self, timeout: Duration) -> Result<()> {
+        if self.timeout == Some(timeout) {
+            return Ok(());
+        }
+        self.inner.set_timeout(timeout)?;
+        self.timeout = Some(timeout);
+        Ok(())
+    }
+
+    fn set_dtr(&mut self, level: bool) -> Result<()> {
+        self.inner.set_dtr(level)
+    }
+
+    fn set_rts(&mut self, level: bool) -> Result<()> {
+        self.inner.set_rts(level)
+    }
+
+    fn clear(&mut self) -> Result<()> {
+        self.rx.clear();
+        self.inner.clear()
+    }
+
+    fn set_baud_rate(&mut self, baud_rate: u32) -> Result<()> {
+        self.inner.set_baud_rate(baud_rate)
+    }
+
+    fn baud_rate(&self) -> Option<u32> {
+        self.inner.baud_rate()
+    }
+
+    fn read_status(&mut self, line: StatusLine) -> Result<bool> {
+        self.inner.read_status(line)
+    }
+
+    fn name(&self) -> Option<String> {
+        self.inner.name()
+    }
+
+    fn link_stats(&mut self) -> Option<&mut LinkStats> {
+        Some(&mut self.stats)
+    }
+}
@@ -0,0 +1,1045 @@
+//! 模拟 STM32 UART Bootloader，实现 `SerialPort`，用于无硬件测试
+
+use std::{
+    collections::{BTreeMap, VecDeque},
+    fmt, io,
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+use serialport::{ClearBuffer, DataBits, FlowControl, Parity, SerialPort, StopBits};
+
+use crate::chipdb;
+use crate::stm32_uart::{
+    ACK, CMD_ERASE, CMD_EXTENDED_ERASE, CMD_GET, CMD_GET_CHECKSUM, CMD_GET_ID, CMD_GET_VERSION,
+    CMD_GO, CMD_READ_MEMORY, CMD_READOUT_PROTECT, CMD_READOUT_UNPROTECT, CMD_WRITE_MEMORY,
+    CMD_WRITE_PROTECT, CMD_WRITE_UNPROTECT, ExternalMemory, FLASH_BASE, NACK, PortInfo, stm32_crc,
+};
+use crate::stub::{
+    OP_ERASE, OP_GO, OP_PING, OP_READ, OP_SET_BAUD, OP_WRITE, STUB_MAGIC, STUB_VERSION,
+};
+
+const SYNC: u8 = 0x7F;
+const PAGE_SIZE: u32 = 1024;
+/// F1 系列选项字节地址
+const OPTION_BYTES: u32 = 0x1FFF_F800;
+/// F1 系列 96 位唯一 ID 地址
+const UNIQUE_ID: u32 = 0x1FFF_F7E8;
+/// F1 系列 Flash 容量寄存器地址（16 位，单位 KB），芯片数据库未收录的型号使用
+const FLASH_SIZE_REG: u32 = 0x1FFF_F7E0;
+/// SRAM 起始地址，RAM 烧录程序加载到这里
+const SRAM_BASE: u32 = 0x2000_0000;
+/// 模拟的 RAM 烧录程序声明的单帧最大负载
+const STUB_MAX_PAYLOAD: u16 = 1024;
+/// 写保护的粒度（F1 中容量每个 WRP 位保护 4 页）
+const WRP_SECTOR_SIZE: u32 = 4 * PAGE_SIZE;
+/// 模拟 USB CDC 端口在芯片复位后重新出现前的时间
+const REENUMERATE_DELAY: Duration = Duration::from_millis(300);
+/// 每页擦除耗时（仅 realistic_timing）
+const PAGE_ERASE_TIME: Duration = Duration::from_millis(20);
+
+/// 注入到 ACK 应答上的故障
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    /// 不发送该 ACK
+    DropAck,
+    /// 用指定字节替换该 ACK
+    Garbage(u8),
+    /// 延迟发送该 ACK
+    Delay(Duration),
+}
+
+#[derive(Debug, Clone)]
+pub struct SimConfig {
+    pub bootloader_version: u8,
+    pub product_id: u16,
+    /// GET 返回的命令列表
+    pub commands: Vec<u8>,
+    pub flash_size: u32,
+    pub read_protected: bool,
+    /// 位于 0x1FFFF800 的选项字节，写入后芯片复位
+    pub option_bytes: Vec<u8>,
+    /// 位于 0x1FFFF7E8 的 12 字节唯一 ID，只读
+    pub unique_id: [u8; 12],
+    /// 写保护的扇区号（每扇区 4 KB），写入这些扇区会被 NACK
+    pub write_protected: Vec<u8>,
+    /// 按波特率模拟传输耗时，擦除按页延迟应答（演示模式用，测试中关闭以保持快速）
+    pub realistic_timing: bool,
+    /// 经扩展命令编程的外部 Flash，命令字需同时列在 `commands` 中；WRITE MEMORY 不能写入该区域
+    pub external: Option<ExternalMemory>,
+    pub sram_size: u32,
+    /// GO 到 SRAM 时按参考协议模拟 RAM 烧录程序（不执行写入的代码）
+    pub stub: bool,
+    /// 高于该波特率时收到的都是乱码，不作应答（模拟适配器或线路跑不到的速率）；None 不限制
+    pub max_baud: Option<u32>,
+    /// RTS / DTR 变化视为 Boot 序列中的复位，芯片重新等待同步；关闭时控制线不影响状态
+    pub reset_on_control_lines: bool,
+    /// 模拟原生 USB CDC 串口的序列号：选项字节变更使芯片复位后端口消失，
+    /// 稍后以新端口名、同一序列号重新出现；None 时端口名不变
+    pub usb_serial: Option<String>,
+}
+
+impl Default for SimConfig {
+    fn default() -> Self {
+        Self {
+            bootloader_version: 0x22,
+            product_id: 0x0410,
+            commands: vec![
+                CMD_GET,
+                CMD_GET_VERSION,
+                CMD_GET_ID,
+                CMD_READ_MEMORY,
+                CMD_GO,
+                CMD_WRITE_MEMORY,
+                CMD_ERASE,
+                CMD_WRITE_PROTECT,
+                CMD_WRITE_UNPROTECT,
+                CMD_READOUT_PROTECT,
+                CMD_READOUT_UNPROTECT,
+            ],
+            flash_size: 64 * 1024,
+            read_protected: false,
+            option_bytes: vec![
+                0xA5, 0x5A, 0xFF, 0x00, 0xFF, 0x00, 0xFF, 0x00, 0xFF, 0x00, 0xFF, 0x00, 0xFF, 0x00,
+                0xFF, 0x00,
+            ],
+            unique_id: [
+                0x30, 0xFF, 0xD6, 0x05, 0x4E, 0x57, 0x34, 0x39, 0x23, 0x71, 0x10, 0x43,
+            ],
+            write_protected: Vec::new(),
+            realistic_timing: false,
+            external: None,
+            sram_size: 20 * 1024,
+            stub: false,
+            max_baud: None,
+            reset_on_control_lines: false,
+            usb_serial: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Phase {
+    /// 等待 0x7F 同步
+    Unsynced,
+    Command,
+    Address(u8),
+    ReadLen(u32),
+    WriteData(u32),
+    Erase,
+    ExtendedErase,
+    /// 外部存储器擦除的长度帧
+    ExternalErase(u32),
+    /// Write Protect 的扇区列表
+    WriteProtect,
+    /// Get Checksum 的参数帧：地址、已收到的字数和多项式及其个数，最后一帧为初值
+    Checksum(u32, [u32; 2], usize),
+    /// GO 到 SRAM 后由 RAM 烧录程序按 stub 协议应答
+    Stub,
+    /// GO 之后运行用户程序，不再响应
+    Running,
+}
+
+struct State {
+    config: SimConfig,
+    flash: BTreeMap<u32, u8>,
+    ram: BTreeMap<u32, u8>,
+    rx: Vec<u8>,
+    tx: VecDeque<u8>,
+    ready_at: Instant,
+    phase: Phase,
+    faults: VecDeque<Option<Fault>>,
+    go_address: Option<u32>,
+    /// 收到的 WRITE_MEMORY 数据帧的地址和长度
+    writes: Vec<(u32, usize)>,
+    /// 收到的命令码，包括被拒绝的
+    received: Vec<u8>,
+    /// USB CDC 端口重新枚举的次数，决定当前端口名
+    enumeration: u32,
+    /// 端口重新出现的时刻
+    attached_at: Instant,
+    /// 注册为演示端口时的端口名
+    port_base: String,
+}
+
+impl State {
+    fn in_flash(&self, addr: u32, len: u32) -> bool {
+        addr >= FLASH_BASE
+            && addr
+                .checked_add(len)
+                .is_some_and(|end| end <= FLASH_BASE + self.config.flash_size)
+    }
+
+    fn in_sram(&self, addr: u32, len: u32) -> bool {
+        addr >= SRAM_BASE
+            && addr
+                .checked_add(len)
+                .is_some_and(|end| end <= SRAM_BASE + self.config.sram_size)
+    }
+
+    fn in_external(&self, addr: u32, len: u32) -> bool {
+        self.config
+            .external
+            .as_ref()
+            .is_some_and(|ext| ext.contains(addr, len as usize))
+    }
+
+    fn is_external_command(&self, cmd: u8) -> bool {
+        self.config
+            .external
+            .as_ref()
+            .is_some_and(|ext| cmd == ext.write_command || Some(cmd) == ext.erase_command)
+    }
+
+    fn in_option_bytes(&self, addr: u32, len: u32) -> bool {
+        let end = OPTION_BYTES + self.config.option_bytes.len() as u32;
+        addr >= OPTION_BYTES && addr.checked_add(len).is_some_and(|e| e <= end)
+    }
+
+    fn write_protected(&self, addr: u32, len: u32) -> bool {
+        let first = (addr - FLASH_BASE) / WRP_SECTOR_SIZE;
+        let last = (addr + len - 1 - FLASH_BASE) / WRP_SECTOR_SIZE;
+        (first..=last).any(|s| self.config.write_protected.contains(&(s as u8)))
+    }
+
+    fn in_unique_id(&self, addr: u32, len: u32) -> bool {
+        addr >= UNIQUE_ID && addr.checked_add(len).is_some_and(|e| e <= UNIQUE_ID + 12)
+    }
+
+    /// Flash 容量寄存器的地址：芯片数据库中收录的按型号取，否则为 F1 的地址
+    fn flash_size_reg(&self) -> u32 {
+        chipdb::lookup(self.config.product_id).map_or(FLASH_SIZE_REG, |chip| chip.flash_size_reg)
+    }
+
+    fn in_flash_size_reg(&self, addr: u32, len: u32) -> bool {
+        let reg = self.flash_size_reg();
+        addr >= reg && addr.checked_add(len).is_some_and(|e| e <= reg + 2)
+    }
+
+    fn byte_at(&self, addr: u32) -> u8 {
+        if self.in_flash_size_reg(addr, 1) {
+            let kb = (self.config.flash_size / 1024) as u16;
+            kb.to_le_bytes()[(addr - self.flash_size_reg()) as usize]
+        } else if self.in_unique_id(addr, 1) {
+            self.config.unique_id[(addr - UNIQUE_ID) as usize]
+        } else if self.in_option_bytes(addr, 1) {
+            self.config.option_bytes[(addr - OPTION_BYTES) as usize]
+        } else if self.in_sram(addr, 1) {
+            self.ram.get(&addr).copied().unwrap_or(0)
+        } else {
+            self.flash.get(&addr).copied().unwrap_or(0xFF)
+        }
+    }
+
+    fn ack(&mut self) {
+        match self.faults.pop_front().flatten() {
+            None => self.tx.push_back(ACK),
+            Some(Fault::DropAck) => {}
+            Some(Fault::Garbage(b)) => self.tx.push_back(b),
+            Some(Fault::Delay(d)) => {
+                self.ready_at = self.ready_at.max(Instant::now() + d);
+                self.tx.push_back(ACK);
+            }
+        }
+    }
+
+    /// 选项字节变更后芯片复位，重新等待同步；USB CDC 端口随之重新枚举
+    fn chip_reset(&mut self) {
+        self.phase = Phase::Unsynced;
+        if self.config.usb_serial.is_some() {
+            self.enumeration += 1;
+            self.attached_at = Instant::now() + REENUMERATE_DELAY;
+        }
+    }
+
+    fn port_name(&self) -> String {
+        match self.enumeration {
+            0 => self.port_base.clone(),
+            n => format!("{}{n}", self.port_base),
+        }
+    }
+
+    fn control_line_changed(&mut self) {
+        if self.config.reset_on_control_lines {
+            self.rx.clear();
+            self.tx.clear();
+            self.phase = Phase::Unsynced;
+        }
+    }
+
+    fn nack(&mut self) {
+        self.tx.push_back(NACK);
+        self.phase = Phase::Command;
+    }
+
+    /// 读保护下被拒绝的命令
+    fn blocked(&self, cmd: u8) -> bool {
+        self.config.read_protected
+            && matches!(
+                cmd,
+                CMD_READ_MEMORY
+                    | CMD_WRITE_MEMORY
+                    | CMD_GO
+                    | CMD_ERASE
+                    | CMD_EXTENDED_ERASE
+                    | CMD_WRITE_PROTECT
+                    | CMD_WRITE_UNPROTECT
+                    | CMD_READOUT_PROTECT
+            )
+    }
+
+    /// 处理已收到的完整帧，不足一帧时等待更多数据
+    fn process(&mut self) {
+        loop {
+            let consumed = match self.phase {
+                Phase::Running => {
+                    self.rx.clear();
+                    return;
+                }
+                Phase::Stub => {
+                    let [op, hi, lo, ..] = self.rx[..] else {
+                        return;
+                    };
+                    let len = u16::from_be_bytes([hi, lo]) as usize;
+                    if self.rx.len() < len + 4 {
+                        return;
+                    }
+                    let frame = self.rx[..len + 4].to_vec();
+                    if frame.iter().fold(0u8, |acc, b| acc ^ b) != 0 {
+                        self.stub_reply(NACK, b"checksum");
+                    } else {
+                        self.stub_request(op, &frame[3..3 + len]);
+                    }
+                    len + 4
+                }
+                Phase::Unsynced => {
+                    let Some(&b) = self.rx.first() else { return };
+                    if b == SYNC {
+                        self.ack();
+                        self.phase = Phase::Command;
+                    }
+                    1
+                }
+                Phase::Command => {
+                    let [cmd, comp, ..] = self.rx[..] else { return };
+                    self.command(cmd, comp);
+                    2
+                }
+                Phase::Address(cmd) => {
+                    let [a0, a1, a2, a3, c, ..] = self.rx[..] else {
+                        return;
+                    };
+                    self.address(cmd, [a0, a1, a2, a3], c);
+                    5
+                }
+                Phase::ReadLen(addr) => {
+                    let [n, comp, ..] = self.rx[..] else { return };
+                    let len = n as u32 + 1;
+                    if n ^ comp != 0xFF
+                        || !(self.in_flash(addr, len)
+                            || self.in_sram(addr, len)
+                            || self.in_external(addr, len)
+                            || self.in_option_bytes(addr, len)
+                            || self.in_unique_id(addr, len)
+                            || self.in_flash_size_reg(addr, len))
+                    {
+                        self.nack();
+                    } else {
+                        self.ack();
+                        for a in addr..addr + len {
+                            let b = self.byte_at(a);
+                            self.tx.push_back(b);
+                        }
+                        self.phase = Phase::Command;
+                    }
+                    2
+                }
+                Phase::WriteData(addr) => {
+                    let Some(&n) = self.rx.first() else { return };
+                    let len = n as usize + 1;
+                    if self.rx.len() < len + 2 {
+                        return;
+                    }
+                    let data = self.rx[1..=len].to_vec();
+                    let checksum = self.rx[..=len].iter().fold(0u8, |acc, b| acc ^ b);
+                    self.writes.push((addr, len));
+                    if checksum != self.rx[len + 1] {
+                        self.nack();
+                    } else if self.in_option_bytes(addr, len as u32) {
+                        // 写选项字节后芯片复位生效
+                        let start = (addr - OPTION_BYTES) as usize;
+                        self.config.option_bytes[start..start + len].copy_from_slice(&data);
+                        self.ack();
+                        self.chip_reset();
+                    } else if self.in_sram(addr, len as u32) {
+                        self.ram.extend((addr..).zip(data));
+                        self.ack();
+                        self.phase = Phase::Command;
+                    } else if self.in_external(addr, len as u32) {
+                        for (a, b) in (addr..).zip(data) {
+                            self.flash.insert(a, b);
+                        }
+                        self.ack();
+                        self.phase = Phase::Command;
+                    } else if !self.in_flash(addr, len as u32)
+                        || self.write_protected(addr, len as u32)
+                    {
+                        self.nack();
+                    } else {
+                        for (a, b) in (addr..).zip(data) {
+                            self.flash.insert(a, b);
+                        }
+                        self.ack();
+                        self.phase = Phase::Command;
+                    }
+                    len + 2
+                }
+                Phase::Erase => {
+                    let Some(&n) = self.rx.first() else { return };
+                    let needed = if n == 0xFF { 2 } else { n as usize + 3 };
+                    if self.rx.len() < needed {
+                        return;
+                    }
+                    let frame = self.rx[..needed].to_vec();
+                    // 全擦除为 0xFF 0x00，页擦除以所有字节异或为 0 校验
+                    let valid = if n == 0xFF {
+                        frame[1] == 0x00
+                    } else {
+                        frame.iter().fold(0u8, |acc, b| acc ^ b) == 0
+                    };
+                    if !valid {
+                        self.nack();
+                    } else {
+                        if n == 0xFF {
+                            self.mass_erase();
+                        } else {
+                            let pages = frame[1..needed - 1].iter().map(|&p| p as u32);
+                            self.erase_pages(pages.collect());
+                        }
+                        self.ack();
+                        self.phase = Phase::Command;
+                    }
+                    needed
+                }
+                Phase::ExtendedErase => {
+                    let [hi, lo, ..] = self.rx[..] else { return };
+                    let n = u16::from_be_bytes([hi, lo]);
+                    let needed = if n >= 0xFFF0 {
+                        3
+                    } else {
+                        2 + 2 * (n as usize + 1) + 1
+                    };
+                    if self.rx.len() < needed {
+                        return;
+                    }
+                    let frame = self.rx[..needed].to_vec();
+                    if frame.iter().fold(0u8, |acc, b| acc ^ b) != 0 {
+                        self.nack();
+                    } else {
+                        if n >= 0xFFF0 {
+                            self.mass_erase();
+                        } else {
+                            let pages = frame[2..needed - 1]
+                                .chunks(2)
+                                .map(|p| u16::from_be_bytes([p[0], p[1]]) as u32);
+                            self.erase_pages(pages.collect());
+                        }
+                        self.ack();
+                        self.phase = Phase::Command;
+                    }
+                    needed
+                }
+                Phase::ExternalErase(addr) => {
+                    let [b0, b1, b2, b3, c, ..] = self.rx[..] else {
+                        return;
+                    };
+                    let len = u32::from_be_bytes([b0, b1, b2, b3]);
+                    if b0 ^ b1 ^ b2 ^ b3 != c || !self.in_external(addr, len) {
+                        self.nack();
+                    } else {
+                        self.flash.retain(|&a, _| a < addr || a - addr >= len);
+                        self.busy(PAGE_ERASE_TIME);
+                        self.ack();
+                        self.phase = Phase::Command;
+                    }
+                    5
+                }
+                Phase::Checksum(addr, mut params, received) => {
+                    let [b0, b1, b2, b3, c, ..] = self.rx[..] else {
+                        return;
+                    };
+                    let value = u32::from_be_bytes([b0, b1, b2, b3]);
+                    if b0 ^ b1 ^ b2 ^ b3 != c
+                        || (received == 0 && !self.in_flash(addr, value.saturating_mul(4)))
+                    {
+                        self.nack();
+                        self.phase = Phase::Command;
+                    } else if received < 2 {
+                        params[received] = value;
+                        self.ack();
+                        self.phase = Phase::Checksum(addr, params, received + 1);
+                    } else {
+                        let [words, polynomial] = params;
+                        let data: Vec<u8> =
+                            (addr..addr + words * 4).map(|a| self.byte_at(a)).collect();
+                        let crc = stm32_crc(&data, polynomial, value).to_be_bytes();
+                        self.ack();
+                        self.ack();
+                        self.tx.extend(crc);
+                        self.tx.push_back(crc.iter().fold(0, |acc, b| acc ^ b));
+                        self.phase = Phase::Command;
+                    }
+                    5
+                }
+                Phase::WriteProtect => {
+                    let Some(&n) = self.rx.first() else { return };
+                    let needed = n as usize + 3;
+                    if self.rx.len() < needed {
+                        return;
+                    }
+                    let frame = self.rx[..needed].to_vec();
+                    if frame.iter().fold(0u8, |acc, b| acc ^ b) != 0 {
+                        self.nack();
+                        self.phase = Phase::Command;
+                    } else {
+                        // 选项字节写入后芯片复位，需要重新同步
+                        self.config.write_protected = frame[1..needed - 1].to_vec();
+                        self.ack();
+                        self.chip_reset();
+                    }
+                    needed
+                }
+            };
+            self.rx.drain(..consumed);
+        }
+    }
+
+    fn command(&mut self, cmd: u8, comp: u8) {
+        self.received.push(cmd);
+        if cmd ^ comp != 0xFF || !self.config.commands.contains(&cmd) || self.blocked(cmd) {
+            self.nack();
+            return;
+        }
+
+        self.ack();
+        match cmd {
+            CMD_GET => {
+                let commands = self.config.commands.clone();
+                self.tx.push_back(commands.len() as u8);
+                self.tx.push_back(self.config.bootloader_version);
+                self.tx.extend(commands);
+                self.ack();
+            }
+            CMD_GET_VERSION => {
+                self.tx.extend([self.config.bootloader_version, 0x00, 0x00]);
+                self.ack();
+            }
+            CMD_GET_ID => {
+                self.tx.push_back(1);
+                self.tx.extend(self.config.product_id.to_be_bytes());
+                self.ack();
+            }
+            CMD_READ_MEMORY | CMD_WRITE_MEMORY | CMD_GO | CMD_GET_CHECKSUM => {
+                self.phase = Phase::Address(cmd)
+            }
+            CMD_ERASE => self.phase = Phase::Erase,
+            CMD_EXTENDED_ERASE => self.phase = Phase::ExtendedErase,
+            CMD_READOUT_PROTECT => {
+                self.config.read_protected = true;
+                self.ack();
+                self.chip_reset();
+            }
+            CMD_READOUT_UNPROTECT => {
+                self.config.read_protected = false;
+                self.flash.clear();
+                self.ack();
+                self.chip_reset();
+            }
+            CMD_WRITE_PROTECT => {
+                self.ack();
+                self.phase = Phase::WriteProtect;
+            }
+            _ if self.is_external_command(cmd) => self.phase = Phase::Address(cmd),
+            CMD_WRITE_UNPROTECT => {
+                // 第二个 ACK 表示选项字节已写入，随后芯片复位
+                self.ack();
+                self.config.write_protected.clear();
+                self.ack();
+                self.chip_reset();
+            }
+            _ => {}
+        }
+    }
+
+    fn address(&mut self, cmd: u8, bytes: [u8; 4], checksum: u8) {
+        let addr = u32::from_be_bytes(bytes);
+        let external = self.is_external_command(cmd);
+        let mapped = if external {
+            self.in_external(addr, 1)
+        } else {
+            self.in_flash(addr, 1)
+                || (matches!(cmd, CMD_READ_MEMORY | CMD_WRITE_MEMORY | CMD_GO)
+                    && self.in_sram(addr, 1))
+                || (cmd != CMD_GO && self.in_option_bytes(addr, 1))
+                || (cmd == CMD_READ_MEMORY
+                    && (self.in_unique_id(addr, 1)
+                        || self.in_flash_size_reg(addr, 1)
+                        || self.in_external(addr, 1)))
+        };
+        if bytes.iter().fold(0u8, |acc, b| acc ^ b) != checksum || !mapped {
+            self.nack();
+            return;
+        }
+
+        self.ack();
+        let erase = self
+            .config
+            .external
+            .as_ref()
+            .and_then(|ext| ext.erase_command);
+        self.phase = match cmd {
+            _ if external && Some(cmd) == erase => Phase::ExternalErase(addr),
+            _ if external => Phase::WriteData(addr),
+            CMD_READ_MEMORY => Phase::ReadLen(addr),
+            CMD_WRITE_MEMORY => Phase::WriteData(addr),
+            CMD_GET_CHECKSUM => Phase::Checksum(addr, [0; 2], 0),
+            _ if self.config.stub && self.in_sram(addr, 1) => {
+                self.tx.extend(STUB_MAGIC);
+                self.tx.push_back(STUB_VERSION);
+                self.tx.extend(STUB_MAX_PAYLOAD.to_be_bytes());
+                Phase::Stub
+            }
+            _ => {
+                self.go_address = Some(addr);
+                Phase::Running
+            }
+        };
+    }
+
+    fn stub_reply(&mut self, status: u8, payload: &[u8]) {
+        let mut frame = vec![status];
+        frame.extend((payload.len() as u16).to_be_bytes());
+        frame.extend_from_slice(payload);
+        frame.push(frame.iter().fold(0u8, |acc, b| acc ^ b));
+        self.tx.extend(frame);
+    }
+
+    /// 参考 stub 协议：擦除 / 写入 / 读取片内 Flash，GO 后运行用户程序
+    fn stub_request(&mut self, op: u8, payload: &[u8]) {
+        let word = |i: usize| {
+            payload
+                .get(i..i + 4)
+                .map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+        };
+        match (op, word(0)) {
+            (OP_PING | OP_SET_BAUD, _) => self.stub_reply(ACK, payload),
+            (OP_ERASE, Some(addr)) => match word(4) {
+                Some(len) if self.in_flash(addr, len) => {
+                    self.flash.retain(|&a, _| a < addr || a - addr >= len);
+                    self.stub_reply(ACK, &[]);
+                }
+                _ => self.stub_reply(NACK, b"erase range"),
+            },
+            (OP_WRITE, Some(addr)) => {
+                let data = &payload[4..];
+                let len = data.len() as u32;
+                if self.in_flash(addr, len) && !self.write_protected(addr, len) {
+                    self.flash.extend((addr..).zip(data.iter().copied()));
+                    self.stub_reply(ACK, &[]);
+                } else {
+                    self.stub_reply(NACK, b"write range");
+                }
+            }
+            (OP_READ, Some(addr)) if payload.len() == 6 => {
+                let len = u16::from_be_bytes([payload[4], payload[5]]) as u32;
+                if self.in_flash(addr, len) {
+                    let data: Vec<u8> = (addr..addr + len).map(|a| self.byte_at(a)).collect();
+                    self.stub_reply(ACK, &data);
+                } else {
+                    self.stub_reply(NACK, b"read range");
+                }
+            }
+            (OP_GO, Some(addr)) => {
+                self.stub_reply(ACK, &[]);
+                self.go_address = Some(addr);
+                self.phase = Phase::Running;
+            }
+            _ => self.stub_reply(NACK, b"unknown op"),
+        }
+    }
+
+    /// 擦除期间不发送应答
+    fn busy(&mut self, time: Duration) {
+        if self.config.realistic_timing {
+            self.ready_at = self.ready_at.max(Instant::now()) + time;
+        }
+    }
+
+    /// 只擦除片内 Flash，外部存储器不受影响
+    fn mass_erase(&mut self) {
+        let flash_end = FLASH_BASE + self.config.flash_size;
+        self.flash
+            .retain(|&a, _| !(FLASH_BASE..flash_end).contains(&a));
+        self.busy(PAGE_ERASE_TIME * 2);
+    }
+
+    fn erase_pages(&mut self, pages: Vec<u32>) {
+        self.busy(PAGE_ERASE_TIME * pages.len() as u32);
+        for page in pages {
+            let start = FLASH_BASE + page * PAGE_SIZE;
+            let addrs: Vec<u32> = self
+                .flash
+                .range(start..start + PAGE_SIZE)
+                .map(|(&a, _)| a)
+                .collect();
+            for a in addrs {
+                self.flash.remove(&a);
+            }
+        }
+    }
+}
+
+/// 模拟的 Bootloader 串口，克隆后共享同一个设备状态
+#[derive(Clone)]
+pub struct SimulatedBootloader {
+    state: Arc<Mutex<State>>,
+    timeout: Duration,
+    baud_rate: u32,
+}
+
+impl fmt::Debug for SimulatedBootloader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SimulatedBootloader")
+            .field("baud_rate", &self.baud_rate)
+            .finish_non_exhaustive()
+    }
+}
+
+impl SimulatedBootloader {
+    pub fn new(config: SimConfig) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(State {
+                config,
+                flash: BTreeMap::new(),
+                ram: BTreeMap::new(),
+                rx: Vec::new(),
+                tx: VecDeque::new(),
+                ready_at: Instant::now(),
+                phase: Phase::Unsynced,
+                faults: VecDeque::new(),
+                go_address: None,
+                writes: Vec::new(),
+                received: Vec::new(),
+                enumeration: 0,
+                attached_at: Instant::now(),
+                port_base: DEMO_PORT.to_string(),
+            })),
+            timeout: Duration::from_millis(100),
+            baud_rate: 115_200,
+        }
+    }
+
+    /// 演示模式用的设备：按波特率模拟传输耗时，Boot 序列的控制线变化使其重新等待同步
+    pub fn demo() -> Self {
+        Self::new(SimConfig {
+            realistic_timing: true,
+            reset_on_control_lines: true,
+            ..SimConfig::default()
+        })
+    }
+
+    /// 串口列表中的模拟端口；USB CDC 端口重新枚举期间为 None
+    pub fn port_info(&self) -> Option<PortInfo> {
+        let state = self.state();
+        if Instant::now() < state.attached_at {
+            return None;
+        }
+        let name = state.port_name();
+        Some(PortInfo {
+            id: name.clone(),
+            label: format!("{name} - Simulated bootloader"),
+            port_name: name,
+            vid: None,
+            pid: None,
+            serial: state.config.usb_serial.clone(),
+            interface: None,
+        })
+    }
+
+    /// 打开当前的模拟端口名时返回共享设备状态的模拟端口，其余端口名返回 None
+    fn open_as(
+        &self,
+        port_name: &str,
+        baud_rate: u32,
+        timeout: Duration,
+    ) -> Option<SimulatedBootloader> {
+        {
+            let mut state = self.state();
+            if port_name != state.port_name() || Instant::now() < state.attached_at {
+                return None;
+            }
+            // 新打开的串口收发缓冲为空
+            state.rx.clear();
+            state.tx.clear();
+        }
+        Some(SimulatedBootloader {
+            baud_rate,
+            timeout,
+            ..self.clone()
+        })
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap()
+    }
+
+    /// 按发送顺序为后续的 ACK 安排故障，`None` 表示正常应答
+    pub fn inject_faults(&self, faults: impl IntoIterator<Item = Option<Fault>>) {
+        self.state().faults.extend(faults);
+    }
+
+    /// 已写入的 Flash 内容（擦除状态的字节不包含在内）
+    pub fn flash(&self) -> BTreeMap<u32, u8> {
+        self.state().flash.clone()
+    }
+
+    pub fn option_bytes(&self) -> Vec<u8> {
+        self.state().config.option_bytes.clone()
+    }
+
+    pub fn read_protected(&self) -> bool {
+        self.state().config.read_protected
+    }
+
+    /// 当前写保护的扇区号
+    pub fn write_protected(&self) -> Vec<u8> {
+        self.state().config.write_protected.clone()
+    }
+
+    /// GO 命令跳转的地址
+    pub fn go_address(&self) -> Option<u32> {
+        self.state().go_address
+    }
+
+    /// 按接收顺序列出 WRITE_MEMORY 数据帧的地址和长度，包括被 NACK 的帧
+    pub fn writes(&self) -> Vec<(u32, usize)> {
+        self.state().writes.clone()
+    }
+
+    /// 按接收顺序列出收到的命令码
+    pub fn commands_received(&self) -> Vec<u8> {
+        self.state().received.clone()
+    }
+}
+
+impl io::Read for SimulatedBootloader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        {
+            let mut state = self.state();
+            if !state.tx.is_empty() && state.ready_at <= Instant::now() {
+                let n = buf.len().min(state.tx.len());
+                for (dst, src) in buf.iter_mut().zip(state.tx.drain(..n)) {
+                    *dst = src;
+                }
+                return Ok(n);
+            }
+        }
+
+        std::thread::sleep(self.timeout.min(Duration::from_millis(1)));
+        Err(io::ErrorKind::TimedOut.into())
+    }
+}
+
+impl io::Write for SimulatedBootloader {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut state = self.state();
+        if state
+            .config
+            .max_baud
+            .is_some_and(|max| self.baud_rate > max)
+        {
+            return Ok(buf.len());
+        }
+        let queued = state.tx.len();
+        state.rx.extend_from_slice(buf);
+        state.process();
+        if state.config.realistic_timing {
+            // 8E1 每字节 11 位，收发都计入
+            let bytes = buf.len() + state.tx.len().saturating_sub(queued);
+            let time = Duration::from_secs_f64(bytes as f64 * 11.0 / self.baud_rate as f64);
+            state.ready_at = state.ready_at.max(Instant::now()) + time;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl SerialPort for SimulatedBootloader {
+    fn name(&self) -> Option<String> {
+        Some("sim".to_string())
+    }
+
+    fn baud_rate(&self) -> serialport::Result<u32> {
+        Ok(self.baud_rate)
+    }
+
+    fn data_bits(&self) -> serialport::Result<DataBits> {
+        Ok(DataBits::Eight)
+    }
+
+    fn flow_control(&self) -> serialport::Result<FlowControl> {
+        Ok(FlowControl::None)
+    }
+
+    fn parity(&self) -> serialport::Result<Parity> {
+        Ok(Parity::Even)
+    }
+
+    fn stop_bits(&self) -> serialport::Result<StopBits> {
+        Ok(StopBits::One)
+    }
+
+    fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    fn set_baud_rate(&mut self, baud_rate: u32) -> serialport::Result<()> {
+        self.baud_rate = baud_rate;
+        Ok(())
+    }
+
+    fn set_data_bits(&mut self, _data_bits: DataBits) -> serialport::Result<()> {
+        Ok(())
+    }
+
+    fn set_flow_control(&mut self, _flow_control: FlowControl) -> serialport::Result<()> {
+        Ok(())
+    }
+
+    fn set_parity(&mut self, _parity: Parity) -> serialport::Result<()> {
+        Ok(())
+    }
+
+    fn set_stop_bits(&mut self, _stop_bits: StopBits) -> serialport::Result<()> {
+        Ok(())
+    }
+
+    fn set_timeout(&mut self, timeout: Duration) -> serialport::Result<()> {
+        self.timeout = timeout;
+        Ok(())
+    }
+
+    fn write_request_to_send(&mut self, _level: bool) -> serialport::Result<()> {
+        self.state().control_line_changed();
+        Ok(())
+    }
+
+    fn write_data_terminal_ready(&mut self, _level: bool) -> serialport::Result<()> {
+        self.state().control_line_changed();
+        Ok(())
+    }
+
+    fn read_clear_to_send(&mut self) -> serialport::Result<bool> {
+        Ok(false)
+    }
+
+    fn read_data_set_ready(&mut self) -> serialport::Result<bool> {
+        Ok(false)
+    }
+
+    fn read_ring_indicator(&mut self) -> serialport::Result<bool> {
+        Ok(false)
+    }
+
+    fn read_carrier_detect(&mut self) -> serialport::Result<bool> {
+        Ok(false)
+    }
+
+    fn bytes_to_read(&self) -> serialport::Result<u32> {
+        Ok(self.state().tx.len() as u32)
+    }
+
+    fn bytes_to_write(&self) -> serialport::Result<u32> {
+        Ok(0)
+    }
+
+    fn clear(&self, buffer_to_clear: ClearBuffer) -> serialport::Result<()> {
+        let mut state = self.state();
+        if matches!(buffer_to_clear, ClearBuffer::Input | ClearBuffer::All) {
+            state.tx.clear();
+        }
+        Ok(())
+    }
+
+    fn try_clone(&self) -> serialport::Result<Box<dyn SerialPort>> {
+        Ok(Box::new(self.clone()))
+    }
+
+    fn set_break(&self) -> serialport::Result<()> {
+        Ok(())
+    }
+
+    fn clear_break(&self) -> serialport::Result<()> {
+        Ok(())
+    }
+}
+
+/// 演示模式下代替真实串口的端口名
+pub const DEMO_PORT: &str = "DEMO";
+
+/// 已注册的演示端口，按端口名打开和列出
+static DEMO_PORTS: Mutex<Vec<SimulatedBootloader>> = Mutex::new(Vec::new());
+
+/// 注册在某个端口名上的模拟 Bootloader：存在期间打开该端口名即连接到模拟器，
+/// 并出现在串口列表中；drop 时注销
+#[derive(Debug)]
+pub struct DemoPort {
+    sim: SimulatedBootloader,
+}
+
+impl DemoPort {
+    pub fn attach(port_name: &str, sim: SimulatedBootloader) -> Self {
+        sim.state().port_base = port_name.to_string();
+        DEMO_PORTS.lock().unwrap().push(sim.clone());
+        Self { sim }
+    }
+
+    pub fn sim(&self) -> &SimulatedBootloader {
+        &self.sim
+    }
+}
+
+impl Drop for DemoPort {
+    fn drop(&mut self) {
+        DEMO_PORTS
+            .lock()
+            .unwrap()
+            .retain(|sim| !Arc::ptr_eq(&sim.state, &self.sim.state));
+    }
+}
+
+/// 打开已注册的演示端口，端口名不是演示端口时返回 None
+pub(crate) fn open_demo(
+    port_name: &str,
+    baud_rate: u32,
+    timeout: Duration,
+) -> Option<SimulatedBootloader> {
+    DEMO_PORTS
+        .lock()
+        .unwrap()
+        .iter()
+        .find_map(|sim| sim.open_as(port_name, baud_rate, timeout))
+}
+
+/// 串口列表中的演示端口
+pub(crate) fn demo_ports() -> Vec<PortInfo> {
+    DEMO_PORTS
+        .lock()
+        .unwrap()
+        .iter()
+        .filter_map(SimulatedBootloader::port_info)
+        .collect()
+}
@@ -0,0 +1,789 @@
+//! USB DFU（DfuSe）后端：经 STM32 系统存储器中的 USB DFU 接口烧录，不需要串口和 Boot 控制线
+//!
+//! 按 AN3156，DFU_DNLOAD 的 wBlockNum 为 0 时负载是 DfuSe 命令（0x21 设置地址指针、0x41 擦除页 /
+//! 整片），为 2 及以上时写入 `地址指针 + (wBlockNum - 2) × wTransferSize`；每次 DNLOAD 后轮询
+//! DFU_GETSTATUS 直到设备离开 dfuDNBUSY。DFU_UPLOAD 按同样的规则读出。内存布局来自 alt setting
+//! 的字符串描述符，如 `@Internal Flash  /0x08000000/04*016Kg,01*064Kg,07*128Kg`。
+
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+use rusb::{Direction, GlobalContext, Recipient, RequestType};
+use tracing::{info, info_span};
+
+use crate::i18n::{Msg, tr};
+use crate::stm32_uart::{
+    self, EraseMode, Error, ErrorCode, FLASH_BASE, FlashOptions, FlashPhase, FlashReport,
+    ImageSource, PhaseTimeline, ProgressGranularity, ProgressPhase, ProgressReporter, ProgressSink,
+    Result, image_to_blocks,
+};
+
+/// ST ROM Bootloader 的 DFU 模式 VID / PID
+pub const ST_VID: u16 = 0x0483;
+pub const ST_DFU_PID: u16 = 0xDF11;
+
+const DFU_DNLOAD: u8 = 1;
+const DFU_UPLOAD: u8 = 2;
+const DFU_GETSTATUS: u8 = 3;
+const DFU_CLRSTATUS: u8 = 4;
+const DFU_ABORT: u8 = 6;
+
+const DFUSE_SET_ADDRESS: u8 = 0x21;
+const DFUSE_ERASE: u8 = 0x41;
+
+const STATE_IDLE: u8 = 2;
+const STATE_DNLOAD_SYNC: u8 = 3;
+const STATE_DNBUSY: u8 = 4;
+const STATE_DNLOAD_IDLE: u8 = 5;
+const STATE_MANIFEST: u8 = 7;
+const STATE_UPLOAD_IDLE: u8 = 9;
+const STATE_ERROR: u8 = 10;
+
+/// 接口描述符中没有 DFU 功能描述符时的传输大小
+const DEFAULT_TRANSFER_SIZE: u16 = 1024;
+
+/// 一次设置地址指针后可用的数据块数（wBlockNum 2..=0xFFFF）
+const BLOCKS_PER_ADDRESS: usize = u16::MAX as usize - 1;
+
+/// USB 和 DfuSe 协议层的错误，经 `stm32_uart::Error::UsbDfu` 上报
+#[derive(thiserror::Error, Debug)]
+pub enum DfuError {
+    #[error("USB error: {0}")]
+    Usb(#[from] rusb::Error),
+    #[error("no USB DFU device matching {0} found")]
+    NotFound(String),
+    #[error("USB DFU protocol error: {0}")]
+    Protocol(String),
+    #[error("DFU device reported status 0x{status:02X} in state {state}")]
+    Status { status: u8, state: u8 },
+}
+
+impl DfuError {
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            DfuError::NotFound(_)
+            | DfuError::Usb(rusb::Error::NoDevice | rusb::Error::NotFound) => {
+                ErrorCode::PortNotFound
+            }
+            DfuError::Usb(rusb::Error::Busy | rusb::Error::Access) => ErrorCode::PortBusy,
+            DfuError::Usb(rusb::Error::Timeout) => ErrorCode::Timeout,
+            DfuError::Usb(_) | DfuError::Protocol(_) | DfuError::Status { .. } => ErrorCode::Usb,
+        }
+    }
+}
+
+/// DFU 接口上的类请求，由 USB 设备或测试中的模拟设备实现
+pub trait DfuDevice {
+    /// 主机到设备的类请求，`value` 为 wValue
+    fn control_out(
+        &mut self,
+        request: u8,
+        value: u16,
+        data: &[u8],
+    ) -> std::result::Result<(), DfuError>;
+
+    /// 设备到主机的类请求，返回实际读到的字节
+    fn control_in(
+        &mut self,
+        request: u8,
+        value: u16,
+        len: usize,
+    ) -> std::result::Result<Vec<u8>, DfuError>;
+}
+
+/// 内存布局中的一组等大的页 / 扇区
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DfuSegment {
+    pub start: u32,
+    pub count: u32,
+    pub size: u32,
+    /// 位 0 可读、位 1 可擦除、位 2 可写
+    pub flags: u8,
+}
+
+impl DfuSegment {
+    pub fn end(&self) -> u64 {
+        self.start as u64 + self.count as u64 * self.size as u64
+    }
+
+    pub fn readable(&self) -> bool {
+        self.flags & 1 != 0
+    }
+
+    pub fn erasable(&self) -> bool {
+        self.flags & 2 != 0
+    }
+
+    pub fn writable(&self) -> bool {
+        self.flags & 4 != 0
+    }
+}
+
+/// alt setting 字符串描述符给出的内存区域
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DfuMemory {
+    pub name: String,
+    pub segments: Vec<DfuSegment>,
+}
+
+impl DfuMemory {
+    /// 解析 `@名称 /起始地址/数量*大小[单位]类型,...`，单位为 K / M（省略或空格为字节），
+    /// 类型 a–g 为可读 / 可擦除 / 可写标志加 1；一个区域可以有多组 `/地址/段列表`
+    pub fn parse(desc: &str) -> std::result::Result<Self, DfuError> {
+        let err = |msg: &str| DfuError::Protocol(format!("{msg} in '{desc}'"));
+        let mut parts = desc
+            .strip_prefix('@')
+            .ok_or_else(|| err("missing '@'"))?
+            .split('/');
+        let name = parts.next().unwrap_or_default().trim().to_string();
+        let mut segments = Vec::new();
+        while let Some(address) = parts.next() {
+            let address = address.trim();
+            let mut start = address
+                .strip_prefix("0x")
+                .or_else(|| address.strip_prefix("0X"))
+                .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+                .ok_or_else(|| err("bad address"))?;
+            let list = parts.next().ok_or_else(|| err("missing segment list"))?;
+            for item in list.split(',').filter(|s| !s.trim().is_empty()) {
+                let (count, rest) = item
+                    .trim()
+                    .split_once('*')
+                    .ok_or_else(|| err("bad segment"))?;
+                let digits = rest
+                    .find(|c: char| !c.is_ascii_digit())
+                    .unwrap_or(rest.len());
+                let (size, suffix) = rest.split_at(digits);
+                let count: u32 = count.trim().parse().map_err(|_| err("bad count"))?;
+                let size: u32 = size.parse().map_err(|_| err("bad size"))?;
+                let kind = suffix.bytes().last().ok_or_else(|| err("missing type"))?;
+                let unit = match suffix.trim_end_matches(kind as char).trim() {
+                    "" | "B" => 1,
+                    "K" => 1024,
+                    "M" => 1024 * 1024,
+                    _ => return Err(err("bad unit")),
+                };
+                if !(b'a'..=b'g').contains(&kind) {
+                    return Err(err("bad type"));
+                }
+                let size = size
+                    .checked_mul(unit)
+                    .filter(|&size| size > 0)
+                    .ok_or_else(|| err("bad size"))?;
+                let segment = DfuSegment {
+                    start,
+                    count,
+                    size,
+                    flags: kind - b'a' + 1,
+                };
+                start = u32::try_from(segment.end()).map_err(|_| err("segment overflows"))?;
+                segments.push(segment);
+            }
+        }
+        if segments.is_empty() {
+            return Err(err("no segments"));
+        }
+        Ok(Self { name, segments })
+    }
+
+    fn segment_at(&self, address: u32) -> Option<&DfuSegment> {
+        self.segments
+            .iter()
+            .find(|s| s.start <= address && (address as u64) < s.end())
+    }
+
+    /// 覆盖 `address..address + len` 的页 / 扇区起始地址；范围必须完全落在可擦写的段内
+    pub fn pages_covering(&self, address: u32, len: usize) -> Result<Vec<u32>> {
+        let end = address as u64 + len as u64;
+        let mut pages = Vec::new();
+        let mut cursor = address as u64;
+        while cursor < end {
+            let segment = self
+                .segment_at(cursor as u32)
+                .filter(|s| s.erasable() && s.writable())
+                .ok_or(Error::AddressOutOfRange {
+                    address: cursor as u32,
+                    len: (end - cursor) as usize,
+                    access: "writable",
+                    chip: "the DFU device",
+                })?;
+            let page = cursor - (cursor - segment.start as u64) % segment.size as u64;
+            pages.push(page as u32);
+            cursor = page + segment.size as u64;
+        }
+        Ok(pages)
+    }
+}
+
+/// DFU_GETSTATUS 的应答
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DfuStatus {
+    pub status: u8,
+    pub poll_timeout: Duration,
+    pub state: u8,
+}
+
+/// 要打开的 DFU 设备
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DfuSelector {
+    pub vid: u16,
+    pub pid: u16,
+    /// 多块板同时连接时按 USB 序列号选择
+    pub serial: Option<String>,
+    /// alt setting，ST ROM Bootloader 中 0 为内部 Flash
+    pub alt: u8,
+}
+
+impl Default for DfuSelector {
+    fn default() -> Self {
+        Self {
+            vid: ST_VID,
+            pid: ST_DFU_PID,
+            serial: None,
+            alt: 0,
+        }
+    }
+}
+
+/// 枚举到的 DFU 设备
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct DfuTarget {
+    pub vid: u16,
+    pub pid: u16,
+    pub serial: Option<String>,
+    pub bus: u8,
+    pub address: u8,
+    /// (alt setting, 内存区域名称)
+    pub alts: Vec<(u8, String)>,
+}
+
+impl DfuTarget {
+    /// 如 "0483:DF11 SN 3359..."，用于日志和界面
+    pub fn label(&self) -> String {
+        match &self.serial {
+            Some(serial) => format!("{:04X}:{:04X} SN {serial}", self.vid, self.pid),
+            None => format!(
+                "{:04X}:{:04X} bus {} addr {}",
+                self.vid, self.pid, self.bus, self.address
+            ),
+        }
+    }
+}
+
+fn is_dfu_interface(desc: &rusb::InterfaceDescriptor) -> bool {
+    desc.class_code() == 0xFE && desc.sub_class_code() == 0x01 && desc.protocol_code() == 0x02
+}
+
+/// DFU 功能描述符（类型 0x21）中的 wTransferSize
+fn transfer_size(extra: &[u8]) -> Option<u16> {
+    let mut rest = extra;
+    while rest.len() >= 2 && rest[0] as usize <= rest.len() && rest[0] > 0 {
+        let (desc, next) = rest.split_at(rest[0] as usize);
+        if desc[1] == 0x21 && desc.len() >= 7 {
+            return Some(u16::from_le_bytes([desc[5], desc[6]]));
+        }
+        rest = next;
+    }
+    None
+}
+
+/// 列出处于 DFU 模式的设备（接口类 0xFE / 子类 0x01 / 协议 0x02）
+pub fn list_devices() -> std::result::Result<Vec<DfuTarget>, DfuError> {
+    let mut targets = Vec::new();
+    for device in rusb::devices()?.iter() {
+        let Ok(desc) = device.device_descriptor() else {
+            continue;
+        };
+        let Ok(config) = device.active_config_descriptor() else {
+            continue;
+        };
+        let dfu: Vec<_> = config
+            .interfaces()
+            .flat_map(|i| i.descriptors())
+            .filter(is_dfu_interface)
+            .map(|d| (d.setting_number(), d.description_string_index()))
+            .collect();
+        if dfu.is_empty() {
+            continue;
+        }
+        // 无权限打开时仍列出设备，只是没有序列号和区域名称
+        let handle = device.open().ok();
+        let read = |index: Option<u8>| {
+            handle
+                .as_ref()
+                .zip(index)
+                .and_then(|(h, i)| h.read_string_descriptor_ascii(i).ok())
+        };
+        targets.push(DfuTarget {
+            vid: desc.vendor_id(),
+            pid: desc.product_id(),
+            serial: read(desc.serial_number_string_index()),
+            bus: device.bus_number(),
+            address: device.address(),
+            alts: dfu
+                .into_iter()
+                .map(|(alt, index)| (alt, read(index).unwrap_or_default()))
+                .collect(),
+        });
+    }
+    Ok(targets)
+}
+
+/// 经 libusb 访问的 DFU 接口
+pub struct UsbDfu {
+    handle: rusb::DeviceHandle<GlobalContext>,
+    interface: u8,
+    timeout: Duration,
+}
+
+impl UsbDfu {
+    /// 打开匹配 `selector` 的设备并选中其 alt setting，返回设备、内存布局、传输大小和设备名
+    pub fn open(
+        selector: &DfuSelector,
+        timeout: Duration,
+    ) -> std::result::Result<(Self, DfuMemory, u16, String), DfuError> {
+        let not_found = || {
+            let mut wanted = format!("{:04X}:{:04X}", selector.vid, selector.pid);
+            if let Some(serial) = &selector.serial {
+                wanted.push_str(&format!(" SN {serial}"));
+            }
+            DfuError::NotFound(wanted)
+        };
+        for device in rusb::devices()?.iter() {
+            let Ok(desc) = device.device_descriptor() else {
+                continue;
+            };
+            if desc.vendor_id() != selector.vid || desc.product_id() != selector.pid {
+                continue;
+            }
+            let handle = device.open()?;
+            let serial = handle.read_serial_number_string_ascii(&desc).ok();
+            if selector.serial.is_some() && serial != selector.serial {
+                continue;
+            }
+            let config = device.active_config_descriptor()?;
+            let Some(iface) = config
+                .interfaces()
+                .flat_map(|i| i.descriptors())
+                .find(|d| is_dfu_interface(d) && d.setting_number() == selector.alt)
+            else {
+                continue;
+            };
+            let layout = iface
+                .description_string_index()
+                .map(|i| handle.read_string_descriptor_ascii(i))
+                .transpose()?
+                .unwrap_or_default();
+            let memory = DfuMemory::parse(&layout)?;
+            let xfer = transfer_size(iface.extra()).unwrap_or(DEFAULT_TRANSFER_SIZE);
+            let interface = iface.interface_number();
+            // 只有 Linux 支持自动卸载内核驱动，其他平台返回不支持
+            let _ = handle.set_auto_detach_kernel_driver(true);
+            handle.claim_interface(interface)?;
+            handle.set_alternate_setting(interface, selector.alt)?;
+            let name = DfuTarget {
+                vid: desc.vendor_id(),
+                pid: desc.product_id(),
+                serial,
+                bus: device.bus_number(),
+                address: device.address(),
+                alts: Vec::new(),
+            }
+            .label();
+            let usb = Self {
+                handle,
+                interface,
+                timeout,
+            };
+            return Ok((usb, memory, xfer, name));
+        }
+        Err(not_found())
+    }
+}
+
+impl DfuDevice for UsbDfu {
+    fn control_out(
+        &mut self,
+        request: u8,
+        value: u16,
+        data: &[u8],
+    ) -> std::result::Result<(), DfuError> {
+        let kind = rusb::request_type(Direction::Out, RequestType::Class, Recipient::Interface);
+        self.handle.write_control(
+            kind,
+            request,
+            value,
+            self.interface as u16,
+            data,
+            self.timeout,
+        )?;
+        Ok(())
+    }
+
+    fn control_in(
+        &mut self,
+        request: u8,
+        value: u16,
+        len: usize,
+    ) -> std::result::Result<Vec<u8>, DfuError> {
+        let kind = rusb::request_type(Direction::In, RequestType::Class, Recipient::Interface);
+        let mut buf = vec![0u8; len];
+        let n = self.handle.read_control(
+            kind,
+            request,
+            value,
+            self.interface as u16,
+            &mut buf,
+            self.timeout,
+        )?;
+        buf.truncate(n);
+        Ok(buf)
+    }
+}
+
+/// 已选中内存区域的 DfuSe 会话
+pub struct DfuSession {
+    device: Box<dyn DfuDevice>,
+    memory: DfuMemory,
+    transfer_size: usize,
+    name: String,
+    options: FlashOptions,
+}
+
+impl DfuSession {
+    /// 打开 USB DFU 设备
+    pub fn open(selector: &DfuSelector, options: &FlashOptions) -> Result<Self> {
+        let (usb, memory, xfer, name) = UsbDfu::open(selector, options.timeouts.ack())?;
+        Self::from_device(Box::new(usb), memory, xfer, &name, options)
+    }
+
+    /// 在任意 `DfuDevice` 上建立会话（模拟设备、测试），先把设备带回 dfuIDLE
+    pub fn from_device(
+        device: Box<dyn DfuDevice>,
+        memory: DfuMemory,
+        transfer_size: u16,
+        name: &str,
+        options: &FlashOptions,
+    ) -> Result<Self> {
+        let mut session = Self {
+            device,
+            memory,
+            transfer_size: transfer_size.max(1) as usize,
+            name: name.to_string(),
+            options: options.clone(),
+        };
+        session.ensure_idle()?;
+        info!(
+            "{}",
+            tr(
+                Msg::DfuOpened,
+                &[&session.name, &session.memory.name, &session.transfer_size]
+            )
+        );
+        Ok(session)
+    }
+
+    pub fn memory(&self) -> &DfuMemory {
+        &self.memory
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn get_status(&mut self) -> Result<DfuStatus> {
+        let bytes = self.device.control_in(DFU_GETSTATUS, 0, 6)?;
+        if bytes.len() < 6 {
+            return Err(DfuError::Protocol(format!(
+                "short GETSTATUS reply ({} bytes)",
+                bytes.len()
+            ))
+            .into());
+        }
+        Ok(DfuStatus {
+            status: bytes[0],
+            poll_timeout: Duration::from_millis(u32::from_le_bytes([
+                bytes[1], bytes[2], bytes[3], 0,
+            ]) as u64),
+            state: bytes[4],
+        })
+    }
+
+    /// 清除错误状态并中止未完成的传输，使设备处于 dfuIDLE
+    fn ensure_idle(&mut self) -> Result<()> {
+        let status = self.get_status()?;
+        match status.state {
+            STATE_IDLE => return Ok(()),
+            STATE_ERROR => self.device.control_out(DFU_CLRSTATUS, 0, &[])?,
+            _ => self.device.control_out(DFU_ABORT, 0, &[])?,
+        }
+        let status = self.get_status()?;
+        if status.state != STATE_IDLE {
+            return Err(DfuError::Status {
+                status: status.status,
+                state: status.state,
+            }
+            .into());
+        }
+        Ok(())
+    }
+
+    /// 发送一次 DNLOAD，轮询 GETSTATUS 直到操作完成
+    fn download(&mut self, block: u16, data: &[u8], timeout: Duration) -> Result<()> {
+        self.device.control_out(DFU_DNLOAD, block, data)?;
+        let started = Instant::now();
+        loop {
+            let status = self.get_status()?;
+            if status.status != 0 {
+                let _ = self.device.control_out(DFU_CLRSTATUS, 0, &[]);
+                return Err(DfuError::Status {
+                    status: status.status,
+                    state: status.state,
+                }
+                .into());
+            }
+            match status.state {
+                STATE_DNLOAD_IDLE | STATE_IDLE => return Ok(()),
+                STATE_DNBUSY | STATE_DNLOAD_SYNC if started.elapsed() < timeout => {
+                    std::thread::sleep(status.poll_timeout.max(Duration::from_millis(1)));
+                }
+                STATE_DNBUSY | STATE_DNLOAD_SYNC => return Err(Error::Timeout),
+                state => {
+                    return Err(DfuError::Status {
+                        status: status.status,
+                        state,
+                    }
+                    .into());
+                }
+            }
+        }
+    }
+
+    fn command(&mut self, command: u8, address: Option<u32>, timeout: Duration) -> Result<()> {
+        let mut frame = vec![command];
+        if let Some(address) = address {
+            frame.extend_from_slice(&address.to_le_bytes());
+        }
+        self.download(0, &frame, timeout)
+    }
+
+    /// DfuSe 设置地址指针，之后的数据块相对它写入 / 读出
+    pub fn set_address(&mut self, address: u32) -> Result<()> {
+        self.command(
+            DFUSE_SET_ADDRESS,
+            Some(address),
+            self.options.timeouts.ack(),
+        )
+    }
+
+    pub fn mass_erase(&mut self, sink: &dyn ProgressSink) -> Result<()> {
+        let _span = info_span!("erase", mode = "mass").entered();
+        info!("{}", tr(Msg::DfuMassErasing, &[]));
+        self.command(DFUSE_ERASE, None, self.options.timeouts.erase())?;
+        sink.progress(&stm32_uart::ProgressEvent::Done {
+            phase: ProgressPhase::Erase,
+            total: 1,
+        });
+        Ok(())
+    }
+
+    /// 擦除镜像覆盖的页 / 扇区，页布局取自设备的内存描述符
+    pub fn erase_for_image(
+        &mut self,
+        image: &BTreeMap<u32, u8>,
+        sink: &dyn ProgressSink,
+    ) -> Result<()> {
+        let mut pages = Vec::new();
+        for (base, data) in image_to_blocks(image) {
+            pages.extend(self.memory.pages_covering(base, data.len())?);
+        }
+        pages.sort_unstable();
+        pages.dedup();
+        let _span = info_span!("erase", pages = pages.len()).entered();
+        info!("{}", tr(Msg::DfuErasing, &[&pages.len()]));
+        // 一页可能要擦除数秒，每页都上报，只保留每秒次数的限制
+        let granularity = ProgressGranularity {
+            every_bytes: 0,
+            ..self.options.progress
+        };
+        let mut progress =
+            ProgressReporter::new(ProgressPhase::ErasePages, pages.len(), granularity);
+        for (i, &page) in pages.iter().enumerate() {
+            self.options.cancel.check()?;
+            self.command(DFUSE_ERASE, Some(page), self.options.timeouts.erase())?;
+            progress.update(page, i + 1, sink);
+        }
+        Ok(())
+    }
+
+    /// 按传输大小分块写入镜像，返回写入的字节数；每段连续数据只设置一次地址指针，
+    /// 之后的块按 wBlockNum 2、3、… 依次写入
+    pub fn write_image(
+        &mut self,
+        image: &BTreeMap<u32, u8>,
+        sink: &dyn ProgressSink,
+    ) -> Result<u64> {
+        info!("{}", tr(Msg::Writing, &[]));
+        let mut progress =
+            ProgressReporter::new(ProgressPhase::Write, image.len(), self.options.progress);
+        let mut written = 0usize;
+        for (base, data) in image_to_blocks(image) {
+            self.memory.pages_covering(base, data.len())?;
+            let _span = info_span!(
+                "write_block",
+                address = %format_args!("0x{base:08X}"),
+                len = data.len()
+            )
+            .entered();
+            for (i, chunk) in data.chunks(self.transfer_size).enumerate() {
+                self.options.cancel.check()?;
+                let addr = base + (i * self.transfer_size) as u32;
+                let block = i % BLOCKS_PER_ADDRESS;
+                if block == 0 {
+                    self.set_address(addr)?;
+                }
+                self.download(block as u16 + 2, chunk, self.options.timeouts.ack())?;
+                written += chunk.len();
+                progress.update(addr, written, sink);
+            }
+        }
+        Ok(written as u64)
+    }
+
+    /// 读出 `address` 起 `len` 字节
+    pub fn read(&mut self, address: u32, len: usize) -> Result<Vec<u8>> {
+        let mut out = Vec::with_capacity(len);
+        while out.len() < len {
+            self.options.cancel.check()?;
+            let addr = address + out.len() as u32;
+            let n = (len - out.len()).min(self.transfer_size);
+            self.set_address(addr)?;
+            // UPLOAD 只能从 dfuIDLE 开始
+            self.device.control_out(DFU_ABORT, 0, &[])?;
+            let chunk = self.device.control_in(DFU_UPLOAD, 2, n)?;
+            if chunk.is_empty() {
+                return Err(
+                    DfuError::Protocol(format!("empty UPLOAD reply at 0x{addr:08X}")).into(),
+                );
+            }
+            out.extend_from_slice(&chunk[..chunk.len().min(n)]);
+        }
+        let status = self.get_status()?;
+        if status.state == STATE_UPLOAD_IDLE {
+            self.device.control_out(DFU_ABORT, 0, &[])?;
+        }
+        Ok(out)
+    }
+
+    /// 回读全部数据与镜像比较
+    pub fn verify(&mut self, image: &BTreeMap<u32, u8>, sink: &dyn ProgressSink) -> Result<()> {
+        info!("{}", tr(Msg::Verifying, &[]));
+        let mut progress =
+            ProgressReporter::new(ProgressPhase::Verify, image.len(), self.options.progress);
+        let mut checked = 0usize;
+        for (base, data) in image_to_blocks(image) {
+            let read = self.read(base, data.len())?;
+            if let Some(pos) = data.iter().zip(&read).position(|(a, b)| a != b) {
+                return Err(Error::VerifyMismatch {
+                    address: base + pos as u32,
+                    expected: data[pos],
+                    actual: read[pos],
+                });
+            }
+            checked += data.len();
+            progress.update(base, checked, sink);
+        }
+        info!("{}", tr(Msg::Verified, &[&checked]));
+        Ok(())
+    }
+
+    /// 退出 DFU：地址指针指向 `address` 后发送空的 DNLOAD，设备在下一次 GETSTATUS 时跳转运行
+    pub fn leave(mut self, address: u32) -> Result<()> {
+        self.set_address(address)?;
+        self.device.control_out(DFU_DNLOAD, 2, &[])?;
+        // 设备跳转后从总线上消失，GETSTATUS 可能得不到应答
+        match self.get_status() {
+            Ok(status) if status.status != 0 && status.state != STATE_MANIFEST => {
+                Err(DfuError::Status {
+                    status: status.status,
+                    state: status.state,
+                }
+                .into())
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// 经 USB DFU 擦除、写入并校验固件，最后按选项启动用户程序
+pub fn flash_dfu(
+    selector: &DfuSelector,
+    sources: &[ImageSource],
+    options: &FlashOptions,
+    sink: &dyn ProgressSink,
+) -> Result<FlashReport> {
+    let image = stm32_uart::merge_sources(sources, options)?;
+    let entry = stm32_uart::sources_entry_point(sources)?;
+    let options = &stm32_uart::apply_entry_point(options, entry, &image);
+    let go_address = stm32_uart::validate_go_address(options.go_address.unwrap_or(FLASH_BASE))?;
+    stm32_uart::report_image_checks(&image, options);
+
+    let mut timeline = PhaseTimeline::default();
+    let started = Instant::now();
+    let mut session = {
+        let _span = info_span!("connect", transport = "dfu").entered();
+        DfuSession::open(selector, options)?
+    };
+    timeline.add(FlashPhase::Sync, started.elapsed());
+    let bytes_written = flash_session(&mut session, &image, options, sink, &mut timeline)?;
+
+    if options.reset_after && !options.stay_in_bootloader {
+        let started = Instant::now();
+        info!(
+            "{}",
+            tr(Msg::StartingApp, &[&format!("0x{go_address:08X}")])
+        );
+        session.leave(go_address)?;
+        timeline.add(FlashPhase::Reset, started.elapsed());
+        info!("{}", tr(Msg::AppStarted, &[]));
+    }
+
+    // DFU 不经过串口，没有 ACK 往返统计
+    Ok(FlashReport {
+        bytes_written,
+        timeline,
+        link: None,
+    })
+}
+
+/// 在已打开的会话上按 `erase_mode` 擦除，写入并按校验策略回读
+pub fn flash_session(
+    session: &mut DfuSession,
+    image: &BTreeMap<u32, u8>,
+    options: &FlashOptions,
+    sink: &dyn ProgressSink,
+    timeline: &mut PhaseTimeline,
+) -> Result<u64> {
+    let started = Instant::now();
+    match options.erase_mode {
+        EraseMode::Mass => session.mass_erase(sink)?,
+        EraseMode::Pages => session.erase_for_image(image, sink)?,
+        EraseMode::Bank => {
+            return Err(Error::Bank(
+                "bank erase is not available over USB DFU".to_string(),
+            ));
+        }
+    }
+    timeline.add(FlashPhase::Erase, started.elapsed());
+
+    let started = Instant::now();
+    let written = session.write_image(image, sink)?;
+    timeline.add(FlashPhase::Write, started.elapsed());
+    // 与 stub 相同，任何校验策略都全量回读
+    if options.verify.enabled() {
+        let started = Instant::now();
+        session.verify(image, sink)?;
+        timeline.add(FlashPhase::Verify, started.elapsed());
+    }
+    Ok(written)
+}
@@ -1,13 +1,37 @@
 use std::{
+    borrow::Cow,
     collections::BTreeMap,
-    path::Path,
+    fmt,
+    path::{Path, PathBuf},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
     time::{Duration, Instant},
 };
 
 use ihex::Record;
-use serialport::{DataBits, FlowControl, Parity, SerialPort, SerialPortType, StopBits};
+use serialport::{DataBits, FlowControl, Parity, SerialPortType, StopBits};
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+use tracing::{info, info_span, warn};
+
+use crate::chipdb::{self, MemoryMap};
+use crate::devicedb::{self, ConnectionDb, ConnectionProfile, Cycle, CycleTracking, DeviceDb};
+use crate::diag;
+use crate::i18n::{Msg, tr};
+use crate::inject;
+use crate::linkstats::{self, LinkStats};
+use crate::optbytes::{ObFamily, OptionBytes, RdpLevel};
+use crate::power::PowerCycle;
+use crate::rxbuf::BufferedPort;
+use crate::sim;
+use crate::stub::{StubClient, StubConfig};
+use crate::transport::Transport;
+
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum, serde::Serialize, serde::Deserialize,
+)]
+#[serde(rename_all = "kebab-case")]
 pub enum BootMode {
     /// 不操作 DTR/RTS
     None,
@@ -33,13 +57,51 @@ pub enum BootMode {
     RtsHighOnly,
 }
 
+impl BootMode {
+    /// 复位线和 BOOT0 线（仅复位的模式没有 BOOT0 线）
+    pub(crate) fn wiring(self) -> Option<(ControlLine, Option<ControlLine>)> {
+        use ControlLine::{Dtr, Rts};
+
+        match self {
+            BootMode::None => None,
+            BootMode::DtrLowRtsHigh | BootMode::DtrHighRtsHigh | BootMode::DtrHighRtsLow => {
+                Some((Dtr, Some(Rts)))
+            }
+            BootMode::DtrHighOnly => Some((Dtr, None)),
+            BootMode::RtsLowDtrHigh | BootMode::RtsLowDtrLow => Some((Rts, Some(Dtr))),
+            BootMode::RtsLowOnly | BootMode::RtsHighOnly => Some((Rts, None)),
+        }
+    }
+}
+
+/// 串口的控制线
 #[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ControlLine {
+    Dtr,
+    Rts,
+}
+
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum, serde::Serialize, serde::Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
 pub enum Level {
     Low,
     High,
 }
 
-#[derive(Debug, Clone, Copy)]
+impl std::ops::Not for Level {
+    type Output = Level;
+
+    fn not(self) -> Level {
+        match self {
+            Level::Low => Level::High,
+            Level::High => Level::Low,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
 pub struct BootLineConfig {
     pub boot_level: Level,
     pub reset_assert_level: Level,
@@ -55,14 +117,290 @@ impl Default for BootLineConfig {
     }
 }
 
+impl BootLineConfig {
+    /// Boot 模式名称所对应的电平，例如 `DtrHighRtsLow` 为 DTR 高电平复位、RTS 低电平进 Boot
+    pub fn for_mode(mode: BootMode) -> Self {
+        let (reset_assert_level, boot_level) = match mode {
+            BootMode::DtrLowRtsHigh | BootMode::RtsLowDtrHigh => (Level::Low, Level::High),
+            BootMode::DtrHighRtsHigh => (Level::High, Level::High),
+            BootMode::DtrHighRtsLow => (Level::High, Level::Low),
+            BootMode::RtsLowDtrLow => (Level::Low, Level::Low),
+            BootMode::DtrHighOnly | BootMode::RtsHighOnly => (Level::High, Level::High),
+            BootMode::RtsLowOnly => (Level::Low, Level::High),
+            BootMode::None => return Self::default(),
+        };
+
+        Self {
+            boot_level,
+            reset_assert_level,
+        }
+    }
+}
+
+/// 进入 Bootloader 时序
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct BootTiming {
+    /// 释放复位后、发出复位脉冲前的稳定时间
+    pub settle: Duration,
+    /// 复位脉冲宽度
+    pub reset_pulse: Duration,
+    /// 复位释放后等待 Bootloader 启动的时间
+    pub boot_delay: Duration,
+}
+
+impl Default for BootTiming {
+    fn default() -> Self {
+        Self {
+            settle: Duration::from_millis(100),
+            reset_pulse: Duration::from_millis(100),
+            boot_delay: Duration::from_millis(200),
+        }
+    }
+}
+
+/// 进度上报粒度，0 表示不限制；最后一次进度总会上报
+#[derive(Debug, Clone, Copy)]
+pub struct ProgressGranularity {
+    /// 距上次上报至少累计的字节数
+    pub every_bytes: usize,
+    /// 每秒最多上报的次数
+    pub max_per_sec: u32,
+}
+
+impl Default for ProgressGranularity {
+    fn default() -> Self {
+        Self {
+            every_bytes: 4096,
+            max_per_sec: 20,
+        }
+    }
+}
+
+/// 进度所属的阶段
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProgressPhase {
+    Erase,
+    /// 逐页擦除（USB DFU），进度按页计
+    ErasePages,
+    Read,
+    Write,
+    Verify,
+    LinkTest,
+}
+
+impl ProgressPhase {
+    /// 稳定的阶段标识，用于 JSON 事件
+    pub fn id(self) -> &'static str {
+        match self {
+            ProgressPhase::Erase => "erase",
+            ProgressPhase::ErasePages => "erase_pages",
+            ProgressPhase::Read => "read",
+            ProgressPhase::Write => "write",
+            ProgressPhase::Verify => "verify",
+            ProgressPhase::LinkTest => "link_test",
+        }
+    }
+
+    /// 界面上显示的阶段名
+    pub fn label(self) -> &'static str {
+        match self {
+            ProgressPhase::Erase | ProgressPhase::ErasePages => "擦除中",
+            ProgressPhase::Read => "读取中",
+            ProgressPhase::Write => "写入中",
+            ProgressPhase::Verify => "校验中",
+            ProgressPhase::LinkTest => "链路测试",
+        }
+    }
+
+    /// 进度的单位：擦除为已用 / 预计的毫秒，逐页擦除为页数，链路测试为轮数，其余为字节
+    pub fn unit(self) -> &'static str {
+        match self {
+            ProgressPhase::Erase => "ms",
+            ProgressPhase::ErasePages => "pages",
+            ProgressPhase::LinkTest => "rounds",
+            _ => "bytes",
+        }
+    }
+}
+
+/// 结构化的进度事件，经 `ProgressSink` 交给 CLI 进度条、JSON 事件和 GUI
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProgressEvent {
+    /// 开始擦除，`expected_ms` 为按芯片估算的耗时
+    EraseStarted {
+        expected_ms: u64,
+    },
+    /// 等待擦除应答期间的心跳，超出预计时间时停在 `expected_ms - 1`
+    EraseTick {
+        elapsed_ms: u64,
+        expected_ms: u64,
+    },
+    /// 逐页擦除时擦完一页，`addr` 为该页的起始地址
+    ErasePage {
+        addr: u32,
+        done: u64,
+        total: u64,
+    },
+    /// `addr` 为刚处理完的块的起始地址
+    ReadChunk {
+        addr: u32,
+        done: u64,
+        total: u64,
+    },
+    WriteChunk {
+        addr: u32,
+        done: u64,
+        total: u64,
+    },
+    VerifyChunk {
+        addr: u32,
+        done: u64,
+        total: u64,
+    },
+    LinkTestRound {
+        done: u64,
+        total: u64,
+    },
+    /// 阶段完成，代替最后一次进度
+    Done {
+        phase: ProgressPhase,
+        total: u64,
+    },
+}
+
+impl ProgressEvent {
+    pub fn phase(&self) -> ProgressPhase {
+        match *self {
+            ProgressEvent::EraseStarted { .. } | ProgressEvent::EraseTick { .. } => {
+                ProgressPhase::Erase
+            }
+            ProgressEvent::ErasePage { .. } => ProgressPhase::ErasePages,
+            ProgressEvent::ReadChunk { .. } => ProgressPhase::Read,
+            ProgressEvent::WriteChunk { .. } => ProgressPhase::Write,
+            ProgressEvent::VerifyChunk { .. } => ProgressPhase::Verify,
+            ProgressEvent::LinkTestRound { .. } => ProgressPhase::LinkTest,
+            ProgressEvent::Done { phase, .. } => phase,
+        }
+    }
+
+    /// (已完成, 总量)，单位见 `ProgressPhase::unit`
+    pub fn counts(&self) -> (u64, u64) {
+        match *self {
+            ProgressEvent::EraseStarted { expected_ms } => (0, expected_ms),
+            ProgressEvent::EraseTick {
+                elapsed_ms,
+                expected_ms,
+            } => (elapsed_ms, expected_ms),
+            ProgressEvent::ErasePage { done, total, .. }
+            | ProgressEvent::ReadChunk { done, total, .. }
+            | ProgressEvent::WriteChunk { done, total, .. }
+            | ProgressEvent::VerifyChunk { done, total, .. }
+            | ProgressEvent::LinkTestRound { done, total } => (done, total),
+            ProgressEvent::Done { total, .. } => (total, total),
+        }
+    }
+
+    /// 完成百分比（0–100）
+    pub fn percent(&self) -> u8 {
+        match self.counts() {
+            (_, 0) => 0,
+            (done, total) => (done.min(total) * 100 / total) as u8,
+        }
+    }
+}
+
+/// 进度事件的接收方；不关心进度的日志器用默认实现忽略即可
+pub trait ProgressSink {
+    fn progress(&self, event: &ProgressEvent) {
+        let _ = event;
+    }
+
+    /// 擦除前的写入分析；默认作为一行摘要写入日志
+    fn plan(&self, plan: &WritePlan) {
+        info!("{}", plan.summary());
+    }
+}
+
+/// 按 `ProgressGranularity` 节流的进度上报，到达总量时发出 `ProgressEvent::Done`
+pub(crate) struct ProgressReporter {
+    phase: ProgressPhase,
+    total: usize,
+    granularity: ProgressGranularity,
+    last_done: usize,
+    last_at: Option<Instant>,
+}
+
+impl ProgressReporter {
+    pub(crate) fn new(
+        phase: ProgressPhase,
+        total: usize,
+        granularity: ProgressGranularity,
+    ) -> Self {
+        Self {
+            phase,
+            total,
+            granularity,
+            last_done: 0,
+            last_at: None,
+        }
+    }
+
+    pub(crate) fn update(&mut self, addr: u32, done: usize, sink: &dyn ProgressSink) {
+        let total = self.total as u64;
+        if done >= self.total {
+            sink.progress(&ProgressEvent::Done {
+                phase: self.phase,
+                total,
+            });
+            return;
+        }
+        if done - self.last_done < self.granularity.every_bytes {
+            return;
+        }
+        if let (Some(last_at), max) = (self.last_at, self.granularity.max_per_sec)
+            && max > 0
+            && last_at.elapsed() < Duration::from_secs(1) / max
+        {
+            return;
+        }
+
+        self.last_done = done;
+        self.last_at = Some(Instant::now());
+        let done = done as u64;
+        sink.progress(&match self.phase {
+            ProgressPhase::Read => ProgressEvent::ReadChunk { addr, done, total },
+            ProgressPhase::Write => ProgressEvent::WriteChunk { addr, done, total },
+            ProgressPhase::Verify => ProgressEvent::VerifyChunk { addr, done, total },
+            ProgressPhase::LinkTest => ProgressEvent::LinkTestRound { done, total },
+            ProgressPhase::Erase => ProgressEvent::EraseTick {
+                elapsed_ms: done,
+                expected_ms: total,
+            },
+            ProgressPhase::ErasePages => ProgressEvent::ErasePage { addr, done, total },
+        });
+    }
+}
+
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
     #[error("serial port error: {0}")]
     Serial(#[from] serialport::Error),
     #[error("io error: {0}")]
     Io(#[from] std::io::Error),
+    #[error("json error: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("zip error: {0}")]
+    Zip(#[from] zip::result::ZipError),
     #[error("hex parse error: {0}")]
     Hex(String),
+    #[error("ELF parse error: {0}")]
+    Elf(String),
+    #[error("S-record parse error: {0}")]
+    Srec(String),
+    #[error("DfuSe parse error: {0}")]
+    Dfu(String),
+    #[error(transparent)]
+    UsbDfu(#[from] crate::stm32_dfu::DfuError),
     #[error("bootloader: unexpected response byte 0x{0:02X}")]
     UnexpectedResponse(u8),
     #[error("bootloader: timeout waiting for response")]
@@ -71,25 +409,313 @@ pub enum Error {
     Nack,
     #[error("bootloader: no supported erase command")]
     NoEraseSupport,
+    #[error("legacy Erase (0x43) only addresses pages 0 to 255, image needs page {0}")]
+    LegacyErasePage(u16),
     #[error("port '{0}' not found or cannot be opened")]
     PortNotFound(String),
+    #[error("port '{0}' is in use by another program")]
+    PortBusy(String),
     #[error("hex file '{0}' not found")]
     HexFileNotFound(String),
     #[error("hex file is empty or contains no valid data")]
     HexFileEmpty,
+    #[error(
+        "image is linked at 0x{0:08X} (flash alias at 0x00000000); enable rebase to write it at 0x08000000"
+    )]
+    ImageInAliasRegion(u32),
+    #[error(
+        "offset {offset} moves image 0x{first:08X}..=0x{last:08X} outside the 32-bit address space"
+    )]
+    ImageOffsetOutOfRange { offset: i64, first: u32, last: u32 },
+    #[error("timed out waiting for port '{0}' to appear")]
+    WaitTimeout(String),
+    #[error(
+        "device is read-protected (RDP active); run `probe-flasher unlock` to remove it (erases flash)"
+    )]
+    ReadProtected,
+    #[error("bootloader does not support command 0x{0:02X}")]
+    CommandNotSupported(u8),
+    #[error(
+        "CRC mismatch in 0x{address:08X} (+{len} bytes): expected 0x{expected:08X}, device returned 0x{actual:08X}"
+    )]
+    ChecksumMismatch {
+        address: u32,
+        len: usize,
+        expected: u32,
+        actual: u32,
+    },
+    #[error("write protect takes 1 to 256 sectors, got {0}")]
+    WrpSectorCount(usize),
+    #[error("readout protection did not take effect")]
+    ProtectionNotApplied,
+    #[error("go address 0x{0:08X} is not a word-aligned address inside main flash")]
+    InvalidGoAddress(u32),
+    #[error("image file '{0}' not found")]
+    ImageFileNotFound(String),
+    #[error("image file is empty or contains no data")]
+    ImageEmpty,
+    #[error("images overlap at 0x{0:08X}")]
+    ImageOverlap(u32),
+    #[error("{len} bytes at 0x{base:08X} run past the end of the 32-bit address space")]
+    AddressOverflow { base: u32, len: usize },
+    #[error("verify failed at 0x{address:08X}: expected 0x{expected:02X}, read 0x{actual:02X}")]
+    VerifyMismatch {
+        address: u32,
+        expected: u8,
+        actual: u8,
+    },
+    #[error("could not drive the boot mode lines: {0}")]
+    BootEntry(String),
+    #[error(
+        "boot sequence ran but the bootloader did not answer {attempts} sync attempt(s): {last}"
+    )]
+    NoSync { attempts: u32, last: Box<Error> },
+    #[error("power hook failed: {0}")]
+    PowerHook(String),
+    #[error("boot mode 'none' has no reset line to drive")]
+    NoResetLine,
+    #[error("option byte layout for product ID 0x{0:03X} is unknown")]
+    UnknownOptionBytes(u16),
+    #[error("page layout for product ID 0x{0:03X} is unknown; use mass erase")]
+    UnknownEraseLayout(u16),
+    #[error(transparent)]
+    Config(#[from] crate::config::ConfigError),
+    #[error("configuration file format {0} is newer than this version supports")]
+    UnsupportedConfig(u32),
+    #[error("option byte file does not match this device ({0})")]
+    OptionBytesMismatch(String),
+    #[error(
+        "image block 0x{start:08X}..0x{end:08X} runs past the end of flash at 0x{flash_end:08X} ({size_kb} KB on {chip})"
+    )]
+    ImageExceedsFlash {
+        start: u32,
+        end: u64,
+        flash_end: u64,
+        size_kb: u64,
+        chip: &'static str,
+    },
+    #[error("0x{address:08X} (+{len} bytes) is outside the {access} memory of {chip}")]
+    AddressOutOfRange {
+        address: u32,
+        len: usize,
+        access: &'static str,
+        chip: &'static str,
+    },
+    #[error("RAM stub error: {0}")]
+    Stub(String),
+    #[error("provisioning data error: {0}")]
+    Provision(String),
+    #[error("port '{0}' is already open in the monitor")]
+    MonitorBusy(String),
+    #[error("port '{0}' is not open in the monitor")]
+    NotMonitored(String),
+    #[error("invalid option byte setting: {0}")]
+    OptionByte(String),
+    #[error("bank selection failed: {0}")]
+    Bank(String),
+    #[error("operation cancelled")]
+    Cancelled,
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
 
-const ACK: u8 = 0x79;
-const NACK: u8 = 0x1F;
+/// 错误类别的稳定编码，随结果传给 GUI 和 JSON 事件，界面据此区分处理而不解析错误文本
+///
+/// 序列化名称一经发布不再修改；新增 `Error` 变体时在 `Error::code` 中归入已有类别或新增编码。
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    PortNotFound,
+    /// 串口被其他程序（或本程序的串口监视器）占用
+    PortBusy,
+    /// 其他串口驱动错误
+    Serial,
+    /// USB DFU 传输失败或设备报告错误状态
+    Usb,
+    Io,
+    Timeout,
+    Nack,
+    UnexpectedResponse,
+    /// Boot 序列执行后 Bootloader 不应答同步
+    NoSync,
+    /// 无法驱动 Boot 控制线或没有复位线
+    BootLines,
+    FileNotFound,
+    /// 固件文件无法解析、为空或地址无效
+    InvalidImage,
+    ImageTooLarge,
+    AddressOutOfRange,
+    ReadProtected,
+    ProtectionFailed,
+    VerifyFailed,
+    /// Bootloader 或当前接口不支持所需命令
+    Unsupported,
+    /// 芯片数据库中没有该产品 ID 的布局
+    UnknownChip,
+    OptionBytes,
+    Bank,
+    WaitTimeout,
+    PowerHook,
+    Config,
+    Stub,
+    Provision,
+    NotMonitored,
+    Cancelled,
+    Internal,
+}
+
+impl Error {
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            Error::PortNotFound(_) => ErrorCode::PortNotFound,
+            Error::UsbDfu(e) => e.code(),
+            Error::PortBusy(_) | Error::MonitorBusy(_) => ErrorCode::PortBusy,
+            Error::Serial(_) => ErrorCode::Serial,
+            Error::Io(_) => ErrorCode::Io,
+            Error::Timeout => ErrorCode::Timeout,
+            Error::Nack => ErrorCode::Nack,
+            Error::UnexpectedResponse(_) => ErrorCode::UnexpectedResponse,
+            Error::NoSync { .. } => ErrorCode::NoSync,
+            Error::BootEntry(_) | Error::NoResetLine => ErrorCode::BootLines,
+            Error::HexFileNotFound(_) | Error::ImageFileNotFound(_) => ErrorCode::FileNotFound,
+            Error::Hex(_)
+            | Error::Elf(_)
+            | Error::Srec(_)
+            | Error::Dfu(_)
+            | Error::HexFileEmpty
+            | Error::ImageEmpty
+            | Error::ImageInAliasRegion(_)
+            | Error::ImageOffsetOutOfRange { .. }
+            | Error::ImageOverlap(_)
+            | Error::AddressOverflow { .. } => ErrorCode::InvalidImage,
+            Error::ImageExceedsFlash { .. } => ErrorCode::ImageTooLarge,
+            Error::AddressOutOfRange { .. }
+            | Error::InvalidGoAddress(_)
+            | Error::LegacyErasePage(_) => ErrorCode::AddressOutOfRange,
+            Error::ReadProtected => ErrorCode::ReadProtected,
+            Error::ProtectionNotApplied => ErrorCode::ProtectionFailed,
+            Error::VerifyMismatch { .. } | Error::ChecksumMismatch { .. } => {
+                ErrorCode::VerifyFailed
+            }
+            Error::NoEraseSupport | Error::CommandNotSupported(_) => ErrorCode::Unsupported,
+            Error::UnknownOptionBytes(_) | Error::UnknownEraseLayout(_) => ErrorCode::UnknownChip,
+            Error::WrpSectorCount(_) | Error::OptionByte(_) | Error::OptionBytesMismatch(_) => {
+                ErrorCode::OptionBytes
+            }
+            Error::Bank(_) => ErrorCode::Bank,
+            Error::WaitTimeout(_) => ErrorCode::WaitTimeout,
+            Error::PowerHook(_) => ErrorCode::PowerHook,
+            Error::Config(_) | Error::UnsupportedConfig(_) => ErrorCode::Config,
+            Error::Stub(_) => ErrorCode::Stub,
+            Error::Provision(_) => ErrorCode::Provision,
+            Error::NotMonitored(_) => ErrorCode::NotMonitored,
+            Error::Cancelled => ErrorCode::Cancelled,
+            Error::Json(_) | Error::Zip(_) => ErrorCode::Internal,
+        }
+    }
+}
+
+pub(crate) const ACK: u8 = 0x79;
+pub(crate) const NACK: u8 = 0x1F;
 
-const CMD_GET: u8 = 0x00;
-const CMD_GET_ID: u8 = 0x02;
-const CMD_GO: u8 = 0x21;
-const CMD_WRITE_MEMORY: u8 = 0x31;
-const CMD_ERASE: u8 = 0x43;
-const CMD_EXTENDED_ERASE: u8 = 0x44;
+pub(crate) const CMD_GET: u8 = 0x00;
+pub(crate) const CMD_GET_VERSION: u8 = 0x01;
+pub(crate) const CMD_GET_ID: u8 = 0x02;
+pub(crate) const CMD_READ_MEMORY: u8 = 0x11;
+pub(crate) const CMD_GO: u8 = 0x21;
+pub(crate) const CMD_WRITE_MEMORY: u8 = 0x31;
+pub(crate) const CMD_ERASE: u8 = 0x43;
+pub(crate) const CMD_EXTENDED_ERASE: u8 = 0x44;
+pub(crate) const CMD_SPECIAL: u8 = 0x50;
+pub(crate) const CMD_EXTENDED_SPECIAL: u8 = 0x51;
+pub(crate) const CMD_WRITE_PROTECT: u8 = 0x63;
+pub(crate) const CMD_WRITE_UNPROTECT: u8 = 0x73;
+pub(crate) const CMD_READOUT_PROTECT: u8 = 0x82;
+pub(crate) const CMD_READOUT_UNPROTECT: u8 = 0x92;
+pub(crate) const CMD_GET_CHECKSUM: u8 = 0xA1;
+
+/// Bootloader 命令字的可读名称
+pub fn command_name(cmd: u8) -> &'static str {
+    match cmd {
+        CMD_GET => "Get",
+        CMD_GET_VERSION => "Get Version",
+        CMD_GET_ID => "Get ID",
+        CMD_READ_MEMORY => "Read Memory",
+        CMD_GO => "Go",
+        CMD_WRITE_MEMORY => "Write Memory",
+        CMD_ERASE => "Erase",
+        CMD_EXTENDED_ERASE => "Extended Erase",
+        CMD_SPECIAL => "Special",
+        CMD_EXTENDED_SPECIAL => "Extended Special",
+        CMD_WRITE_PROTECT => "Write Protect",
+        CMD_WRITE_UNPROTECT => "Write Unprotect",
+        CMD_READOUT_PROTECT => "Readout Protect",
+        CMD_READOUT_UNPROTECT => "Readout Unprotect",
+        CMD_GET_CHECKSUM => "Get Checksum",
+        _ => "Unknown",
+    }
+}
+
+/// 由支持的命令推导出的能力
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Capabilities {
+    pub can_read: bool,
+    pub can_write: bool,
+    pub can_go: bool,
+    /// 支持按页擦除（Erase 或 Extended Erase）
+    pub can_erase_pages: bool,
+    /// 使用 16 位页号的 Extended Erase
+    pub extended_erase: bool,
+    /// 支持 Get Checksum，可做 CRC 校验
+    pub has_crc: bool,
+    pub write_protection: bool,
+    pub readout_protection: bool,
+    pub special_commands: bool,
+}
+
+impl Capabilities {
+    pub fn from_commands(cmds: &[u8]) -> Self {
+        let has = |c: u8| cmds.contains(&c);
+        Self {
+            can_read: has(CMD_READ_MEMORY),
+            can_write: has(CMD_WRITE_MEMORY),
+            can_go: has(CMD_GO),
+            can_erase_pages: has(CMD_ERASE) || has(CMD_EXTENDED_ERASE),
+            extended_erase: has(CMD_EXTENDED_ERASE),
+            has_crc: has(CMD_GET_CHECKSUM),
+            write_protection: has(CMD_WRITE_PROTECT) && has(CMD_WRITE_UNPROTECT),
+            readout_protection: has(CMD_READOUT_PROTECT) && has(CMD_READOUT_UNPROTECT),
+            special_commands: has(CMD_SPECIAL) || has(CMD_EXTENDED_SPECIAL),
+        }
+    }
+
+    /// 已具备能力的简短描述列表
+    pub fn summary(&self) -> Vec<&'static str> {
+        [
+            (self.can_read, "can read"),
+            (self.can_write, "can write"),
+            (self.can_go, "can jump"),
+            (self.can_erase_pages, "can erase pages"),
+            (self.extended_erase, "extended erase"),
+            (self.has_crc, "has CRC"),
+            (self.write_protection, "write protection"),
+            (self.readout_protection, "readout protection"),
+            (self.special_commands, "special commands"),
+        ]
+        .into_iter()
+        .filter_map(|(on, name)| on.then_some(name))
+        .collect()
+    }
+}
+
+/// 主 Flash 起始地址
+pub const FLASH_BASE: u32 = 0x0800_0000;
+
+/// 各系列主 Flash 的最大容量，用于校验地址参数
+pub const FLASH_MAX_SIZE: u32 = 0x0020_0000;
+
+/// 自动波特率探测时依次尝试的波特率
+pub const BAUD_LADDER: &[u32] = &[115_200, 57_600, 38_400, 19_200, 9_600];
 
 #[derive(Debug, Clone)]
 pub struct PortInfo {
@@ -99,12 +725,64 @@ pub struct PortInfo {
     pub vid: Option<u16>,
     pub pid: Option<u16>,
     pub serial: Option<String>,
+    /// USB 接口号，用于区分 FT2232H 等多通道适配器的各个端口
+    pub interface: Option<u8>,
+}
+
+impl PortInfo {
+    /// `spec` 可以是端口名、USB 序列号，或 `usb:序列号[:ifaceN]`
+    pub fn matches(&self, spec: &str) -> bool {
+        if self.port_name == spec || self.serial.as_deref() == Some(spec) {
+            return true;
+        }
+        let Some(usb) = spec.strip_prefix("usb:") else {
+            return false;
+        };
+        let (serial, interface) = match usb.rsplit_once(':') {
+            Some((serial, iface)) => match iface.strip_prefix("iface").map(str::parse::<u8>) {
+                Some(Ok(n)) => (serial, Some(n)),
+                _ => (usb, None),
+            },
+            None => (usb, None),
+        };
+        self.serial.as_deref() == Some(serial)
+            && interface.is_none_or(|n| self.interface == Some(n))
+    }
+}
+
+/// 把 `usb:序列号[:ifaceN]` 形式的端口描述解析为端口名，其他形式原样返回
+pub fn resolve_port(spec: &str) -> Result<String> {
+    if !spec.starts_with("usb:") {
+        return Ok(spec.to_string());
+    }
+    list_ports()?
+        .into_iter()
+        .find(|p| p.matches(spec))
+        .map(|p| p.port_name)
+        .ok_or_else(|| Error::PortNotFound(spec.to_string()))
 }
 
+/// 打开串口失败时区分被占用和不存在：POSIX 上 NoDevice 来自 EBUSY / 文件锁，
+/// Windows 上两者都报 NoDevice，端口仍在系统列表中时视为被占用
+pub(crate) fn open_error(device: &str, e: serialport::Error) -> Error {
+    let listed = || {
+        serialport::available_ports().is_ok_and(|ports| ports.iter().any(|p| p.port_name == device))
+    };
+    match e.kind {
+        serialport::ErrorKind::NoDevice if cfg!(unix) || listed() => {
+            Error::PortBusy(device.to_string())
+        }
+        serialport::ErrorKind::NoDevice | serialport::ErrorKind::Io(_) => {
+            Error::PortNotFound(device.to_string())
+        }
+        _ => Error::Serial(e),
+    }
+}
+
+/// 列出串口，已注册的演示端口排在最前
 pub fn list_ports() -> Result<Vec<PortInfo>> {
     let ports = serialport::available_ports()?;
-    let mut out = Vec::with_capacity(ports.len());
-
+    let mut out = sim::demo_ports();
     for p in ports {
         // 在 macOS 上过滤掉不需要的端口
         #[cfg(target_os = "macos")]
@@ -117,14 +795,15 @@ pub fn list_ports() -> Result<Vec<PortInfo>> {
             }
         }
 
-        let (vid, pid, serial, product) = match &p.port_type {
+        let (vid, pid, serial, product, interface) = match &p.port_type {
             SerialPortType::UsbPort(info) => (
                 Some(info.vid),
                 Some(info.pid),
                 info.serial_number.clone(),
                 info.product.clone(),
+                info.interface,
             ),
-            _ => (None, None, None, None),
+            _ => (None, None, None, None, None),
         };
 
         let mut label = p.port_name.clone();
@@ -142,607 +821,4929 @@ pub fn list_ports() -> Result<Vec<PortInfo>> {
             vid,
             pid,
             serial,
+            interface,
         });
     }
 
+    // 同一适配器的多个通道标签相同，加上接口号区分
+    let composite: Vec<bool> = out
+        .iter()
+        .map(|p| {
+            p.interface.is_some()
+                && out
+                    .iter()
+                    .filter(|q| (q.vid, q.pid, &q.serial) == (p.vid, p.pid, &p.serial))
+                    .count()
+                    > 1
+        })
+        .collect();
+    for (p, composite) in out.iter_mut().zip(composite) {
+        if let (true, Some(n)) = (composite, p.interface) {
+            p.label.push_str(&format!(" [iface{n}]"));
+        }
+    }
+
     Ok(out)
 }
 
-#[derive(Debug, Clone)]
-pub struct IdentifyResult {
-    pub ok: bool,
-    pub bootloader_version: Option<u8>,
-    pub supported_commands: Vec<u8>,
-    pub product_id: Option<u16>,
-    pub error: Option<String>,
-}
+/// 等待串口出现，`spec` 可以是端口名或 USB 序列号，返回匹配到的端口名
+///
+/// `timeout` 为 None 时一直等待。
+pub fn wait_for_port(spec: &str, timeout: Option<Duration>) -> Result<String> {
+    let start = Instant::now();
+    info!("{}", tr(Msg::WaitingForDevice, &[&spec]));
 
-#[derive(Debug, Clone)]
-pub struct FlashOptions {
-    pub baud_rate: u32,
-    pub boot_mode: BootMode,
-    pub lines: BootLineConfig,
-    pub verify: bool,
-    pub reset_after: bool,
-    pub read_timeout: Duration,
-}
+    loop {
+        let found = list_ports()?.into_iter().find(|p| p.matches(spec));
+        if let Some(p) = found {
+            info!("{}", tr(Msg::DeviceDetected, &[&p.label]));
+            return Ok(p.port_name);
+        }
 
-impl Default for FlashOptions {
-    fn default() -> Self {
-        Self {
-            baud_rate: 115_200,
-            boot_mode: BootMode::None,
-            lines: BootLineConfig::default(),
-            verify: false,
-            reset_after: false,
-            read_timeout: Duration::from_millis(800),
+        if timeout.is_some_and(|t| start.elapsed() >= t) {
+            return Err(Error::WaitTimeout(spec.to_string()));
         }
+        std::thread::sleep(Duration::from_millis(200));
     }
 }
 
-pub trait Logger {
-    fn line(&self, level: &'static str, msg: &str);
+/// 调制解调器状态输入线，可用于治具触发或检测目标上电
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum, serde::Serialize, serde::Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum StatusLine {
+    Cts,
+    Dsr,
+    /// 载波检测（DCD）
+    Cd,
+    Ri,
 }
 
-pub struct StdoutLogger;
-
-impl Logger for StdoutLogger {
-    fn line(&self, level: &'static str, msg: &str) {
-        println!("[{level}] {msg}");
-    }
+/// 各状态输入线的当前电平，true 表示有效
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+pub struct ModemStatus {
+    pub cts: bool,
+    pub dsr: bool,
+    pub cd: bool,
+    pub ri: bool,
 }
 
-fn xor_checksum(bytes: impl IntoIterator<Item = u8>) -> u8 {
-    bytes.into_iter().fold(0u8, |acc, b| acc ^ b)
+/// 触发边沿：rising 为线变为有效，falling 为线变为无效
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum, serde::Serialize, serde::Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Edge {
+    Rising,
+    Falling,
 }
 
-fn read_byte_with_timeout(port: &mut dyn SerialPort, timeout: Duration) -> Result<u8> {
-    let start = Instant::now();
-    let mut buf = [0u8; 1];
+/// 治具触发配置：等待状态线边沿后开始烧录，可选在完成后翻转 DTR 通知治具
+#[derive(Debug, Clone)]
+pub struct FixtureTrigger {
+    pub line: StatusLine,
+    pub edge: Edge,
+    /// 边沿后电平需保持的时间（按键消抖）
+    pub debounce: Duration,
+    /// 完成后 DTR 有效脉冲的宽度，None 表示不发信号
+    pub done_pulse: Option<Duration>,
+}
 
-    while start.elapsed() < timeout {
-        match port.read(&mut buf) {
-            Ok(1) => return Ok(buf[0]),
-            Ok(0) => continue,
-            Ok(_) => return Ok(buf[0]), // 不应该发生（1字节缓冲区）
-            Err(e) if e.kind() == std::io::ErrorKind::TimedOut => continue,
-            Err(e) => return Err(Error::Io(e)),
+impl Default for FixtureTrigger {
+    fn default() -> Self {
+        Self {
+            line: StatusLine::Cts,
+            edge: Edge::Rising,
+            debounce: Duration::from_millis(50),
+            done_pulse: None,
         }
     }
-
-    Err(Error::Timeout)
 }
 
-fn expect_ack(port: &mut dyn SerialPort, timeout: Duration) -> Result<()> {
-    let b = read_byte_with_timeout(port, timeout)?;
-    match b {
-        ACK => Ok(()),
-        NACK => Err(Error::Nack),
-        other => Err(Error::UnexpectedResponse(other)),
-    }
+pub fn read_status_line(port: &mut dyn Transport, line: StatusLine) -> Result<bool> {
+    port.read_status(line)
 }
 
-fn send_cmd(port: &mut dyn SerialPort, cmd: u8, timeout: Duration) -> Result<()> {
-    let pkt = [cmd, cmd ^ 0xFF];
-    port.write_all(&pkt)?;
-    port.flush()?;
-    expect_ack(port, timeout)
+pub fn read_modem_status(port: &mut dyn Transport) -> Result<ModemStatus> {
+    Ok(ModemStatus {
+        cts: read_status_line(port, StatusLine::Cts)?,
+        dsr: read_status_line(port, StatusLine::Dsr)?,
+        cd: read_status_line(port, StatusLine::Cd)?,
+        ri: read_status_line(port, StatusLine::Ri)?,
+    })
 }
 
-fn send_address(port: &mut dyn SerialPort, address: u32, timeout: Duration) -> Result<()> {
-    let a = address.to_be_bytes();
-    let c = xor_checksum(a);
-    port.write_all(&a)?;
-    port.write_all(&[c])?;
-    port.flush()?;
-    expect_ack(port, timeout)
+/// 打开串口读取一次状态输入线
+pub fn modem_status(port_name: &str, options: &FlashOptions) -> Result<ModemStatus> {
+    let mut port = open_port_with(port_name, options.baud_rate, options)?;
+    read_modem_status(&mut *port)
 }
 
-fn write_memory(
-    port: &mut dyn SerialPort,
-    address: u32,
-    data: &[u8],
-    timeout: Duration,
+/// 打开串口并等待触发边沿，消抖通过后关闭串口返回
+///
+/// 必须先观察到边沿前的电平，所以启动时线已处于目标电平不会触发。
+pub fn wait_for_trigger(
+    port_name: &str,
+    trigger: &FixtureTrigger,
+    options: &FlashOptions,
 ) -> Result<()> {
-    if data.is_empty() || data.len() > 256 {
-        return Err(Error::Io(std::io::Error::new(
-            std::io::ErrorKind::InvalidInput,
-            "write size must be 1..=256",
-        )));
+    let mut port = open_port_with(port_name, options.baud_rate, options)?;
+    let line = format!("{:?}", trigger.line).to_uppercase();
+    let edge = format!("{:?}", trigger.edge).to_lowercase();
+    info!(
+        "{}",
+        tr(Msg::WaitingForTrigger, &[&line, &edge, &port_name])
+    );
+
+    let target = trigger.edge == Edge::Rising;
+    let mut armed = false;
+    loop {
+        options.cancel.check()?;
+        let level = read_status_line(&mut *port, trigger.line)?;
+        if level != target {
+            armed = true;
+        } else if armed {
+            std::thread::sleep(trigger.debounce);
+            if read_status_line(&mut *port, trigger.line)? == target {
+                info!("{}", tr(Msg::Triggered, &[&line]));
+                return Ok(());
+            }
+        }
+        std::thread::sleep(Duration::from_millis(10));
     }
+}
 
-    send_cmd(port, CMD_WRITE_MEMORY, timeout)?;
-    send_address(port, address, timeout)?;
+/// 按配置输出 DTR 完成脉冲
+pub fn signal_done(
+    port_name: &str,
+    trigger: &FixtureTrigger,
+    options: &FlashOptions,
+) -> Result<()> {
+    let Some(pulse) = trigger.done_pulse else {
+        return Ok(());
+    };
+    let mut port = open_port_with(port_name, options.baud_rate, options)?;
+    info!("{}", tr(Msg::SignallingDone, &[&pulse.as_millis()]));
+    port.set_dtr(true)?;
+    std::thread::sleep(pulse);
+    port.set_dtr(false)?;
+    Ok(())
+}
 
-    let len_minus_one = (data.len() as u8).wrapping_sub(1);
-    let checksum = xor_checksum(std::iter::once(len_minus_one).chain(data.iter().copied()));
+/// 烧录流程的各个阶段
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlashPhase {
+    /// 断电重启、Boot 序列和重试等待
+    BootEntry,
+    Sync,
+    Erase,
+    Write,
+    Verify,
+    /// GO / 复位 / 完成后的断电重启
+    Reset,
+}
 
-    port.write_all(&[len_minus_one])?;
-    port.write_all(data)?;
-    port.write_all(&[checksum])?;
-    port.flush()?;
+impl FlashPhase {
+    pub const ALL: [FlashPhase; 6] = [
+        FlashPhase::BootEntry,
+        FlashPhase::Sync,
+        FlashPhase::Erase,
+        FlashPhase::Write,
+        FlashPhase::Verify,
+        FlashPhase::Reset,
+    ];
 
-    expect_ack(port, timeout)
+    pub fn name(self) -> &'static str {
+        match self {
+            FlashPhase::BootEntry => "boot_entry",
+            FlashPhase::Sync => "sync",
+            FlashPhase::Erase => "erase",
+            FlashPhase::Write => "write",
+            FlashPhase::Verify => "verify",
+            FlashPhase::Reset => "reset",
+        }
+    }
 }
 
-fn extended_erase_all(
-    port: &mut dyn SerialPort,
-    timeout: Duration,
-    long_timeout: Duration,
-) -> Result<()> {
-    send_cmd(port, CMD_EXTENDED_ERASE, timeout)?;
+/// 各阶段的累计耗时，用于比较不同适配器 / 波特率的时间分布
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PhaseTimeline {
+    durations: [Duration; 6],
+}
 
-    // 全擦除
-    port.write_all(&[0xFF, 0xFF, 0x00])?;
-    port.flush()?;
+impl PhaseTimeline {
+    pub fn add(&mut self, phase: FlashPhase, elapsed: Duration) {
+        self.durations[phase as usize] += elapsed;
+    }
 
-    expect_ack(port, long_timeout)
-}
+    pub fn get(&self, phase: FlashPhase) -> Duration {
+        self.durations[phase as usize]
+    }
 
-fn erase_all(port: &mut dyn SerialPort, timeout: Duration, long_timeout: Duration) -> Result<()> {
-    send_cmd(port, CMD_ERASE, timeout)?;
+    pub fn entries(&self) -> impl Iterator<Item = (FlashPhase, Duration)> + '_ {
+        FlashPhase::ALL.into_iter().map(|p| (p, self.get(p)))
+    }
 
-    // 全擦除（旧版）
-    port.write_all(&[0xFF, 0x00])?;
-    port.flush()?;
+    pub fn total(&self) -> Duration {
+        self.durations.iter().sum()
+    }
+}
 
-    expect_ack(port, long_timeout)
+/// 一次成功烧录的结果
+#[derive(Debug, Clone)]
+pub struct FlashReport {
+    pub bytes_written: u64,
+    pub timeline: PhaseTimeline,
+    /// 本次连接的 ACK 往返时间、重试和链路评分；没有串口命令往返的传输（USB DFU、RAM 烧录程序）为 None
+    pub link: Option<LinkStats>,
 }
 
-fn go_command(port: &mut dyn SerialPort, address: u32, timeout: Duration) -> Result<()> {
-    send_cmd(port, CMD_GO, timeout)?;
-    send_address(port, address, timeout)?;
-    // GO 命令后 Bootloader 跳转，不会响应
-    Ok(())
+/// 写入前对镜像的分析：空白数据、擦除范围和预计耗时
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct WritePlan {
+    pub bytes: usize,
+    /// 值为 0xFF 的字节数，擦除后本就是该值
+    pub blank_bytes: usize,
+    /// 镜像覆盖的页 / 扇区数及其中只含 0xFF 的数量，芯片未知时为 None
+    pub pages: Option<usize>,
+    pub blank_pages: Option<usize>,
+    pub mass_erase: bool,
+    /// 擦除计划覆盖的页 / 扇区数，全片擦除时为芯片的全部页数
+    pub erase_pages: Option<usize>,
+    pub baud: u32,
+    /// 擦除 + 写入 + 校验的预计耗时，芯片未知时不含擦除
+    pub estimated_ms: u64,
 }
 
-fn do_hardware_reset(port: &mut dyn SerialPort) -> Result<()> {
-    // 设置 BOOT0=LOW 然后脉冲复位
-    port.write_request_to_send(false)?;
-    std::thread::sleep(Duration::from_millis(50));
+/// 每个写入帧除数据外的串口字节（命令、地址、长度、校验和与 ACK）和往返延迟
+const FRAME_OVERHEAD_BYTES: u64 = 12;
+const FRAME_TURNAROUND_US: u64 = 1000;
+/// 典型编程速度：每字节约 25 µs
+const PROGRAM_US_PER_BYTE: u64 = 25;
 
-    port.write_data_terminal_ready(true)?;
-    std::thread::sleep(Duration::from_millis(100));
+impl WritePlan {
+    /// 按镜像、芯片页布局和烧录参数估算；`baud` 为实际连接的波特率
+    pub fn analyze(
+        image: &BTreeMap<u32, u8>,
+        chip: Option<&chipdb::ChipInfo>,
+        options: &FlashOptions,
+        baud: u32,
+    ) -> Self {
+        let blank_bytes = image.values().filter(|&&b| b == 0xFF).count();
+        let units: Option<BTreeMap<u16, (chipdb::EraseUnit, bool)>> = chip.map(|chip| {
+            image_to_blocks(image)
+                .iter()
+                .flat_map(|(base, data)| chip.erase_units_covering(*base, data.len()))
+                .map(|unit| {
+                    let last = (unit.end() - 1) as u32;
+                    let blank = image.range(unit.start..=last).all(|(_, &b)| b == 0xFF);
+                    (unit.index, (unit, blank))
+                })
+                .collect()
+        });
+
+        let mass_erase = options.erase_mode == EraseMode::Mass;
+        let (erase_pages, erase_time) = match (chip, &units) {
+            (Some(chip), _) if mass_erase => {
+                (Some(chip.erase_units().count()), chip.mass_erase_time())
+            }
+            (Some(chip), _) if options.erase_mode == EraseMode::Bank => {
+                let units = chip.bank_units(options.bank.unwrap_or(1));
+                (
+                    Some(units.len()),
+                    units.iter().map(chipdb::EraseUnit::erase_time).sum(),
+                )
+            }
+            (Some(_), Some(units)) => (
+                Some(units.len()),
+                units.values().map(|(unit, _)| unit.erase_time()).sum(),
+            ),
+            _ => (None, Duration::ZERO),
+        };
 
-    port.write_data_terminal_ready(false)?;
-    std::thread::sleep(Duration::from_millis(100));
+        let chunk = options.write_chunk.clamp(MIN_WRITE_CHUNK, MAX_WRITE_CHUNK);
+        let blocks = image_to_blocks(image);
+        let chunks = || blocks.iter().flat_map(|(_, data)| data.chunks(chunk));
+        let frames = chunks().count() as u64;
+        // 跳过全 0xFF 的帧时只计算实际发送的帧
+        let written: Vec<usize> = chunks()
+            .filter(|c| !options.skip_blank || c.iter().any(|&b| b != 0xFF))
+            .map(<[u8]>::len)
+            .collect();
+        let transfer_us = |bytes: u64, frames: u64| {
+            (bytes + frames * FRAME_OVERHEAD_BYTES) * 10 * 1_000_000 / baud.max(1) as u64
+                + frames * FRAME_TURNAROUND_US
+        };
+        let bytes = image.len() as u64;
+        let written_bytes = written.iter().sum::<usize>() as u64;
+        let write_us =
+            transfer_us(written_bytes, written.len() as u64) + written_bytes * PROGRAM_US_PER_BYTE;
+        let verify_us = match options.verify {
+            VerifyStrategy::None => 0,
+            VerifyStrategy::ReadBackFull => transfer_us(bytes, frames),
+            VerifyStrategy::ReadBackSampled(percent) => {
+                transfer_us(bytes, frames) * u64::from(percent) / 100
+            }
+            VerifyStrategy::CrcCommand => frames * FRAME_TURNAROUND_US,
+        };
 
-    port.write_data_terminal_ready(true)?;
-    std::thread::sleep(Duration::from_millis(100));
+        Self {
+            bytes: image.len(),
+            blank_bytes,
+            pages: units.as_ref().map(BTreeMap::len),
+            blank_pages: units
+                .as_ref()
+                .map(|units| units.values().filter(|(_, blank)| *blank).count()),
+            mass_erase,
+            erase_pages,
+            baud,
+            estimated_ms: erase_time.as_millis() as u64 + (write_us + verify_us) / 1000,
+        }
+    }
 
-    Ok(())
+    /// 一行本地化摘要
+    pub fn summary(&self) -> String {
+        let seconds = format!("{:.1}", self.estimated_ms as f64 / 1000.0);
+        let erase = match (self.mass_erase, self.erase_pages) {
+            (true, _) => tr(Msg::PlanMassErase, &[]),
+            (false, Some(pages)) => tr(Msg::PlanPageErase, &[&pages]),
+            (false, None) => tr(Msg::PlanPageErase, &[&"?"]),
+        };
+        let pages = match (self.pages, self.blank_pages) {
+            (Some(pages), Some(blank)) => format!("{blank}/{pages}"),
+            _ => "?".to_string(),
+        };
+        tr(
+            Msg::WritePlan,
+            &[
+                &self.bytes,
+                &self.blank_bytes,
+                &pages,
+                &erase,
+                &seconds,
+                &self.baud,
+            ],
+        )
+    }
 }
 
-fn connect_bootloader_with_log(
-    port: &mut dyn SerialPort,
-    timeout: Duration,
-    _logger: &dyn Logger,
-) -> Result<()> {
-    // 清除接收缓冲区
-    let _ = port.clear(serialport::ClearBuffer::Input);
-    std::thread::sleep(Duration::from_millis(50));
-    let _ = port.clear(serialport::ClearBuffer::Input);
+#[derive(Debug, Clone)]
+pub struct IdentifyResult {
+    pub ok: bool,
+    pub bootloader_version: Option<u8>,
+    pub supported_commands: Vec<u8>,
+    pub capabilities: Capabilities,
+    pub product_id: Option<u16>,
+    /// GET_ID 返回的原始字节，用于非标准长度的型号
+    pub product_id_raw: Vec<u8>,
+    /// GET VERSION 的应答，不支持该命令时为 None
+    pub version_info: Option<VersionInfo>,
+    /// 读保护是否生效（读 Flash 被 NACK），无法判断时为 None
+    pub read_protected: Option<bool>,
+    /// 按版本号识别的协议变体及其默认超时
+    pub protocol: Option<BootloaderProtocol>,
+    /// Flash 容量寄存器读出的实际容量（字节），同一产品 ID 的不同容量型号据此区分
+    pub flash_size: Option<u32>,
+    pub error: Option<String>,
+    pub error_code: Option<ErrorCode>,
+}
+
+impl IdentifyResult {
+    /// 连接或查询失败时的结果
+    fn failed(error: &Error) -> Self {
+        Self {
+            ok: false,
+            bootloader_version: None,
+            supported_commands: vec![],
+            capabilities: Capabilities::default(),
+            product_id: None,
+            product_id_raw: vec![],
+            version_info: None,
+            read_protected: None,
+            protocol: None,
+            flash_size: None,
+            error: Some(error.to_string()),
+            error_code: Some(error.code()),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FlashOptions {
+    pub baud_rate: u32,
+    pub boot_mode: BootMode,
+    /// 复位 / BOOT0 的有效电平，通常由 `BootLineConfig::for_mode` 得到
+    pub lines: BootLineConfig,
+    pub verify: VerifyStrategy,
+    pub reset_after: bool,
+    /// 各类操作的超时，未设置的项连接后按协议版本和芯片取默认值
+    pub timeouts: Timeouts,
+    /// 0x7F 同步的尝试次数及两次尝试之间的额外等待
+    pub sync_retry: RetryPolicy,
+    /// 单条写入 / 擦除命令被 NACK 时的重试；写入在用完次数后再减半块大小
+    pub command_retry: RetryPolicy,
+    /// 同步无响应时重新执行 Boot 序列的总轮数（含第一轮），部分板子需要多次复位才能进入 Bootloader
+    pub boot_cycles: u32,
+    /// 第一次重试前的等待，之后每轮翻倍
+    pub boot_backoff: Duration,
+    pub boot_timing: BootTiming,
+    /// 配置的波特率无响应时，按 BAUD_LADDER 依次重试
+    pub auto_baud: bool,
+    /// 同步后切换到的更高波特率（如 460800 / 921600），适配器不支持或重新同步失败时回退
+    pub high_baud: Option<u32>,
+    /// 链接在 0x00000000 镜像区的固件自动重定位到 FLASH_BASE
+    pub rebase_alias: bool,
+    /// 读入的每个镜像整体平移的字节数（可为负），在镜像区检查之前应用
+    pub offset: i64,
+    /// 烧录完成后设置读保护（RDP Level 1）
+    pub lock_after: bool,
+    /// 擦除前解除写保护（出厂即开启 WRP 的器件）
+    pub unprotect_before: bool,
+    /// 烧录成功后对这些扇区重新开启写保护
+    pub protect_after: Option<Vec<u8>>,
+    /// 烧录成功后修改的选项字节（字段名, 值），见 `optbytes`
+    pub option_bytes: Vec<(String, u32)>,
+    /// 在烧录报告中显示固件版本字符串
+    pub version_locator: Option<VersionLocator>,
+    /// 烧录后 GO 命令的跳转地址，默认 FLASH_BASE
+    pub go_address: Option<u32>,
+    /// 操作完成后不执行 GO / 复位，保持 BOOT0 有效，芯片停留在 Bootloader
+    pub stay_in_bootloader: bool,
+    pub progress: ProgressGranularity,
+    /// 写入前擦除全片、只擦除镜像覆盖的页，还是只擦除目标 Bank
+    pub erase_mode: EraseMode,
+    /// 双 Bank 器件的目标 Bank（1 或 2）：链接在 Bank 1 地址的镜像平移到该 Bank 写入
+    pub bank: Option<u8>,
+    /// 写入后翻转 Bank 交换选项位（BFB2），下次复位从另一个 Bank 启动
+    pub swap_banks: bool,
+    /// 连接时先不执行 Boot 序列直接同步：Flash 为空的全新芯片上电即进入 Bootloader
+    pub virgin_probe: bool,
+    /// 写入前按编程粒度补 0xFF
+    pub padding: Padding,
+    /// 每个 WRITE MEMORY 帧的最大字节数（32–256，4 的倍数），连续 NACK 时自动减半
+    pub write_chunk: usize,
+    /// 写入每帧后等待串口发送完成；关闭后直接等待应答，部分 USB 串口上更快
+    pub flush_frames: bool,
+    /// 整体烧录时跳过落在本次已擦除范围内、全为 0xFF 的写入帧，稀疏镜像烧录更快
+    pub skip_blank: bool,
+    /// 按芯片唯一 ID 记录擦写次数，None 时不记录
+    pub cycle_tracking: Option<CycleTracking>,
+    /// 连接前 / 完成后断电重启目标，None 时不控制电源
+    pub power_cycle: Option<PowerCycle>,
+    /// 记住每个设备上次成功的连接参数的文件，下次优先尝试；None 时不记
+    pub remember_connection: Option<PathBuf>,
+    /// 经 Bootloader 扩展命令编程的外部存储器，落在这些地址范围内的数据不受芯片存储器映射限制
+    pub external_memory: Vec<ExternalMemory>,
+    /// GUI 的取消按钮和 CLI 的 Ctrl-C 触发，在协议步骤之间检查
+    pub cancel: CancelToken,
+}
+
+/// 外部 QSPI / OSPI Flash 的地址空间及其扩展命令
+///
+/// 写入命令的帧格式与 WRITE MEMORY 相同；擦除命令依次发送命令、起始地址和
+/// 4 字节长度（大端，附异或校验），擦除完成后应答。读取和校验仍使用 READ MEMORY
+/// （外部 Flash 工作在内存映射模式）。
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExternalMemory {
+    pub start: u32,
+    pub size: u32,
+    pub write_command: u8,
+    /// None 时不单独擦除（由扩展在写入时自行处理）
+    pub erase_command: Option<u8>,
+}
+
+impl ExternalMemory {
+    /// 常见 STM32 上 QSPI / OSPI 内存映射区的起始地址
+    pub const QSPI_BASE: u32 = 0x9000_0000;
+
+    /// [address, address + len) 是否完全落在该区域内
+    pub fn contains(&self, address: u32, len: usize) -> bool {
+        address >= self.start && address as u64 + len as u64 <= self.start as u64 + self.size as u64
+    }
+}
+
+impl Default for FlashOptions {
+    fn default() -> Self {
+        Self {
+            baud_rate: 115_200,
+            boot_mode: BootMode::None,
+            lines: BootLineConfig::default(),
+            verify: VerifyStrategy::None,
+            reset_after: false,
+            timeouts: Timeouts::default(),
+            sync_retry: RetryPolicy::new(5, Duration::ZERO),
+            command_retry: RetryPolicy::new(2, Duration::ZERO),
+            boot_cycles: 1,
+            boot_backoff: Duration::from_millis(200),
+            boot_timing: BootTiming::default(),
+            auto_baud: false,
+            high_baud: None,
+            rebase_alias: false,
+            offset: 0,
+            lock_after: false,
+            unprotect_before: false,
+            protect_after: None,
+            option_bytes: Vec::new(),
+            version_locator: None,
+            go_address: None,
+            stay_in_bootloader: false,
+            progress: ProgressGranularity::default(),
+            erase_mode: EraseMode::default(),
+            bank: None,
+            swap_banks: false,
+            virgin_probe: false,
+            padding: Padding::default(),
+            flush_frames: true,
+            skip_blank: true,
+            write_chunk: MAX_WRITE_CHUNK,
+            cycle_tracking: None,
+            power_cycle: None,
+            remember_connection: None,
+            external_memory: Vec::new(),
+            cancel: CancelToken::default(),
+        }
+    }
+}
+
+/// 取消正在进行的操作；克隆共享同一个标志。只在协议步骤之间检查，
+/// 不会打断正在等待应答的命令，取消后会话随错误返回而关闭、释放串口
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    /// 开始新的操作前清除上次的取消
+    pub fn reset(&self) {
+        self.0.store(false, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    pub fn check(&self) -> Result<()> {
+        if self.is_cancelled() {
+            Err(Error::Cancelled)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// 终端输出：日志经 `logging::init` 转来逐行打印，进度直接作为 `ProgressSink`
+pub struct StdoutLogger;
+
+impl StdoutLogger {
+    pub fn line(&self, level: &'static str, msg: &str) {
+        diag::record_log(level, msg);
+        println!("[{level}] {msg}");
+    }
+}
+
+/// 进度在同一行刷新，不写入会话日志：擦除为旋转指示和已用时间，其余阶段为进度条
+impl ProgressSink for StdoutLogger {
+    fn progress(&self, event: &ProgressEvent) {
+        const WIDTH: u64 = 30;
+        let phase = event.phase();
+        let (done, total) = event.counts();
+        let finished = matches!(event, ProgressEvent::Done { .. });
+        if phase == ProgressPhase::Erase {
+            let frame = if finished {
+                '✓'
+            } else {
+                ['|', '/', '-', '\\'][(done / 250 % 4) as usize]
+            };
+            print!(
+                "\r{frame} {} {:.1}s / ~{:.1}s   ",
+                phase.label(),
+                done as f64 / 1000.0,
+                total as f64 / 1000.0
+            );
+        } else {
+            let filled = u64::from(event.percent()) * WIDTH / 100;
+            print!(
+                "\r{} [{}{}] {:>3}% {done}/{total}",
+                phase.label(),
+                "#".repeat(filled as usize),
+                "-".repeat((WIDTH - filled) as usize),
+                event.percent()
+            );
+        }
+        if finished {
+            println!();
+        }
+        let _ = std::io::Write::flush(&mut std::io::stdout());
+    }
+}
+
+/// 一次阶段耗时，用于结果事件
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct PhaseDuration {
+    pub phase: &'static str,
+    pub ms: u64,
+}
+
+/// 结构化的烧录事件，`--events-json` 时每个事件输出为一行 JSON
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum FlashEvent {
+    /// 进入新的阶段：erase / read / write / verify
+    Phase {
+        phase: String,
+    },
+    /// 擦除阶段单位为毫秒（预计耗时），其余阶段为字节
+    Progress {
+        phase: String,
+        done: u64,
+        total: u64,
+    },
+    Log {
+        level: String,
+        message: String,
+    },
+    /// 写入前的镜像分析
+    Plan(WritePlan),
+    Result {
+        command: String,
+        ok: bool,
+        error: Option<String>,
+        error_code: Option<ErrorCode>,
+        bytes_written: Option<u64>,
+        timeline: Vec<PhaseDuration>,
+        /// 链路评分（0–100），见 `linkstats`
+        link_score: Option<u8>,
+    },
+}
+
+impl FlashEvent {
+    /// 进度事件统一为 `Progress`，阶段完成时 done 等于 total
+    pub fn from_progress(event: &ProgressEvent) -> Self {
+        let (done, total) = event.counts();
+        FlashEvent::Progress {
+            phase: event.phase().id().to_string(),
+            done,
+            total,
+        }
+    }
+
+    /// 命令结束时的结果事件
+    pub fn result(command: &str, result: std::result::Result<&FlashReport, &Error>) -> Self {
+        let report = result.ok();
+        FlashEvent::Result {
+            command: command.to_string(),
+            ok: report.is_some(),
+            error: result.err().map(ToString::to_string),
+            error_code: result.err().map(Error::code),
+            bytes_written: report.map(|r| r.bytes_written),
+            timeline: report
+                .map(|r| {
+                    r.timeline
+                        .entries()
+                        .map(|(phase, elapsed)| PhaseDuration {
+                            phase: phase.name(),
+                            ms: elapsed.as_millis() as u64,
+                        })
+                        .collect()
+                })
+                .unwrap_or_default(),
+            link_score: report.and_then(|r| r.link.as_ref()).map(LinkStats::score),
+        }
+    }
+}
+
+/// 每个事件输出一行带 RFC 3339 时间戳的 JSON，供外部工具渲染自己的进度界面
+#[derive(Default)]
+pub struct JsonEventLogger {
+    phase: Mutex<Option<String>>,
+}
+
+impl JsonEventLogger {
+    pub fn emit(&self, event: &FlashEvent) {
+        #[derive(serde::Serialize)]
+        struct Stamped<'a> {
+            ts: String,
+            #[serde(flatten)]
+            event: &'a FlashEvent,
+        }
+
+        let stamped = Stamped {
+            ts: chrono::Local::now().to_rfc3339(),
+            event,
+        };
+        if let Ok(line) = serde_json::to_string(&stamped) {
+            println!("{line}");
+        }
+    }
+}
+
+impl JsonEventLogger {
+    pub fn line(&self, level: &'static str, msg: &str) {
+        diag::record_log(level, msg);
+        self.emit(&FlashEvent::Log {
+            level: level.to_string(),
+            message: msg.to_string(),
+        });
+    }
+}
+
+/// 阶段变化时先输出 `Phase` 事件
+impl ProgressSink for JsonEventLogger {
+    fn progress(&self, event: &ProgressEvent) {
+        let phase = event.phase().id();
+        let mut current = self.phase.lock().unwrap();
+        if current.as_deref() != Some(phase) {
+            *current = Some(phase.to_string());
+            drop(current);
+            self.emit(&FlashEvent::Phase {
+                phase: phase.to_string(),
+            });
+        }
+        self.emit(&FlashEvent::from_progress(event));
+    }
+
+    /// 输出 `Plan` 事件，会话日志中记录摘要
+    fn plan(&self, plan: &WritePlan) {
+        diag::record_log("info", &plan.summary());
+        self.emit(&FlashEvent::Plan(plan.clone()));
+    }
+}
+
+pub(crate) fn xor_checksum(bytes: impl IntoIterator<Item = u8>) -> u8 {
+    bytes.into_iter().fold(0u8, |acc, b| acc ^ b)
+}
+
+fn read_byte_with_timeout(port: &mut dyn Transport, timeout: Duration) -> Result<u8> {
+    let mut buf = [0u8; 1];
+    read_exact_with_timeout(port, &mut buf, timeout)?;
+    Ok(buf[0])
+}
+
+/// 读满 `buf`，相邻两次收到数据的间隔超过 `timeout` 即超时；
+/// 按剩余时间设置串口超时后阻塞读取，不做忙等
+pub(crate) fn read_exact_with_timeout(
+    port: &mut dyn Transport,
+    buf: &mut [u8],
+    timeout: Duration,
+) -> Result<()> {
+    let mut filled = 0;
+    let mut deadline = Instant::now() + timeout;
+    while filled < buf.len() {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(Error::Timeout);
+        }
+        port.set_timeout(remaining)?;
+        match port.read(&mut buf[filled..]) {
+            Ok(0) => {}
+            Ok(n) => {
+                filled += n;
+                deadline = Instant::now() + timeout;
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::TimedOut => {}
+            Err(e) => return Err(Error::Io(e)),
+        }
+    }
+    Ok(())
+}
+
+fn read_u16_with_timeout(port: &mut dyn Transport, timeout: Duration) -> Result<u16> {
+    let mut buf = [0u8; 2];
+    read_exact_with_timeout(port, &mut buf, timeout)?;
+    Ok(u16::from_be_bytes(buf))
+}
+
+fn expect_ack(port: &mut dyn Transport, timeout: Duration) -> Result<()> {
+    let b = read_byte_with_timeout(port, timeout)?;
+    match b {
+        ACK => Ok(()),
+        NACK => Err(Error::Nack),
+        other => Err(Error::UnexpectedResponse(other)),
+    }
+}
+
+/// 协议跟踪中应答的简短描述
+fn outcome(result: &Result<()>) -> String {
+    match result {
+        Ok(()) => "ACK".to_string(),
+        Err(Error::Nack) => "NACK".to_string(),
+        Err(Error::Timeout) => "timeout".to_string(),
+        Err(Error::UnexpectedResponse(b)) => format!("0x{b:02X}"),
+        Err(e) => e.to_string(),
+    }
+}
+
+/// 整帧一次写入；`flush` 为 false 时不等待发送完成，直接去读应答
+fn write_frame(port: &mut dyn Transport, frame: &[u8], flush: bool) -> Result<()> {
+    port.write_all(frame)?;
+    if flush {
+        port.flush()?;
+    }
+    Ok(())
+}
+
+fn send_cmd(port: &mut dyn Transport, cmd: u8, timeout: Duration) -> Result<()> {
+    send_cmd_frame(port, cmd, timeout, true)
+}
+
+fn send_cmd_frame(port: &mut dyn Transport, cmd: u8, timeout: Duration, flush: bool) -> Result<()> {
+    write_frame(port, &[cmd, cmd ^ 0xFF], flush)?;
+    let sent = Instant::now();
+    let result = expect_ack(port, timeout);
+    linkstats::record(port, cmd, sent.elapsed(), &result);
+    diag::record_trace(&format!(
+        "{} (0x{cmd:02X}) -> {}",
+        command_name(cmd),
+        outcome(&result)
+    ));
+    result
+}
+
+fn send_address(port: &mut dyn Transport, address: u32, timeout: Duration) -> Result<()> {
+    send_address_frame(port, address, timeout, true)
+}
+
+fn send_address_frame(
+    port: &mut dyn Transport,
+    address: u32,
+    timeout: Duration,
+    flush: bool,
+) -> Result<()> {
+    let a = address.to_be_bytes();
+    let [a0, a1, a2, a3] = a;
+    write_frame(port, &[a0, a1, a2, a3, xor_checksum(a)], flush)?;
+    let result = expect_ack(port, timeout);
+    diag::record_trace(&format!("address 0x{address:08X} -> {}", outcome(&result)));
+    result
+}
+
+/// 命令、地址、数据三个阶段各自组成一帧一次写出，减少高波特率下的每块开销
+///
+/// `command` 通常为 WRITE MEMORY，外部存储器使用配置的扩展写入命令。
+fn write_memory(
+    port: &mut dyn Transport,
+    command: u8,
+    address: u32,
+    data: &[u8],
+    timeout: Duration,
+    flush: bool,
+) -> Result<()> {
+    if data.is_empty() || data.len() > 256 {
+        return Err(Error::Io(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "write size must be 1..=256",
+        )));
+    }
+
+    send_cmd_frame(port, command, timeout, flush)?;
+    send_address_frame(port, address, timeout, flush)?;
+
+    let len_minus_one = (data.len() as u8).wrapping_sub(1);
+    let mut frame = Vec::with_capacity(data.len() + 2);
+    frame.push(len_minus_one);
+    frame.extend_from_slice(data);
+    frame.push(xor_checksum(frame.iter().copied()));
+    write_frame(port, &frame, flush)?;
+
+    expect_ack(port, timeout)
+}
+
+/// Special 命令（0x50）的返回内容
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SpecialResponse {
+    pub data: Vec<u8>,
+    pub status: Vec<u8>,
+}
+
+/// 发送 opcode + 长度 + 数据 + 校验的帧
+fn send_special_frame(
+    port: &mut dyn Transport,
+    opcode: u16,
+    data: &[u8],
+    timeout: Duration,
+) -> Result<()> {
+    let mut frame = Vec::with_capacity(data.len() + 5);
+    frame.extend_from_slice(&opcode.to_be_bytes());
+    frame.extend_from_slice(&(data.len() as u16).to_be_bytes());
+    frame.extend_from_slice(data);
+    frame.push(xor_checksum(frame.iter().copied()));
+
+    port.write_all(&frame)?;
+    port.flush()?;
+    expect_ack(port, timeout)
+}
+
+/// 发送 Special 命令（0x50），data 最多 128 字节
+pub fn special_command(
+    port: &mut dyn Transport,
+    opcode: u16,
+    data: &[u8],
+    timeout: Duration,
+) -> Result<SpecialResponse> {
+    if data.len() > 128 {
+        return Err(Error::Io(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "special command data must be at most 128 bytes",
+        )));
+    }
+
+    send_cmd(port, CMD_SPECIAL, timeout)?;
+    send_special_frame(port, opcode, data, timeout)?;
+
+    let n = read_u16_with_timeout(port, timeout)? as usize;
+    let mut data = vec![0u8; n];
+    read_exact_with_timeout(port, &mut data, timeout)?;
+
+    let m = read_u16_with_timeout(port, timeout)? as usize;
+    let mut status = vec![0u8; m];
+    read_exact_with_timeout(port, &mut status, timeout)?;
+
+    expect_ack(port, timeout)?;
+    Ok(SpecialResponse { data, status })
+}
+
+/// 发送 Extended Special 命令（0x51），data 最多 128 字节，data2 最多 1024 字节，返回状态字节
+pub fn extended_special_command(
+    port: &mut dyn Transport,
+    opcode: u16,
+    data: &[u8],
+    data2: &[u8],
+    timeout: Duration,
+) -> Result<Vec<u8>> {
+    if data.len() > 128 || data2.len() > 1024 {
+        return Err(Error::Io(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "extended special data must be at most 128 bytes, data2 at most 1024 bytes",
+        )));
+    }
+
+    send_cmd(port, CMD_EXTENDED_SPECIAL, timeout)?;
+    send_special_frame(port, opcode, data, timeout)?;
+
+    let mut frame = Vec::with_capacity(data2.len() + 3);
+    frame.extend_from_slice(&(data2.len() as u16).to_be_bytes());
+    frame.extend_from_slice(data2);
+    frame.push(xor_checksum(frame.iter().copied()));
+    port.write_all(&frame)?;
+    port.flush()?;
+    expect_ack(port, timeout)?;
+
+    let m = read_u16_with_timeout(port, timeout)? as usize;
+    let mut status = vec![0u8; m];
+    read_exact_with_timeout(port, &mut status, timeout)?;
+
+    expect_ack(port, timeout)?;
+    Ok(status)
+}
+
+/// 读取内存，长度 1..=256
+pub fn read_memory(
+    port: &mut dyn Transport,
+    address: u32,
+    len: usize,
+    timeout: Duration,
+) -> Result<Vec<u8>> {
+    if len == 0 || len > 256 {
+        return Err(Error::Io(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "read size must be 1..=256",
+        )));
+    }
+
+    send_cmd(port, CMD_READ_MEMORY, timeout)?;
+    send_address(port, address, timeout)?;
+
+    let n = (len - 1) as u8;
+    port.write_all(&[n, n ^ 0xFF])?;
+    port.flush()?;
+    expect_ack(port, timeout)?;
+
+    let mut data = vec![0u8; len];
+    read_exact_with_timeout(port, &mut data, timeout)?;
+    Ok(data)
+}
+
+/// 发送 Readout Unprotect，等待芯片完成全片擦除（第二个 ACK）
+fn readout_unprotect(
+    port: &mut dyn Transport,
+    timeout: Duration,
+    long_timeout: Duration,
+) -> Result<()> {
+    send_cmd(port, CMD_READOUT_UNPROTECT, timeout)?;
+    expect_ack(port, long_timeout)
+}
+
+/// 发送 Readout Protect，等待选项字节写入完成（第二个 ACK）
+fn readout_protect(
+    port: &mut dyn Transport,
+    timeout: Duration,
+    long_timeout: Duration,
+) -> Result<()> {
+    send_cmd(port, CMD_READOUT_PROTECT, timeout)?;
+    expect_ack(port, long_timeout)
+}
+
+/// 发送 Write Unprotect，等待选项字节写入完成（第二个 ACK），之后芯片复位
+fn write_unprotect_cmd(
+    port: &mut dyn Transport,
+    timeout: Duration,
+    long_timeout: Duration,
+) -> Result<()> {
+    send_cmd(port, CMD_WRITE_UNPROTECT, timeout)?;
+    expect_ack(port, long_timeout)
+}
+
+/// 发送 Write Protect 和扇区列表（N-1、扇区号、异或校验），之后芯片复位
+fn write_protect_cmd(
+    port: &mut dyn Transport,
+    sectors: &[u8],
+    timeout: Duration,
+    long_timeout: Duration,
+) -> Result<()> {
+    if sectors.is_empty() || sectors.len() > 256 {
+        return Err(Error::WrpSectorCount(sectors.len()));
+    }
+    send_cmd(port, CMD_WRITE_PROTECT, timeout)?;
+    let mut frame = Vec::with_capacity(sectors.len() + 2);
+    frame.push((sectors.len() - 1) as u8);
+    frame.extend_from_slice(sectors);
+    frame.push(xor_checksum(frame.iter().copied()));
+    port.write_all(&frame)?;
+    port.flush()?;
+    expect_ack(port, long_timeout)
+}
+
+/// Get Checksum 使用的 CRC 参数，与 STM32 CRC 外设的复位值一致
+pub const CRC_POLYNOMIAL: u32 = 0x04C1_1DB7;
+pub const CRC_INIT: u32 = 0xFFFF_FFFF;
+
+/// 按 STM32 CRC 外设的方式计算 CRC：数据按小端 32 位字输入、高位先移，
+/// 不反转也不做最终异或；末尾不足一个字的部分按擦除值 0xFF 补齐
+pub fn stm32_crc(data: &[u8], polynomial: u32, init: u32) -> u32 {
+    data.chunks(4).fold(init, |crc, word| {
+        let mut w = [0xFF; 4];
+        w[..word.len()].copy_from_slice(word);
+        let mut crc = crc ^ u32::from_le_bytes(w);
+        for _ in 0..32 {
+            crc = if crc & 0x8000_0000 != 0 {
+                (crc << 1) ^ polynomial
+            } else {
+                crc << 1
+            };
+        }
+        crc
+    })
+}
+
+/// 发送一个 32 位参数（大端）和异或校验
+fn send_word(port: &mut dyn Transport, value: u32, timeout: Duration) -> Result<()> {
+    let bytes = value.to_be_bytes();
+    let mut frame = bytes.to_vec();
+    frame.push(xor_checksum(bytes));
+    write_frame(port, &frame, true)?;
+    expect_ack(port, timeout)
+}
+
+/// Get Checksum：地址、字数、多项式、初值依次各占一帧，目标计算完成后返回 ACK、
+/// 4 字节 CRC 和异或校验
+fn get_checksum(
+    port: &mut dyn Transport,
+    address: u32,
+    words: u32,
+    timeout: Duration,
+    long_timeout: Duration,
+) -> Result<u32> {
+    send_cmd(port, CMD_GET_CHECKSUM, timeout)?;
+    send_address(port, address, timeout)?;
+    send_word(port, words, timeout)?;
+    send_word(port, CRC_POLYNOMIAL, timeout)?;
+    send_word(port, CRC_INIT, timeout)?;
+    expect_ack(port, long_timeout)?;
+
+    let mut crc = [0u8; 4];
+    read_exact_with_timeout(port, &mut crc, timeout)?;
+    let checksum = read_byte_with_timeout(port, timeout)?;
+    if xor_checksum(crc) != checksum {
+        return Err(Error::UnexpectedResponse(checksum));
+    }
+    Ok(u32::from_be_bytes(crc))
+}
+
+/// 写入后的校验策略；命令行和配置中写作 `none`、`crc`、`read-back`、`sampled:<百分比>`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum VerifyStrategy {
+    /// 不校验
+    #[default]
+    None,
+    /// 由目标用 Get Checksum 计算 CRC 与主机比较，不支持时退回回读
+    CrcCommand,
+    /// 用 READ MEMORY 按 256 字节分块回读全部数据
+    ReadBackFull,
+    /// 只回读均匀分布的一部分 256 字节块（1–100%），用速度换覆盖率
+    ReadBackSampled(u8),
+}
+
+impl VerifyStrategy {
+    pub fn enabled(self) -> bool {
+        self != VerifyStrategy::None
+    }
+
+    /// 第 `index` 个 256 字节块是否需要回读：按百分比均匀抽取，第一块总会被选中
+    fn samples(self, index: usize) -> bool {
+        match self {
+            VerifyStrategy::ReadBackSampled(percent) => {
+                let percent = usize::from(percent);
+                index == 0 || (index + 1) * percent / 100 > index * percent / 100
+            }
+            _ => true,
+        }
+    }
+}
+
+impl fmt::Display for VerifyStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VerifyStrategy::None => f.write_str("none"),
+            VerifyStrategy::CrcCommand => f.write_str("crc"),
+            VerifyStrategy::ReadBackFull => f.write_str("read-back"),
+            VerifyStrategy::ReadBackSampled(percent) => write!(f, "sampled:{percent}"),
+        }
+    }
+}
+
+impl std::str::FromStr for VerifyStrategy {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, String> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "none" => Ok(VerifyStrategy::None),
+            "crc" => Ok(VerifyStrategy::CrcCommand),
+            "read-back" | "full" => Ok(VerifyStrategy::ReadBackFull),
+            other => other
+                .strip_prefix("sampled:")
+                .and_then(|p| p.trim_end_matches('%').parse::<u8>().ok())
+                .filter(|p| (1..=100).contains(p))
+                .map(VerifyStrategy::ReadBackSampled)
+                .ok_or_else(|| {
+                    format!("expected none, crc, read-back or sampled:<1-100>, got '{s}'")
+                }),
+        }
+    }
+}
+
+impl serde::Serialize for VerifyStrategy {
+    fn serialize<S: serde::Serializer>(
+        &self,
+        serializer: S,
+    ) -> std::result::Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for VerifyStrategy {
+    fn deserialize<D: serde::Deserializer<'de>>(
+        deserializer: D,
+    ) -> std::result::Result<Self, D::Error> {
+        // 旧版本的设置和任务文件用布尔值表示是否回读校验
+        #[derive(serde::Deserialize)]
+        #[serde(untagged)]
+        enum Repr {
+            Flag(bool),
+            Name(String),
+        }
+        match Repr::deserialize(deserializer)? {
+            Repr::Flag(true) => Ok(VerifyStrategy::ReadBackFull),
+            Repr::Flag(false) => Ok(VerifyStrategy::None),
+            Repr::Name(name) => name.parse().map_err(serde::de::Error::custom),
+        }
+    }
+}
+
+/// 写入前的擦除范围
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum EraseMode {
+    /// 全片擦除
+    #[default]
+    Mass,
+    /// 只擦除镜像覆盖的页 / 扇区
+    Pages,
+    /// 擦除 `bank` 选择的整个 Bank（双 Bank 器件），另一个 Bank 保持不变
+    Bank,
+}
+
+/// WRITE MEMORY 单帧的字节数范围
+pub const MAX_WRITE_CHUNK: usize = 256;
+pub const MIN_WRITE_CHUNK: usize = 32;
+
+/// 按操作区分的超时；未设置的项连接后按协议版本和芯片取默认值，此前使用内置默认值
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Timeouts {
+    /// 每次发送 0x7F 后等待同步应答
+    pub sync: Option<Duration>,
+    /// 普通命令等待 ACK
+    pub ack: Option<Duration>,
+    /// 等待擦除完成
+    pub erase: Option<Duration>,
+    /// READ MEMORY 读回一块数据（回读校验、读出固件）
+    pub read_chunk: Option<Duration>,
+}
+
+/// 协议版本未知时的应答超时
+const DEFAULT_REPLY_TIMEOUT: Duration = Duration::from_millis(800);
+/// 协议版本未知时的擦除超时
+const DEFAULT_ERASE_TIMEOUT: Duration = Duration::from_secs(25);
+
+impl Timeouts {
+    pub fn sync(&self) -> Duration {
+        self.sync.unwrap_or(DEFAULT_REPLY_TIMEOUT)
+    }
+
+    pub fn ack(&self) -> Duration {
+        self.ack.unwrap_or(DEFAULT_REPLY_TIMEOUT)
+    }
+
+    pub fn erase(&self) -> Duration {
+        self.erase.unwrap_or(DEFAULT_ERASE_TIMEOUT)
+    }
+
+    pub fn read_chunk(&self) -> Duration {
+        self.read_chunk.unwrap_or(DEFAULT_REPLY_TIMEOUT)
+    }
+
+    /// 未设置的项改用 `defaults` 中的值，显式设置的（即使与默认值相同）保持不变
+    pub fn or_defaults(self, defaults: Timeouts) -> Self {
+        Self {
+            sync: self.sync.or(defaults.sync),
+            ack: self.ack.or(defaults.ack),
+            erase: self.erase.or(defaults.erase),
+            read_chunk: self.read_chunk.or(defaults.read_chunk),
+        }
+    }
+}
+
+/// 重试策略：总尝试次数（含第一次，至少为 1）和每次重试前的等待
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    pub attempts: u32,
+    pub backoff: Duration,
+}
+
+impl RetryPolicy {
+    pub const fn new(attempts: u32, backoff: Duration) -> Self {
+        Self { attempts, backoff }
+    }
+
+    /// 第 `attempt` 次（从 1 开始）失败后是否还能重试
+    pub fn allows(&self, attempt: u32) -> bool {
+        attempt < self.attempts.max(1)
+    }
+
+    /// 在 `port` 上执行一条命令，Bootloader 回 NACK 时等待 `backoff` 后重发，用完次数后返回最后的错误
+    pub(crate) fn run<T>(
+        &self,
+        port: &mut dyn Transport,
+        mut op: impl FnMut(&mut dyn Transport) -> Result<T>,
+    ) -> Result<T> {
+        let mut attempt = 1;
+        loop {
+            match op(port) {
+                Err(Error::Nack) if self.allows(attempt) => {
+                    attempt += 1;
+                    linkstats::record_command_retry(port);
+                    std::thread::sleep(self.backoff);
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+/// 长时间等待应答时回调心跳的间隔
+const HEARTBEAT_INTERVAL: Duration = Duration::from_millis(250);
+
+/// 分段等待 ACK，每段超时后以已等待的时间回调 `heartbeat`
+fn expect_ack_with_heartbeat(
+    port: &mut dyn Transport,
+    timeout: Duration,
+    heartbeat: &mut dyn FnMut(Duration),
+) -> Result<()> {
+    let start = Instant::now();
+    loop {
+        let remaining = timeout.saturating_sub(start.elapsed());
+        match expect_ack(port, remaining.min(HEARTBEAT_INTERVAL)) {
+            Err(Error::Timeout) if start.elapsed() < timeout => heartbeat(start.elapsed()),
+            result => return result,
+        }
+    }
+}
+
+fn extended_erase_all(
+    port: &mut dyn Transport,
+    timeout: Duration,
+    long_timeout: Duration,
+    heartbeat: &mut dyn FnMut(Duration),
+) -> Result<()> {
+    send_cmd(port, CMD_EXTENDED_ERASE, timeout)?;
+
+    // 全擦除
+    port.write_all(&[0xFF, 0xFF, 0x00])?;
+    port.flush()?;
+
+    expect_ack_with_heartbeat(port, long_timeout, heartbeat)
+}
+
+/// 外部存储器扩展擦除：命令、起始地址、4 字节长度加异或校验
+fn erase_external(
+    port: &mut dyn Transport,
+    command: u8,
+    address: u32,
+    len: u32,
+    timeout: Duration,
+    long_timeout: Duration,
+    heartbeat: &mut dyn FnMut(Duration),
+) -> Result<()> {
+    send_cmd(port, command, timeout)?;
+    send_address(port, address, timeout)?;
+
+    let n = len.to_be_bytes();
+    port.write_all(&n)?;
+    port.write_all(&[xor_checksum(n)])?;
+    port.flush()?;
+
+    expect_ack_with_heartbeat(port, long_timeout, heartbeat)
+}
+
+fn erase_all(
+    port: &mut dyn Transport,
+    timeout: Duration,
+    long_timeout: Duration,
+    heartbeat: &mut dyn FnMut(Duration),
+) -> Result<()> {
+    send_cmd(port, CMD_ERASE, timeout)?;
+
+    // 全擦除（旧版）
+    port.write_all(&[0xFF, 0x00])?;
+    port.flush()?;
+
+    expect_ack_with_heartbeat(port, long_timeout, heartbeat)
+}
+
+/// 每条擦除命令最多携带的页号数，帧长不超过 Bootloader 的 256 字节缓冲区
+const ERASE_BATCH: usize = 126;
+
+/// 按页 / 扇区号擦除：Extended Erase 用 16 位页号，旧版 Erase 用 8 位页号；每批被 NACK 时按 `retry` 重发
+fn erase_pages(
+    port: &mut dyn Transport,
+    pages: &[u16],
+    extended: bool,
+    retry: RetryPolicy,
+    timeout: Duration,
+    long_timeout: Duration,
+    heartbeat: &mut dyn FnMut(Duration),
+) -> Result<()> {
+    for batch in pages.chunks(ERASE_BATCH) {
+        retry.run(port, |port| {
+            erase_batch(port, batch, extended, timeout, long_timeout, heartbeat)
+        })?;
+    }
+    Ok(())
+}
+
+/// 发送一条擦除命令，页号数不超过 `ERASE_BATCH`
+fn erase_batch(
+    port: &mut dyn Transport,
+    batch: &[u16],
+    extended: bool,
+    timeout: Duration,
+    long_timeout: Duration,
+    heartbeat: &mut dyn FnMut(Duration),
+) -> Result<()> {
+    let mut frame = Vec::with_capacity(2 + 2 * batch.len() + 1);
+    if extended {
+        send_cmd(port, CMD_EXTENDED_ERASE, timeout)?;
+        frame.extend_from_slice(&(batch.len() as u16 - 1).to_be_bytes());
+        for &page in batch {
+            frame.extend_from_slice(&page.to_be_bytes());
+        }
+    } else {
+        // 旧版 Erase 只能表示 0..=255 的页号
+        let pages: Vec<u8> = batch
+            .iter()
+            .map(|&p| u8::try_from(p).map_err(|_| Error::LegacyErasePage(p)))
+            .collect::<Result<_>>()?;
+        send_cmd(port, CMD_ERASE, timeout)?;
+        frame.push(batch.len() as u8 - 1);
+        frame.extend_from_slice(&pages);
+    }
+    frame.push(frame.iter().fold(0u8, |acc, b| acc ^ b));
+    port.write_all(&frame)?;
+    port.flush()?;
+    expect_ack_with_heartbeat(port, long_timeout, heartbeat)
+}
+
+pub(crate) fn set_line(port: &mut dyn Transport, line: ControlLine, level: Level) -> Result<()> {
+    let high = level == Level::High;
+    match line {
+        ControlLine::Dtr => port.set_dtr(high)?,
+        ControlLine::Rts => port.set_rts(high)?,
+    }
+    Ok(())
+}
+
+fn go_command(port: &mut dyn Transport, address: u32, timeout: Duration) -> Result<()> {
+    send_cmd(port, CMD_GO, timeout)?;
+    send_address(port, address, timeout)?;
+    // GO 命令后 Bootloader 跳转，不会响应
+    Ok(())
+}
+
+/// 按进入 Bootloader 时的接线释放 BOOT0 并脉冲复位，让芯片从 Flash 启动
+///
+/// Boot 模式为 None 时无法控制复位，只记录警告。
+fn do_hardware_reset(port: &mut dyn Transport, options: &FlashOptions) -> Result<()> {
+    let Some((reset_line, boot_line)) = options.boot_mode.wiring() else {
+        warn!("{}", tr(Msg::NoResetLine, &[]));
+        return Ok(());
+    };
+    let lines = options.lines;
+    let timing = options.boot_timing;
+
+    // 释放 BOOT0
+    if let Some(boot_line) = boot_line {
+        set_line(port, boot_line, !lines.boot_level)?;
+        std::thread::sleep(Duration::from_millis(50));
+    }
+
+    // 复位脉冲
+    set_line(port, reset_line, lines.reset_assert_level)?;
+    std::thread::sleep(timing.reset_pulse);
+
+    set_line(port, reset_line, !lines.reset_assert_level)?;
+    std::thread::sleep(timing.boot_delay);
+
+    Ok(())
+}
+
+/// 按接线设置 BOOT0 的电平（复位线保持释放），断电重启期间保持
+fn hold_boot_line(port: &mut dyn Transport, options: &FlashOptions, boot: bool) -> Result<()> {
+    let Some((reset_line, boot_line)) = options.boot_mode.wiring() else {
+        return Ok(());
+    };
+    set_line(port, reset_line, !options.lines.reset_assert_level)?;
+    if let Some(boot_line) = boot_line {
+        let level = options.lines.boot_level;
+        set_line(port, boot_line, if boot { level } else { !level })?;
+    }
+    Ok(())
+}
+
+/// 连接的第二步：发送 0x7F 同步波特率，不触碰 DTR/RTS，可在同一次 Boot 序列后反复重试
+///
+/// 所有尝试都收不到有效应答时返回 `Error::NoSync`，区别于串口本身的错误。
+pub fn sync(port: &mut dyn Transport, options: &FlashOptions) -> Result<()> {
+    let timeout = options.timeouts.sync();
+    let attempts = options.sync_retry.attempts;
+
+    // 清除接收缓冲区
+    let _ = port.clear();
+    std::thread::sleep(Duration::from_millis(50));
+    let _ = port.clear();
+
+    // macOS 需要更多的稳定时间
+    #[cfg(target_os = "macos")]
+    {
+        std::thread::sleep(Duration::from_millis(100));
+        let _ = port.clear();
+        std::thread::sleep(Duration::from_millis(50));
+    }
+
+    inject::on_sync()?;
+
+    // 自动波特率同步
+    let mut last_err = Error::Timeout;
+    let attempts = attempts.max(1);
+    for attempt in 1..=attempts {
+        options.cancel.check()?;
+        if attempt > 1 {
+            linkstats::record_sync_retry(port);
+            std::thread::sleep(options.sync_retry.backoff);
+        }
+        port.write_all(&[0x7F])?;
+        port.flush()?;
+
+        // macOS 需要更长的等待时间
+        #[cfg(target_os = "macos")]
+        std::thread::sleep(Duration::from_millis(150));
+
+        #[cfg(not(target_os = "macos"))]
+        std::thread::sleep(Duration::from_millis(100));
+
+        let result = expect_ack(port, timeout);
+        diag::record_trace(&format!("sync 0x7F #{attempt} -> {}", outcome(&result)));
+        match result {
+            Ok(()) => return Ok(()),
+            // 已同步的 Bootloader 把 0x7F 当作未知命令回 NACK（上次操作保持在 Bootloader）
+            Err(Error::Nack) => {
+                info!("{}", tr(Msg::AlreadySynced, &[]));
+                return Ok(());
+            }
+            Err(Error::Timeout) if attempt < attempts => {
+                std::thread::sleep(Duration::from_millis(50));
+                continue;
+            }
+            Err(Error::UnexpectedResponse(_)) if attempt < attempts => {
+                // 清除旧数据并重试
+                let _ = port.clear();
+                std::thread::sleep(Duration::from_millis(100));
+                continue;
+            }
+            Err(e) => {
+                last_err = e;
+                if attempt < attempts {
+                    let _ = port.clear();
+                    std::thread::sleep(Duration::from_millis(100));
+                    continue;
+                }
+            }
+        }
+    }
+
+    Err(match last_err {
+        e @ (Error::Timeout | Error::UnexpectedResponse(_)) => Error::NoSync {
+            attempts,
+            last: Box::new(e),
+        },
+        e => e,
+    })
+}
+
+pub(crate) fn get_info(port: &mut dyn Transport, timeout: Duration) -> Result<(u8, Vec<u8>)> {
+    send_cmd(port, CMD_GET, timeout)?;
+
+    let n = read_byte_with_timeout(port, timeout)? as usize;
+    let version = read_byte_with_timeout(port, timeout)?;
+
+    let mut cmds = vec![0u8; n];
+    read_exact_with_timeout(port, &mut cmds, timeout)?;
+
+    expect_ack(port, timeout)?;
+    Ok((version, cmds))
+}
+
+/// GET VERSION 的应答
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VersionInfo {
+    pub version: u8,
+    /// 读保护解除 / 启用计数（选项字节 1 / 2），新版 Bootloader 固定返回 0
+    pub read_protection_disabled: u8,
+    pub read_protection_enabled: u8,
+}
+
+/// Bootloader 协议的大版本，决定应答时间等行为差异
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ProtocolVariant {
+    /// V1.x：早期 USART Bootloader，应答和全片擦除都较慢
+    V1,
+    /// V2.x：F0 / F1 / F3 等页式 Flash 的 USART Bootloader
+    V2,
+    /// V3.x 及以上：F2 / F4 / F7 / H7 / L4 等，大扇区全片擦除可达数十秒
+    V3,
+}
+
+/// 由 GET 返回的版本号识别的 Bootloader 协议
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+pub struct BootloaderProtocol {
+    /// 版本字节，高 4 位为主版本，如 0x31 表示 V3.1
+    pub version: u8,
+    pub variant: ProtocolVariant,
+}
+
+impl BootloaderProtocol {
+    pub fn from_version(version: u8) -> Self {
+        let variant = match version >> 4 {
+            0 | 1 => ProtocolVariant::V1,
+            2 => ProtocolVariant::V2,
+            _ => ProtocolVariant::V3,
+        };
+        Self { version, variant }
+    }
+
+    /// 该协议版本的默认超时，取代与版本无关的固定值；已知芯片时擦除超时按其整片擦除耗时估算
+    pub fn timing(&self, chip: Option<&chipdb::ChipInfo>) -> Timeouts {
+        let (reply_ms, erase_s) = match self.variant {
+            ProtocolVariant::V1 => (1500, 40),
+            ProtocolVariant::V2 => (800, 25),
+            ProtocolVariant::V3 => (800, 60),
+        };
+        let reply = Some(Duration::from_millis(reply_ms));
+        Timeouts {
+            sync: reply,
+            ack: reply,
+            erase: Some(chip.map_or(
+                Duration::from_secs(erase_s),
+                chipdb::ChipInfo::erase_timeout,
+            )),
+            read_chunk: reply,
+        }
+    }
+
+    /// 如 "v3.1"
+    pub fn label(&self) -> String {
+        format!("v{}.{}", self.version >> 4, self.version & 0x0F)
+    }
+
+    /// `requested` 中未设置的超时改用协议和芯片相关的默认值，用户显式设置的保持不变
+    fn apply_timing(
+        &self,
+        options: &mut FlashOptions,
+        requested: Timeouts,
+        product_id: Option<u16>,
+    ) {
+        let chip = product_id.and_then(chipdb::lookup);
+        options.timeouts = requested.or_defaults(self.timing(chip));
+        info!(
+            "{}",
+            tr(
+                Msg::ProtocolTiming,
+                &[
+                    &self.label(),
+                    &options.timeouts.ack().as_millis(),
+                    &options.timeouts.erase().as_secs(),
+                ],
+            )
+        );
+    }
+}
+
+fn get_version(port: &mut dyn Transport, timeout: Duration) -> Result<VersionInfo> {
+    send_cmd(port, CMD_GET_VERSION, timeout)?;
+
+    let mut bytes = [0u8; 3];
+    read_exact_with_timeout(port, &mut bytes, timeout)?;
+
+    expect_ack(port, timeout)?;
+    Ok(VersionInfo {
+        version: bytes[0],
+        read_protection_disabled: bytes[1],
+        read_protection_enabled: bytes[2],
+    })
+}
+
+/// 读 Flash 起始处探测读保护：NACK 说明 RDP 生效，其他错误无法判断
+fn probe_read_protection(port: &mut dyn Transport, timeout: Duration) -> Option<bool> {
+    match read_memory(port, FLASH_BASE, 4, timeout) {
+        Ok(_) => Some(false),
+        Err(Error::Nack) => Some(true),
+        Err(_) => None,
+    }
+}
+
+/// 读取 Flash 容量寄存器，返回字节数；芯片不在数据库中、不支持或读保护拒绝读取、
+/// 寄存器未编程（0 / 0xFFFF）时为 None
+fn read_flash_size(
+    port: &mut dyn Transport,
+    commands: &[u8],
+    product_id: Option<u16>,
+    timeout: Duration,
+) -> Option<u32> {
+    let chip = chipdb::lookup(product_id?)?;
+    if !commands.contains(&CMD_READ_MEMORY) {
+        return None;
+    }
+    let raw = read_memory(port, chip.flash_size_reg, 2, timeout).ok()?;
+    match u16::from_le_bytes([raw[0], raw[1]]) {
+        0 | 0xFFFF => None,
+        kb => Some(kb as u32 * 1024),
+    }
+}
+
+/// 返回 GET_ID 的原始字节，长度由设备声明（通常为 2 字节）
+fn get_id(port: &mut dyn Transport, timeout: Duration) -> Result<Vec<u8>> {
+    send_cmd(port, CMD_GET_ID, timeout)?;
+
+    let n = read_byte_with_timeout(port, timeout)? as usize;
+    let mut pid_bytes = vec![0u8; n + 1];
+    read_exact_with_timeout(port, &mut pid_bytes, timeout)?;
+
+    expect_ack(port, timeout)?;
+    Ok(pid_bytes)
+}
+
+/// 从 GET_ID 原始字节中取出 16 位 PID（前两个字节，高位在前）
+fn canonical_pid(raw: &[u8]) -> Option<u16> {
+    match raw {
+        [] => None,
+        [single] => Some(*single as u16),
+        [msb, lsb, ..] => Some(u16::from_be_bytes([*msb, *lsb])),
+    }
+}
+
+/// 按选项打开端口：已注册的演示端口名（见 `sim::DemoPort`）连接到模拟 Bootloader
+pub fn open_port_with(
+    port_name: &str,
+    baud_rate: u32,
+    options: &FlashOptions,
+) -> Result<Box<dyn Transport>> {
+    if let Some(port) = sim::open_demo(port_name, baud_rate, options.timeouts.ack()) {
+        return Ok(Box::new(BufferedPort::new(Box::new(port))));
+    }
+    open_port(port_name, baud_rate, options.timeouts.ack())
+}
+
+pub fn open_port(
+    port_name: &str,
+    baud_rate: u32,
+    read_timeout: Duration,
+) -> Result<Box<dyn Transport>> {
+    let port_name = &resolve_port(port_name)?;
+    #[allow(unused_mut)] // macOS need
+    let mut p = serialport::new(port_name, baud_rate)
+        .timeout(read_timeout)
+        .data_bits(DataBits::Eight)
+        .stop_bits(StopBits::One)
+        .parity(Parity::Even)
+        .flow_control(FlowControl::None)
+        .open_native()
+        .map_err(|e| open_error(port_name, e))?;
+
+    // macOS 特定初始化
+    #[cfg(target_os = "macos")]
+    {
+        p.set_dtr(false)?;
+        p.set_rts(false)?;
+
+        std::thread::sleep(Duration::from_millis(200));
+
+        let _ = p.clear();
+        std::thread::sleep(Duration::from_millis(50));
+    }
+
+    Ok(Box::new(BufferedPort::new(Box::new(p))))
+}
+
+pub fn apply_boot_mode(
+    port: &mut dyn Transport,
+    boot_mode: BootMode,
+    lines: BootLineConfig,
+    timing: BootTiming,
+) -> Result<()> {
+    let Some((reset_line, boot_line)) = boot_mode.wiring() else {
+        return Ok(());
+    };
+    let reset_level = lines.reset_assert_level;
+
+    // 先释放复位
+    set_line(port, reset_line, !reset_level)?;
+    std::thread::sleep(timing.settle);
+
+    // 拉起 BOOT0
+    if let Some(boot_line) = boot_line {
+        set_line(port, boot_line, lines.boot_level)?;
+        std::thread::sleep(Duration::from_millis(50));
+    }
+
+    // 复位脉冲
+    set_line(port, reset_line, reset_level)?;
+    std::thread::sleep(timing.reset_pulse);
+
+    set_line(port, reset_line, !reset_level)?;
+    std::thread::sleep(timing.boot_delay);
+
+    Ok(())
+}
+
+/// 不执行 Boot 序列直接同步一次：Flash 为空的全新芯片（以及上次停留在 Bootloader 的芯片）
+/// 会应答；无应答时关闭串口返回 None，再按正常流程连接
+fn probe_virgin(
+    port_name: &str,
+    options: &FlashOptions,
+    timeline: &mut PhaseTimeline,
+) -> Result<Option<Box<dyn Transport>>> {
+    let started = Instant::now();
+    let mut port = open_port_with(port_name, options.baud_rate, options)?;
+    let attempt = FlashOptions {
+        sync_retry: RetryPolicy {
+            attempts: 2,
+            ..options.sync_retry
+        },
+        ..options.clone()
+    };
+    let synced = sync(&mut *port, &attempt);
+    timeline.add(FlashPhase::Sync, started.elapsed());
+    match synced {
+        Ok(()) => Ok(Some(port)),
+        Err(Error::NoSync { .. }) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// 先用该设备上次成功的连接参数连接，无响应再按 `options` 连接（含自动波特率），
+/// 成功后记住实际使用的参数；返回端口和实际生效的选项
+fn connect_remembered(
+    port_name: &str,
+    options: &FlashOptions,
+    timeline: &mut PhaseTimeline,
+) -> Result<(Box<dyn Transport>, FlashOptions)> {
+    let cache = options
+        .remember_connection
+        .as_deref()
+        .and_then(|path| Some((path, devicedb::connection_key(port_name)?)));
+    let Some((path, key)) = cache else {
+        let (port, baud) = connect_timed(port_name, options, timeline)?;
+        return Ok((
+            port,
+            FlashOptions {
+                baud_rate: baud,
+                ..options.clone()
+            },
+        ));
+    };
+
+    let mut db = ConnectionDb::load(path).unwrap_or_default();
+    if let Some(profile) = db.profiles.get(&key).filter(|p| !p.same_as(options)) {
+        info!(
+            "{}",
+            tr(
+                Msg::TryingRemembered,
+                &[&profile.baud_rate, &format!("{:?}", profile.boot_mode)],
+            )
+        );
+        let remembered = profile.apply(options);
+        let attempt = FlashOptions {
+            auto_baud: false,
+            sync_retry: RetryPolicy {
+                attempts: 2,
+                ..remembered.sync_retry
+            },
+            ..remembered.clone()
+        };
+        match connect_timed(port_name, &attempt, timeline) {
+            Ok((port, _)) => return Ok((port, remembered)),
+            Err(Error::NoSync { .. } | Error::BootEntry(_)) => {
+                warn!("{}", tr(Msg::RememberedFailed, &[]));
+            }
+            Err(e) => return Err(e),
+        }
+    }
+
+    let (port, baud) = connect_timed(port_name, options, timeline)?;
+    let used = FlashOptions {
+        baud_rate: baud,
+        ..options.clone()
+    };
+    if db.profiles.get(&key).is_none_or(|p| !p.same_as(&used)) {
+        db.profiles
+            .insert(key, ConnectionProfile::from_options(&used));
+        if let Err(e) = db.save(path) {
+            warn!("{}", tr(Msg::ConnectionSaveFailed, &[&e]));
+        }
+    }
+    Ok((port, used))
+}
+
+/// 连接的第一步：执行 Boot 模式序列，让芯片进入 Bootloader
+///
+/// 控制线无法设置时返回 `Error::BootEntry`，此时芯片没有被复位。
+pub fn enter_bootloader(port: &mut dyn Transport, options: &FlashOptions) -> Result<()> {
+    apply_boot_mode(port, options.boot_mode, options.lines, options.boot_timing)
+        .map_err(|e| Error::BootEntry(e.to_string()))
+}
+
+/// 打开串口并连接 Bootloader，返回端口和实际使用的波特率
+///
+/// 启用 `auto_baud` 时，若配置的波特率收不到 ACK，会按 `BAUD_LADDER`
+/// 重新打开串口并重新进入 Bootloader，直到设备响应。
+pub fn open_and_connect(
+    port_name: &str,
+    options: &FlashOptions,
+) -> Result<(Box<dyn Transport>, u32)> {
+    connect_timed(port_name, options, &mut PhaseTimeline::default())
+}
+
+/// 同 `open_and_connect`，把进入 Bootloader 和同步的耗时累加到 `timeline`
+fn connect_timed(
+    port_name: &str,
+    options: &FlashOptions,
+    timeline: &mut PhaseTimeline,
+) -> Result<(Box<dyn Transport>, u32)> {
+    let _span = info_span!("connect", port = port_name, baud = options.baud_rate).entered();
+    let mut started = Instant::now();
+    let mut port = open_port_with(port_name, options.baud_rate, options)?;
+    if let Some(power) = options.power_cycle.as_ref().filter(|p| p.before) {
+        hold_boot_line(&mut *port, options, true)?;
+        power.cycle()?;
+    }
+
+    let cycles = options.boot_cycles.max(1);
+    let mut backoff = options.boot_backoff;
+    let mut cycle = 1;
+    let err = loop {
+        options.cancel.check()?;
+        enter_bootloader(&mut *port, options)?;
+        timeline.add(FlashPhase::BootEntry, started.elapsed());
+        let sync_started = Instant::now();
+        let synced = sync(&mut *port, options);
+        timeline.add(FlashPhase::Sync, sync_started.elapsed());
+        started = Instant::now();
+        match synced {
+            Ok(()) => {
+                if options.auto_baud {
+                    info!("{}", tr(Msg::BaudAnswered, &[&options.baud_rate]));
+                }
+                return Ok((port, options.baud_rate));
+            }
+            Err(Error::NoSync { .. }) if cycle < cycles => {
+                warn!(
+                    "{}",
+                    tr(
+                        Msg::BootCycleRetry,
+                        &[&cycle, &cycles, &backoff.as_millis()],
+                    )
+                );
+                std::thread::sleep(backoff);
+                backoff *= 2;
+                cycle += 1;
+            }
+            Err(e @ Error::NoSync { .. }) if options.auto_baud => break e,
+            Err(e) => return Err(e),
+        }
+    };
+    drop(port);
+
+    warn!("{}", tr(Msg::AutoBaudStart, &[&options.baud_rate]));
+
+    for &baud in BAUD_LADDER.iter().filter(|&&b| b != options.baud_rate) {
+        options.cancel.check()?;
+        info!("{}", tr(Msg::TryingBaud, &[&baud]));
+        let started = Instant::now();
+        let mut port = open_port_with(port_name, baud, options)?;
+        enter_bootloader(&mut *port, options)?;
+        timeline.add(FlashPhase::BootEntry, started.elapsed());
+
+        let started = Instant::now();
+        let synced = sync(&mut *port, options);
+        timeline.add(FlashPhase::Sync, started.elapsed());
+        match synced {
+            Ok(()) => {
+                info!("{}", tr(Msg::BaudAnswered, &[&baud]));
+                return Ok((port, baud));
+            }
+            Err(Error::NoSync { .. }) => continue,
+            Err(e) => return Err(e),
+        }
+    }
+
+    Err(err)
+}
+
+/// 原生 USB CDC 串口重新枚举的最长等待时间
+const REENUMERATE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// 端口名和 USB 序列号；须在芯片复位前记录，复位后原生 USB CDC 端口会消失
+#[derive(Debug, Clone)]
+struct PortIdentity {
+    name: String,
+    usb_serial: Option<String>,
+}
+
+impl PortIdentity {
+    fn of(port_name: &str) -> Self {
+        let usb_serial = list_ports()
+            .ok()
+            .and_then(|ports| ports.into_iter().find(|p| p.port_name == port_name))
+            .and_then(|p| p.serial);
+        Self {
+            name: port_name.to_string(),
+            usb_serial,
+        }
+    }
+
+    /// 重新打开端口；端口已消失时按 USB 序列号（没有则按端口名）等待重新枚举
+    fn reopen(&self, baud: u32, options: &FlashOptions) -> Result<Box<dyn Transport>> {
+        match open_port_with(&self.name, baud, options) {
+            Err(Error::PortNotFound(_)) => {}
+            other => return other,
+        }
+
+        let spec = self.usb_serial.as_deref().unwrap_or(&self.name);
+        info!("{}", tr(Msg::UsbReenumerating, &[&spec]));
+        let start = Instant::now();
+        loop {
+            let found = list_ports()?.into_iter().find(|p| p.matches(spec));
+            if let Some(p) = found {
+                // 旧端口可能尚未完全移除，打不开时继续等待
+                match open_port_with(&p.port_name, baud, options) {
+                    Err(Error::PortNotFound(_)) => {}
+                    other => {
+                        info!("{}", tr(Msg::DeviceDetected, &[&p.label]));
+                        return other;
+                    }
+                }
+            }
+
+            if start.elapsed() >= REENUMERATE_TIMEOUT {
+                return Err(Error::WaitTimeout(spec.to_string()));
+            }
+            std::thread::sleep(Duration::from_millis(200));
+        }
+    }
+}
+
+/// 芯片自行复位（读保护变更等）后，重新打开串口并进入 Bootloader
+fn reconnect_after_reset(
+    mut port: Box<dyn Transport>,
+    id: &PortIdentity,
+    baud: u32,
+    options: &FlashOptions,
+) -> Result<Box<dyn Transport>> {
+    info!("{}", tr(Msg::WaitingForReset, &[]));
+    std::thread::sleep(options.boot_timing.boot_delay);
+    let link = linkstats::of(&mut *port);
+    drop(port);
+
+    let mut port = id.reopen(baud, options)?;
+    if let Some(stats) = port.link_stats() {
+        *stats = link;
+    }
+    enter_bootloader(&mut *port, options)?;
+    sync(&mut *port, options)?;
+    Ok(port)
+}
+
+/// 设置读保护并在芯片复位后确认读取已被拒绝
+fn lock_and_verify(
+    mut port: Box<dyn Transport>,
+    id: &PortIdentity,
+    baud: u32,
+    cmds: &[u8],
+    options: &FlashOptions,
+) -> Result<Box<dyn Transport>> {
+    if !cmds.contains(&CMD_READOUT_PROTECT) {
+        return Err(Error::CommandNotSupported(CMD_READOUT_PROTECT));
+    }
+
+    info!("{}", tr(Msg::SettingRdp, &[]));
+    readout_protect(&mut *port, options.timeouts.ack(), options.timeouts.erase())?;
+
+    let mut port = reconnect_after_reset(port, id, baud, options)?;
+    match read_memory(&mut *port, FLASH_BASE, 4, options.timeouts.read_chunk()) {
+        Err(Error::Nack) => {
+            info!("{}", tr(Msg::RdpActive, &[]));
+            Ok(port)
+        }
+        Ok(_) => Err(Error::ProtectionNotApplied),
+        Err(e) => Err(e),
+    }
+}
+
+/// 解除写保护并在芯片复位后重新进入 Bootloader
+fn unprotect_and_reconnect(
+    mut port: Box<dyn Transport>,
+    id: &PortIdentity,
+    baud: u32,
+    cmds: &[u8],
+    options: &FlashOptions,
+) -> Result<Box<dyn Transport>> {
+    if !cmds.contains(&CMD_WRITE_UNPROTECT) {
+        return Err(Error::CommandNotSupported(CMD_WRITE_UNPROTECT));
+    }
+    info!("{}", tr(Msg::RemovingWrp, &[]));
+    write_unprotect_cmd(&mut *port, options.timeouts.ack(), options.timeouts.erase())?;
+    reconnect_after_reset(port, id, baud, options)
+}
+
+/// 对指定扇区开启写保护并在芯片复位后重新进入 Bootloader
+fn protect_and_reconnect(
+    mut port: Box<dyn Transport>,
+    id: &PortIdentity,
+    baud: u32,
+    cmds: &[u8],
+    sectors: &[u8],
+    options: &FlashOptions,
+) -> Result<Box<dyn Transport>> {
+    if !cmds.contains(&CMD_WRITE_PROTECT) {
+        return Err(Error::CommandNotSupported(CMD_WRITE_PROTECT));
+    }
+    let list = sectors
+        .iter()
+        .map(u8::to_string)
+        .collect::<Vec<_>>()
+        .join(",");
+    info!("{}", tr(Msg::ApplyingWrp, &[&list]));
+    write_protect_cmd(
+        &mut *port,
+        sectors,
+        options.timeouts.ack(),
+        options.timeouts.erase(),
+    )?;
+    reconnect_after_reset(port, id, baud, options)
+}
+
+/// 双 Bank 器件上第 `bank` 个 Bank 的范围；单 Bank 器件或 Bank 号无效时报错
+fn dual_bank_range(chip: &chipdb::ChipInfo, bank: u8) -> Result<(u32, u32)> {
+    if chip.flash_banks.len() < 2 {
+        return Err(Error::Bank(format!(
+            "{} has a single flash bank",
+            chip.name
+        )));
+    }
+    chip.bank_range(bank).ok_or_else(|| {
+        Error::Bank(format!(
+            "{} has banks 1..={}, not {bank}",
+            chip.name,
+            chip.flash_banks.len()
+        ))
+    })
+}
+
+fn empty_check(product_id: Option<u16>) -> bool {
+    product_id
+        .and_then(chipdb::lookup)
+        .is_some_and(|chip| chip.quirks().contains(&chipdb::ChipQuirk::EmptyCheck))
+}
+
+/// 原样写回选项字节，触发选项字节重新加载；芯片随即复位，不再重新连接
+fn reload_option_bytes(port: &mut dyn Transport, pid: u16, options: &FlashOptions) -> Result<()> {
+    let (address, len) = option_bytes_region(pid).ok_or(Error::UnknownOptionBytes(pid))?;
+    let data = read_memory(port, address, len, options.timeouts.read_chunk())?;
+    write_memory(
+        port,
+        CMD_WRITE_MEMORY,
+        address,
+        &data,
+        options.timeouts.ack(),
+        options.flush_frames,
+    )
+}
+
+/// 选项字节区域（起始地址, 长度），按 GET_ID 返回的产品 ID 查找
+pub fn option_bytes_region(pid: u16) -> Option<(u32, usize)> {
+    chipdb::lookup(pid).map(|chip| (chip.option_bytes.0, chip.option_bytes.1 as usize))
+}
+
+/// 读取产品 ID 并定位选项字节区域
+fn detect_option_bytes(port: &mut dyn Transport, timeout: Duration) -> Result<(u32, usize)> {
+    let pid = canonical_pid(&get_id(port, timeout)?).unwrap_or(0);
+    option_bytes_region(pid).ok_or(Error::UnknownOptionBytes(pid))
+}
+
+/// 读出选项字节，原子地保存为 Intel HEX 文件（地址随文件保存）
+pub fn backup_option_bytes(port_name: &str, path: &Path, options: &FlashOptions) -> Result<()> {
+    info!("{}", tr(Msg::Connecting, &[]));
+    let (mut port, _baud) = open_and_connect(port_name, options)?;
+    let (address, len) = detect_option_bytes(&mut *port, options.timeouts.ack())?;
+
+    info!(
+        "{}",
+        tr(Msg::ReadingOptionBytes, &[&format!("0x{address:08X}")])
+    );
+    let data = match read_memory(&mut *port, address, len, options.timeouts.read_chunk()) {
+        Err(Error::Nack) => return Err(Error::ReadProtected),
+        other => other?,
+    };
+
+    let hex = image_to_hex(&addressed(address, &data)?.collect())?;
+    write_file_atomic(path, |file| {
+        Ok(std::io::Write::write_all(file, hex.as_bytes())?)
+    })?;
+    info!("{}", tr(Msg::OptionBytesSaved, &[&path.display()]));
+    Ok(())
+}
+
+/// 把备份的选项字节原样写回；芯片写入后自动复位，重新连接后读回确认
+pub fn restore_option_bytes(port_name: &str, path: &Path, options: &FlashOptions) -> Result<()> {
+    let image = parse_hex_to_image(path)?;
+    let id = PortIdentity::of(port_name);
+    info!("{}", tr(Msg::Connecting, &[]));
+    let (mut port, baud) = open_and_connect(port_name, options)?;
+    let (address, len) = detect_option_bytes(&mut *port, options.timeouts.ack())?;
+
+    let blocks = image_to_blocks(&image);
+    let data = match blocks.as_slice() {
+        [(base, data)] if *base == address && data.len() == len => data.clone(),
+        _ => {
+            return Err(Error::OptionBytesMismatch(format!(
+                "expected {len} bytes at 0x{address:08X}"
+            )));
+        }
+    };
+
+    info!("{}", tr(Msg::WritingOptionBytes, &[]));
+    write_memory(
+        &mut *port,
+        CMD_WRITE_MEMORY,
+        address,
+        &data,
+        options.timeouts.ack(),
+        options.flush_frames,
+    )?;
+
+    let mut port = reconnect_after_reset(port, &id, baud, options)?;
+    let readback = read_memory(&mut *port, address, len, options.timeouts.read_chunk())?;
+    if readback != data {
+        return Err(Error::OptionBytesMismatch(
+            "read-back differs after restore".to_string(),
+        ));
+    }
+
+    info!("{}", tr(Msg::OptionBytesRestored, &[]));
+    if options.reset_after {
+        do_hardware_reset(&mut *port, options)?;
+    }
+    Ok(())
+}
+
+/// 读出选项字节并按系列解码
+fn read_decoded_option_bytes(port: &mut dyn Transport, timeout: Duration) -> Result<OptionBytes> {
+    let pid = canonical_pid(&get_id(port, timeout)?).unwrap_or(0);
+    let chip = chipdb::lookup(pid).ok_or(Error::UnknownOptionBytes(pid))?;
+    let family = ObFamily::for_chip(chip).ok_or(Error::UnknownOptionBytes(pid))?;
+    let (address, len) = chip.option_bytes;
+    let raw = match read_memory(port, address, len as usize, timeout) {
+        Err(Error::Nack) => return Err(Error::ReadProtected),
+        other => other?,
+    };
+    OptionBytes::new(family, address, raw)
+}
+
+/// 按 `changes` 修改选项字节并写回，芯片复位后重新连接并读回确认；已是目标值时不写入。
+/// 设置 RDP Level 1 后读取会被拒绝，此时以读取被拒绝作为确认
+fn set_option_bytes_and_reconnect(
+    mut port: Box<dyn Transport>,
+    id: &PortIdentity,
+    baud: u32,
+    changes: &[(String, u32)],
+    options: &FlashOptions,
+) -> Result<(Box<dyn Transport>, OptionBytes)> {
+    let current = read_decoded_option_bytes(&mut *port, options.timeouts.ack())?;
+    let mut target = current.clone();
+    for (name, value) in changes {
+        let old = target.get(name)?;
+        target.set(name, *value)?;
+        if old != *value {
+            info!(
+                "{}",
+                tr(
+                    Msg::SettingOptionByte,
+                    &[name, &format!("0x{old:X}"), &format!("0x{value:X}")],
+                )
+            );
+        }
+    }
+    if target == current {
+        info!("{}", tr(Msg::OptionBytesUnchanged, &[]));
+        return Ok((port, current));
+    }
+
+    info!("{}", tr(Msg::WritingOptionBytes, &[]));
+    write_memory(
+        &mut *port,
+        CMD_WRITE_MEMORY,
+        target.address,
+        &target.raw,
+        options.timeouts.ack(),
+        options.flush_frames,
+    )?;
+
+    let mut port = reconnect_after_reset(port, id, baud, options)?;
+    if target.rdp() == RdpLevel::Level1 {
+        return match read_memory(&mut *port, FLASH_BASE, 4, options.timeouts.read_chunk()) {
+            Err(Error::Nack) => {
+                info!("{}", tr(Msg::RdpActive, &[]));
+                Ok((port, target))
+            }
+            Ok(_) => Err(Error::ProtectionNotApplied),
+            Err(e) => Err(e),
+        };
+    }
+    let readback = read_decoded_option_bytes(&mut *port, options.timeouts.ack())?;
+    if let Some((name, _)) = changes
+        .iter()
+        .find(|(name, value)| readback.get(name).ok() != Some(*value))
+    {
+        return Err(Error::OptionBytesMismatch(format!(
+            "{name} did not take effect"
+        )));
+    }
+    info!("{}", tr(Msg::OptionBytesProgrammed, &[]));
+    Ok((port, readback))
+}
+
+/// 读出并解码选项字节（RDP、BOR、nBOOT、看门狗、WRP 等）
+pub fn read_option_bytes(port_name: &str, options: &FlashOptions) -> Result<OptionBytes> {
+    info!("{}", tr(Msg::Connecting, &[]));
+    let (mut port, _baud) = open_and_connect(port_name, options)?;
+    let ob = read_decoded_option_bytes(&mut *port, options.timeouts.ack())?;
+    if options.reset_after {
+        do_hardware_reset(&mut *port, options)?;
+    }
+    Ok(ob)
+}
+
+/// 按字段名修改选项字节，返回写入后读回的结果；
+/// 不认识的字段、超出位宽的值和 RDP Level 2 在写入前就会被拒绝
+pub fn program_option_bytes(
+    port_name: &str,
+    changes: &[(String, u32)],
+    options: &FlashOptions,
+) -> Result<OptionBytes> {
+    let id = PortIdentity::of(port_name);
+    info!("{}", tr(Msg::Connecting, &[]));
+    let (port, baud) = open_and_connect(port_name, options)?;
+    let (mut port, ob) = set_option_bytes_and_reconnect(port, &id, baud, changes, options)?;
+    if options.reset_after && !options.stay_in_bootloader {
+        do_hardware_reset(&mut *port, options)?;
+    }
+    Ok(ob)
+}
+
+/// 设置读保护（RDP Level 1），并确认保护已生效
+pub fn lock(port_name: &str, options: &FlashOptions) -> Result<()> {
+    let id = PortIdentity::of(port_name);
+    info!("{}", tr(Msg::Connecting, &[]));
+    let (mut port, baud) = open_and_connect(port_name, options)?;
+    let (_ver, cmds) = get_info(&mut *port, options.timeouts.ack())?;
+
+    let mut port = lock_and_verify(port, &id, baud, &cmds, options)?;
+    if options.reset_after && !options.stay_in_bootloader {
+        do_hardware_reset(&mut *port, options)?;
+    }
+    Ok(())
+}
+
+/// 解除读保护：发送 Readout Unprotect，等待全片擦除和芯片复位后重新进入 Bootloader，
+/// 并通过读取 Flash 确认设备已可写
+///
+/// 会清空整个 Flash！
+pub fn unlock(port_name: &str, options: &FlashOptions) -> Result<()> {
+    warn!("{}", tr(Msg::UnlockWarning, &[]));
+
+    let id = PortIdentity::of(port_name);
+    info!("{}", tr(Msg::Connecting, &[]));
+    let (mut port, baud) = open_and_connect(port_name, options)?;
+
+    let (_ver, cmds) = get_info(&mut *port, options.timeouts.ack())?;
+    if !cmds.contains(&CMD_READOUT_UNPROTECT) {
+        return Err(Error::CommandNotSupported(CMD_READOUT_UNPROTECT));
+    }
+
+    info!("{}", tr(Msg::Unlocking, &[]));
+    readout_unprotect(&mut *port, options.timeouts.ack(), options.timeouts.erase())?;
+
+    // 芯片擦除后自动复位，重新进入 Bootloader
+    let mut port = reconnect_after_reset(port, &id, baud, options)?;
+
+    match read_memory(&mut *port, FLASH_BASE, 4, options.timeouts.read_chunk()) {
+        Ok(_) => {
+            info!("{}", tr(Msg::Unlocked, &[]));
+            Ok(())
+        }
+        Err(Error::Nack) => Err(Error::ReadProtected),
+        Err(e) => Err(e),
+    }
+}
+
+/// 解除所有扇区的写保护（芯片会复位，Flash 内容保持不变）
+pub fn write_unprotect(port_name: &str, options: &FlashOptions) -> Result<()> {
+    let id = PortIdentity::of(port_name);
+    info!("{}", tr(Msg::Connecting, &[]));
+    let (mut port, baud) = open_and_connect(port_name, options)?;
+    let (_ver, cmds) = get_info(&mut *port, options.timeouts.ack())?;
+
+    let mut port = unprotect_and_reconnect(port, &id, baud, &cmds, options)?;
+    if options.reset_after && !options.stay_in_bootloader {
+        do_hardware_reset(&mut *port, options)?;
+    }
+    Ok(())
+}
+
+/// 对指定扇区开启写保护（扇区号按芯片参考手册的 WRP 位编号）
+pub fn write_protect(port_name: &str, sectors: &[u8], options: &FlashOptions) -> Result<()> {
+    let id = PortIdentity::of(port_name);
+    info!("{}", tr(Msg::Connecting, &[]));
+    let (mut port, baud) = open_and_connect(port_name, options)?;
+    let (_ver, cmds) = get_info(&mut *port, options.timeouts.ack())?;
+
+    let mut port = protect_and_reconnect(port, &id, baud, &cmds, sectors, options)?;
+    if options.reset_after && !options.stay_in_bootloader {
+        do_hardware_reset(&mut *port, options)?;
+    }
+    Ok(())
+}
+
+/// 不连接 Bootloader，仅释放 BOOT0 并按配置的时序复位，让芯片运行用户程序
+pub fn reset(port_name: &str, options: &FlashOptions) -> Result<()> {
+    if options.boot_mode.wiring().is_none() {
+        return Err(Error::NoResetLine);
+    }
+
+    let mut port = open_port_with(port_name, options.baud_rate, options)?;
+    info!("{}", tr(Msg::Resetting, &[]));
+    do_hardware_reset(&mut *port, options)?;
+    info!("{}", tr(Msg::AppStarted, &[]));
+    Ok(())
+}
+
+/// 自动探测时依次尝试的 Boot 模式，常见接法在前
+pub const BOOT_MODE_CANDIDATES: [BootMode; 8] = [
+    BootMode::RtsLowDtrHigh,
+    BootMode::DtrLowRtsHigh,
+    BootMode::RtsLowDtrLow,
+    BootMode::DtrHighRtsLow,
+    BootMode::DtrHighRtsHigh,
+    BootMode::RtsLowOnly,
+    BootMode::RtsHighOnly,
+    BootMode::DtrHighOnly,
+];
+
+/// 依次用各 Boot 模式进入 Bootloader 并同步，返回第一个得到应答的模式
+///
+/// 每个模式只做少量同步尝试；成功后释放 BOOT0 并复位，让芯片回到用户程序。
+pub fn probe_boot_mode(port_name: &str, options: &FlashOptions) -> Result<Option<BootMode>> {
+    for mode in BOOT_MODE_CANDIDATES {
+        let mode_name = format!("{mode:?}");
+        info!("{}", tr(Msg::ProbingBootMode, &[&mode_name]));
+        let opts = FlashOptions {
+            boot_mode: mode,
+            lines: BootLineConfig::for_mode(mode),
+            sync_retry: RetryPolicy {
+                attempts: options.sync_retry.attempts.min(2),
+                ..options.sync_retry
+            },
+            boot_cycles: 1,
+            auto_baud: false,
+            remember_connection: None,
+            ..options.clone()
+        };
+        match open_and_connect(port_name, &opts) {
+            Ok((mut port, _baud)) => {
+                do_hardware_reset(&mut *port, &opts)?;
+                return Ok(Some(mode));
+            }
+            Err(Error::NoSync { .. }) => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(None)
+}
+
+pub fn identify(port_name: &str, options: &FlashOptions) -> IdentifyResult {
+    match (|| -> Result<IdentifyResult> {
+        let (mut port, _options) =
+            connect_remembered(port_name, options, &mut PhaseTimeline::default())?;
+        let (ver, cmds) = get_info(&mut *port, options.timeouts.ack())?;
+        Ok(query_identity(
+            &mut *port,
+            ver,
+            cmds,
+            options.timeouts.ack(),
+        ))
+    })() {
+        Ok(ok) => ok,
+        Err(e) => IdentifyResult::failed(&e),
+    }
+}
+
+/// GET 之后查询产品 ID、版本和读保护状态；单项查询失败时该项留空
+fn query_identity(
+    port: &mut dyn Transport,
+    ver: u8,
+    cmds: Vec<u8>,
+    timeout: Duration,
+) -> IdentifyResult {
+    let pid_raw = get_id(port, timeout).unwrap_or_default();
+    let version_info = cmds
+        .contains(&CMD_GET_VERSION)
+        .then(|| get_version(port, timeout).ok())
+        .flatten();
+    let read_protected = cmds
+        .contains(&CMD_READ_MEMORY)
+        .then(|| probe_read_protection(port, timeout))
+        .flatten();
+    let product_id = canonical_pid(&pid_raw);
+    let flash_size = if read_protected == Some(true) {
+        None
+    } else {
+        read_flash_size(port, &cmds, product_id, timeout)
+    };
+    IdentifyResult {
+        ok: true,
+        bootloader_version: Some(ver),
+        capabilities: Capabilities::from_commands(&cmds),
+        supported_commands: cmds,
+        product_id,
+        product_id_raw: pid_raw,
+        version_info,
+        read_protected,
+        protocol: Some(BootloaderProtocol::from_version(ver)),
+        flash_size,
+        error: None,
+        error_code: None,
+    }
+}
+
+/// 读取 Intel HEX；地址扩展支持 Extended Linear Address（类型 04）和旧工具输出的
+/// Extended Segment Address（类型 02，段基址 × 16，记录内偏移在 64 KB 内回绕）
+pub fn parse_hex_to_image(path: &Path) -> Result<BTreeMap<u32, u8>> {
+    let text = std::fs::read_to_string(path).map_err(|e| {
+        if e.kind() == std::io::ErrorKind::NotFound {
+            Error::HexFileNotFound(path.display().to_string())
+        } else {
+            Error::Io(e)
+        }
+    })?;
+
+    let mut image = BTreeMap::<u32, u8>::new();
+
+    let mut base: u32 = 0;
+    let mut segmented = false;
+
+    for line in text.lines() {
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let rec = ihex::Record::from_record_string(line).map_err(|e| Error::Hex(format!("{e}")))?;
+
+        match rec {
+            Record::Data { offset, value } if segmented => {
+                for (i, b) in value.into_iter().enumerate() {
+                    let address = base.wrapping_add((offset as u32 + i as u32) & 0xFFFF);
+                    image.insert(address, b);
+                }
+            }
+            Record::Data { offset, value } => {
+                // 线性地址模式下记录越过 4 GB 时报错，不回绕到 0
+                image.extend(addressed(base + offset as u32, &value)?);
+            }
+            Record::ExtendedLinearAddress(hi) => {
+                base = (hi as u32) << 16;
+                segmented = false;
+            }
+            Record::ExtendedSegmentAddress(segment) => {
+                base = (segment as u32) << 4;
+                segmented = true;
+            }
+            Record::EndOfFile => break,
+            _ => {
+                // 忽略 MVP 中不支持的记录类型
+            }
+        }
+    }
+
+    if image.is_empty() {
+        return Err(Error::HexFileEmpty);
+    }
+
+    Ok(image)
+}
+
+/// 读取 Motorola S-record（IAR / Renesas 等工具链输出），支持 S1 / S2 / S3 数据记录
+///
+/// 每条记录的校验和都会检查；S0 头、S5 / S6 计数记录忽略，遇到 S7 / S8 / S9 结束。
+pub fn parse_srec_to_image(path: &Path) -> Result<BTreeMap<u32, u8>> {
+    let text = std::fs::read_to_string(path).map_err(|e| {
+        if e.kind() == std::io::ErrorKind::NotFound {
+            Error::ImageFileNotFound(path.display().to_string())
+        } else {
+            Error::Io(e)
+        }
+    })?;
+
+    let mut image = BTreeMap::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let err = |msg: &str| Error::Srec(format!("line {}: {msg}", number + 1));
+
+        let kind = line
+            .strip_prefix(['S', 's'])
+            .and_then(|rest| rest.chars().next())
+            .ok_or_else(|| err("record must start with 'S'"))?;
+        // 类型字符不是 ASCII 时第 2 个字节不在字符边界上
+        let hex = line.get(2..).ok_or_else(|| err("invalid record type"))?;
+        if hex.len() % 2 != 0 || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(err("invalid hex digits"));
+        }
+        let bytes: Vec<u8> = (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+            .collect();
+        let (&count, body) = bytes
+            .split_first()
+            .ok_or_else(|| err("missing byte count"))?;
+        if body.len() != count as usize {
+            return Err(err("byte count does not match record length"));
+        }
+        let sum = bytes.iter().fold(0u8, |acc, &b| acc.wrapping_add(b));
+        if sum != 0xFF {
+            return Err(err("checksum mismatch"));
+        }
+
+        let address_len = match kind {
+            '1' | '9' => 2,
+            '2' | '8' => 3,
+            '3' | '7' => 4,
+            '0' | '5' | '6' => continue,
+            _ => return Err(err("unknown record type")),
+        };
+        if body.len() < address_len + 1 {
+            return Err(err("record too short"));
+        }
+        if matches!(kind, '7' | '8' | '9') {
+            break;
+        }
+
+        let (address, data) = body[..body.len() - 1].split_at(address_len);
+        let base = address.iter().fold(0u32, |acc, &b| (acc << 8) | b as u32);
+        image.extend(addressed(base, data)?);
+    }
+
+    if image.is_empty() {
+        return Err(Error::ImageEmpty);
+    }
+    Ok(image)
+}
+
+/// 镜像文件格式
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize, clap::ValueEnum,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum ImageFormat {
+    #[serde(rename = "hex")]
+    #[value(name = "hex")]
+    IntelHex,
+    /// 原始二进制，需要基地址
+    #[serde(rename = "bin")]
+    #[value(name = "bin")]
+    Binary,
+    /// ELF 可执行文件，按程序头中的可加载段放置
+    Elf,
+    /// Motorola S-record
+    Srec,
+    /// ST DfuSe 容器
+    Dfu,
+}
+
+impl ImageFormat {
+    /// 按扩展名推断：.bin 为二进制，.elf / .axf / .out 为 ELF，.srec / .s19 / .s28 / .s37 / .mot
+    /// 为 S-record，.dfu 为 DfuSe，其余为 Intel HEX
+    pub fn from_path(path: &Path) -> Self {
+        let ext = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+        match ext.as_str() {
+            "bin" => Self::Binary,
+            "elf" | "axf" | "out" => Self::Elf,
+            "srec" | "s19" | "s28" | "s37" | "mot" => Self::Srec,
+            "dfu" => Self::Dfu,
+            _ => Self::IntelHex,
+        }
+    }
+}
+
+/// 待烧录的一个镜像文件
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImageSource {
+    pub path: PathBuf,
+    pub format: ImageFormat,
+    /// Binary：写入起始地址，默认 FLASH_BASE；IntelHex / Elf：整体平移到该地址，默认使用文件中的地址
+    pub base_address: Option<u32>,
+}
+
+impl ImageSource {
+    /// 按扩展名识别格式，见 `ImageFormat::from_path`
+    pub fn from_path(path: impl Into<PathBuf>, base_address: Option<u32>) -> Self {
+        let path = path.into();
+        let format = ImageFormat::from_path(&path);
+        Self::with_format(path, format, base_address)
+    }
+
+    /// 使用指定格式，扩展名不符合惯例时使用
+    pub fn with_format(
+        path: impl Into<PathBuf>,
+        format: ImageFormat,
+        base_address: Option<u32>,
+    ) -> Self {
+        Self {
+            path: path.into(),
+            format,
+            base_address,
+        }
+    }
+
+    /// HEX 文件中的 Start Linear Address；整体平移过的镜像不使用
+    pub fn entry_point(&self) -> Result<Option<u32>> {
+        match (self.format, self.base_address) {
+            (ImageFormat::IntelHex, None) => hex_start_address(&self.path),
+            _ => Ok(None),
+        }
+    }
+
+    pub fn load(&self) -> Result<BTreeMap<u32, u8>> {
+        match self.format {
+            ImageFormat::IntelHex | ImageFormat::Elf | ImageFormat::Srec | ImageFormat::Dfu => {
+                let image = match self.format {
+                    ImageFormat::Elf => parse_elf_to_image(&self.path)?,
+                    ImageFormat::Srec => parse_srec_to_image(&self.path)?,
+                    ImageFormat::Dfu => parse_dfu_to_image(&self.path)?,
+                    _ => parse_hex_to_image(&self.path)?,
+                };
+                match self.base_address {
+                    Some(base) => {
+                        let first = image.first_key_value().map(|(&a, _)| a).unwrap_or(0);
+                        offset_image(image, i64::from(base) - i64::from(first))
+                    }
+                    None => Ok(image),
+                }
+            }
+            ImageFormat::Binary => {
+                parse_bin_to_image(&self.path, self.base_address.unwrap_or(FLASH_BASE))
+            }
+        }
+    }
+}
+
+/// 读取原始二进制文件，从 `base` 开始连续放置
+pub fn parse_bin_to_image(path: &Path, base: u32) -> Result<BTreeMap<u32, u8>> {
+    let data = std::fs::read(path).map_err(|e| {
+        if e.kind() == std::io::ErrorKind::NotFound {
+            Error::ImageFileNotFound(path.display().to_string())
+        } else {
+            Error::Io(e)
+        }
+    })?;
+
+    Ok(addressed(base, &data)?.collect())
+}
+
+/// `data` 从 `base` 起连续放置时的 (地址, 字节)；末尾超出 32 位地址空间时报错
+pub(crate) fn addressed(base: u32, data: &[u8]) -> Result<impl Iterator<Item = (u32, u8)> + '_> {
+    let overflow = || Error::AddressOverflow {
+        base,
+        len: data.len(),
+    };
+    let last = u32::try_from(data.len().saturating_sub(1)).map_err(|_| overflow())?;
+    base.checked_add(last).ok_or_else(overflow)?;
+    Ok((0..data.len() as u32)
+        .map(move |i| base + i)
+        .zip(data.iter().copied()))
+}
+
+/// 镜像中 `addr` 处的小端 32 位字，任一字节缺失时为 None
+fn image_word(image: &BTreeMap<u32, u8>, addr: u32) -> Option<u32> {
+    addr.checked_add(3)?;
+    let bytes: Option<Vec<u8>> = (0..4).map(|i| image.get(&(addr + i)).copied()).collect();
+    Some(u32::from_le_bytes(bytes?.try_into().ok()?))
+}
+
+const PT_LOAD: u32 = 1;
+
+/// 读取 32 位小端 ELF（Cortex-M 工具链的输出），把 PT_LOAD 段的文件内容按物理地址（LMA）放置
+///
+/// 只取 `p_filesz` 部分；`.bss` 等仅占内存的尾部不写入 Flash。`.data` 的初值按 LMA 落在 Flash 中，
+/// 与 objcopy 生成的 HEX 一致。
+pub fn parse_elf_to_image(path: &Path) -> Result<BTreeMap<u32, u8>> {
+    let data = std::fs::read(path).map_err(|e| {
+        if e.kind() == std::io::ErrorKind::NotFound {
+            Error::ImageFileNotFound(path.display().to_string())
+        } else {
+            Error::Io(e)
+        }
+    })?;
+    let err = |msg: &str| Error::Elf(msg.to_string());
+
+    if data.len() < 52 || data[..4] != *b"\x7fELF" {
+        return Err(err("not an ELF file"));
+    }
+    if data[4] != 1 || data[5] != 1 {
+        return Err(err("only 32-bit little-endian ELF is supported"));
+    }
+    let u16_at = |off: usize| u16::from_le_bytes([data[off], data[off + 1]]) as usize;
+    let u32_at =
+        |off: usize| u32::from_le_bytes([data[off], data[off + 1], data[off + 2], data[off + 3]]);
+
+    let phoff = u32_at(0x1C) as usize;
+    let phentsize = u16_at(0x2A);
+    let phnum = u16_at(0x2C);
+    if phentsize < 32 || phoff + phentsize * phnum > data.len() {
+        return Err(err("program header table is truncated"));
+    }
+
+    let mut image = BTreeMap::new();
+    for i in 0..phnum {
+        let ph = phoff + i * phentsize;
+        let (p_type, offset, paddr, filesz) = (
+            u32_at(ph),
+            u32_at(ph + 4) as usize,
+            u32_at(ph + 12),
+            u32_at(ph + 16) as usize,
+        );
+        if p_type != PT_LOAD || filesz == 0 {
+            continue;
+        }
+        let bytes = data
+            .get(offset..offset + filesz)
+            .ok_or_else(|| err("segment extends past end of file"))?;
+        let bytes = addressed(paddr, bytes)
+            .map_err(|_| err("segment runs past the end of the 32-bit address space"))?;
+        for (addr, b) in bytes {
+            if image.insert(addr, b).is_some() {
+                return Err(Error::ImageOverlap(addr));
+            }
+        }
+    }
+
+    if image.is_empty() {
+        return Err(Error::ImageEmpty);
+    }
+    Ok(image)
+}
+
+/// DFU 后缀中的 CRC：标准 CRC-32 不做最终取反
+fn dfu_crc(data: &[u8]) -> u32 {
+    !crc32fast::hash(data)
+}
+
+/// 读取 ST DfuSe 容器（.dfu），把所有 target 的 element 按各自地址放置
+///
+/// 检查前缀、后缀签名和文件 CRC；element 之间重叠时报错。
+pub fn parse_dfu_to_image(path: &Path) -> Result<BTreeMap<u32, u8>> {
+    let data = std::fs::read(path).map_err(|e| {
+        if e.kind() == std::io::ErrorKind::NotFound {
+            Error::ImageFileNotFound(path.display().to_string())
+        } else {
+            Error::Io(e)
+        }
+    })?;
+    let err = |msg: &str| Error::Dfu(msg.to_string());
+    let u32_at = |off: usize| {
+        data.get(off..off + 4)
+            .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .ok_or_else(|| err("file is truncated"))
+    };
+
+    const PREFIX_LEN: usize = 11;
+    const SUFFIX_LEN: usize = 16;
+    const TARGET_PREFIX_LEN: usize = 274;
+    if data.len() < PREFIX_LEN + SUFFIX_LEN || data[..5] != *b"DfuSe" {
+        return Err(err("not a DfuSe file"));
+    }
+    let suffix = &data[data.len() - SUFFIX_LEN..];
+    if suffix[8..11] != *b"UFD" || suffix[11] as usize != SUFFIX_LEN {
+        return Err(err("missing DFU suffix"));
+    }
+    let expected_crc = u32_at(data.len() - 4)?;
+    if dfu_crc(&data[..data.len() - 4]) != expected_crc {
+        return Err(err("file CRC mismatch"));
+    }
+
+    let targets = data[10];
+    let mut offset = PREFIX_LEN;
+    let mut image = BTreeMap::new();
+    for _ in 0..targets {
+        if data.get(offset..offset + 6) != Some(b"Target".as_slice()) {
+            return Err(err("bad target prefix"));
+        }
+        let elements = u32_at(offset + 270)?;
+        offset += TARGET_PREFIX_LEN;
+        for _ in 0..elements {
+            let address = u32_at(offset)?;
+            let size = u32_at(offset + 4)? as usize;
+            offset += 8;
+            let bytes = data
+                .get(offset..offset + size)
+                .ok_or_else(|| err("element extends past end of file"))?;
+            let bytes = addressed(address, bytes)
+                .map_err(|_| err("element runs past the end of the 32-bit address space"))?;
+            for (addr, b) in bytes {
+                if image.insert(addr, b).is_some() {
+                    return Err(Error::ImageOverlap(addr));
+                }
+            }
+            offset += size;
+        }
+    }
+
+    if image.is_empty() {
+        return Err(Error::ImageEmpty);
+    }
+    Ok(image)
+}
+
+/// 固件是否整体落在 0x00000000 起的 Flash 镜像区
+pub fn image_in_alias_region(image: &BTreeMap<u32, u8>) -> bool {
+    image
+        .last_key_value()
+        .is_some_and(|(&last, _)| last < FLASH_BASE)
+}
+
+/// 将固件整体平移 `base`（镜像区固件平移 FLASH_BASE 即落到主 Flash）
+pub fn rebase_image(image: BTreeMap<u32, u8>, base: u32) -> BTreeMap<u32, u8> {
+    image
+        .into_iter()
+        .map(|(addr, b)| (addr.wrapping_add(base), b))
+        .collect()
+}
 
-    // macOS 需要更多的稳定时间
-    #[cfg(target_os = "macos")]
-    {
-        std::thread::sleep(Duration::from_millis(100));
-        let _ = port.clear(serialport::ClearBuffer::All);
-        std::thread::sleep(Duration::from_millis(50));
+/// 将固件整体平移有符号的 `offset`，平移后越出 32 位地址空间时报错
+pub fn offset_image(image: BTreeMap<u32, u8>, offset: i64) -> Result<BTreeMap<u32, u8>> {
+    let (Some((&first, _)), Some((&last, _))) = (image.first_key_value(), image.last_key_value())
+    else {
+        return Ok(image);
+    };
+    let shift = |addr: u32| u32::try_from(i64::from(addr) + offset).ok();
+    if shift(first).is_none() || shift(last).is_none() {
+        return Err(Error::ImageOffsetOutOfRange {
+            offset,
+            first,
+            last,
+        });
     }
+    Ok(image
+        .into_iter()
+        .filter_map(|(addr, b)| Some((shift(addr)?, b)))
+        .collect())
+}
 
-    // 自动波特率同步
-    let mut last_err = Error::Timeout;
-    for attempt in 1..=5 {
-        port.write_all(&[0x7F])?;
-        port.flush()?;
+/// 数据段起止与编程粒度不对齐时的处理方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum BoundaryPolicy {
+    /// 按原样分段写入
+    #[default]
+    Split,
+    /// 用 0xFF 把数据段起止扩展到粒度边界，落在同一粒度单元内的相邻段因此合并
+    Pad,
+}
 
-        // macOS 需要更长的等待时间
-        #[cfg(target_os = "macos")]
-        std::thread::sleep(Duration::from_millis(150));
+/// 写入前对镜像做的对齐填充
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Padding {
+    /// 编程粒度（字节），H7 的 Flash 字为 32 字节
+    pub granularity: u32,
+    pub boundaries: BoundaryPolicy,
+    /// 每段末尾不足一个字（4 字节）的部分用 0xFF 补齐
+    pub tail_word: bool,
+}
 
-        #[cfg(not(target_os = "macos"))]
-        std::thread::sleep(Duration::from_millis(100));
+impl Default for Padding {
+    fn default() -> Self {
+        Self {
+            granularity: 4,
+            boundaries: BoundaryPolicy::Split,
+            tail_word: false,
+        }
+    }
+}
 
-        match expect_ack(port, timeout) {
-            Ok(()) => return Ok(()),
-            Err(Error::Timeout) if attempt < 5 => {
-                std::thread::sleep(Duration::from_millis(50));
-                continue;
+/// 按填充策略在数据段周围补 0xFF，不覆盖已有数据，返回补入的字节数
+pub fn pad_image(image: &mut BTreeMap<u32, u8>, padding: &Padding) -> u64 {
+    let round_up = |addr: u64, unit: u64| addr.div_ceil(unit) * unit;
+    let granularity = u64::from(padding.granularity.max(1));
+    let mut added = 0;
+
+    for (base, data) in image_to_blocks(image) {
+        let start = u64::from(base);
+        let end = start + data.len() as u64;
+        let (start, end) = match padding.boundaries {
+            BoundaryPolicy::Pad => (start - start % granularity, round_up(end, granularity)),
+            BoundaryPolicy::Split if padding.tail_word => (start, round_up(end, 4)),
+            BoundaryPolicy::Split => continue,
+        };
+        // 不越过 32 位地址空间末尾
+        for addr in start..end.min(1 << 32) {
+            if let std::collections::btree_map::Entry::Vacant(e) = image.entry(addr as u32) {
+                e.insert(0xFF);
+                added += 1;
             }
-            Err(Error::UnexpectedResponse(_)) if attempt < 5 => {
-                // 清除旧数据并重试
-                let _ = port.clear(serialport::ClearBuffer::Input);
-                std::thread::sleep(Duration::from_millis(100));
-                continue;
+        }
+    }
+    added
+}
+
+fn apply_padding(image: &mut BTreeMap<u32, u8>, options: &FlashOptions) {
+    let added = pad_image(image, &options.padding);
+    if added > 0 {
+        info!(
+            "{}",
+            tr(Msg::Padded, &[&added, &options.padding.granularity])
+        );
+    }
+}
+
+/// 按 `--offset` 平移，再检查镜像区固件，按选项重定位或报错
+fn prepare_image(
+    mut image: BTreeMap<u32, u8>,
+    options: &FlashOptions,
+) -> Result<BTreeMap<u32, u8>> {
+    if options.offset != 0 {
+        image = offset_image(image, options.offset)?;
+        if let (Some((&first, _)), Some((&last, _))) =
+            (image.first_key_value(), image.last_key_value())
+        {
+            info!(
+                "{}",
+                tr(
+                    Msg::Offset,
+                    &[
+                        &format_offset(options.offset),
+                        &format!("0x{first:08X}"),
+                        &format!("0x{last:08X}"),
+                    ],
+                )
+            );
+        }
+    }
+    if image_in_alias_region(&image) {
+        let first = image.first_key_value().map(|(&a, _)| a).unwrap_or(0);
+        if !options.rebase_alias {
+            return Err(Error::ImageInAliasRegion(first));
+        }
+        warn!(
+            "{}",
+            tr(
+                Msg::Rebased,
+                &[
+                    &format!("0x{first:08X}"),
+                    &format!("0x{:08X}", first.wrapping_add(FLASH_BASE)),
+                ],
+            )
+        );
+        image = rebase_image(image, FLASH_BASE);
+    }
+
+    Ok(image)
+}
+
+/// 有符号偏移的十六进制写法，如 `0x8000000` / `-0x1000`
+pub fn format_offset(offset: i64) -> String {
+    let sign = if offset < 0 { "-" } else { "" };
+    format!("{sign}0x{:X}", offset.unsigned_abs())
+}
+
+/// 在固件中定位版本字符串的方式
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VersionLocator {
+    /// 查找前缀（如 "VERSION="），读取其后的可打印字符
+    Pattern(String),
+    /// 从相对固件起始地址的固定偏移读取以 NUL 结尾的字符串
+    Offset(u32),
+}
+
+const MAX_VERSION_LEN: usize = 64;
+
+/// 从 `start` 开始读取连续的可打印 ASCII 字符
+fn printable_at(image: &BTreeMap<u32, u8>, start: u32) -> Option<String> {
+    let mut s = String::new();
+    for (expected, (&addr, &b)) in (start..).zip(image.range(start..)) {
+        if addr != expected || !(0x20..0x7F).contains(&b) || s.len() >= MAX_VERSION_LEN {
+            break;
+        }
+        s.push(b as char);
+    }
+
+    (!s.is_empty()).then_some(s)
+}
+
+/// 按配置从固件中提取版本字符串
+pub fn extract_version(image: &BTreeMap<u32, u8>, locator: &VersionLocator) -> Option<String> {
+    match locator {
+        VersionLocator::Offset(offset) => {
+            let (&first, _) = image.first_key_value()?;
+            printable_at(image, first.checked_add(*offset)?)
+        }
+        VersionLocator::Pattern(pattern) => {
+            let needle = pattern.as_bytes();
+            if needle.is_empty() {
+                return None;
             }
-            Err(e) => {
-                last_err = e;
-                if attempt < 5 {
-                    let _ = port.clear(serialport::ClearBuffer::Input);
-                    std::thread::sleep(Duration::from_millis(100));
-                    continue;
+
+            image_to_blocks(image).into_iter().find_map(|(base, data)| {
+                let pos = data.windows(needle.len()).position(|w| w == needle)?;
+                printable_at(image, base + (pos + needle.len()) as u32)
+            })
+        }
+    }
+}
+
+/// 未识别芯片时向量表检查使用的范围：SRAM 最大 512 KB、F4 的 64 KB CCM、Flash 最大 2 MB
+const GENERIC_SRAM: (u32, u32) = (0x2000_0000, 512 * 1024);
+const CCM_RAM: (u32, u32) = (0x1000_0000, 64 * 1024);
+const GENERIC_FLASH_SIZE: u32 = 2 * 1024 * 1024;
+
+/// 向量表检查发现的问题，通常意味着固件是为其他型号或其他地址链接的
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VectorTableIssue {
+    /// 初始栈指针不在 RAM 中
+    StackOutsideRam(u32),
+    /// 复位向量不在 Flash 中
+    ResetOutsideFlash(u32),
+    /// 复位向量未置 Thumb 位
+    ResetNotThumb(u32),
+    /// 复位向量指向固件之外
+    ResetOutsideImage(u32),
+}
+
+impl VectorTableIssue {
+    pub fn message(&self) -> String {
+        match *self {
+            Self::StackOutsideRam(sp) => tr(Msg::VectorStackOutsideRam, &[&format!("0x{sp:08X}")]),
+            Self::ResetOutsideFlash(pc) => {
+                tr(Msg::VectorResetOutsideFlash, &[&format!("0x{pc:08X}")])
+            }
+            Self::ResetNotThumb(pc) => tr(Msg::VectorResetNotThumb, &[&format!("0x{pc:08X}")]),
+            Self::ResetOutsideImage(pc) => {
+                tr(Msg::VectorResetOutsideImage, &[&format!("0x{pc:08X}")])
+            }
+        }
+    }
+}
+
+/// 把固件开头两个字当作 Cortex-M 向量表（初始 SP、复位向量）检查
+///
+/// 给出存储器映射时按芯片的 SRAM 和 Flash 判断，否则用 STM32 的通用范围。
+/// 固件开头不足 8 字节时不检查。
+pub fn check_vector_table(
+    image: &BTreeMap<u32, u8>,
+    map: Option<&MemoryMap>,
+) -> Vec<VectorTableIssue> {
+    let Some((&start, _)) = image.first_key_value() else {
+        return Vec::new();
+    };
+    let word = |addr: u32| image_word(image, addr);
+    let (Some(sp), Some(reset)) = (word(start), start.checked_add(4).and_then(word)) else {
+        return Vec::new();
+    };
+
+    // 初始 SP 通常是 RAM 末尾的下一个地址，因此按 (start, end] 判断
+    let in_ram = |(base, size): (u32, u32)| sp > base && sp as u64 <= base as u64 + size as u64;
+    let handler = reset & !1;
+    let (ram_ok, flash_ok) = match map {
+        Some(map) => (
+            map.regions
+                .iter()
+                .filter(|r| r.kind == chipdb::RegionKind::Sram)
+                .any(|r| in_ram((r.start, r.size)))
+                || in_ram(CCM_RAM),
+            map.region_at(handler)
+                .is_some_and(|r| r.kind == chipdb::RegionKind::Flash),
+        ),
+        None => (
+            in_ram(GENERIC_SRAM) || in_ram(CCM_RAM),
+            (FLASH_BASE..FLASH_BASE + GENERIC_FLASH_SIZE).contains(&handler),
+        ),
+    };
+
+    let mut issues = Vec::new();
+    if !ram_ok {
+        issues.push(VectorTableIssue::StackOutsideRam(sp));
+    }
+    if reset & 1 == 0 {
+        issues.push(VectorTableIssue::ResetNotThumb(reset));
+    }
+    if !flash_ok {
+        issues.push(VectorTableIssue::ResetOutsideFlash(reset));
+    } else if !image.contains_key(&handler) {
+        issues.push(VectorTableIssue::ResetOutsideImage(reset));
+    }
+    issues
+}
+
+/// 固件概要，用于 inspect 和 GUI 预览
+#[derive(Debug, Clone)]
+pub struct ImageInfo {
+    pub size: usize,
+    pub start: u32,
+    pub end: u32,
+    /// 连续数据段（起始地址, 长度）
+    pub segments: Vec<(u32, usize)>,
+    pub version: Option<String>,
+    /// 按通用 STM32 范围检查向量表发现的问题
+    pub vector_issues: Vec<VectorTableIssue>,
+}
+
+pub fn inspect_image(image: &BTreeMap<u32, u8>, locator: Option<&VersionLocator>) -> ImageInfo {
+    let start = image.first_key_value().map(|(&a, _)| a).unwrap_or(0);
+    let end = image.last_key_value().map(|(&a, _)| a + 1).unwrap_or(0);
+
+    ImageInfo {
+        size: image.len(),
+        start,
+        end,
+        segments: image_to_blocks(image)
+            .into_iter()
+            .map(|(base, data)| (base, data.len()))
+            .collect(),
+        version: locator.and_then(|l| extract_version(image, l)),
+        vector_issues: check_vector_table(image, None),
+    }
+}
+
+pub(crate) fn image_to_blocks(image: &BTreeMap<u32, u8>) -> Vec<(u32, Vec<u8>)> {
+    let mut blocks: Vec<(u32, Vec<u8>)> = Vec::new();
+    let mut cur_addr: Option<u32> = None;
+    let mut cur: Vec<u8> = Vec::new();
+
+    for (&addr, &b) in image.iter() {
+        match cur_addr {
+            None => {
+                cur_addr = Some(addr);
+                cur.push(b);
+            }
+            Some(a0) => {
+                let expected = a0 + (cur.len() as u32);
+                if addr == expected {
+                    cur.push(b);
+                } else {
+                    blocks.push((a0, std::mem::take(&mut cur)));
+                    cur_addr = Some(addr);
+                    cur.push(b);
                 }
             }
         }
     }
 
-    Err(last_err)
+    if let Some(a0) = cur_addr
+        && !cur.is_empty()
+    {
+        blocks.push((a0, cur));
+    }
+
+    blocks
 }
 
-fn get_info(port: &mut dyn SerialPort, timeout: Duration) -> Result<(u8, Vec<u8>)> {
-    send_cmd(port, CMD_GET, timeout)?;
+/// 校验 GO 跳转地址：须 4 字节对齐且位于主 Flash 范围内
+pub fn validate_go_address(address: u32) -> Result<u32> {
+    let in_flash = (FLASH_BASE..FLASH_BASE + FLASH_MAX_SIZE).contains(&address);
+    if in_flash && address.is_multiple_of(4) {
+        Ok(address)
+    } else {
+        Err(Error::InvalidGoAddress(address))
+    }
+}
 
-    let n = read_byte_with_timeout(port, timeout)? as usize;
-    let version = read_byte_with_timeout(port, timeout)?;
+/// 一次 Bootloader 连接：同步并查询命令后，可在其中连续擦写多个镜像
+pub struct BootloaderSession {
+    port: Box<dyn Transport>,
+    id: PortIdentity,
+    baud: u32,
+    commands: Vec<u8>,
+    product_id: Option<u16>,
+    /// Flash 容量寄存器读出的实际容量，读不到时为 None
+    flash_size: Option<u32>,
+    protocol: BootloaderProtocol,
+    memory_map: Option<MemoryMap>,
+    /// 擦写次数跟踪用的唯一 ID，首次记录时读取
+    unique_id: Option<String>,
+    options: FlashOptions,
+    /// 本次连接各阶段的累计耗时
+    timeline: PhaseTimeline,
+    /// 未执行 Boot 序列就已在 Bootloader 中，且 Flash 为空
+    virgin: bool,
+    /// 本次会话擦除过的地址范围 [start, end)，`skip_blank` 只跳过其中的全 0xFF 帧
+    erased: Vec<(u64, u64)>,
+}
 
-    let mut cmds = vec![0u8; n];
-    let mut read_total = 0usize;
-    while read_total < cmds.len() {
-        match port.read(&mut cmds[read_total..]) {
-            Ok(0) => {}
-            Ok(k) => read_total += k,
-            Err(e) if e.kind() == std::io::ErrorKind::TimedOut => {}
-            Err(e) => return Err(Error::Io(e)),
+/// 支持 GET_ID 时读取产品 ID
+fn query_product_id(
+    port: &mut dyn Transport,
+    commands: &[u8],
+    timeout: Duration,
+) -> Result<Option<u16>> {
+    if !commands.contains(&CMD_GET_ID) {
+        return Ok(None);
+    }
+    Ok(canonical_pid(&get_id(port, timeout)?))
+}
+
+/// 芯片数据库中的存储器映射，读到 Flash 容量寄存器时按实际容量截短
+fn session_memory_map(product_id: Option<u16>, flash_size: Option<u32>) -> Option<MemoryMap> {
+    let mut map = chipdb::chip_memory_map(product_id?)?;
+    if let Some(size) = flash_size {
+        map.limit_flash(size);
+    }
+    Some(map)
+}
+
+impl BootloaderSession {
+    pub fn open(port_name: &str, options: &FlashOptions) -> Result<Self> {
+        let id = PortIdentity::of(port_name);
+        info!("{}", tr(Msg::Connecting, &[]));
+        let mut timeline = PhaseTimeline::default();
+        let virgin = if options.virgin_probe {
+            probe_virgin(port_name, options, &mut timeline)?
+        } else {
+            None
+        };
+        let virgin_port = virgin.is_some();
+        let (mut port, mut options) = match virgin {
+            Some(port) => (port, options.clone()),
+            None => connect_remembered(port_name, options, &mut timeline)?,
+        };
+        let baud = options.baud_rate;
+
+        info!("{}", tr(Msg::QueryingCommands, &[]));
+        let (version, commands) = get_info(&mut *port, options.timeouts.ack())?;
+        let protocol = BootloaderProtocol::from_version(version);
+        // 芯片型号确定前先按协议版本取默认值，解除读保护的整片擦除也需要它
+        let requested = options.timeouts;
+        options.timeouts = requested.or_defaults(protocol.timing(None));
+        if options.unprotect_before {
+            port = unprotect_and_reconnect(port, &id, baud, &commands, &options)?;
+        }
+        let product_id = query_product_id(&mut *port, &commands, options.timeouts.ack())?;
+        protocol.apply_timing(&mut options, requested, product_id);
+        let flash_size = read_flash_size(&mut *port, &commands, product_id, options.timeouts.ack());
+
+        let high_baud = options.high_baud;
+        let mut session = Self {
+            port,
+            id,
+            baud,
+            commands,
+            product_id,
+            flash_size,
+            protocol,
+            memory_map: session_memory_map(product_id, flash_size),
+            unique_id: None,
+            options,
+            timeline,
+            virgin: false,
+            erased: Vec::new(),
+        };
+        if virgin_port {
+            session.virgin = session.flash_is_empty()?;
+            let msg = if session.virgin {
+                Msg::VirginDevice
+            } else {
+                Msg::AlreadyInBootloader
+            };
+            info!("{}", tr(msg, &[]));
+        }
+        session.check_bank_swap()?;
+        if let Some(target) = high_baud.filter(|&target| target > baud) {
+            session.raise_baud(target)?;
+        }
+        Ok(session)
+    }
+
+    /// 在已打开的串口上直接同步（不执行 Boot 模式序列），用于模拟器等非系统串口
+    pub fn from_port(port: Box<dyn Transport>, options: &FlashOptions) -> Result<Self> {
+        let mut port: Box<dyn Transport> = Box::new(BufferedPort::new(port));
+        sync(&mut *port, options)?;
+        let (version, commands) = get_info(&mut *port, options.timeouts.ack())?;
+        let product_id = query_product_id(&mut *port, &commands, options.timeouts.ack())?;
+        let flash_size = read_flash_size(&mut *port, &commands, product_id, options.timeouts.ack());
+        let protocol = BootloaderProtocol::from_version(version);
+        let mut options = options.clone();
+        let requested = options.timeouts;
+        protocol.apply_timing(&mut options, requested, product_id);
+
+        let session = Self {
+            id: PortIdentity {
+                name: port.name().unwrap_or_default(),
+                usb_serial: None,
+            },
+            baud: port.baud_rate().unwrap_or(options.baud_rate),
+            port,
+            commands,
+            product_id,
+            flash_size,
+            protocol,
+            memory_map: session_memory_map(product_id, flash_size),
+            unique_id: None,
+            options,
+            timeline: PhaseTimeline::default(),
+            virgin: false,
+            erased: Vec::new(),
+        };
+        session.check_bank_swap()?;
+        Ok(session)
+    }
+
+    /// 要求交换 Bank 时，在任何擦除之前确认芯片是双 Bank 且选项字节有 BFB2 位
+    fn check_bank_swap(&self) -> Result<()> {
+        if !self.options.swap_banks {
+            return Ok(());
+        }
+        let pid = self.product_id.unwrap_or(0);
+        let chip = chipdb::lookup(pid).ok_or(Error::UnknownOptionBytes(pid))?;
+        self.bank_range(chip, 2)?;
+        let family = ObFamily::for_chip(chip).ok_or(Error::UnknownOptionBytes(pid))?;
+        if family.field("bfb2").is_none() {
+            return Err(Error::Bank(format!(
+                "{} has no BFB2 option bit, banks cannot be swapped",
+                chip.name
+            )));
+        }
+        Ok(())
+    }
+
+    /// 第 `bank` 个 Bank 的范围，按实际 Flash 容量划分：两个 Bank 等大的系列各占一半，
+    /// 否则 Bank 1 大小不变，Bank 2 为剩余容量
+    fn bank_range(&self, chip: &chipdb::ChipInfo, bank: u8) -> Result<(u32, u32)> {
+        let range = dual_bank_range(chip, bank)?;
+        let Some(measured) = self.flash_size.filter(|&size| size < chip.flash_size()) else {
+            return Ok(range);
+        };
+        let (_, first) = dual_bank_range(chip, 1)?;
+        let (_, second) = dual_bank_range(chip, 2)?;
+        let first = if first == second {
+            measured / 2
+        } else {
+            first.min(measured)
+        };
+        Ok(if bank == 1 {
+            (FLASH_BASE, first)
+        } else {
+            (FLASH_BASE + first, measured - first)
+        })
+    }
+
+    /// 实际建立连接的波特率（自动探测时可能与配置不同）
+    pub fn baud(&self) -> u32 {
+        self.baud
+    }
+
+    /// 切换到更高的波特率，返回实际生效的波特率
+    ///
+    /// ROM Bootloader 只在同步时检测一次波特率，所以串口切换到 `target` 后要重新执行
+    /// Boot 序列并同步。适配器不支持该波特率、或新波特率下同步失败时回到原波特率；
+    /// Boot 模式为 None 时无法复位芯片，保持原波特率。
+    pub fn raise_baud(&mut self, target: u32) -> Result<u32> {
+        let baud = self.baud;
+        if self.options.boot_mode == BootMode::None {
+            warn!("{}", tr(Msg::BaudRaiseNeedsReset, &[&baud]));
+            return Ok(baud);
+        }
+        if let Err(e) = self.port.set_baud_rate(target) {
+            warn!("{}", tr(Msg::BaudUnsupported, &[&target, &e, &baud]));
+            return Ok(baud);
+        }
+
+        info!("{}", tr(Msg::RaisingBaud, &[&target]));
+        let attempt = FlashOptions {
+            baud_rate: target,
+            sync_retry: RetryPolicy {
+                attempts: self.options.sync_retry.attempts.min(2),
+                ..self.options.sync_retry
+            },
+            ..self.options.clone()
+        };
+        match self.resync(&attempt) {
+            Ok(()) => {
+                info!("{}", tr(Msg::BaudAnswered, &[&target]));
+                self.baud = target;
+                self.options.baud_rate = target;
+                Ok(target)
+            }
+            Err(Error::NoSync { .. }) => {
+                warn!("{}", tr(Msg::BaudRaiseFailed, &[&target, &baud]));
+                self.port.set_baud_rate(baud)?;
+                let options = self.options.clone();
+                self.resync(&options)?;
+                Ok(baud)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// 重新执行 Boot 序列并同步，耗时计入时间线
+    fn resync(&mut self, options: &FlashOptions) -> Result<()> {
+        let started = Instant::now();
+        enter_bootloader(&mut *self.port, options)?;
+        self.timeline.add(FlashPhase::BootEntry, started.elapsed());
+        let started = Instant::now();
+        let synced = sync(&mut *self.port, options);
+        self.timeline.add(FlashPhase::Sync, started.elapsed());
+        synced
+    }
+
+    /// 到目前为止各阶段的累计耗时
+    pub fn timeline(&self) -> &PhaseTimeline {
+        &self.timeline
+    }
+
+    pub fn commands(&self) -> &[u8] {
+        &self.commands
+    }
+
+    pub fn options(&self) -> &FlashOptions {
+        &self.options
+    }
+
+    pub fn product_id(&self) -> Option<u16> {
+        self.product_id
+    }
+
+    /// 连接时芯片未经 Boot 序列就在 Bootloader 中且 Flash 为空（见 `FlashOptions::virgin_probe`）
+    pub fn is_virgin(&self) -> bool {
+        self.virgin
+    }
+
+    /// Flash 起始的初始 SP 字是否为空（0xFFFFFFFF）
+    fn flash_is_empty(&mut self) -> Result<bool> {
+        let word = read_memory(
+            &mut *self.port,
+            FLASH_BASE,
+            4,
+            self.options.timeouts.read_chunk(),
+        )?;
+        Ok(word.iter().all(|&b| b == 0xFF))
+    }
+
+    /// Flash 容量寄存器读出的实际容量（字节）
+    pub fn flash_size(&self) -> Option<u32> {
+        self.flash_size
+    }
+
+    pub fn protocol(&self) -> BootloaderProtocol {
+        self.protocol
+    }
+
+    /// 重新查询芯片信息（产品 ID、版本、读保护、Flash 容量），结果与 `identify` 相同
+    pub fn info(&mut self) -> IdentifyResult {
+        query_identity(
+            &mut *self.port,
+            self.protocol.version,
+            self.commands.clone(),
+            self.options.timeouts.ack(),
+        )
+    }
+
+    /// 按连接到的芯片检查向量表，发现问题时逐条告警（不中止烧录）
+    pub fn check_vector_table(&self, image: &BTreeMap<u32, u8>) {
+        // 从外部存储器开始的镜像（资源数据、XIP 代码）不含启动向量表
+        if image
+            .first_key_value()
+            .is_some_and(|(&start, _)| self.external_for(start, 1).is_some())
+        {
+            return;
+        }
+        let issues = check_vector_table(image, self.memory_map());
+        if issues.is_empty() {
+            return;
+        }
+        let chip = self.memory_map().map_or("STM32", |map| map.chip);
+        warn!("{}", tr(Msg::VectorTableSuspicious, &[&chip]));
+        for issue in issues {
+            warn!("  {}", issue.message());
+        }
+    }
+
+    /// 按连接到的芯片和实际波特率分析待写入的镜像
+    pub fn plan(&self, image: &BTreeMap<u32, u8>) -> WritePlan {
+        let chip = self.product_id.and_then(chipdb::lookup);
+        WritePlan::analyze(image, chip, &self.options, self.baud)
+    }
+
+    /// 芯片数据库中登记的存储器映射，未知芯片为 None
+    pub fn memory_map(&self) -> Option<&MemoryMap> {
+        self.memory_map.as_ref()
+    }
+
+    /// 完全包含 [address, address + len) 的外部存储器
+    fn external_for(&self, address: u32, len: usize) -> Option<&ExternalMemory> {
+        self.options
+            .external_memory
+            .iter()
+            .find(|ext| ext.contains(address, len))
+    }
+
+    /// 按存储器映射检查访问范围；未知芯片不做检查。外部存储器范围内只要求 Bootloader 支持其写入命令
+    fn check_range(&self, address: u32, len: usize, write: bool) -> Result<()> {
+        if let Some(ext) = self.external_for(address, len) {
+            if write && !self.commands.contains(&ext.write_command) {
+                return Err(Error::CommandNotSupported(ext.write_command));
+            }
+            return Ok(());
+        }
+        let Some(map) = &self.memory_map else {
+            return Ok(());
+        };
+        if map.covers(address, len, |r| !write || r.kind.writable()) {
+            return Ok(());
+        }
+        Err(Error::AddressOutOfRange {
+            address,
+            len,
+            access: if write { "writable" } else { "readable" },
+            chip: map.chip,
+        })
+    }
+
+    /// 按 `options.bank` 把镜像放到目标 Bank：A/B 升级的应用始终链接在 FLASH_BASE，
+    /// 写入时平移到另一个 Bank，由 Bank 交换决定运行哪一份；已在目标 Bank 内的镜像不变
+    pub fn place_in_bank<'a>(
+        &self,
+        image: &'a BTreeMap<u32, u8>,
+    ) -> Result<Cow<'a, BTreeMap<u32, u8>>> {
+        let Some(bank) = self.options.bank else {
+            return Ok(Cow::Borrowed(image));
+        };
+        let pid = self.product_id.unwrap_or(0);
+        let chip = chipdb::lookup(pid).ok_or(Error::UnknownEraseLayout(pid))?;
+        let (start, size) = self.bank_range(chip, bank)?;
+        let within = |(first, last): (u32, u32), (base, len): (u32, u32)| {
+            first >= base && u64::from(last) < u64::from(base) + u64::from(len)
+        };
+        let (Some((&first, _)), Some((&last, _))) =
+            (image.first_key_value(), image.last_key_value())
+        else {
+            return Ok(Cow::Borrowed(image));
+        };
+        if within((first, last), (start, size)) {
+            return Ok(Cow::Borrowed(image));
+        }
+        let other = if bank == 1 { 2 } else { 1 };
+        let (other_start, other_size) = self.bank_range(chip, other)?;
+        if !within((first, last), (other_start, other_size.min(size))) {
+            return Err(Error::Bank(format!(
+                "image 0x{first:08X}..=0x{last:08X} does not fit in bank {bank} (0x{start:08X}, {} KB)",
+                size / 1024
+            )));
+        }
+        info!(
+            "{}",
+            tr(Msg::PlacedInBank, &[&bank, &format!("0x{start:08X}")])
+        );
+        Ok(Cow::Owned(
+            image
+                .iter()
+                .map(|(&addr, &b)| (addr - other_start + start, b))
+                .collect(),
+        ))
+    }
+
+    /// 写入前（擦除之前）检查镜像的每个连续块都落在可写区域内，
+    /// 从 Flash 中开始却超出实际容量的块单独报告
+    pub fn check_image(&self, image: &BTreeMap<u32, u8>) -> Result<()> {
+        image_to_blocks(image).iter().try_for_each(|(base, data)| {
+            self.check_flash_end(*base, data.len())?;
+            self.check_range(*base, data.len(), true)
+        })
+    }
+
+    fn check_flash_end(&self, start: u32, len: usize) -> Result<()> {
+        let Some(map) = &self.memory_map else {
+            return Ok(());
+        };
+        let Some(flash_end) = map.flash_end() else {
+            return Ok(());
+        };
+        let end = start as u64 + len as u64;
+        if start < FLASH_BASE || start as u64 >= flash_end || end <= flash_end {
+            return Ok(());
+        }
+        if self.external_for(start, len).is_some() {
+            return Ok(());
+        }
+        Err(Error::ImageExceedsFlash {
+            start,
+            end,
+            flash_end,
+            size_kb: (flash_end - FLASH_BASE as u64) / 1024,
+            chip: map.chip,
+        })
+    }
+
+    /// 全片擦除，优先使用扩展擦除命令
+    pub fn erase_all(&mut self, sink: &dyn ProgressSink) -> Result<()> {
+        let erase_timeout = self.options.timeouts.erase();
+        let expected = self
+            .product_id
+            .and_then(chipdb::lookup)
+            .map_or(erase_timeout, |chip| chip.mass_erase_time());
+        info!(
+            "{}",
+            tr(Msg::Erasing, &[&format!("{:.1}", expected.as_secs_f32())])
+        );
+
+        let extended = self.extended_erase()?;
+        let timeout = self.options.timeouts.ack();
+        let retry = self.options.command_retry;
+        self.erase_with_progress(expected, sink, |port, heartbeat| {
+            retry.run(port, |port| {
+                if extended {
+                    extended_erase_all(port, timeout, erase_timeout, heartbeat)
+                } else {
+                    erase_all(port, timeout, erase_timeout, heartbeat)
+                }
+            })
+        })?;
+        // 容量未知时不记录，之后的写入不跳过空白帧
+        let size = self.flash_size.or_else(|| {
+            self.product_id
+                .and_then(chipdb::lookup)
+                .map(|c| c.flash_size())
+        });
+        if let Some(size) = size {
+            self.erased.push((
+                u64::from(FLASH_BASE),
+                u64::from(FLASH_BASE) + u64::from(size),
+            ));
+        }
+        Ok(())
+    }
+
+    /// 只擦除镜像覆盖的页 / 扇区，其余 Flash（如 EEPROM 模拟页）保持不变
+    ///
+    /// 页布局来自芯片数据库，产品 ID 未知时报错而不是退回全片擦除。
+    pub fn erase_for_image(
+        &mut self,
+        image: &BTreeMap<u32, u8>,
+        sink: &dyn ProgressSink,
+    ) -> Result<()> {
+        let pid = self.product_id.unwrap_or(0);
+        let chip = chipdb::lookup(pid).ok_or(Error::UnknownEraseLayout(pid))?;
+        let units: BTreeMap<u16, chipdb::EraseUnit> = image_to_blocks(image)
+            .iter()
+            .flat_map(|(base, data)| chip.erase_units_covering(*base, data.len()))
+            .map(|unit| (unit.index, unit))
+            .collect();
+        self.erase_units(units, sink)
+    }
+
+    /// 擦除覆盖 `address..address + len` 的页 / 扇区，如只擦除应用区而保留配置页
+    pub fn erase_range(&mut self, address: u32, len: usize, sink: &dyn ProgressSink) -> Result<()> {
+        self.check_range(address, len, true)?;
+        let pid = self.product_id.unwrap_or(0);
+        let chip = chipdb::lookup(pid).ok_or(Error::UnknownEraseLayout(pid))?;
+        let units = chip
+            .erase_units_covering(address, len)
+            .into_iter()
+            .map(|unit| (unit.index, unit))
+            .collect();
+        self.erase_units(units, sink)
+    }
+
+    /// 擦除双 Bank 器件的一个 Bank（从 1 开始），逐页 / 扇区擦除，另一个 Bank 中运行的固件不受影响
+    pub fn erase_bank(&mut self, bank: u8, sink: &dyn ProgressSink) -> Result<()> {
+        let pid = self.product_id.unwrap_or(0);
+        let chip = chipdb::lookup(pid).ok_or(Error::UnknownEraseLayout(pid))?;
+        let (start, size) = self.bank_range(chip, bank)?;
+        info!(
+            "{}",
+            tr(
+                Msg::ErasingBank,
+                &[&bank, &format!("0x{start:08X}"), &(size / 1024)],
+            )
+        );
+        let units = chip
+            .erase_units_covering(start, size as usize)
+            .into_iter()
+            .map(|unit| (unit.index, unit))
+            .collect();
+        self.erase_units(units, sink)
+    }
+
+    fn erase_units(
+        &mut self,
+        units: BTreeMap<u16, chipdb::EraseUnit>,
+        sink: &dyn ProgressSink,
+    ) -> Result<()> {
+        if units.is_empty() {
+            return Ok(());
+        }
+
+        let expected: Duration = units.values().map(chipdb::EraseUnit::erase_time).sum();
+        info!(
+            "{}",
+            tr(
+                Msg::ErasingPages,
+                &[&units.len(), &format!("{:.1}", expected.as_secs_f32())],
+            )
+        );
+
+        let extended = self.extended_erase()?;
+        let timeout = self.options.timeouts.ack();
+        let erase_timeout = self.options.timeouts.erase();
+        let retry = self.options.command_retry;
+        let ranges: Vec<_> = units
+            .values()
+            .map(|unit| (u64::from(unit.start), unit.end()))
+            .collect();
+        let pages: Vec<u16> = units.into_keys().collect();
+        self.erase_with_progress(expected, sink, |port, heartbeat| {
+            erase_pages(
+                port,
+                &pages,
+                extended,
+                retry,
+                timeout,
+                erase_timeout,
+                heartbeat,
+            )
+        })?;
+        self.erased.extend(ranges);
+        Ok(())
+    }
+
+    /// 按 `erase_mode` 为写入镜像擦除 Flash；外部存储器部分用其扩展擦除命令只擦除覆盖的范围，
+    /// 镜像只包含外部存储器数据时不擦除片内 Flash
+    pub fn erase_before_write(
+        &mut self,
+        image: &BTreeMap<u32, u8>,
+        sink: &dyn ProgressSink,
+    ) -> Result<()> {
+        let (external, internal): (Vec<_>, Vec<_>) = image_to_blocks(image)
+            .into_iter()
+            .partition(|(base, data)| self.external_for(*base, data.len()).is_some());
+        for (base, data) in &external {
+            self.erase_external(*base, data.len(), sink)?;
+        }
+        if internal.is_empty() && !external.is_empty() {
+            return Ok(());
+        }
+        match self.options.erase_mode {
+            EraseMode::Mass => self.erase_all(sink),
+            EraseMode::Pages => self.erase_for_image(image, sink),
+            EraseMode::Bank => self.erase_bank(self.options.bank.unwrap_or(1), sink),
+        }
+    }
+
+    /// 用外部存储器的扩展擦除命令擦除一段数据覆盖的范围，未配置擦除命令时跳过
+    fn erase_external(&mut self, address: u32, len: usize, sink: &dyn ProgressSink) -> Result<()> {
+        let Some(command) = self
+            .external_for(address, len)
+            .and_then(|ext| ext.erase_command)
+        else {
+            return Ok(());
+        };
+        if !self.commands.contains(&command) {
+            return Err(Error::CommandNotSupported(command));
         }
+        info!(
+            "{}",
+            tr(Msg::ErasingExternal, &[&format!("0x{address:08X}"), &len])
+        );
+
+        let timeout = self.options.timeouts.ack();
+        let erase_timeout = self.options.timeouts.erase();
+        let retry = self.options.command_retry;
+        self.erase_with_progress(erase_timeout, sink, |port, heartbeat| {
+            retry.run(port, |port| {
+                erase_external(
+                    port,
+                    command,
+                    address,
+                    len as u32,
+                    timeout,
+                    erase_timeout,
+                    heartbeat,
+                )
+            })
+        })?;
+        self.erased
+            .push((u64::from(address), u64::from(address) + len as u64));
+        Ok(())
     }
 
-    expect_ack(port, timeout)?;
-    Ok((version, cmds))
-}
+    /// `address..address + len` 是否整段落在本次会话擦除过的某个范围内
+    fn is_erased(&self, address: u32, len: usize) -> bool {
+        let (start, end) = (u64::from(address), u64::from(address) + len as u64);
+        self.erased.iter().any(|&(s, e)| s <= start && end <= e)
+    }
 
-fn get_id(port: &mut dyn SerialPort, timeout: Duration) -> Result<u16> {
-    send_cmd(port, CMD_GET_ID, timeout)?;
+    /// 支持 Extended Erase 时返回 true，只支持旧版 Erase 时返回 false
+    fn extended_erase(&self) -> Result<bool> {
+        if self.commands.contains(&CMD_EXTENDED_ERASE) {
+            Ok(true)
+        } else if self.commands.contains(&CMD_ERASE) {
+            Ok(false)
+        } else {
+            Err(Error::NoEraseSupport)
+        }
+    }
 
-    let n = read_byte_with_timeout(port, timeout)? as usize;
-    let mut pid_bytes = vec![0u8; n + 1];
+    /// 执行擦除；等待期间上报已用时间（毫秒），总量为 `expected`
+    fn erase_with_progress(
+        &mut self,
+        expected: Duration,
+        sink: &dyn ProgressSink,
+        erase: impl FnOnce(&mut dyn Transport, &mut dyn FnMut(Duration)) -> Result<()>,
+    ) -> Result<()> {
+        self.options.cancel.check()?;
+        let expected_ms = expected.as_millis().max(1) as u64;
+        let _span = info_span!("erase", expected_ms).entered();
+        sink.progress(&ProgressEvent::EraseStarted { expected_ms });
+        // 分多条命令擦除时按总用时计算，进度不会回退
+        let start = Instant::now();
+        let mut heartbeat = |_: Duration| {
+            // 超出预计时间时停在 99%，直到收到应答
+            let elapsed_ms = (start.elapsed().as_millis() as u64).min(expected_ms - 1);
+            sink.progress(&ProgressEvent::EraseTick {
+                elapsed_ms,
+                expected_ms,
+            });
+        };
+        let result = erase(&mut *self.port, &mut heartbeat);
+        self.timeline.add(FlashPhase::Erase, start.elapsed());
+        result.map_err(|e| self.diagnose_nack(e))?;
+        sink.progress(&ProgressEvent::Done {
+            phase: ProgressPhase::Erase,
+            total: expected_ms,
+        });
+        self.record_cycle(Cycle::Erase);
+        Ok(())
+    }
 
-    let mut read_total = 0usize;
-    while read_total < pid_bytes.len() {
-        match port.read(&mut pid_bytes[read_total..]) {
-            Ok(0) => {}
-            Ok(k) => read_total += k,
-            Err(e) if e.kind() == std::io::ErrorKind::TimedOut => {}
-            Err(e) => return Err(Error::Io(e)),
+    /// 在设备数据库中记一次擦写；记录失败只告警，不影响烧录
+    fn record_cycle(&mut self, cycle: Cycle) {
+        let Some(tracking) = self.options.cycle_tracking.clone() else {
+            return;
+        };
+        match self.try_record_cycle(&tracking, cycle) {
+            Ok(Some((uid, erases)))
+                if cycle == Cycle::Erase && erases > tracking.warn_threshold =>
+            {
+                warn!(
+                    "{}",
+                    tr(
+                        Msg::CycleThresholdExceeded,
+                        &[&uid, &erases, &tracking.warn_threshold],
+                    )
+                );
+            }
+            Ok(_) => {}
+            Err(e) => warn!("{}", tr(Msg::CycleTrackingFailed, &[&e])),
         }
     }
 
-    expect_ack(port, timeout)?;
-
-    let pid = match pid_bytes.as_slice() {
-        [msb, lsb] => u16::from_be_bytes([*msb, *lsb]),
-        [single] => *single as u16,
-        _ => return Err(Error::UnexpectedResponse(0x00)),
-    };
+    /// 返回唯一 ID 和累计擦除次数；芯片不在数据库中时不记录
+    fn try_record_cycle(
+        &mut self,
+        tracking: &CycleTracking,
+        cycle: Cycle,
+    ) -> Result<Option<(String, u64)>> {
+        let uid = match &self.unique_id {
+            Some(uid) => uid.clone(),
+            None => {
+                let Some(chip) = self.product_id.and_then(chipdb::lookup) else {
+                    return Ok(None);
+                };
+                let bytes = read_memory(
+                    &mut *self.port,
+                    chip.unique_id,
+                    12,
+                    self.options.timeouts.read_chunk(),
+                )?;
+                self.unique_id.insert(devicedb::uid_string(&bytes)).clone()
+            }
+        };
 
-    Ok(pid)
-}
+        let mut db = DeviceDb::load(&tracking.db_path)?;
+        let erases = db.record(&uid, self.product_id, cycle).erase_count;
+        db.save(&tracking.db_path)?;
+        Ok(Some((uid, erases)))
+    }
 
-pub fn open_port(
-    port_name: &str,
-    baud_rate: u32,
-    read_timeout: Duration,
-) -> Result<Box<dyn SerialPort>> {
-    #[allow(unused_mut)] // macOS need
-    let mut p = serialport::new(port_name, baud_rate)
-        .timeout(read_timeout)
-        .data_bits(DataBits::Eight)
-        .stop_bits(StopBits::One)
-        .parity(Parity::Even)
-        .flow_control(FlowControl::None)
-        .open()
-        .map_err(|e| match &e.kind {
-            serialport::ErrorKind::NoDevice | serialport::ErrorKind::Io(_) => {
-                Error::PortNotFound(port_name.to_string())
-            }
-            _ => Error::Serial(e),
-        })?;
+    /// GET 成功后擦写仍被 NACK，多半是读保护：读一次 Flash 确认并换成可操作的错误
+    fn diagnose_nack(&mut self, err: Error) -> Error {
+        if !matches!(err, Error::Nack) {
+            return err;
+        }
 
-    // macOS 特定初始化
-    #[cfg(target_os = "macos")]
-    {
-        p.write_data_terminal_ready(false)?;
-        p.write_request_to_send(false)?;
+        info!("{}", tr(Msg::CheckingRdp, &[]));
+        // 之前超时的应答可能迟到，先丢弃，免得被当作探测读取的 NACK
+        let _ = self.port.clear();
+        match read_memory(
+            &mut *self.port,
+            FLASH_BASE,
+            4,
+            self.options.timeouts.read_chunk(),
+        ) {
+            Err(Error::Nack) => Error::ReadProtected,
+            _ => err,
+        }
+    }
 
-        std::thread::sleep(Duration::from_millis(200));
+    /// 按 256 字节分块读取 Flash
+    pub fn read(&mut self, address: u32, len: usize, sink: &dyn ProgressSink) -> Result<Vec<u8>> {
+        self.check_range(address, len, false)?;
+        let mut data = Vec::with_capacity(len);
+        let mut progress = ProgressReporter::new(ProgressPhase::Read, len, self.options.progress);
+        while data.len() < len {
+            self.options.cancel.check()?;
+            let chunk = (len - data.len()).min(256);
+            let addr = address + data.len() as u32;
+            match read_memory(
+                &mut *self.port,
+                addr,
+                chunk,
+                self.options.timeouts.read_chunk(),
+            ) {
+                Ok(bytes) => data.extend(bytes),
+                Err(e) if data.is_empty() => return Err(self.diagnose_nack(e)),
+                Err(e) => return Err(e),
+            }
 
-        let _ = p.clear(serialport::ClearBuffer::All);
-        std::thread::sleep(Duration::from_millis(50));
+            progress.update(addr, data.len(), sink);
+        }
+        Ok(data)
     }
 
-    Ok(p)
-}
+    /// 链路测试：反复执行 GET、GET_ID 和读取 Flash 开头 256 字节（读保护时跳过），
+    /// 不修改芯片内容；超时和乱码应答记入统计后继续，连续失败 3 次才放弃
+    pub fn bench(&mut self, rounds: u32, sink: &dyn ProgressSink) -> Result<LinkStats> {
+        let timeout = self.options.timeouts.ack();
+        let has_id = self.commands.contains(&CMD_GET_ID);
+        let mut can_read = self.commands.contains(&CMD_READ_MEMORY);
+        let mut failures = 0;
+        let mut progress = ProgressReporter::new(
+            ProgressPhase::LinkTest,
+            rounds as usize,
+            self.options.progress,
+        );
+        for round in 0..rounds {
+            self.options.cancel.check()?;
+            let port = &mut *self.port;
+            let mut result = get_info(port, timeout).map(drop);
+            if result.is_ok() && has_id {
+                result = get_id(port, timeout).map(drop);
+            }
+            if result.is_ok() && can_read {
+                result = match read_memory(port, FLASH_BASE, 256, timeout) {
+                    Err(Error::Nack) => {
+                        can_read = false;
+                        Ok(())
+                    }
+                    other => other.map(drop),
+                };
+            }
+            match result {
+                Ok(()) => failures = 0,
+                Err(Error::Timeout | Error::UnexpectedResponse(_)) if failures < 2 => {
+                    failures += 1;
+                    std::thread::sleep(Duration::from_millis(50));
+                    let _ = port.clear();
+                }
+                Err(e) => return Err(e),
+            }
+            progress.update(0, round as usize + 1, sink);
+        }
+        Ok(self.link_stats())
+    }
 
-pub fn apply_boot_mode(
-    port: &mut dyn SerialPort,
-    boot_mode: BootMode,
-    _lines: BootLineConfig,
-    _logger: &dyn Logger,
-) -> Result<()> {
-    if boot_mode == BootMode::None {
-        return Ok(());
+    /// 按 256 字节分块写入镜像，返回写入的字节数；每一帧都会写入，不跳过全 0xFF 的帧
+    pub fn write_image(
+        &mut self,
+        image: &BTreeMap<u32, u8>,
+        sink: &dyn ProgressSink,
+    ) -> Result<u64> {
+        self.write_blocks(image, false, sink)
     }
 
-    match boot_mode {
-        BootMode::DtrLowRtsHigh => {
-            port.write_data_terminal_ready(true)?;
-            port.write_request_to_send(false)?;
-            std::thread::sleep(Duration::from_millis(100));
+    /// `skip_blank` 时不发送全为 0xFF 且落在本次会话已擦除范围内的帧
+    fn write_blocks(
+        &mut self,
+        image: &BTreeMap<u32, u8>,
+        skip_blank: bool,
+        sink: &dyn ProgressSink,
+    ) -> Result<u64> {
+        self.check_image(image)?;
+        info!("{}", tr(Msg::Writing, &[]));
+        let started = Instant::now();
+        let mut written: u64 = 0;
+        let mut progress =
+            ProgressReporter::new(ProgressPhase::Write, image.len(), self.options.progress);
+        let mut chunk_size = self
+            .options
+            .write_chunk
+            .clamp(MIN_WRITE_CHUNK, MAX_WRITE_CHUNK)
+            & !3;
+        let mut nacks = 0;
+        let mut skipped: u64 = 0;
 
-            port.write_request_to_send(true)?;
-            std::thread::sleep(Duration::from_millis(50));
+        for (base, data) in image_to_blocks(image) {
+            let _span = info_span!(
+                "write_block",
+                address = %format_args!("0x{base:08X}"),
+                len = data.len()
+            )
+            .entered();
+            let command = self
+                .external_for(base, data.len())
+                .map_or(CMD_WRITE_MEMORY, |ext| ext.write_command);
+            let mut offset = 0usize;
+            while offset < data.len() {
+                self.options.cancel.check()?;
+                let end = (offset + chunk_size).min(data.len());
+                let chunk = &data[offset..end];
+                let addr = base + offset as u32;
+                if skip_blank
+                    && chunk.iter().all(|&b| b == 0xFF)
+                    && self.is_erased(addr, chunk.len())
+                {
+                    skipped += chunk.len() as u64;
+                    progress.update(addr, (written + skipped) as usize, sink);
+                    offset = end;
+                    continue;
+                }
+                // 注入的故障与真实的应答走同一套重试 / 减半处理
+                let result =
+                    inject::on_write((written + skipped) as usize, image.len()).and_then(|()| {
+                        write_memory(
+                            &mut *self.port,
+                            command,
+                            addr,
+                            chunk,
+                            self.options.timeouts.ack(),
+                            self.options.flush_frames,
+                        )
+                    });
+                match result {
+                    Ok(()) => nacks = 0,
+                    // 按 command_retry 重发同一块，仍被 NACK 时减半块大小，到下限仍失败才放弃
+                    Err(Error::Nack) if self.options.command_retry.allows(nacks + 1) => {
+                        nacks += 1;
+                        linkstats::record_command_retry(&mut *self.port);
+                        std::thread::sleep(self.options.command_retry.backoff);
+                        continue;
+                    }
+                    Err(Error::Nack) if chunk_size > MIN_WRITE_CHUNK => {
+                        linkstats::record_command_retry(&mut *self.port);
+                        chunk_size = ((chunk_size / 2) & !3).max(MIN_WRITE_CHUNK);
+                        nacks = 0;
+                        warn!(
+                            "{}",
+                            tr(Msg::ChunkShrunk, &[&format!("0x{addr:08X}"), &chunk_size])
+                        );
+                        continue;
+                    }
+                    Err(e) => {
+                        // 仅第一块被拒绝时才可能是读保护，中途失败照常上报
+                        return Err(if written == 0 {
+                            self.diagnose_nack(e)
+                        } else {
+                            e
+                        });
+                    }
+                }
+                written += chunk.len() as u64;
 
-            port.write_data_terminal_ready(false)?;
-            std::thread::sleep(Duration::from_millis(100));
+                progress.update(addr, (written + skipped) as usize, sink);
 
-            port.write_data_terminal_ready(true)?;
-            std::thread::sleep(Duration::from_millis(200));
+                offset = end;
+            }
+        }
+        if skipped > 0 {
+            info!("{}", tr(Msg::SkippedBlank, &[&skipped]));
         }
-        BootMode::DtrHighRtsHigh => {
-            port.write_data_terminal_ready(false)?;
-            std::thread::sleep(Duration::from_millis(100));
 
-            port.write_request_to_send(true)?;
-            std::thread::sleep(Duration::from_millis(50));
+        self.timeline.add(FlashPhase::Write, started.elapsed());
+        self.record_cycle(Cycle::Flash);
+        if self.options.verify.enabled() {
+            self.verify_image(image, sink)?;
+        }
+        Ok(written)
+    }
 
-            port.write_data_terminal_ready(true)?;
-            std::thread::sleep(Duration::from_millis(100));
+    /// 按 `options.verify` 校验已写入的镜像：按 256 字节分块用 READ MEMORY 回读比较，
+    /// 抽样时只回读其中一部分块；CRC 方式下对整字对齐的数据段改用 Get Checksum，
+    /// 不一致时再回读该段定位地址。未开启校验时按全量回读处理
+    pub fn verify_image(
+        &mut self,
+        image: &BTreeMap<u32, u8>,
+        sink: &dyn ProgressSink,
+    ) -> Result<()> {
+        info!("{}", tr(Msg::Verifying, &[]));
+        let started = Instant::now();
+        let mut checked = 0usize;
+        // 实际比较过的字节数，抽样时少于 checked
+        let mut compared = 0usize;
+        let mut index = 0usize;
+        let mut progress =
+            ProgressReporter::new(ProgressPhase::Verify, image.len(), self.options.progress);
 
-            port.write_data_terminal_ready(false)?;
-            std::thread::sleep(Duration::from_millis(200));
+        let strategy = self.options.verify;
+        let use_crc = strategy == VerifyStrategy::CrcCommand;
+        if use_crc && !self.commands.contains(&CMD_GET_CHECKSUM) {
+            warn!("{}", tr(Msg::CrcVerifyUnsupported, &[]));
         }
-        BootMode::DtrHighRtsLow => {
-            port.write_data_terminal_ready(false)?;
-            std::thread::sleep(Duration::from_millis(100));
+        let use_crc = use_crc && self.commands.contains(&CMD_GET_CHECKSUM);
 
-            port.write_request_to_send(false)?;
-            std::thread::sleep(Duration::from_millis(50));
+        for (base, data) in image_to_blocks(image) {
+            // Get Checksum 只覆盖片内 Flash，外部存储器始终回读
+            self.options.cancel.check()?;
+            let external = self.external_for(base, data.len()).is_some();
+            if use_crc && !external && base % 4 == 0 && data.len() % 4 == 0 {
+                self.verify_crc(base, &data)?;
+                checked += data.len();
+                compared += data.len();
+                progress.update(base, checked, sink);
+            } else {
+                self.read_back(base, &data, |addr, n| {
+                    let sampled = strategy.samples(index);
+                    index += 1;
+                    checked += n;
+                    if sampled {
+                        compared += n;
+                    }
+                    progress.update(addr, checked, sink);
+                    sampled
+                })?;
+            }
+        }
 
-            port.write_data_terminal_ready(true)?;
-            std::thread::sleep(Duration::from_millis(100));
+        self.timeline.add(FlashPhase::Verify, started.elapsed());
+        if compared < checked {
+            info!("{}", tr(Msg::VerifiedSampled, &[&compared, &checked]));
+        } else {
+            info!("{}", tr(Msg::Verified, &[&checked]));
+        }
+        Ok(())
+    }
 
-            port.write_data_terminal_ready(false)?;
-            std::thread::sleep(Duration::from_millis(200));
+    /// 比较目标计算的 CRC，不一致时回读该段找出第一个不同的字节
+    fn verify_crc(&mut self, base: u32, data: &[u8]) -> Result<()> {
+        let actual = get_checksum(
+            &mut *self.port,
+            base,
+            (data.len() / 4) as u32,
+            self.options.timeouts.ack(),
+            self.options.timeouts.erase(),
+        )?;
+        let expected = stm32_crc(data, CRC_POLYNOMIAL, CRC_INIT);
+        if actual == expected {
+            return Ok(());
         }
-        BootMode::DtrHighOnly => {
-            port.write_data_terminal_ready(false)?;
-            std::thread::sleep(Duration::from_millis(100));
+        self.read_back(base, data, |_, _| true)?;
+        Err(Error::ChecksumMismatch {
+            address: base,
+            len: data.len(),
+            expected,
+            actual,
+        })
+    }
 
-            port.write_data_terminal_ready(true)?;
-            std::thread::sleep(Duration::from_millis(100));
+    /// 逐段与镜像比较，不中途停止：每个连续数据段给出不一致的字节数和第一个不一致的地址；
+    /// CRC 方式下 CRC 一致的段不再回读，抽样时只回读部分块
+    pub fn compare_image(
+        &mut self,
+        image: &BTreeMap<u32, u8>,
+        sink: &dyn ProgressSink,
+    ) -> Result<Vec<RegionDiff>> {
+        self.check_image(image)?;
+        info!("{}", tr(Msg::Verifying, &[]));
+        let started = Instant::now();
+        let mut checked = 0usize;
+        let mut progress =
+            ProgressReporter::new(ProgressPhase::Verify, image.len(), self.options.progress);
+        let strategy = self.options.verify;
+        let use_crc =
+            strategy == VerifyStrategy::CrcCommand && self.commands.contains(&CMD_GET_CHECKSUM);
 
-            port.write_data_terminal_ready(false)?;
-            std::thread::sleep(Duration::from_millis(200));
+        let mut index = 0usize;
+        let mut diffs = Vec::new();
+        for (base, data) in image_to_blocks(image) {
+            let mut diff = RegionDiff {
+                start: base,
+                len: data.len(),
+                mismatched: 0,
+                first: None,
+            };
+            let external = self.external_for(base, data.len()).is_some();
+            if use_crc && !external && base % 4 == 0 && data.len() % 4 == 0 {
+                let actual = get_checksum(
+                    &mut *self.port,
+                    base,
+                    (data.len() / 4) as u32,
+                    self.options.timeouts.ack(),
+                    self.options.timeouts.erase(),
+                )?;
+                if actual == stm32_crc(&data, CRC_POLYNOMIAL, CRC_INIT) {
+                    checked += data.len();
+                    progress.update(base, checked, sink);
+                    diffs.push(diff);
+                    continue;
+                }
+            }
+            for (i, chunk) in data.chunks(256).enumerate() {
+                self.options.cancel.check()?;
+                let addr = base + (i * 256) as u32;
+                let sampled = strategy.samples(index);
+                index += 1;
+                checked += chunk.len();
+                progress.update(addr, checked, sink);
+                if !sampled {
+                    continue;
+                }
+                let read = read_memory(
+                    &mut *self.port,
+                    addr,
+                    chunk.len(),
+                    self.options.timeouts.read_chunk(),
+                )?;
+                for (pos, (&expected, &actual)) in chunk.iter().zip(&read).enumerate() {
+                    if expected != actual {
+                        diff.mismatched += 1;
+                        diff.first
+                            .get_or_insert((addr + pos as u32, expected, actual));
+                    }
+                }
+            }
+            diffs.push(diff);
         }
+        self.timeline.add(FlashPhase::Verify, started.elapsed());
+        Ok(diffs)
+    }
+
+    /// 按 256 字节分块回读比较，返回第一个不一致的地址；每块先调用 `visit`，返回 false 的块跳过
+    fn read_back(
+        &mut self,
+        base: u32,
+        data: &[u8],
+        mut visit: impl FnMut(u32, usize) -> bool,
+    ) -> Result<()> {
+        for (i, chunk) in data.chunks(256).enumerate() {
+            self.options.cancel.check()?;
+            let addr = base + (i * 256) as u32;
+            if !visit(addr, chunk.len()) {
+                continue;
+            }
+            let read = read_memory(
+                &mut *self.port,
+                addr,
+                chunk.len(),
+                self.options.timeouts.read_chunk(),
+            )?;
+            if let Some(pos) = chunk.iter().zip(&read).position(|(a, b)| a != b) {
+                return Err(Error::VerifyMismatch {
+                    address: addr + pos as u32,
+                    expected: chunk[pos],
+                    actual: read[pos],
+                });
+            }
+        }
+        Ok(())
+    }
 
-        BootMode::RtsLowDtrHigh => {
-            port.write_request_to_send(true)?;
-            std::thread::sleep(Duration::from_millis(100));
+    /// 把 RAM 烧录程序写入 SRAM 并 GO 过去，握手成功后返回其客户端；Bootloader 会话就此结束
+    pub fn start_stub(mut self, stub: &StubConfig) -> Result<StubClient> {
+        if !self.commands.contains(&CMD_GO) {
+            return Err(Error::CommandNotSupported(CMD_GO));
+        }
+        if !stub.address.is_multiple_of(4) {
+            return Err(Error::Stub(format!(
+                "load address 0x{:08X} is not word-aligned",
+                stub.address
+            )));
+        }
+        self.check_range(stub.address, stub.image.len(), true)?;
+        info!(
+            "{}",
+            tr(
+                Msg::LoadingStub,
+                &[&stub.image.len(), &format!("0x{:08X}", stub.address)],
+            )
+        );
 
-            port.write_data_terminal_ready(true)?;
-            std::thread::sleep(Duration::from_millis(50));
+        for (i, chunk) in stub.image.chunks(MAX_WRITE_CHUNK).enumerate() {
+            write_memory(
+                &mut *self.port,
+                CMD_WRITE_MEMORY,
+                stub.address + (i * MAX_WRITE_CHUNK) as u32,
+                chunk,
+                self.options.timeouts.ack(),
+                self.options.flush_frames,
+            )
+            .map_err(|e| self.diagnose_nack(e))?;
+        }
+        go_command(&mut *self.port, stub.address, self.options.timeouts.ack())?;
+        StubClient::connect(self.port, &self.options)
+    }
 
-            port.write_request_to_send(false)?;
-            std::thread::sleep(Duration::from_millis(100));
+    /// 从 `address` 起写入一段连续数据，返回写入的字节数；目标区域需已擦除
+    pub fn write(&mut self, address: u32, data: &[u8], sink: &dyn ProgressSink) -> Result<u64> {
+        let image = addressed(address, data)?.collect();
+        self.write_image(&image, sink)
+    }
 
-            port.write_request_to_send(true)?;
-            std::thread::sleep(Duration::from_millis(200));
+    /// 用 GO 命令跳转到 `address` 处的程序（向量表地址），结束会话；
+    /// 不处理选项字节、读保护等收尾步骤，需要时使用 `finish`
+    pub fn go(mut self, address: u32) -> Result<()> {
+        self.options.cancel.check()?;
+        let address = validate_go_address(address)?;
+        if !self.commands.contains(&CMD_GO) {
+            return Err(Error::CommandNotSupported(CMD_GO));
         }
-        BootMode::RtsLowDtrLow => {
-            port.write_request_to_send(true)?;
-            std::thread::sleep(Duration::from_millis(100));
+        info!("{}", tr(Msg::StartingApp, &[&format!("0x{address:08X}")]));
+        go_command(&mut *self.port, address, self.options.timeouts.ack())
+    }
 
-            port.write_data_terminal_ready(false)?;
-            std::thread::sleep(Duration::from_millis(50));
+    /// 本次连接的 ACK 往返时间、重试和链路评分
+    pub fn link_stats(&mut self) -> LinkStats {
+        linkstats::of(&mut *self.port)
+    }
 
-            port.write_request_to_send(false)?;
-            std::thread::sleep(Duration::from_millis(100));
+    /// 按选项设置读保护并启动用户程序，结束会话；返回整个会话的链路统计
+    pub fn finish(self) -> Result<LinkStats> {
+        // 取消后不再跳转或改写选项字节
+        self.options.cancel.check()?;
+        let Self {
+            mut port,
+            id,
+            baud,
+            commands,
+            product_id,
+            options,
+            virgin,
+            ..
+        } = self;
+        let go_address = validate_go_address(options.go_address.unwrap_or(FLASH_BASE))?;
 
-            port.write_request_to_send(true)?;
-            std::thread::sleep(Duration::from_millis(200));
+        let mut ob_changes = options.option_bytes.clone();
+        // 打开会话时已确认芯片支持交换 Bank
+        if options.swap_banks {
+            let current = read_decoded_option_bytes(&mut *port, options.timeouts.ack())?;
+            let bfb2 = current.get("bfb2")?;
+            info!("{}", tr(Msg::SwappingBanks, &[&(2 - bfb2)]));
+            ob_changes.push(("bfb2".to_string(), bfb2 ^ 1));
+        }
+        if !ob_changes.is_empty() {
+            (port, _) = set_option_bytes_and_reconnect(port, &id, baud, &ob_changes, &options)?;
+        }
+        if let Some(sectors) = &options.protect_after {
+            port = protect_and_reconnect(port, &id, baud, &commands, sectors, &options)?;
+        }
+        if options.lock_after {
+            port = lock_and_verify(port, &id, baud, &commands, &options)?;
         }
-        BootMode::RtsLowOnly => {
-            port.write_request_to_send(true)?;
-            std::thread::sleep(Duration::from_millis(100));
-
-            port.write_request_to_send(false)?;
-            std::thread::sleep(Duration::from_millis(100));
 
-            port.write_request_to_send(true)?;
-            std::thread::sleep(Duration::from_millis(200));
+        let power_after = options.power_cycle.as_ref().filter(|p| p.after);
+        if options.stay_in_bootloader {
+            info!("{}", tr(Msg::StayInBootloader, &[]));
+        } else if let Some(power) = power_after {
+            // BOOT0 只在上电时采样的板子需要断电重启才能从 Flash 启动
+            hold_boot_line(&mut *port, &options, false)?;
+            power.cycle()?;
+            info!("{}", tr(Msg::AppStarted, &[]));
+        } else if options.reset_after && virgin && empty_check(product_id) {
+            // 空检查标志不会因写入 Flash 而更新，GO 或复位后下次仍会进入 Bootloader；
+            // 原样写回选项字节触发重新加载，芯片随即复位并从新固件启动
+            hold_boot_line(&mut *port, &options, false)?;
+            info!("{}", tr(Msg::ReloadingOptionBytes, &[]));
+            reload_option_bytes(&mut *port, product_id.unwrap_or(0), &options)?;
+            info!("{}", tr(Msg::AppStarted, &[]));
+        } else if options.reset_after {
+            // 读保护下 GO 不可用
+            start_application(
+                &mut *port,
+                &commands,
+                &options,
+                go_address,
+                !options.lock_after,
+            )?;
         }
-        BootMode::RtsHighOnly => {
-            port.write_request_to_send(false)?;
-            std::thread::sleep(Duration::from_millis(100));
 
-            port.write_request_to_send(true)?;
-            std::thread::sleep(Duration::from_millis(100));
+        Ok(linkstats::of(&mut *port))
+    }
 
-            port.write_request_to_send(false)?;
-            std::thread::sleep(Duration::from_millis(200));
+    /// 只读操作（比对、读取）后结束会话：不改写选项字节、不交换 Bank、不加锁，
+    /// 只按 `reset_after` 用 GO 或硬件复位启动用户程序
+    pub fn release(self) -> Result<LinkStats> {
+        let Self {
+            mut port,
+            commands,
+            options,
+            ..
+        } = self;
+        if options.reset_after && !options.stay_in_bootloader {
+            let go_address = validate_go_address(options.go_address.unwrap_or(FLASH_BASE))?;
+            start_application(&mut *port, &commands, &options, go_address, true)?;
         }
-
-        BootMode::None => unreachable!(),
+        Ok(linkstats::of(&mut *port))
     }
+}
 
+/// 用 GO 命令跳转到用户程序地址，不支持或 `go_allowed` 为 false 时硬件复位
+fn start_application(
+    port: &mut dyn Transport,
+    commands: &[u8],
+    options: &FlashOptions,
+    go_address: u32,
+    go_allowed: bool,
+) -> Result<()> {
+    let supports_go = commands.contains(&CMD_GO) && go_allowed;
+    if !supports_go && go_address != FLASH_BASE {
+        warn!(
+            "{}",
+            tr(
+                Msg::GoAddressIgnored,
+                &[
+                    &format!("0x{FLASH_BASE:08X}"),
+                    &format!("0x{go_address:08X}"),
+                ],
+            )
+        );
+    }
+    if supports_go {
+        info!(
+            "{}",
+            tr(Msg::StartingApp, &[&format!("0x{go_address:08X}")])
+        );
+        if let Err(e) = go_command(port, go_address, options.timeouts.ack()) {
+            warn!("{}", tr(Msg::GoFailed, &[&e]));
+            // 回退到硬件复位
+            do_hardware_reset(port, options)?;
+        }
+    } else {
+        info!("{}", tr(Msg::Resetting, &[]));
+        do_hardware_reset(port, options)?;
+    }
+    info!("{}", tr(Msg::AppStarted, &[]));
     Ok(())
 }
 
-pub fn identify(port_name: &str, options: &FlashOptions, logger: &dyn Logger) -> IdentifyResult {
-    match (|| -> Result<IdentifyResult> {
-        let mut port = open_port(port_name, options.baud_rate, options.read_timeout)?;
-        apply_boot_mode(&mut *port, options.boot_mode, options.lines, logger)?;
-        connect_bootloader_with_log(&mut *port, options.read_timeout, logger)?;
-        let (ver, cmds) = get_info(&mut *port, options.read_timeout)?;
-        let pid = get_id(&mut *port, options.read_timeout).ok();
-        Ok(IdentifyResult {
-            ok: true,
-            bootloader_version: Some(ver),
-            supported_commands: cmds,
-            product_id: pid,
-            error: None,
-        })
-    })() {
-        Ok(ok) => ok,
-        Err(e) => IdentifyResult {
-            ok: false,
-            bootloader_version: None,
-            supported_commands: vec![],
-            product_id: None,
-            error: Some(e.to_string()),
-        },
+/// 先写入同目录下的临时文件，成功后原子重命名为目标文件；失败时删除临时文件，
+/// 中断的读取不会留下被截断的目标文件
+pub fn write_file_atomic(
+    path: &Path,
+    write: impl FnOnce(&mut std::fs::File) -> Result<()>,
+) -> Result<()> {
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| Error::Io(std::io::ErrorKind::InvalidInput.into()))?;
+    let tmp = path.with_file_name(format!(
+        ".{}.{}.tmp",
+        file_name.to_string_lossy(),
+        std::process::id()
+    ));
+
+    let result = (|| {
+        let mut file = std::fs::File::create(&tmp)?;
+        write(&mut file)?;
+        file.sync_all()?;
+        drop(file);
+        std::fs::rename(&tmp, path)?;
+        Ok(())
+    })();
+
+    if result.is_err() {
+        let _ = std::fs::remove_file(&tmp);
     }
+    result
 }
 
-pub fn parse_hex_to_image(path: &Path) -> Result<BTreeMap<u32, u8>> {
-    let text = std::fs::read_to_string(path).map_err(|e| {
-        if e.kind() == std::io::ErrorKind::NotFound {
-            Error::HexFileNotFound(path.display().to_string())
-        } else {
-            Error::Io(e)
+/// 读出数据的保存格式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum DumpFormat {
+    /// 原始二进制，需要另行记录基地址
+    Bin,
+    /// Intel HEX，自带地址，可直接重新烧录
+    Hex,
+}
+
+impl DumpFormat {
+    /// 按扩展名推断：.hex / .ihex 为 Intel HEX，其余为二进制
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some(ext) if ext.eq_ignore_ascii_case("hex") || ext.eq_ignore_ascii_case("ihex") => {
+                Self::Hex
+            }
+            _ => Self::Bin,
         }
-    })?;
+    }
+}
 
-    let mut image = BTreeMap::<u32, u8>::new();
+/// 把镜像序列化为 Intel HEX：每条数据记录最多 16 字节且不跨越 64 KB 边界，
+/// 高 16 位地址变化时插入 Extended Linear Address 记录，最后以 EOF 结束
+pub fn image_to_hex(image: &BTreeMap<u32, u8>) -> Result<String> {
+    let mut records = Vec::new();
+    let mut upper: Option<u16> = None;
 
-    let mut upper: u32 = 0;
+    for (base, data) in image_to_blocks(image) {
+        let mut offset = 0usize;
+        while offset < data.len() {
+            let addr = base + offset as u32;
+            let hi = (addr >> 16) as u16;
+            if upper != Some(hi) {
+                records.push(Record::ExtendedLinearAddress(hi));
+                upper = Some(hi);
+            }
 
-    for line in text.lines() {
-        if line.trim().is_empty() {
-            continue;
+            let to_boundary = 0x1_0000 - (addr & 0xFFFF) as usize;
+            let end = (offset + 16.min(to_boundary)).min(data.len());
+            records.push(Record::Data {
+                offset: addr as u16,
+                value: data[offset..end].to_vec(),
+            });
+            offset = end;
         }
+    }
+    records.push(Record::EndOfFile);
 
-        let rec = ihex::Record::from_record_string(line).map_err(|e| Error::Hex(format!("{e}")))?;
+    ihex::create_object_file_representation(&records).map_err(|e| Error::Hex(e.to_string()))
+}
 
-        match rec {
-            Record::Data { offset, value } => {
-                let base = (upper << 16) | (offset as u32);
-                for (i, b) in value.into_iter().enumerate() {
-                    image.insert(base + (i as u32), b);
-                }
-            }
-            Record::ExtendedLinearAddress(hi) => {
-                upper = hi as u32;
-            }
-            Record::EndOfFile => break,
-            _ => {
-                // 忽略 MVP 中不支持的记录类型
-            }
+/// 读取 Flash 区域并原子地保存为二进制或 Intel HEX 文件
+pub fn dump_flash(
+    port_name: &str,
+    address: u32,
+    len: usize,
+    path: &Path,
+    format: DumpFormat,
+    options: &FlashOptions,
+    sink: &dyn ProgressSink,
+) -> Result<()> {
+    let mut session = BootloaderSession::open(port_name, options)?;
+
+    info!(
+        "{}",
+        tr(Msg::ReadingFlash, &[&format!("0x{address:08X}"), &len])
+    );
+    let data = session.read(address, len, sink)?;
+
+    let bytes = match format {
+        DumpFormat::Bin => data,
+        DumpFormat::Hex => image_to_hex(&addressed(address, &data)?.collect())?.into_bytes(),
+    };
+    write_file_atomic(path, |file| Ok(std::io::Write::write_all(file, &bytes)?))?;
+    info!("{}", tr(Msg::SavedTo, &[&path.display()]));
+    Ok(())
+}
+
+/// 一个连续数据段的比较结果
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct RegionDiff {
+    pub start: u32,
+    pub len: usize,
+    /// 与镜像不一致的字节数
+    pub mismatched: usize,
+    /// 第一个不一致的地址、期望值和读出值
+    pub first: Option<(u32, u8, u8)>,
+}
+
+impl RegionDiff {
+    pub fn matches(&self) -> bool {
+        self.mismatched == 0
+    }
+}
+
+impl fmt::Display for RegionDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let end = self.start as u64 + self.len as u64;
+        write!(
+            f,
+            "0x{:08X}..0x{end:08X} ({} bytes): ",
+            self.start, self.len
+        )?;
+        match self.first {
+            None => write!(f, "OK"),
+            Some((address, expected, actual)) => write!(
+                f,
+                "{} byte(s) differ, first at 0x{address:08X} (expected 0x{expected:02X}, read 0x{actual:02X})",
+                self.mismatched
+            ),
         }
     }
+}
 
-    if image.is_empty() {
-        return Err(Error::HexFileEmpty);
+/// 只读校验：连接后把 Flash 与固件逐段比较，不擦除、不写入，用于核查已出货设备；
+/// 完成后按选项复位运行或保持在 Bootloader
+pub fn verify_sources(
+    port_name: &str,
+    sources: &[ImageSource],
+    options: &FlashOptions,
+    sink: &dyn ProgressSink,
+) -> Result<Vec<RegionDiff>> {
+    let merged = merge_sources(sources, options)?;
+    let mut session = BootloaderSession::open(port_name, options)?;
+    let diffs = session.compare_image(&merged, sink)?;
+    let differing = diffs.iter().filter(|d| !d.matches()).count();
+    if differing == 0 {
+        info!("{}", tr(Msg::Verified, &[&merged.len()]));
+    } else {
+        warn!("{}", tr(Msg::RegionsDiffer, &[&differing, &diffs.len()]));
     }
+    session.release()?;
+    Ok(diffs)
+}
 
-    Ok(image)
+/// 在同一次 Bootloader 连接中烧录多个镜像（如 bootloader + 应用 + 配置区）
+///
+/// 所有镜像先合并并检查地址重叠，再统一擦除和写入，返回写入的字节数
+pub fn flash_images(
+    session: &mut BootloaderSession,
+    sources: &[ImageSource],
+    sink: &dyn ProgressSink,
+) -> Result<u64> {
+    let merged = merge_sources(sources, session.options())?;
+    write_merged(session, &merged, sink)
 }
 
-fn image_to_blocks(image: &BTreeMap<u32, u8>) -> Vec<(u32, Vec<u8>)> {
-    let mut blocks: Vec<(u32, Vec<u8>)> = Vec::new();
-    let mut cur_addr: Option<u32> = None;
-    let mut cur: Vec<u8> = Vec::new();
+fn write_merged(
+    session: &mut BootloaderSession,
+    image: &BTreeMap<u32, u8>,
+    sink: &dyn ProgressSink,
+) -> Result<u64> {
+    report_image_checks(image, session.options());
+    let image = &*session.place_in_bank(image)?;
+    session.check_image(image)?;
+    session.check_vector_table(image);
+    sink.plan(&session.plan(image));
+    session.erase_before_write(image, sink)?;
+    let skip_blank = session.options().skip_blank;
+    session.write_blocks(image, skip_blank, sink)
+}
 
-    for (&addr, &b) in image.iter() {
-        match cur_addr {
-            None => {
-                cur_addr = Some(addr);
-                cur.push(b);
-            }
-            Some(a0) => {
-                let expected = a0 + (cur.len() as u32);
-                if addr == expected {
-                    cur.push(b);
-                } else {
-                    blocks.push((a0, std::mem::take(&mut cur)));
-                    cur_addr = Some(addr);
-                    cur.push(b);
-                }
+/// 读取并合并多个镜像，地址重叠时报错；合并结果按烧录参数做对齐填充
+pub fn merge_sources(sources: &[ImageSource], options: &FlashOptions) -> Result<BTreeMap<u32, u8>> {
+    let mut merged = BTreeMap::new();
+    for source in sources {
+        let image = prepare_image(source.load()?, options)?;
+        info!(
+            "{}",
+            tr(Msg::LoadedSource, &[&source.path.display(), &image.len()])
+        );
+        for (addr, b) in image {
+            if merged.insert(addr, b).is_some() {
+                return Err(Error::ImageOverlap(addr));
             }
         }
     }
 
-    if let Some(a0) = cur_addr
-        && !cur.is_empty()
-    {
-        blocks.push((a0, cur));
+    if merged.is_empty() {
+        return Err(Error::ImageEmpty);
     }
-
-    blocks
+    apply_padding(&mut merged, options);
+    Ok(merged)
 }
 
-pub fn flash_hex(
+/// 合并多个镜像文件（如 Bootloader + 应用）在一次会话中擦除和写入，返回写入字节数和各阶段耗时
+pub fn flash_sources(
     port_name: &str,
-    hex_path: &Path,
+    sources: &[ImageSource],
     options: &FlashOptions,
-    logger: &dyn Logger,
-) -> Result<()> {
-    let image = parse_hex_to_image(hex_path)?;
-    let blocks = image_to_blocks(&image);
-
-    logger.line("info", &format!("已加载固件：{} 字节", image.len()));
-
-    let mut port = open_port(port_name, options.baud_rate, options.read_timeout)?;
-    apply_boot_mode(&mut *port, options.boot_mode, options.lines, logger)?;
+    sink: &dyn ProgressSink,
+) -> Result<FlashReport> {
+    let merged = merge_sources(sources, options)?;
+    flash_merged(
+        port_name,
+        &merged,
+        sources_entry_point(sources)?,
+        options,
+        sink,
+    )
+}
 
-    logger.line("info", "正在连接 Bootloader...");
-    connect_bootloader_with_log(&mut *port, options.read_timeout, logger)?;
+/// 多个文件都带入口地址时以第一个为准（通常是先运行的 Bootloader）
+pub fn sources_entry_point(sources: &[ImageSource]) -> Result<Option<u32>> {
+    sources
+        .iter()
+        .map(ImageSource::entry_point)
+        .find_map(|e| e.transpose())
+        .transpose()
+}
 
-    logger.line("info", "正在查询支持的命令...");
-    let (_ver, cmds) = get_info(&mut *port, options.read_timeout)?;
+/// 在一次会话中烧录已合并的镜像；`entry` 为固件文件中的入口地址
+pub fn flash_merged(
+    port_name: &str,
+    merged: &BTreeMap<u32, u8>,
+    entry: Option<u32>,
+    options: &FlashOptions,
+    sink: &dyn ProgressSink,
+) -> Result<FlashReport> {
+    let options = &apply_entry_point(options, entry, merged);
+    validate_go_address(options.go_address.unwrap_or(FLASH_BASE))?;
 
-    let supports_ext_erase = cmds.contains(&CMD_EXTENDED_ERASE);
-    let supports_erase = cmds.contains(&CMD_ERASE);
+    let mut session = BootloaderSession::open(port_name, options)?;
+    let bytes_written = write_merged(&mut session, merged, sink)?;
 
-    logger.line("info", "正在擦除...");
-    let erase_timeout = Duration::from_secs(25);
-    if supports_ext_erase {
-        extended_erase_all(&mut *port, options.read_timeout, erase_timeout)?;
-    } else if supports_erase {
-        erase_all(&mut *port, options.read_timeout, erase_timeout)?;
-    } else {
-        return Err(Error::NoEraseSupport);
-    }
+    let mut timeline = session.timeline().clone();
+    let started = Instant::now();
+    let link = session.finish()?;
+    timeline.add(FlashPhase::Reset, started.elapsed());
+    info!("{}", tr(Msg::LinkQuality, &[&link]));
+    Ok(FlashReport {
+        bytes_written,
+        timeline,
+        link: Some(link),
+    })
+}
 
-    logger.line("info", "正在写入...");
-    let total = image.len() as u64;
-    let mut written: u64 = 0;
+/// 单个固件文件，即一个 `ImageSource`；单文件的接口沿用这个名字
+pub type FirmwareSource = ImageSource;
 
-    for (base, data) in blocks {
-        let mut offset = 0usize;
-        while offset < data.len() {
-            let end = (offset + 256).min(data.len());
-            let chunk = &data[offset..end];
-            let addr = base + offset as u32;
-            write_memory(&mut *port, addr, chunk, options.read_timeout)?;
-            written += chunk.len() as u64;
+/// 烧录 HEX 文件，返回写入字节数和各阶段耗时
+pub fn flash_hex(
+    port_name: &str,
+    hex_path: &Path,
+    options: &FlashOptions,
+    sink: &dyn ProgressSink,
+) -> Result<FlashReport> {
+    flash_firmware(
+        port_name,
+        &ImageSource::with_format(hex_path, ImageFormat::IntelHex, None),
+        options,
+        sink,
+    )
+}
 
-            logger.line("info", &format!("PROGRESS:写入中:{written}:{total}"));
+/// 读取固件并按烧录参数重定位、对齐填充，得到可直接交给会话写入的镜像
+pub fn load_image(source: &ImageSource, options: &FlashOptions) -> Result<BTreeMap<u32, u8>> {
+    merge_sources(std::slice::from_ref(source), options)
+}
 
-            offset = end;
+/// 读取 HEX 文件中的 Start Linear Address 记录（链接器写入的入口地址），没有时为 None
+pub fn hex_start_address(path: &Path) -> Result<Option<u32>> {
+    let text = std::fs::read_to_string(path)?;
+    for line in text.lines().filter(|l| !l.trim().is_empty()) {
+        match ihex::Record::from_record_string(line).map_err(|e| Error::Hex(format!("{e}")))? {
+            Record::StartLinearAddress(address) => return Ok(Some(address)),
+            Record::EndOfFile => break,
+            _ => {}
         }
     }
+    Ok(None)
+}
 
-    if options.reset_after {
-        // 使用 GO 命令跳转到用户程序地址 0x08000000
-        let supports_go = cmds.contains(&CMD_GO);
-        if supports_go {
-            logger.line("info", "正在启动用户程序...");
-            if let Err(e) = go_command(&mut *port, 0x08000000, options.read_timeout) {
-                logger.line("warn", &format!("GO 命令失败: {}, 尝试硬件复位", e));
-                // 回退到硬件复位
-                do_hardware_reset(&mut *port)?;
-            }
-        } else {
-            logger.line("info", "正在复位以运行用户程序...");
-            do_hardware_reset(&mut *port)?;
+/// 入口地址对应的 GO 目标
+///
+/// GO 命令需要向量表地址（从中加载 SP 和复位向量），而链接器写入的入口通常是复位处理函数
+/// （带 Thumb 位）。入口本身按字对齐时视为向量表地址；否则在镜像中按 128 字节对齐查找复位向量
+/// 等于入口的向量表。
+pub fn go_target_for_entry(image: &BTreeMap<u32, u8>, entry: u32) -> Option<u32> {
+    let word = |addr: u32| image_word(image, addr);
+    let next = |addr: u32| addr.checked_add(4).and_then(word);
+    if entry.is_multiple_of(4) {
+        return word(entry).and(next(entry)).map(|_| entry);
+    }
+    image
+        .keys()
+        .copied()
+        .filter(|addr| addr.is_multiple_of(128))
+        .find(|&addr| next(addr) == Some(entry))
+}
+
+/// 输出固件版本字符串；复位运行时 GO 地址不在镜像内则告警
+pub(crate) fn report_image_checks(image: &BTreeMap<u32, u8>, options: &FlashOptions) {
+    if let Some(locator) = &options.version_locator {
+        match extract_version(image, locator) {
+            Some(version) => info!("{}", tr(Msg::FirmwareVersion, &[&version])),
+            None => warn!("{}", tr(Msg::VersionNotFound, &[])),
         }
-        logger.line("info", "程序已启动");
     }
+    let go_address = options.go_address.unwrap_or(FLASH_BASE);
+    if options.reset_after && !image.contains_key(&go_address) {
+        warn!(
+            "{}",
+            tr(
+                Msg::GoAddressOutsideImage,
+                &[&format!("0x{go_address:08X}")],
+            )
+        );
+    }
+}
 
-    if options.verify {
-        logger.line("warn", "verify not implemented in MVP yet");
+/// 未指定 `go_address` 时采用固件文件中的入口地址
+pub(crate) fn apply_entry_point(
+    options: &FlashOptions,
+    entry: Option<u32>,
+    image: &BTreeMap<u32, u8>,
+) -> FlashOptions {
+    let mut options = options.clone();
+    let Some(entry) = entry.filter(|_| options.go_address.is_none()) else {
+        return options;
+    };
+    match go_target_for_entry(image, entry) {
+        Some(target) => {
+            info!(
+                "{}",
+                tr(
+                    Msg::GoFromEntryPoint,
+                    &[&format!("0x{entry:08X}"), &format!("0x{target:08X}")],
+                )
+            );
+            options.go_address = Some(target);
+        }
+        None => warn!(
+            "{}",
+            tr(
+                Msg::EntryPointWithoutVectorTable,
+                &[&format!("0x{entry:08X}"), &format!("0x{FLASH_BASE:08X}")],
+            )
+        ),
     }
+    options
+}
 
-    Ok(())
+/// 烧录单个固件文件，同 `flash_sources`
+pub fn flash_firmware(
+    port_name: &str,
+    source: &FirmwareSource,
+    options: &FlashOptions,
+    sink: &dyn ProgressSink,
+) -> Result<FlashReport> {
+    flash_sources(port_name, std::slice::from_ref(source), options, sink)
 }
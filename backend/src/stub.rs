@@ -0,0 +1,353 @@
+//! RAM 烧录程序（stub）：经 Bootloader 写入 SRAM 并 GO 执行，之后改用更高效的自定义协议
+//!
+//! Bootloader 每帧最多 256 字节且每帧都要等待编程完成，stub 可以使用更大的帧、更高的波特率，
+//! 也可以实现 Bootloader 没有的操作（如外部 Flash 编程）。协议中的多字节字段均为大端：
+//!
+//! - GO 之后 stub 主动发送握手：`"PFS"`、协议版本、单帧最大负载（u16）
+//! - 请求帧：操作码、负载长度（u16）、负载、前面所有字节的异或校验
+//! - 应答帧：ACK / NACK、负载长度（u16）、负载、异或校验；NACK 的负载为错误说明
+//!
+//! 操作码 0x80 及以上留给自定义操作，由 [`StubClient::custom`] 原样透传。
+
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use tracing::info;
+
+use crate::i18n::{Msg, tr};
+use crate::stm32_uart::{
+    self, ACK, BootloaderSession, Error, FLASH_BASE, FlashOptions, FlashPhase, FlashReport,
+    ImageSource, NACK, ProgressPhase, ProgressReporter, ProgressSink, Result, image_to_blocks,
+    read_exact_with_timeout, xor_checksum,
+};
+use crate::transport::Transport;
+
+pub(crate) const STUB_MAGIC: [u8; 3] = *b"PFS";
+/// 本机实现的协议版本
+pub const STUB_VERSION: u8 = 1;
+
+pub(crate) const OP_PING: u8 = 0x01;
+pub(crate) const OP_SET_BAUD: u8 = 0x02;
+pub(crate) const OP_ERASE: u8 = 0x10;
+pub(crate) const OP_WRITE: u8 = 0x11;
+pub(crate) const OP_READ: u8 = 0x12;
+pub(crate) const OP_GO: u8 = 0x20;
+/// 自定义操作码的下限
+pub const OP_CUSTOM: u8 = 0x80;
+
+/// 握手声明的单帧负载至少能放下地址和一些数据
+const MIN_PAYLOAD: u16 = 16;
+/// 切换波特率后等待 stub 重新配置串口
+const BAUD_SETTLE: Duration = Duration::from_millis(20);
+
+/// 要加载的 stub 镜像
+#[derive(Debug, Clone)]
+pub struct StubConfig {
+    /// 链接在 `address` 的原始二进制，开头为向量表（GO 从中加载 SP 和复位向量）
+    pub image: Vec<u8>,
+    pub address: u32,
+    /// 握手后切换到的波特率，None 时保持 Bootloader 的波特率
+    pub baud: Option<u32>,
+}
+
+impl StubConfig {
+    /// SRAM 起始地址，大多数 STM32 的 stub 链接在这里
+    pub const SRAM_BASE: u32 = 0x2000_0000;
+
+    pub fn load(path: &Path, address: u32, baud: Option<u32>) -> Result<Self> {
+        let image = std::fs::read(path).map_err(|e| {
+            if e.kind() == std::io::ErrorKind::NotFound {
+                Error::ImageFileNotFound(path.display().to_string())
+            } else {
+                Error::Io(e)
+            }
+        })?;
+        if image.is_empty() {
+            return Err(Error::Stub(format!("stub '{}' is empty", path.display())));
+        }
+        Ok(Self {
+            image,
+            address,
+            baud,
+        })
+    }
+}
+
+/// stub 握手中声明的能力
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StubInfo {
+    pub version: u8,
+    /// 单帧请求 / 应答的最大负载字节数
+    pub max_payload: u16,
+}
+
+/// 已启动的 stub 的主机端
+pub struct StubClient {
+    port: Box<dyn Transport>,
+    info: StubInfo,
+    timeout: Duration,
+    erase_timeout: Duration,
+}
+
+impl StubClient {
+    /// 读取 GO 之后 stub 发出的握手
+    pub fn connect(mut port: Box<dyn Transport>, options: &FlashOptions) -> Result<Self> {
+        let mut hello = [0u8; 6];
+        read_exact_with_timeout(&mut *port, &mut hello, options.timeouts.ack())
+            .map_err(|e| Error::Stub(format!("no handshake after GO: {e}")))?;
+        if hello[..3] != STUB_MAGIC {
+            return Err(Error::Stub(format!("unexpected handshake {hello:02X?}")));
+        }
+        let info = StubInfo {
+            version: hello[3],
+            max_payload: u16::from_be_bytes([hello[4], hello[5]]),
+        };
+        if info.version != STUB_VERSION {
+            return Err(Error::Stub(format!(
+                "stub speaks protocol v{}, expected v{STUB_VERSION}",
+                info.version
+            )));
+        }
+        if info.max_payload < MIN_PAYLOAD {
+            return Err(Error::Stub(format!(
+                "stub payload limit {} is below {MIN_PAYLOAD} bytes",
+                info.max_payload
+            )));
+        }
+        info!(
+            "{}",
+            tr(Msg::StubStarted, &[&info.version, &info.max_payload])
+        );
+        Ok(Self {
+            port,
+            info,
+            timeout: options.timeouts.ack(),
+            erase_timeout: options.timeouts.erase(),
+        })
+    }
+
+    pub fn info(&self) -> StubInfo {
+        self.info
+    }
+
+    /// 发送一帧请求并读取应答负载
+    fn request(&mut self, op: u8, payload: &[u8], timeout: Duration) -> Result<Vec<u8>> {
+        let mut frame = Vec::with_capacity(payload.len() + 4);
+        frame.push(op);
+        frame.extend_from_slice(&(payload.len() as u16).to_be_bytes());
+        frame.extend_from_slice(payload);
+        frame.push(xor_checksum(frame.iter().copied()));
+        self.port.write_all(&frame)?;
+        self.port.flush()?;
+
+        let mut header = [0u8; 3];
+        read_exact_with_timeout(&mut *self.port, &mut header, timeout)?;
+        let mut body = vec![0u8; u16::from_be_bytes([header[1], header[2]]) as usize + 1];
+        read_exact_with_timeout(&mut *self.port, &mut body, self.timeout)?;
+        if xor_checksum(header.into_iter().chain(body.iter().copied())) != 0 {
+            return Err(Error::Stub(format!("corrupt reply to op 0x{op:02X}")));
+        }
+        body.pop();
+        match header[0] {
+            ACK => Ok(body),
+            NACK => Err(Error::Stub(format!(
+                "op 0x{op:02X} failed: {}",
+                String::from_utf8_lossy(&body)
+            ))),
+            other => Err(Error::UnexpectedResponse(other)),
+        }
+    }
+
+    /// 回显测试
+    pub fn ping(&mut self, payload: &[u8]) -> Result<()> {
+        let echo = self.request(OP_PING, payload, self.timeout)?;
+        if echo != payload {
+            return Err(Error::Stub("ping echo does not match".to_string()));
+        }
+        Ok(())
+    }
+
+    /// 双方切换到新的波特率，切换后用 PING 确认链路
+    pub fn set_baud(&mut self, baud: u32) -> Result<()> {
+        self.request(OP_SET_BAUD, &baud.to_be_bytes(), self.timeout)?;
+        self.port.set_baud_rate(baud)?;
+        std::thread::sleep(BAUD_SETTLE);
+        self.ping(&baud.to_be_bytes())?;
+        info!("{}", tr(Msg::StubBaudSwitched, &[&baud]));
+        Ok(())
+    }
+
+    /// 擦除覆盖 [address, address + len) 的页 / 扇区，粒度由 stub 决定
+    pub fn erase(&mut self, address: u32, len: u32) -> Result<()> {
+        let mut payload = address.to_be_bytes().to_vec();
+        payload.extend_from_slice(&len.to_be_bytes());
+        self.request(OP_ERASE, &payload, self.erase_timeout)?;
+        Ok(())
+    }
+
+    /// 按握手声明的负载上限分帧写入
+    pub fn write(&mut self, address: u32, data: &[u8]) -> Result<()> {
+        self.write_with_progress(address, data, |_| {})
+    }
+
+    fn write_with_progress(
+        &mut self,
+        address: u32,
+        data: &[u8],
+        mut on_chunk: impl FnMut(usize),
+    ) -> Result<()> {
+        let chunk = self.info.max_payload as usize - 4;
+        for (i, part) in data.chunks(chunk).enumerate() {
+            let mut payload = (address + (i * chunk) as u32).to_be_bytes().to_vec();
+            payload.extend_from_slice(part);
+            self.request(OP_WRITE, &payload, self.timeout)?;
+            on_chunk(part.len());
+        }
+        Ok(())
+    }
+
+    /// 按握手声明的负载上限分帧读取
+    pub fn read(&mut self, address: u32, len: usize) -> Result<Vec<u8>> {
+        let chunk = self.info.max_payload as usize;
+        let mut data = Vec::with_capacity(len);
+        while data.len() < len {
+            let n = chunk.min(len - data.len());
+            let mut payload = (address + data.len() as u32).to_be_bytes().to_vec();
+            payload.extend_from_slice(&(n as u16).to_be_bytes());
+            let part = self.request(OP_READ, &payload, self.timeout)?;
+            if part.len() != n {
+                return Err(Error::Stub(format!(
+                    "read returned {} bytes, expected {n}",
+                    part.len()
+                )));
+            }
+            data.extend(part);
+        }
+        Ok(data)
+    }
+
+    /// 透传自定义操作（操作码 ≥ 0x80），返回应答负载
+    pub fn custom(&mut self, op: u8, payload: &[u8]) -> Result<Vec<u8>> {
+        if op < OP_CUSTOM {
+            return Err(Error::Stub(format!(
+                "op 0x{op:02X} is reserved; custom ops start at 0x{OP_CUSTOM:02X}"
+            )));
+        }
+        self.request(op, payload, self.erase_timeout)
+    }
+
+    /// 让 stub 从 `address` 处的向量表启动用户程序
+    pub fn go(mut self, address: u32) -> Result<()> {
+        self.request(OP_GO, &address.to_be_bytes(), self.timeout)?;
+        Ok(())
+    }
+}
+
+/// 加载 stub 后经其擦除、写入并校验固件，最后按选项启动用户程序
+pub fn flash_with_stub(
+    port_name: &str,
+    sources: &[ImageSource],
+    stub: &StubConfig,
+    options: &FlashOptions,
+    sink: &dyn ProgressSink,
+) -> Result<FlashReport> {
+    let image = stm32_uart::merge_sources(sources, options)?;
+    let entry = stm32_uart::sources_entry_point(sources)?;
+    let options = &stm32_uart::apply_entry_point(options, entry, &image);
+    let go_address = stm32_uart::validate_go_address(options.go_address.unwrap_or(FLASH_BASE))?;
+    stm32_uart::report_image_checks(&image, options);
+
+    let session = BootloaderSession::open(port_name, options)?;
+    session.check_image(&image)?;
+    session.check_vector_table(&image);
+    let mut timeline = session.timeline().clone();
+    let mut client = session.start_stub(stub)?;
+    if let Some(baud) = stub.baud {
+        client.set_baud(baud)?;
+    }
+
+    let blocks = image_to_blocks(&image);
+    let started = Instant::now();
+    info!("{}", tr(Msg::StubErasing, &[&blocks.len()]));
+    for (base, data) in &blocks {
+        client.erase(*base, data.len() as u32)?;
+    }
+    timeline.add(FlashPhase::Erase, started.elapsed());
+
+    let started = Instant::now();
+    let bytes_written = write_blocks(&mut client, &blocks, image.len(), options, sink)?;
+    timeline.add(FlashPhase::Write, started.elapsed());
+    // stub 读取很快，任何校验策略都全量回读
+    if options.verify.enabled() {
+        let started = Instant::now();
+        verify_blocks(&mut client, &image, options, sink)?;
+        timeline.add(FlashPhase::Verify, started.elapsed());
+    }
+
+    if options.reset_after && !options.stay_in_bootloader {
+        let started = Instant::now();
+        info!(
+            "{}",
+            tr(Msg::StartingApp, &[&format!("0x{go_address:08X}")])
+        );
+        client.go(go_address)?;
+        timeline.add(FlashPhase::Reset, started.elapsed());
+        info!("{}", tr(Msg::AppStarted, &[]));
+    }
+
+    // stub 帧不是 Bootloader 命令，不给链路统计
+    Ok(FlashReport {
+        bytes_written,
+        timeline,
+        link: None,
+    })
+}
+
+fn write_blocks(
+    client: &mut StubClient,
+    blocks: &[(u32, Vec<u8>)],
+    total: usize,
+    options: &FlashOptions,
+    sink: &dyn ProgressSink,
+) -> Result<u64> {
+    info!("{}", tr(Msg::Writing, &[]));
+    let mut progress = ProgressReporter::new(ProgressPhase::Write, total, options.progress);
+    let mut written = 0usize;
+    for (base, data) in blocks {
+        options.cancel.check()?;
+        let mut offset = 0;
+        client.write_with_progress(*base, data, |n| {
+            progress.update(base + offset, written + n, sink);
+            offset += n as u32;
+            written += n;
+        })?;
+    }
+    Ok(written as u64)
+}
+
+fn verify_blocks(
+    client: &mut StubClient,
+    image: &BTreeMap<u32, u8>,
+    options: &FlashOptions,
+    sink: &dyn ProgressSink,
+) -> Result<()> {
+    info!("{}", tr(Msg::Verifying, &[]));
+    let mut progress = ProgressReporter::new(ProgressPhase::Verify, image.len(), options.progress);
+    let mut checked = 0usize;
+    for (base, data) in image_to_blocks(image) {
+        options.cancel.check()?;
+        let read = client.read(base, data.len())?;
+        if let Some(pos) = data.iter().zip(&read).position(|(a, b)| a != b) {
+            return Err(Error::VerifyMismatch {
+                address: base + pos as u32,
+                expected: data[pos],
+                actual: read[pos],
+            });
+        }
+        checked += data.len();
+        progress.update(base, checked, sink);
+    }
+    info!("{}", tr(Msg::Verified, &[&checked]));
+    Ok(())
+}
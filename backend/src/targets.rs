@@ -0,0 +1,197 @@
+//! 目标芯片族注册表：列出已支持的目标 / 协议、可用的固件格式和选项描述，
+//! GUI 据此动态生成目标选择器和选项表单
+
+use clap::ValueEnum;
+use serde::Serialize;
+
+use crate::i18n::{Msg, tr};
+use crate::stm32_dfu::DfuSelector;
+use crate::stm32_uart::{BootMode, FLASH_BASE, FlashOptions};
+
+/// 下拉选项
+#[derive(Debug, Clone, Serialize)]
+pub struct Choice {
+    pub value: String,
+    pub label: String,
+}
+
+/// 选项的取值类型
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum OptionKind {
+    Bool {
+        default: bool,
+    },
+    Number {
+        default: u64,
+    },
+    /// 十进制或 0x 前缀的地址
+    Address {
+        default: Option<u32>,
+    },
+    Text {
+        default: String,
+    },
+    Choice {
+        default: String,
+        choices: Vec<Choice>,
+    },
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct OptionSchema {
+    /// 与对应 Tauri 命令参数名一致
+    pub key: &'static str,
+    /// 按当前语言翻译的标签
+    pub label: String,
+    #[serde(flatten)]
+    pub kind: OptionKind,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TargetFamily {
+    pub id: &'static str,
+    pub name: &'static str,
+    pub protocol: &'static str,
+    /// 支持的固件文件扩展名
+    pub file_formats: &'static [&'static str],
+    pub options: Vec<OptionSchema>,
+}
+
+/// GUI 提供的常用波特率
+pub const BAUD_RATES: &[u32] = &[
+    600, 1200, 4800, 9600, 14400, 19200, 38400, 57600, 76800, 115200, 128000, 230400, 256000,
+    460800,
+];
+
+fn stm32_uart() -> TargetFamily {
+    let defaults = FlashOptions::default();
+    let boot_modes = BootMode::value_variants()
+        .iter()
+        .filter_map(|m| m.to_possible_value())
+        .map(|v| Choice {
+            value: v.get_name().to_string(),
+            label: v
+                .get_help()
+                .map_or(v.get_name().to_string(), |h| h.to_string()),
+        })
+        .collect();
+    let baud_rates = BAUD_RATES
+        .iter()
+        .map(|b| Choice {
+            value: b.to_string(),
+            label: b.to_string(),
+        })
+        .collect();
+
+    TargetFamily {
+        id: "stm32-uart",
+        name: "STM32 UART Bootloader",
+        protocol: "AN3155",
+        file_formats: &["hex", "ihex", "bin", "elf", "axf", "srec", "s19", "dfu"],
+        options: vec![
+            OptionSchema {
+                key: "baud",
+                label: tr(Msg::OptBaud, &[]),
+                kind: OptionKind::Choice {
+                    default: defaults.baud_rate.to_string(),
+                    choices: baud_rates,
+                },
+            },
+            OptionSchema {
+                key: "bootMode",
+                label: tr(Msg::OptBootMode, &[]),
+                kind: OptionKind::Choice {
+                    default: defaults
+                        .boot_mode
+                        .to_possible_value()
+                        .map_or(String::new(), |v| v.get_name().to_string()),
+                    choices: boot_modes,
+                },
+            },
+            OptionSchema {
+                key: "resetAfter",
+                label: tr(Msg::OptResetAfter, &[]),
+                kind: OptionKind::Bool { default: true },
+            },
+            OptionSchema {
+                key: "autoBaud",
+                label: tr(Msg::OptAutoBaud, &[]),
+                kind: OptionKind::Bool {
+                    default: defaults.auto_baud,
+                },
+            },
+            OptionSchema {
+                key: "rebaseAlias",
+                label: tr(Msg::OptRebaseAlias, &[]),
+                kind: OptionKind::Bool {
+                    default: defaults.rebase_alias,
+                },
+            },
+            OptionSchema {
+                key: "goAddress",
+                label: tr(Msg::OptGoAddress, &[]),
+                kind: OptionKind::Address {
+                    default: defaults.go_address,
+                },
+            },
+            OptionSchema {
+                key: "versionPattern",
+                label: tr(Msg::OptVersionPattern, &[]),
+                kind: OptionKind::Text {
+                    default: String::new(),
+                },
+            },
+        ],
+    }
+}
+
+fn stm32_dfu() -> TargetFamily {
+    let defaults = FlashOptions::default();
+    TargetFamily {
+        id: "stm32-dfu",
+        name: "STM32 USB DFU Bootloader",
+        protocol: "AN3156",
+        file_formats: &["hex", "ihex", "bin", "elf", "axf", "srec", "s19", "dfu"],
+        options: vec![
+            OptionSchema {
+                key: "serial",
+                label: tr(Msg::OptDfuSerial, &[]),
+                kind: OptionKind::Text {
+                    default: String::new(),
+                },
+            },
+            OptionSchema {
+                key: "alt",
+                label: tr(Msg::OptDfuAlt, &[]),
+                kind: OptionKind::Number {
+                    default: DfuSelector::default().alt.into(),
+                },
+            },
+            OptionSchema {
+                key: "address",
+                label: tr(Msg::OptLoadAddress, &[]),
+                kind: OptionKind::Address {
+                    default: Some(FLASH_BASE),
+                },
+            },
+            OptionSchema {
+                key: "resetAfter",
+                label: tr(Msg::OptResetAfter, &[]),
+                kind: OptionKind::Bool { default: true },
+            },
+            OptionSchema {
+                key: "goAddress",
+                label: tr(Msg::OptGoAddress, &[]),
+                kind: OptionKind::Address {
+                    default: defaults.go_address,
+                },
+            },
+        ],
+    }
+}
+
+/// 已注册的目标芯片族
+pub fn target_families() -> Vec<TargetFamily> {
+    vec![stm32_uart(), stm32_dfu()]
+}
@@ -0,0 +1,96 @@
+//! Bootloader 协议使用的传输层：收发字节、控制 DTR/RTS、清空接收缓冲
+//!
+//! 协议代码只依赖 `Transport`，系统串口和模拟器之外，TCP 透传的串口服务器、测试用的 mock
+//! 以及以后的 USB / CAN 后端实现这个 trait 即可复用全部协议流程。所有 `serialport::SerialPort`
+//! 自动实现该 trait。
+
+use std::{io, time::Duration};
+
+use serialport::{ClearBuffer, SerialPort};
+
+use crate::linkstats::LinkStats;
+use crate::stm32_uart::{Result, StatusLine};
+
+fn unsupported(what: &str) -> crate::stm32_uart::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!("transport does not support {what}"),
+    )
+    .into()
+}
+
+pub trait Transport: io::Read + io::Write + Send {
+    /// 之后每次 `read` 最多阻塞的时间，读不到数据时返回 `ErrorKind::TimedOut`
+    fn set_timeout(&mut self, timeout: Duration) -> Result<()>;
+
+    fn set_dtr(&mut self, level: bool) -> Result<()>;
+
+    fn set_rts(&mut self, level: bool) -> Result<()>;
+
+    /// 丢弃已收到但未读取的数据
+    fn clear(&mut self) -> Result<()>;
+
+    /// 切换波特率；不支持时返回错误，调用方保持原波特率
+    fn set_baud_rate(&mut self, _baud_rate: u32) -> Result<()> {
+        Err(unsupported("baud rate changes"))
+    }
+
+    /// 当前波特率，没有这一概念的传输为 None
+    fn baud_rate(&self) -> Option<u32> {
+        None
+    }
+
+    /// 读取 CTS / DSR / CD / RI 输入线
+    fn read_status(&mut self, _line: StatusLine) -> Result<bool> {
+        Err(unsupported("status lines"))
+    }
+
+    /// 端口名，芯片复位后据此重新打开；None 时不跟踪
+    fn name(&self) -> Option<String> {
+        None
+    }
+
+    /// 链路统计；只有会话使用的 `BufferedPort` 记录，其他传输为 None
+    fn link_stats(&mut self) -> Option<&mut LinkStats> {
+        None
+    }
+}
+
+impl<T: SerialPort + ?Sized> Transport for T {
+    fn set_timeout(&mut self, timeout: Duration) -> Result<()> {
+        Ok(SerialPort::set_timeout(self, timeout)?)
+    }
+
+    fn set_dtr(&mut self, level: bool) -> Result<()> {
+        Ok(self.write_data_terminal_ready(level)?)
+    }
+
+    fn set_rts(&mut self, level: bool) -> Result<()> {
+        Ok(self.write_request_to_send(level)?)
+    }
+
+    fn clear(&mut self) -> Result<()> {
+        Ok(SerialPort::clear(self, ClearBuffer::Input)?)
+    }
+
+    fn set_baud_rate(&mut self, baud_rate: u32) -> Result<()> {
+        Ok(SerialPort::set_baud_rate(self, baud_rate)?)
+    }
+
+    fn baud_rate(&self) -> Option<u32> {
+        SerialPort::baud_rate(self).ok()
+    }
+
+    fn read_status(&mut self, line: StatusLine) -> Result<bool> {
+        Ok(match line {
+            StatusLine::Cts => self.read_clear_to_send()?,
+            StatusLine::Dsr => self.read_data_set_ready()?,
+            StatusLine::Cd => self.read_carrier_detect()?,
+            StatusLine::Ri => self.read_ring_indicator()?,
+        })
+    }
+
+    fn name(&self) -> Option<String> {
+        SerialPort::name(self)
+    }
+}
@@ -0,0 +1,218 @@
+//! 接线检查向导：依次改变 DTR / RTS 的电平，由用户报告每一步板上复位和 BOOT0 的状态，
+//! 按观察结果推断 Boot 模式和有效电平
+//!
+//! 共三步：两根线都为低，再分别单独拉高一根。每种「复位线 + 有效电平 + BOOT0 线 + 有效电平」
+//! 组合在这三步中的表现都不相同，所以三步的观察结果足以唯一确定接法。
+
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+use crate::devicedb::{self, ConnectionDb, ConnectionProfile};
+use crate::i18n::{Msg, tr};
+use crate::stm32_uart::{self, BootLineConfig, BootMode, ControlLine, FlashOptions, Level, Result};
+use crate::transport::Transport;
+
+/// 一步中 DTR 和 RTS 的电平
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct WiringStep {
+    pub dtr: Level,
+    pub rts: Level,
+}
+
+pub const STEPS: [WiringStep; 3] = [
+    WiringStep {
+        dtr: Level::Low,
+        rts: Level::Low,
+    },
+    WiringStep {
+        dtr: Level::High,
+        rts: Level::Low,
+    },
+    WiringStep {
+        dtr: Level::Low,
+        rts: Level::High,
+    },
+];
+
+fn level_name(level: Level) -> String {
+    tr(
+        match level {
+            Level::High => Msg::LevelHigh,
+            Level::Low => Msg::LevelLow,
+        },
+        &[],
+    )
+}
+
+impl WiringStep {
+    fn level(self, line: ControlLine) -> Level {
+        match line {
+            ControlLine::Dtr => self.dtr,
+            ControlLine::Rts => self.rts,
+        }
+    }
+
+    /// 提示用户观察的说明
+    pub fn prompt(self) -> String {
+        tr(
+            Msg::WiringStepPrompt,
+            &[&level_name(self.dtr), &level_name(self.rts)],
+        )
+    }
+}
+
+/// 用户在某一步看到的现象
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Observation {
+    /// 芯片处于复位：运行指示灯熄灭、程序停止
+    pub in_reset: bool,
+    /// BOOT0 为高：BOOT0 指示灯亮或测得高电平
+    pub boot0_high: bool,
+}
+
+/// 推断出的接法
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct WiringVerdict {
+    pub boot_mode: BootMode,
+    pub lines: BootLineConfig,
+}
+
+impl WiringVerdict {
+    /// 这种接法在某一步应有的现象
+    pub fn predict(&self, step: WiringStep) -> Observation {
+        let Some((reset_line, boot_line)) = self.boot_mode.wiring() else {
+            return Observation::default();
+        };
+        Observation {
+            in_reset: step.level(reset_line) == self.lines.reset_assert_level,
+            boot0_high: boot_line.is_some_and(|line| step.level(line) == self.lines.boot_level),
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        tr(
+            Msg::WiringDetected,
+            &[
+                &format!("{:?}", self.boot_mode),
+                &level_name(self.lines.boot_level),
+                &level_name(self.lines.reset_assert_level),
+            ],
+        )
+    }
+
+    /// 需要在高级设置中覆盖的电平个数，0 表示与 Boot 模式名称一致
+    pub fn overrides(&self) -> usize {
+        let named = BootLineConfig::for_mode(self.boot_mode);
+        usize::from(self.lines.boot_level != named.boot_level)
+            + usize::from(self.lines.reset_assert_level != named.reset_assert_level)
+    }
+
+    /// 作为该串口设备记住的连接参数保存，下次连接时优先使用；非 USB 串口返回 false
+    pub fn remember(&self, port_name: &str, options: &FlashOptions) -> Result<bool> {
+        let Some(key) = devicedb::connection_key(port_name) else {
+            return Ok(false);
+        };
+        let path = options
+            .remember_connection
+            .clone()
+            .unwrap_or_else(devicedb::default_connections_path);
+        let mut db = ConnectionDb::load(&path)?;
+        let options = FlashOptions {
+            boot_mode: self.boot_mode,
+            lines: self.lines,
+            ..options.clone()
+        };
+        db.profiles
+            .insert(key, ConnectionProfile::from_options(&options));
+        db.save(&path)?;
+        Ok(true)
+    }
+}
+
+/// 所有可能的接法，需要覆盖的电平少的排在前面
+fn candidates() -> Vec<WiringVerdict> {
+    let mut all = Vec::new();
+    for &boot_mode in BootMode::value_variants() {
+        if boot_mode == BootMode::None {
+            continue;
+        }
+        for reset_assert_level in [Level::Low, Level::High] {
+            for boot_level in [Level::High, Level::Low] {
+                all.push(WiringVerdict {
+                    boot_mode,
+                    lines: BootLineConfig {
+                        boot_level,
+                        reset_assert_level,
+                    },
+                });
+            }
+        }
+    }
+    all.sort_by_key(WiringVerdict::overrides);
+    all
+}
+
+/// 按三步的观察结果推断接法；现象前后矛盾（如从未复位）时返回 None
+pub fn infer(observations: &[Observation]) -> Option<WiringVerdict> {
+    if observations.len() != STEPS.len() {
+        return None;
+    }
+    candidates().into_iter().find(|verdict| {
+        STEPS
+            .iter()
+            .zip(observations)
+            .all(|(&step, &seen)| verdict.predict(step) == seen)
+    })
+}
+
+/// 进行中的接线检查，串口在整个过程中保持打开
+pub struct WiringCheck {
+    port: Box<dyn Transport>,
+    observations: Vec<Observation>,
+}
+
+impl WiringCheck {
+    /// 打开串口并切换到第一步
+    pub fn start(port_name: &str, options: &FlashOptions) -> Result<Self> {
+        let port = stm32_uart::open_port_with(port_name, options.baud_rate, options)?;
+        let mut check = Self {
+            port,
+            observations: Vec::new(),
+        };
+        check.apply(STEPS[0])?;
+        Ok(check)
+    }
+
+    fn apply(&mut self, step: WiringStep) -> Result<()> {
+        stm32_uart::set_line(&mut *self.port, ControlLine::Dtr, step.dtr)?;
+        stm32_uart::set_line(&mut *self.port, ControlLine::Rts, step.rts)
+    }
+
+    /// 当前一步，全部完成后为 None
+    pub fn step(&self) -> Option<WiringStep> {
+        STEPS.get(self.observations.len()).copied()
+    }
+
+    /// 序号从 0 开始
+    pub fn index(&self) -> usize {
+        self.observations.len()
+    }
+
+    /// 记录当前一步的现象并切换到下一步，返回下一步
+    pub fn observe(&mut self, seen: Observation) -> Result<Option<WiringStep>> {
+        if self.step().is_some() {
+            self.observations.push(seen);
+        }
+        let next = self.step();
+        if let Some(step) = next {
+            self.apply(step)?;
+        }
+        Ok(next)
+    }
+
+    /// 两根线恢复为低电平后关闭串口，返回推断结果
+    pub fn finish(mut self) -> Option<WiringVerdict> {
+        let _ = self.apply(STEPS[0]);
+        infer(&self.observations)
+    }
+}
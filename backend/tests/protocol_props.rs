@@ -0,0 +1,1991 @@
+//! 在模拟 Bootloader 上随机生成稀疏镜像和应答故障，检查烧录结果要么与镜像一致，
+//! 要么返回可归类的协议错误
+
+use std::{collections::BTreeMap, time::Duration};
+
+use probe_flasher::{
+    config::ProjectConfig,
+    i18n::{Msg, tr},
+    logging::LineLayer,
+    sim::{DemoPort, Fault, SimConfig, SimulatedBootloader},
+    stm32_dfu::{self, DfuDevice, DfuError, DfuMemory, DfuSegment, DfuSession},
+    stm32_uart::{
+        self, BootloaderProtocol, BootloaderSession, BoundaryPolicy, CancelToken, EraseMode, Error,
+        ErrorCode, FLASH_BASE, FlashEvent, FlashOptions, ImageFormat, ImageSource, Padding,
+        ProgressEvent, ProgressGranularity, ProgressPhase, ProgressSink, ProtocolVariant,
+        RetryPolicy, Timeouts, VerifyStrategy,
+    },
+    transport::Transport,
+};
+use proptest::prelude::*;
+
+struct NullLogger;
+
+impl ProgressSink for NullLogger {}
+
+fn options() -> FlashOptions {
+    FlashOptions {
+        timeouts: Timeouts {
+            sync: Some(Duration::from_millis(30)),
+            ack: Some(Duration::from_millis(30)),
+            erase: Some(Duration::from_millis(100)),
+            read_chunk: Some(Duration::from_millis(30)),
+        },
+        sync_retry: RetryPolicy::new(3, Duration::ZERO),
+        ..FlashOptions::default()
+    }
+}
+
+/// 64 KB Flash 内的若干随机数据段
+fn sparse_image() -> impl Strategy<Value = BTreeMap<u32, u8>> {
+    prop::collection::vec(
+        (0u32..0xF000, prop::collection::vec(any::<u8>(), 1..600)),
+        1..6,
+    )
+    .prop_map(|segments| {
+        let mut image = BTreeMap::new();
+        for (offset, data) in segments {
+            image.extend((FLASH_BASE + offset..).zip(data));
+        }
+        image
+    })
+}
+
+fn fault() -> impl Strategy<Value = Option<Fault>> {
+    prop_oneof![
+        12 => Just(None),
+        1 => Just(Some(Fault::DropAck)),
+        1 => any::<u8>().prop_map(|b| Some(Fault::Garbage(b))),
+        1 => (0u64..60).prop_map(|ms| Some(Fault::Delay(Duration::from_millis(ms)))),
+    ]
+}
+
+fn flash(sim: &SimulatedBootloader, image: &BTreeMap<u32, u8>) -> Result<u64, Error> {
+    let logger = NullLogger;
+    let mut session = BootloaderSession::from_port(Box::new(sim.clone()), &options())?;
+    session.erase_all(&logger)?;
+    session.write_image(image, &logger)
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(48))]
+
+    #[test]
+    fn clean_link_flashes_exact_image(image in sparse_image()) {
+        let sim = SimulatedBootloader::new(SimConfig::default());
+
+        let written = flash(&sim, &image).unwrap();
+        prop_assert_eq!(written, image.len() as u64);
+        prop_assert_eq!(sim.flash(), image);
+    }
+
+    #[test]
+    fn faulty_link_flashes_or_classifies(
+        image in sparse_image(),
+        faults in prop::collection::vec(fault(), 0..40),
+    ) {
+        let sim = SimulatedBootloader::new(SimConfig::default());
+        sim.inject_faults(faults);
+
+        match flash(&sim, &image) {
+            Ok(written) => {
+                prop_assert_eq!(written, image.len() as u64);
+                prop_assert_eq!(sim.flash(), image);
+            }
+            Err(e) => prop_assert!(
+                matches!(
+                    e,
+                    Error::Timeout
+                        | Error::Nack
+                        | Error::UnexpectedResponse(_)
+                        | Error::NoSync { .. }
+                ),
+                "unclassified error: {e}"
+            ),
+        }
+    }
+}
+
+proptest! {
+    #[test]
+    fn hex_serialization_roundtrips(
+        segments in prop::collection::vec(
+            (0u32..0x3_0000, prop::collection::vec(any::<u8>(), 1..300)),
+            1..8,
+        ),
+    ) {
+        let mut image = BTreeMap::new();
+        for (offset, data) in segments {
+            image.extend((FLASH_BASE + offset..).zip(data));
+        }
+
+        let hex = stm32_uart::image_to_hex(&image).unwrap();
+        let path = std::env::temp_dir().join(format!("probe-flasher-{}-roundtrip.hex", std::process::id()));
+        std::fs::write(&path, hex).unwrap();
+        let parsed = stm32_uart::parse_hex_to_image(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        prop_assert_eq!(parsed, image);
+    }
+}
+
+#[test]
+fn read_protected_write_reports_unlock_hint() {
+    let sim = SimulatedBootloader::new(SimConfig {
+        read_protected: true,
+        ..SimConfig::default()
+    });
+    let image = BTreeMap::from([(FLASH_BASE, 0xAA)]);
+
+    assert!(matches!(flash(&sim, &image), Err(Error::ReadProtected)));
+}
+
+#[test]
+fn late_ack_is_not_mistaken_for_read_protection() {
+    // 超时后迟到的 ACK 留在接收缓冲里，不能被读保护探测当作应答
+    let sim = SimulatedBootloader::new(SimConfig::default());
+    let image: BTreeMap<u32, u8> = (FLASH_BASE..FLASH_BASE + 289)
+        .map(|a| (a, a as u8))
+        .collect();
+    let mut faults = vec![None; 21];
+    faults[6] = Some(Fault::Delay(Duration::from_millis(50)));
+    faults[11] = Some(Fault::Delay(Duration::from_millis(33)));
+    faults[16] = Some(Fault::Delay(Duration::from_millis(56)));
+    faults[17] = Some(Fault::Garbage(14));
+    sim.inject_faults(faults);
+
+    let result = flash(&sim, &image);
+    assert!(!matches!(result, Err(Error::ReadProtected)), "{result:?}");
+}
+
+#[test]
+fn access_outside_memory_map_is_rejected() {
+    let sim = SimulatedBootloader::new(SimConfig::default());
+    let logger = NullLogger;
+    let mut session = BootloaderSession::from_port(Box::new(sim.clone()), &options()).unwrap();
+    assert!(session.memory_map().is_some());
+
+    // 模拟器的 Flash 容量寄存器为 64 KB，跨出末尾的读取和写入系统存储器都应被拒绝
+    let result = session.read(FLASH_BASE + 0x1_FF00, 0x200, &logger);
+    assert!(matches!(result, Err(Error::AddressOutOfRange { .. })));
+    let image = BTreeMap::from([(0x1FFF_F000, 0xAA)]);
+    let result = session.write_image(&image, &logger);
+    assert!(matches!(result, Err(Error::AddressOutOfRange { .. })));
+}
+
+#[test]
+fn protocol_log_lines_reach_line_layer() {
+    use std::sync::{Arc, Mutex};
+    use tracing_subscriber::prelude::*;
+
+    let lines = Arc::new(Mutex::new(Vec::new()));
+    let captured = lines.clone();
+    let subscriber = tracing_subscriber::registry().with(LineLayer::new(move |level, msg| {
+        captured.lock().unwrap().push((level, msg.to_string()))
+    }));
+    let sim = SimulatedBootloader::new(SimConfig::default());
+    let image = BTreeMap::from([(FLASH_BASE, 0x5A)]);
+    tracing::subscriber::with_default(subscriber, || flash(&sim, &image)).unwrap();
+    // 本 crate 之外的事件不转发
+    tracing::subscriber::with_default(
+        tracing_subscriber::registry().with(LineLayer::new(|_, _| panic!("forwarded"))),
+        || tracing::info!("other crate"),
+    );
+
+    let lines = lines.lock().unwrap();
+    assert!(
+        lines.contains(&("info", tr(Msg::Writing, &[]))),
+        "{lines:?}"
+    );
+    assert!(lines.iter().all(|(level, _)| *level == "info"));
+}
+
+#[test]
+fn slow_erase_reports_heartbeats() {
+    struct Recorder(std::sync::Mutex<Vec<ProgressEvent>>);
+
+    impl ProgressSink for Recorder {
+        fn progress(&self, event: &ProgressEvent) {
+            self.0.lock().unwrap().push(*event);
+        }
+    }
+
+    let sim = SimulatedBootloader::new(SimConfig::default());
+    let logger = Recorder(Default::default());
+    let opts = FlashOptions {
+        timeouts: Timeouts {
+            erase: Some(Duration::from_secs(2)),
+            ..options().timeouts
+        },
+        ..options()
+    };
+    let mut session = BootloaderSession::from_port(Box::new(sim.clone()), &opts).unwrap();
+    // 命令 ACK 正常，全擦除完成的 ACK 延迟 700 ms
+    sim.inject_faults([None, Some(Fault::Delay(Duration::from_millis(700)))]);
+    session.erase_all(&logger).unwrap();
+
+    let events = logger.0.into_inner().unwrap();
+    // 0x410 预计 40 ms：心跳停在 39，最后一条为完成
+    assert!(events.len() >= 4, "{events:?}");
+    assert_eq!(events[0], ProgressEvent::EraseStarted { expected_ms: 40 });
+    assert!(events[1..events.len() - 1].iter().all(|e| *e
+        == ProgressEvent::EraseTick {
+            elapsed_ms: 39,
+            expected_ms: 40
+        }));
+    assert_eq!(
+        events.last(),
+        Some(&ProgressEvent::Done {
+            phase: ProgressPhase::Erase,
+            total: 40
+        })
+    );
+}
+
+#[test]
+fn cancel_stops_write_between_chunks() {
+    struct CancelOnProgress(CancelToken);
+
+    impl ProgressSink for CancelOnProgress {
+        fn progress(&self, _event: &ProgressEvent) {
+            self.0.cancel();
+        }
+    }
+
+    let sim = SimulatedBootloader::new(SimConfig::default());
+    let opts = FlashOptions {
+        progress: ProgressGranularity {
+            every_bytes: 0,
+            max_per_sec: 0,
+        },
+        ..options()
+    };
+    let logger = CancelOnProgress(opts.cancel.clone());
+    let mut session = BootloaderSession::from_port(Box::new(sim.clone()), &opts).unwrap();
+    let image: BTreeMap<u32, u8> = (0..1024).map(|i| (FLASH_BASE + i, i as u8)).collect();
+
+    let result = session.write_image(&image, &logger);
+    assert!(matches!(result, Err(Error::Cancelled)), "{result:?}");
+    // 第一块写完后取消，之后不再发出写命令
+    assert_eq!(sim.flash().len(), 256);
+    assert!(matches!(session.finish(), Err(Error::Cancelled)));
+}
+
+#[test]
+fn sync_failure_is_distinct_and_retryable() {
+    let sim = SimulatedBootloader::new(SimConfig::default());
+    let mut port: Box<dyn Transport> = Box::new(sim.clone());
+    sim.inject_faults([Some(Fault::Garbage(0x00))]);
+
+    let once = FlashOptions {
+        sync_retry: RetryPolicy::new(1, Duration::ZERO),
+        ..options()
+    };
+    let err = stm32_uart::sync(&mut *port, &once).unwrap_err();
+    assert!(
+        matches!(&err, Error::NoSync { attempts: 1, last } if matches!(**last, Error::UnexpectedResponse(0x00))),
+        "{err}"
+    );
+    // 同一个串口上直接重试同步，无需再次执行 Boot 序列
+    stm32_uart::sync(&mut *port, &options()).unwrap();
+}
+
+#[test]
+fn synthetic_hex_flashes_and_starts_in_process() {
+    let image: BTreeMap<u32, u8> = (FLASH_BASE..FLASH_BASE + 0x900)
+        .chain(FLASH_BASE + 0x4000..FLASH_BASE + 0x4011)
+        .zip((0u8..=250).cycle())
+        .collect();
+    let path = std::env::temp_dir().join(format!(
+        "probe-flasher-{}-synthetic.hex",
+        std::process::id()
+    ));
+    std::fs::write(&path, stm32_uart::image_to_hex(&image).unwrap()).unwrap();
+    let parsed = stm32_uart::parse_hex_to_image(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    let sim = SimulatedBootloader::new(SimConfig::default());
+    let logger = NullLogger;
+    let options = FlashOptions {
+        reset_after: true,
+        ..options()
+    };
+    let mut session = BootloaderSession::from_port(Box::new(sim.clone()), &options).unwrap();
+    // GET 返回的命令表里有 GET / GET_ID / READ / GO / WRITE
+    for cmd in [0x00, 0x02, 0x11, 0x21, 0x31] {
+        assert!(session.commands().contains(&cmd), "missing 0x{cmd:02X}");
+    }
+    assert!(session.product_id().is_some());
+    session.erase_for_image(&parsed, &logger).unwrap();
+    assert_eq!(
+        session.write_image(&parsed, &logger).unwrap(),
+        image.len() as u64
+    );
+    session.verify_image(&parsed, &logger).unwrap();
+    session.finish().unwrap();
+
+    assert_eq!(sim.flash(), image);
+    assert_eq!(sim.go_address(), Some(FLASH_BASE));
+}
+
+#[test]
+fn session_steps_compose_custom_sequence() {
+    let sim = SimulatedBootloader::new(SimConfig::default());
+    let logger = NullLogger;
+    let config_page = FLASH_BASE + 0x8000;
+    let mut session = BootloaderSession::from_port(Box::new(sim.clone()), &options()).unwrap();
+    let info = session.info();
+    assert!(info.ok);
+    assert_eq!(info.product_id, session.product_id());
+    assert_eq!(info.read_protected, Some(false));
+
+    session.erase_all(&logger).unwrap();
+    session.write(FLASH_BASE, &[0x11; 600], &logger).unwrap();
+    session.write(config_page, b"serial=0042", &logger).unwrap();
+
+    // 读出配置页，只擦除并重写应用区
+    let config = session.read(config_page, 11, &logger).unwrap();
+    session.erase_range(FLASH_BASE, 600, &logger).unwrap();
+    assert_eq!(session.read(FLASH_BASE, 4, &logger).unwrap(), [0xFF; 4]);
+    session.write(FLASH_BASE, &[0x22; 300], &logger).unwrap();
+    let app: BTreeMap<u32, u8> = (FLASH_BASE..).zip([0x22; 300]).collect();
+    session.verify_image(&app, &logger).unwrap();
+    assert_eq!(session.read(config_page, 11, &logger).unwrap(), config);
+    assert!(matches!(
+        session.erase_range(0x1000_0000, 4, &logger),
+        Err(Error::AddressOutOfRange { .. })
+    ));
+    session.go(FLASH_BASE).unwrap();
+
+    assert_eq!(sim.go_address(), Some(FLASH_BASE));
+    assert_eq!(&sim.flash()[&config_page], &b's');
+}
+
+/// 只实现 `Transport` 的传输（如 TCP 透传串口），没有控制线和波特率
+struct Tunnel(SimulatedBootloader);
+
+impl std::io::Read for Tunnel {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl std::io::Write for Tunnel {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Transport for Tunnel {
+    fn set_timeout(&mut self, timeout: Duration) -> stm32_uart::Result<()> {
+        Ok(serialport::SerialPort::set_timeout(&mut self.0, timeout)?)
+    }
+
+    fn set_dtr(&mut self, _level: bool) -> stm32_uart::Result<()> {
+        Ok(())
+    }
+
+    fn set_rts(&mut self, _level: bool) -> stm32_uart::Result<()> {
+        Ok(())
+    }
+
+    fn clear(&mut self) -> stm32_uart::Result<()> {
+        Ok(serialport::SerialPort::clear(
+            &self.0,
+            serialport::ClearBuffer::Input,
+        )?)
+    }
+}
+
+#[test]
+fn session_runs_over_custom_transport() {
+    let sim = SimulatedBootloader::new(SimConfig::default());
+    let logger = NullLogger;
+    let mut session =
+        BootloaderSession::from_port(Box::new(Tunnel(sim.clone())), &options()).unwrap();
+    let image: BTreeMap<u32, u8> = (FLASH_BASE..).zip(0u8..=255).collect();
+    session.erase_all(&logger).unwrap();
+    assert_eq!(session.write_image(&image, &logger).unwrap(), 256);
+    assert_eq!(sim.flash(), image);
+    // 不支持改波特率的传输保持原波特率
+    assert_eq!(session.raise_baud(921_600).unwrap(), session.baud());
+}
+
+#[test]
+fn verify_reports_first_mismatch() {
+    let sim = SimulatedBootloader::new(SimConfig::default());
+    let logger = NullLogger;
+    let image: BTreeMap<u32, u8> = (FLASH_BASE..FLASH_BASE + 600)
+        .map(|a| (a, a as u8))
+        .collect();
+    flash(&sim, &image).unwrap();
+
+    let opts = FlashOptions {
+        verify: VerifyStrategy::ReadBackFull,
+        ..options()
+    };
+    let mut session = BootloaderSession::from_port(Box::new(sim.clone()), &opts).unwrap();
+    session.verify_image(&image, &logger).unwrap();
+
+    let mut changed = image.clone();
+    changed.insert(FLASH_BASE + 300, 0x5A);
+    let result = session.verify_image(&changed, &logger);
+    assert!(matches!(
+        result,
+        Err(Error::VerifyMismatch { address, expected: 0x5A, actual: 0x2C }) if address == FLASH_BASE + 300
+    ));
+}
+
+#[test]
+fn crc_verify_matches_and_locates_mismatch() {
+    // STM32 CRC 外设对字 0x12345678 的参考结果
+    assert_eq!(
+        stm32_uart::stm32_crc(
+            &[0x78, 0x56, 0x34, 0x12],
+            stm32_uart::CRC_POLYNOMIAL,
+            stm32_uart::CRC_INIT
+        ),
+        0xDF8A_8A2B
+    );
+
+    let mut config = SimConfig::default();
+    config.commands.push(0xA1);
+    let sim = SimulatedBootloader::new(config);
+    let logger = NullLogger;
+    let image: BTreeMap<u32, u8> = (FLASH_BASE..FLASH_BASE + 600)
+        .map(|a| (a, (a * 7) as u8))
+        .collect();
+    flash(&sim, &image).unwrap();
+
+    let opts = FlashOptions {
+        verify: VerifyStrategy::CrcCommand,
+        ..options()
+    };
+    let mut session = BootloaderSession::from_port(Box::new(sim.clone()), &opts).unwrap();
+    session.verify_image(&image, &logger).unwrap();
+
+    let mut changed = image.clone();
+    changed.insert(FLASH_BASE + 300, 0x5A);
+    let result = session.verify_image(&changed, &logger);
+    assert!(matches!(
+        result,
+        Err(Error::VerifyMismatch { address, expected: 0x5A, .. }) if address == FLASH_BASE + 300
+    ));
+}
+
+#[test]
+fn crc_verify_falls_back_without_get_checksum() {
+    let sim = SimulatedBootloader::new(SimConfig::default());
+    let logger = NullLogger;
+    let image: BTreeMap<u32, u8> = (FLASH_BASE..FLASH_BASE + 64).map(|a| (a, 0x11)).collect();
+    flash(&sim, &image).unwrap();
+
+    let opts = FlashOptions {
+        verify: VerifyStrategy::CrcCommand,
+        ..options()
+    };
+    let mut session = BootloaderSession::from_port(Box::new(sim.clone()), &opts).unwrap();
+    session.verify_image(&image, &logger).unwrap();
+}
+
+#[test]
+fn sampled_verify_reads_only_selected_chunks() {
+    assert_eq!(
+        "sampled:25".parse::<VerifyStrategy>(),
+        Ok(VerifyStrategy::ReadBackSampled(25))
+    );
+    assert_eq!(
+        VerifyStrategy::ReadBackSampled(25).to_string(),
+        "sampled:25"
+    );
+    assert!("sampled:0".parse::<VerifyStrategy>().is_err());
+    assert!("sampled:101".parse::<VerifyStrategy>().is_err());
+
+    let sim = SimulatedBootloader::new(SimConfig::default());
+    let logger = NullLogger;
+    // 16 个 256 字节块，25% 抽样回读第 0、3、7、11、15 块
+    let image: BTreeMap<u32, u8> = (FLASH_BASE..FLASH_BASE + 4096)
+        .map(|a| (a, (a * 3) as u8))
+        .collect();
+    flash(&sim, &image).unwrap();
+
+    let opts = FlashOptions {
+        verify: VerifyStrategy::ReadBackSampled(25),
+        ..options()
+    };
+    let mut session = BootloaderSession::from_port(Box::new(sim.clone()), &opts).unwrap();
+    session.verify_image(&image, &logger).unwrap();
+
+    let mut skipped = image.clone();
+    skipped.insert(FLASH_BASE + 256 + 10, 0x5A);
+    session.verify_image(&skipped, &logger).unwrap();
+
+    let mut sampled = image.clone();
+    sampled.insert(FLASH_BASE + 3 * 256 + 10, 0x5A);
+    let result = session.verify_image(&sampled, &logger);
+    assert!(matches!(
+        result,
+        Err(Error::VerifyMismatch { address, expected: 0x5A, .. }) if address == FLASH_BASE + 3 * 256 + 10
+    ));
+}
+
+#[test]
+fn bank_selection_moves_image_to_second_bank() {
+    use probe_flasher::chipdb;
+
+    let f42x = chipdb::lookup(0x419).unwrap();
+    assert_eq!(f42x.bank_range(2), Some((0x0810_0000, 1024 * 1024)));
+    assert_eq!(f42x.bank_units(2).first().map(|u| u.index), Some(12));
+    assert_eq!(chipdb::lookup(0x410).unwrap().bank_range(2), None);
+
+    let logger = NullLogger;
+    let image: BTreeMap<u32, u8> = (FLASH_BASE..FLASH_BASE + 300)
+        .map(|a| (a, a as u8))
+        .collect();
+    let opts = FlashOptions {
+        bank: Some(2),
+        ..options()
+    };
+
+    // 单 Bank 器件拒绝选择 Bank 2
+    let sim = SimulatedBootloader::new(SimConfig::default());
+    let session = BootloaderSession::from_port(Box::new(sim.clone()), &opts).unwrap();
+    assert!(matches!(session.place_in_bank(&image), Err(Error::Bank(_))));
+
+    // F1 XL-density：Bank 2 从 0x08080000 开始
+    let sim = SimulatedBootloader::new(SimConfig {
+        product_id: 0x430,
+        flash_size: 1024 * 1024,
+        ..SimConfig::default()
+    });
+    let mut session = BootloaderSession::from_port(Box::new(sim.clone()), &opts).unwrap();
+    let placed = session.place_in_bank(&image).unwrap().into_owned();
+    assert_eq!(placed.first_key_value(), Some((&0x0808_0000, &0x00)));
+    session.write_image(&placed, &logger).unwrap();
+    let flash = sim.flash();
+    assert_eq!(flash.get(&0x0808_0101), Some(&0x01));
+    assert!(!flash.contains_key(&FLASH_BASE));
+
+    // 已在目标 Bank 内的镜像不再平移
+    assert_eq!(*session.place_in_bank(&placed).unwrap(), placed);
+
+    // 512 KB 的 L47x：两个 Bank 按实际容量各占一半，Bank 2 从 0x08040000 开始
+    let sim = SimulatedBootloader::new(SimConfig {
+        product_id: 0x415,
+        flash_size: 512 * 1024,
+        ..SimConfig::default()
+    });
+    let session = BootloaderSession::from_port(Box::new(sim.clone()), &opts).unwrap();
+    let placed = session.place_in_bank(&image).unwrap();
+    assert_eq!(placed.first_key_value(), Some((&0x0804_0000, &0x00)));
+}
+
+#[test]
+fn swap_banks_on_unsupported_chip_fails_before_erase() {
+    // F1 XL 的选项字节没有 BFB2，H7 的选项字节布局未收录
+    for product_id in [0x430, 0x450] {
+        let sim = SimulatedBootloader::new(SimConfig {
+            product_id,
+            flash_size: 1024 * 1024,
+            ..SimConfig::default()
+        });
+        let opts = FlashOptions {
+            swap_banks: true,
+            ..options()
+        };
+        let result = BootloaderSession::from_port(Box::new(sim.clone()), &opts);
+        assert!(
+            matches!(result, Err(Error::Bank(_) | Error::UnknownOptionBytes(_))),
+            "0x{product_id:03X}"
+        );
+        // 0x43 Erase / 0x44 Extended Erase
+        let received = sim.commands_received();
+        assert!(!received.iter().any(|&cmd| cmd == 0x43 || cmd == 0x44));
+    }
+}
+
+#[test]
+fn release_starts_app_without_finishing_flash_steps() {
+    let sim = SimulatedBootloader::new(SimConfig::default());
+    let logger = NullLogger;
+    let opts = FlashOptions {
+        reset_after: true,
+        lock_after: true,
+        option_bytes: vec![("nrst_stop".to_string(), 0)],
+        ..options()
+    };
+    let mut session = BootloaderSession::from_port(Box::new(sim.clone()), &opts).unwrap();
+    let image: BTreeMap<u32, u8> = (FLASH_BASE..FLASH_BASE + 16).map(|a| (a, 0xFF)).collect();
+    session.compare_image(&image, &logger).unwrap();
+    session.release().unwrap();
+
+    // 只发送了 GO，没有写选项字节（0x31）或设置读保护（0x82）
+    let received = sim.commands_received();
+    assert_eq!(received.last(), Some(&0x21));
+    assert!(!received.iter().any(|&cmd| cmd == 0x31 || cmd == 0x82));
+    assert!(!sim.read_protected());
+}
+
+#[test]
+fn repeated_nacks_shrink_write_chunk() {
+    let sim = SimulatedBootloader::new(SimConfig::default());
+    let logger = NullLogger;
+    let image: BTreeMap<u32, u8> = (FLASH_BASE..FLASH_BASE + 512)
+        .map(|a| (a, (a >> 3) as u8))
+        .collect();
+    let mut session = BootloaderSession::from_port(Box::new(sim.clone()), &options()).unwrap();
+    // 第一块的数据 ACK 连续两次变成 NACK，之后按 128 字节写完
+    let nack_data = [None, None, Some(Fault::Garbage(0x1F))];
+    sim.inject_faults(nack_data.iter().chain(&nack_data).cloned());
+
+    assert_eq!(session.write_image(&image, &logger).unwrap(), 512);
+    assert_eq!(sim.flash(), image);
+
+    // 已是最小块大小时不再重试，直接报告 NACK
+    let small = FlashOptions {
+        write_chunk: 32,
+        ..options()
+    };
+    let mut session = BootloaderSession::from_port(Box::new(sim.clone()), &small).unwrap();
+    sim.inject_faults(nack_data.iter().chain(&nack_data).cloned());
+    assert!(matches!(
+        session.write_image(&image, &logger),
+        Err(Error::Nack)
+    ));
+}
+
+#[test]
+fn shrunk_write_chunk_stays_word_aligned() {
+    let sim = SimulatedBootloader::new(SimConfig::default());
+    let image: BTreeMap<u32, u8> = (FLASH_BASE..FLASH_BASE + 512)
+        .map(|a| (a, a as u8))
+        .collect();
+    let opts = FlashOptions {
+        write_chunk: 100,
+        ..options()
+    };
+    let mut session = BootloaderSession::from_port(Box::new(sim.clone()), &opts).unwrap();
+    // 100 字节的首帧连续被 NACK，减半后的块大小仍按 4 字节对齐
+    let nack_data = [None, None, Some(Fault::Garbage(0x1F))];
+    sim.inject_faults(nack_data.iter().chain(&nack_data).cloned());
+
+    assert_eq!(session.write_image(&image, &NullLogger).unwrap(), 512);
+    assert_eq!(sim.flash(), image);
+    let writes = sim.writes();
+    assert_eq!(writes[0], (FLASH_BASE, 100));
+    for &(addr, len) in &writes[2..] {
+        assert_eq!(addr % 4, 0, "0x{addr:08X}");
+        assert_eq!(len % 4, 0, "{len} bytes at 0x{addr:08X}");
+    }
+}
+
+#[test]
+fn command_retry_resends_nacked_erase_and_write() {
+    let sim = SimulatedBootloader::new(SimConfig::default());
+    let logger = NullLogger;
+    let image: BTreeMap<u32, u8> = (FLASH_BASE..FLASH_BASE + 64)
+        .map(|a| (a, a as u8))
+        .collect();
+    let patient = FlashOptions {
+        write_chunk: 32,
+        command_retry: RetryPolicy::new(4, Duration::from_millis(1)),
+        ..options()
+    };
+    let nack_data = [None, None, Some(Fault::Garbage(0x1F))];
+
+    // 擦除数据帧被 NACK 一次后重发成功
+    let mut session = BootloaderSession::from_port(Box::new(sim.clone()), &patient).unwrap();
+    sim.inject_faults([None, Some(Fault::Garbage(0x1F))]);
+    session.erase_all(&logger).unwrap();
+
+    // 最小块连续三次 NACK，仍在重试次数内
+    sim.inject_faults(nack_data.iter().cycle().take(9).cloned());
+    assert_eq!(session.write_image(&image, &logger).unwrap(), 64);
+    assert_eq!(sim.flash(), image);
+
+    // 只尝试一次时擦除的 NACK 直接上报
+    let once = FlashOptions {
+        command_retry: RetryPolicy::new(1, Duration::ZERO),
+        ..patient
+    };
+    let mut session = BootloaderSession::from_port(Box::new(sim.clone()), &once).unwrap();
+    sim.inject_faults([None, Some(Fault::Garbage(0x1F))]);
+    assert!(matches!(session.erase_all(&logger), Err(Error::Nack)));
+}
+
+proptest! {
+    #[test]
+    fn padding_keeps_data_and_fills_whole_units(
+        image in sparse_image(),
+        shift in 0u32..5,
+        tail_word in any::<bool>(),
+    ) {
+        let granularity = 4 << shift;
+        let mut padded = image.clone();
+        let added = stm32_uart::pad_image(&mut padded, &Padding {
+            granularity,
+            boundaries: BoundaryPolicy::Pad,
+            tail_word,
+        });
+
+        prop_assert_eq!(padded.len() as u64, image.len() as u64 + added);
+        for (addr, b) in &padded {
+            prop_assert_eq!(*b, image.get(addr).copied().unwrap_or(0xFF));
+            let unit = addr - addr % granularity;
+            prop_assert!((unit..unit + granularity).all(|a| padded.contains_key(&a)));
+        }
+    }
+}
+
+#[test]
+fn page_erase_keeps_data_outside_image() {
+    for extended in [false, true] {
+        let mut config = SimConfig::default();
+        if extended {
+            // Erase (0x43) 换成 Extended Erase (0x44)
+            for cmd in config.commands.iter_mut().filter(|c| **c == 0x43) {
+                *cmd = 0x44;
+            }
+        }
+        let sim = SimulatedBootloader::new(config);
+        let logger = NullLogger;
+        let opts = FlashOptions {
+            erase_mode: EraseMode::Pages,
+            ..options()
+        };
+        let mut session = BootloaderSession::from_port(Box::new(sim.clone()), &opts).unwrap();
+
+        // 第 60 页模拟 EEPROM 数据，第 2 页末尾有旧数据
+        let eeprom: BTreeMap<u32, u8> = (FLASH_BASE + 60 * 1024..FLASH_BASE + 60 * 1024 + 16)
+            .map(|a| (a, 0x42))
+            .collect();
+        let stale = BTreeMap::from([(FLASH_BASE + 0xBF0, 0x11)]);
+        session.erase_all(&logger).unwrap();
+        session.write_image(&eeprom, &logger).unwrap();
+        session.write_image(&stale, &logger).unwrap();
+
+        // 镜像跨第 0–2 页
+        let image: BTreeMap<u32, u8> = (FLASH_BASE + 0x300..FLASH_BASE + 0x900)
+            .map(|a| (a, a as u8))
+            .collect();
+        session.erase_before_write(&image, &logger).unwrap();
+        session.write_image(&image, &logger).unwrap();
+
+        let mut expected = eeprom;
+        expected.extend(image);
+        assert_eq!(sim.flash(), expected, "extended erase: {extended}");
+    }
+
+    // 旧版 Erase 的页号只有一个字节，1 MB 的 F1 XL 上第 256 页之后无法按页擦除
+    let sim = SimulatedBootloader::new(SimConfig {
+        product_id: 0x430,
+        flash_size: 1024 * 1024,
+        ..SimConfig::default()
+    });
+    let opts = FlashOptions {
+        erase_mode: EraseMode::Pages,
+        ..options()
+    };
+    let mut session = BootloaderSession::from_port(Box::new(sim.clone()), &opts).unwrap();
+    let image = BTreeMap::from([(FLASH_BASE + 300 * 2048, 0x55)]);
+    let result = session.erase_before_write(&image, &NullLogger);
+    assert!(
+        matches!(result, Err(Error::LegacyErasePage(300))),
+        "{result:?}"
+    );
+    assert!(!sim.commands_received().contains(&0x43));
+}
+
+#[test]
+fn demo_port_is_backed_by_simulator() {
+    let logger = NullLogger;
+    let demo = DemoPort::attach("DEMO-props", SimulatedBootloader::demo());
+    let opts = options();
+
+    let result = stm32_uart::identify("DEMO-props", &opts);
+    assert!(result.ok, "{:?}", result.error);
+    assert_eq!(result.product_id, Some(0x0410));
+
+    // 设备状态在多次打开之间保留
+    let image: BTreeMap<u32, u8> = (FLASH_BASE..FLASH_BASE + 300)
+        .map(|a| (a, a as u8))
+        .collect();
+    let mut session = BootloaderSession::open("DEMO-props", &opts).unwrap();
+    session.erase_all(&logger).unwrap();
+    session.write_image(&image, &logger).unwrap();
+    drop(session);
+    let mut session = BootloaderSession::open("DEMO-props", &opts).unwrap();
+    session.verify_image(&image, &logger).unwrap();
+    drop(session);
+    assert_eq!(demo.sim().flash(), image);
+
+    // 注销后该端口名不再可用
+    drop(demo);
+    assert!(!stm32_uart::identify("DEMO-props", &opts).ok);
+}
+
+#[test]
+fn usb_cdc_port_is_found_by_serial_after_reset() {
+    let demo = DemoPort::attach(
+        "USBSIM",
+        SimulatedBootloader::new(SimConfig {
+            usb_serial: Some("SIM0001".to_string()),
+            ..SimConfig::default()
+        }),
+    );
+    let opts = options();
+
+    // 设置读保护后芯片复位，原端口消失，同一序列号以新端口名重新出现
+    stm32_uart::lock("USBSIM", &opts).unwrap();
+    assert!(demo.sim().read_protected());
+    let port = demo.sim().port_info().unwrap();
+    assert_ne!(port.port_name, "USBSIM");
+    assert_eq!(port.serial.as_deref(), Some("SIM0001"));
+    assert!(!stm32_uart::identify("USBSIM", &opts).ok);
+    assert!(stm32_uart::identify(&port.port_name, &opts).ok);
+}
+
+#[test]
+fn progress_events_become_json_events() {
+    assert_eq!(
+        FlashEvent::from_progress(&ProgressEvent::WriteChunk {
+            addr: FLASH_BASE + 0x1000,
+            done: 4096,
+            total: 65536,
+        }),
+        FlashEvent::Progress {
+            phase: "write".into(),
+            done: 4096,
+            total: 65536,
+        }
+    );
+
+    let event = FlashEvent::from_progress(&ProgressEvent::Done {
+        phase: ProgressPhase::Erase,
+        total: 1000,
+    });
+    let json = serde_json::to_value(&event).unwrap();
+    assert_eq!(json["type"], "progress");
+    assert_eq!(json["phase"], "erase");
+    assert_eq!(json["done"], 1000);
+
+    let failed = FlashEvent::result("flash", Err(&Error::HexFileEmpty));
+    let json = serde_json::to_value(&failed).unwrap();
+    assert_eq!(json["type"], "result");
+    assert_eq!(json["ok"], false);
+    assert!(json["error"].is_string());
+}
+
+#[test]
+fn project_config_roundtrips_through_toml() {
+    let config = ProjectConfig {
+        port: Some("COM9".into()),
+        baud: Some(57600),
+        boot_mode: Some(stm32_uart::BootMode::RtsLowDtrHigh),
+        ..ProjectConfig::default()
+    };
+    let path = std::env::temp_dir().join(format!("probe-flasher-{}.toml", std::process::id()));
+    config.save(&path).unwrap();
+    let text = std::fs::read_to_string(&path).unwrap();
+    let loaded = ProjectConfig::load(&path);
+    std::fs::remove_file(&path).unwrap();
+
+    assert!(text.contains("boot_mode = \"rts-low-dtr-high\""), "{text}");
+    assert_eq!(loaded.unwrap(), config);
+
+    // 手写的配置可以用十六进制写跳转地址
+    let parsed: ProjectConfig = toml::from_str("go_address = 0x08004000").unwrap();
+    assert_eq!(parsed.go_address, Some(0x0800_4000));
+}
+
+/// 最小的 32 位小端 ELF：程序头表紧跟文件头，段数据在其后
+fn build_elf(segments: &[(u32, u32, u32, &[u8])]) -> Vec<u8> {
+    let phoff = 52u32;
+    let mut data_off = phoff + 32 * segments.len() as u32;
+    let mut elf = vec![0u8; 52];
+    elf[..7].copy_from_slice(&[0x7F, b'E', b'L', b'F', 1, 1, 1]);
+    elf[0x1C..0x20].copy_from_slice(&phoff.to_le_bytes());
+    elf[0x2A..0x2C].copy_from_slice(&32u16.to_le_bytes());
+    elf[0x2C..0x2E].copy_from_slice(&(segments.len() as u16).to_le_bytes());
+    let mut payload = Vec::new();
+    for &(p_type, vaddr, paddr, bytes) in segments {
+        for word in [
+            p_type,
+            data_off,
+            vaddr,
+            paddr,
+            bytes.len() as u32,
+            bytes.len() as u32 + 16,
+            0,
+            4,
+        ] {
+            elf.extend(word.to_le_bytes());
+        }
+        payload.extend_from_slice(bytes);
+        data_off += bytes.len() as u32;
+    }
+    elf.extend(payload);
+    elf
+}
+
+#[test]
+fn elf_loadable_segments_are_placed_at_lma() {
+    let elf = build_elf(&[
+        (1, FLASH_BASE, FLASH_BASE, &[1, 2, 3, 4]),
+        // .data：运行地址在 SRAM，初值按 LMA 紧跟在代码后面
+        (1, 0x2000_0000, FLASH_BASE + 4, &[0xAA, 0xBB]),
+        // PT_NOTE 等非加载段被忽略
+        (4, 0, 0x0800_1000, &[9, 9]),
+    ]);
+    let path = std::env::temp_dir().join(format!("probe-flasher-{}.elf", std::process::id()));
+    std::fs::write(&path, elf).unwrap();
+    let image = stm32_uart::FirmwareSource::from_path(&path, None).load();
+    std::fs::remove_file(&path).unwrap();
+
+    let image = image.unwrap();
+    assert_eq!(image.len(), 6);
+    assert_eq!(image[&(FLASH_BASE + 4)], 0xAA);
+    assert!(!image.contains_key(&0x0800_1000));
+}
+
+#[test]
+fn srec_records_are_checked_and_placed() {
+    let path = std::env::temp_dir().join(format!("probe-flasher-{}.s19", std::process::id()));
+    let lines = [
+        "S00600004844521B",
+        "S3090800000001020304E4",
+        "S1040010AA41",
+        "S70508000000F2",
+    ];
+    std::fs::write(&path, lines.join("\n")).unwrap();
+    let image = stm32_uart::FirmwareSource::from_path(&path, None).load();
+
+    std::fs::write(&path, "S3090800000001020304E5\n").unwrap();
+    let corrupt = stm32_uart::parse_srec_to_image(&path);
+    // 第二个字符是多字节字符时报错而不是越过字符边界切片
+    std::fs::write(&path, "Sé…\n").unwrap();
+    let non_ascii = stm32_uart::parse_srec_to_image(&path);
+    // 只有头记录时报空镜像，而不是 HEX 专用的错误
+    std::fs::write(&path, "S00600004844521B\n").unwrap();
+    let empty = stm32_uart::parse_srec_to_image(&path);
+    std::fs::remove_file(&path).unwrap();
+
+    let image = image.unwrap();
+    assert_eq!(image.len(), 5);
+    assert_eq!(image[&(FLASH_BASE + 3)], 4);
+    assert_eq!(image[&0x0010], 0xAA);
+    assert!(matches!(corrupt, Err(Error::Srec(_))));
+    assert!(matches!(non_ascii, Err(Error::Srec(_))));
+    let empty = empty.unwrap_err();
+    assert!(matches!(empty, Error::ImageEmpty));
+    assert_eq!(empty.code(), ErrorCode::InvalidImage);
+}
+
+#[test]
+fn images_past_the_address_space_are_rejected() {
+    let path = std::env::temp_dir().join(format!("probe-flasher-{}-top.bin", std::process::id()));
+    std::fs::write(&path, [0u8; 32]).unwrap();
+    let top = stm32_uart::parse_bin_to_image(&path, 0xFFFF_FFF0);
+    let last = stm32_uart::parse_bin_to_image(&path, 0xFFFF_FFE0);
+    std::fs::remove_file(&path).unwrap();
+    assert!(matches!(top, Err(Error::AddressOverflow { len: 32, .. })));
+    assert_eq!(last.unwrap().last_key_value(), Some((&u32::MAX, &0)));
+
+    // 指定基地址后镜像尾部越过 4 GB 时报错，不回绕到 0
+    let path = std::env::temp_dir().join(format!("probe-flasher-{}-top.hex", std::process::id()));
+    std::fs::write(
+        &path,
+        ":020000040800F2\n:1000000000000000000000000000000000000000F0\n:00000001FF\n",
+    )
+    .unwrap();
+    let source = ImageSource::with_format(&path, ImageFormat::IntelHex, Some(0xFFFF_FFF8));
+    let rebased = source.load();
+    std::fs::remove_file(&path).unwrap();
+    assert!(matches!(rebased, Err(Error::ImageOffsetOutOfRange { .. })));
+
+    // 线性地址记录越过 4 GB 时报错，不回绕到 0
+    let lines = [
+        ":02000004FFFFFC",
+        ":10FFF80000000000000000000000000000000000F9",
+        ":00000001FF",
+    ];
+    std::fs::write(&path, lines.join("\n")).unwrap();
+    let linear = stm32_uart::parse_hex_to_image(&path);
+    std::fs::remove_file(&path).unwrap();
+    assert!(matches!(
+        linear,
+        Err(Error::AddressOverflow {
+            base: 0xFFFF_FFF8,
+            len: 16
+        })
+    ));
+
+    // S3 记录的数据越过 4 GB 时报错，不回绕到 0
+    let path = std::env::temp_dir().join(format!("probe-flasher-{}-top.s3", std::process::id()));
+    std::fs::write(&path, "S309FFFFFFFE01020304F1\n").unwrap();
+    let srec = stm32_uart::parse_srec_to_image(&path);
+    std::fs::remove_file(&path).unwrap();
+    assert!(matches!(
+        srec,
+        Err(Error::AddressOverflow {
+            base: 0xFFFF_FFFE,
+            len: 4
+        })
+    ));
+
+    // 地址空间末尾的入口不越界
+    let image: BTreeMap<u32, u8> = (0xFFFF_FFF8..=u32::MAX).map(|a| (a, 0)).collect();
+    assert_eq!(stm32_uart::go_target_for_entry(&image, 0xFFFF_FFFC), None);
+
+    let sim = SimulatedBootloader::new(SimConfig::default());
+    let mut session = BootloaderSession::from_port(Box::new(sim), &options()).unwrap();
+    assert!(matches!(
+        session.write(u32::MAX, &[0, 1], &NullLogger),
+        Err(Error::AddressOverflow { .. })
+    ));
+}
+
+#[test]
+fn extended_segment_address_records_are_applied() {
+    let path =
+        std::env::temp_dir().join(format!("probe-flasher-{}-segment.hex", std::process::id()));
+    let lines = [
+        // 段 0x8000 → 0x00080000，之后切换回线性地址 0x0800xxxx
+        ":0200000280007C",
+        ":02FFFF00AABB9B",
+        ":020000040800F2",
+        ":01001000559A",
+        ":00000001FF",
+    ];
+    std::fs::write(&path, lines.join("\n")).unwrap();
+    let image = stm32_uart::parse_hex_to_image(&path);
+    std::fs::remove_file(&path).unwrap();
+
+    let image = image.unwrap();
+    // 段地址下记录内的偏移在 64 KB 内回绕
+    assert_eq!(image[&0x0008_FFFF], 0xAA);
+    assert_eq!(image[&0x0008_0000], 0xBB);
+    assert_eq!(image[&(FLASH_BASE + 0x10)], 0x55);
+    assert_eq!(image.len(), 3);
+}
+
+#[test]
+fn errors_carry_stable_codes() {
+    let code = |e: &Error| serde_json::to_value(e.code()).unwrap();
+    assert_eq!(code(&Error::Nack), "nack");
+    assert_eq!(code(&Error::PortBusy("COM3".into())), "port_busy");
+    let no_sync = Error::NoSync {
+        attempts: 3,
+        last: Box::new(Error::Timeout),
+    };
+    assert_eq!(code(&no_sync), "no_sync");
+    let missing = stm32_uart::parse_hex_to_image(std::path::Path::new("/nonexistent/app.hex"));
+    assert_eq!(code(&missing.unwrap_err()), "file_not_found");
+
+    let result = stm32_uart::identify("/dev/probe-flasher-missing", &options());
+    assert!(!result.ok);
+    assert_eq!(result.error_code, Some(ErrorCode::PortNotFound));
+
+    let event = serde_json::to_value(FlashEvent::result("flash", Err(&no_sync))).unwrap();
+    assert_eq!(event["error_code"], "no_sync");
+}
+
+#[test]
+fn protocol_version_selects_default_timeouts() {
+    let v3 = BootloaderProtocol::from_version(0x31);
+    assert_eq!(v3.variant, ProtocolVariant::V3);
+    assert_eq!(v3.label(), "v3.1");
+    assert_eq!(
+        BootloaderProtocol::from_version(0x10).variant,
+        ProtocolVariant::V1
+    );
+
+    let sim = SimulatedBootloader::new(SimConfig {
+        bootloader_version: 0x31,
+        ..SimConfig::default()
+    });
+    // 内置默认值按协议和芯片替换，显式设置的超时保持不变
+    let chip = probe_flasher::chipdb::lookup(SimConfig::default().product_id).unwrap();
+    let session =
+        BootloaderSession::from_port(Box::new(sim.clone()), &FlashOptions::default()).unwrap();
+    assert_eq!(session.options().timeouts, v3.timing(Some(chip)));
+    assert_eq!(session.options().timeouts.erase(), chip.erase_timeout());
+    let session = BootloaderSession::from_port(Box::new(sim), &options()).unwrap();
+    assert_eq!(session.protocol(), v3);
+    assert_eq!(session.options().timeouts, options().timeouts);
+
+    // 只改过擦除超时时，其余项仍按协议取默认值
+    let v1 = BootloaderProtocol::from_version(0x10);
+    let requested = Timeouts {
+        erase: Some(Duration::from_secs(90)),
+        ..Timeouts::default()
+    };
+    let resolved = requested.or_defaults(v1.timing(Some(chip)));
+    assert_eq!(resolved.erase(), Duration::from_secs(90));
+    assert_eq!(resolved.ack(), Duration::from_millis(1500));
+    assert_eq!(resolved.sync(), Duration::from_millis(1500));
+
+    // 显式设置的值即使等于内置默认值，也不被协议默认值替换
+    let sim = SimulatedBootloader::new(SimConfig {
+        bootloader_version: 0x10,
+        ..SimConfig::default()
+    });
+    let opts = FlashOptions {
+        timeouts: Timeouts {
+            ack: Some(Duration::from_millis(800)),
+            erase: Some(Duration::from_secs(25)),
+            ..Timeouts::default()
+        },
+        ..FlashOptions::default()
+    };
+    let session = BootloaderSession::from_port(Box::new(sim), &opts).unwrap();
+    assert_eq!(session.protocol(), v1);
+    let timeouts = session.options().timeouts;
+    assert_eq!(timeouts.ack(), Duration::from_millis(800));
+    assert_eq!(timeouts.erase(), Duration::from_secs(25));
+    assert_eq!(timeouts.sync(), Duration::from_millis(1500));
+}
+
+#[test]
+fn blank_chunks_are_skipped_after_erase() {
+    let mut image: BTreeMap<u32, u8> = (FLASH_BASE..FLASH_BASE + 1024).map(|a| (a, 0xFF)).collect();
+    image.insert(FLASH_BASE + 300, 0x42);
+    let logger = NullLogger;
+
+    let path = std::env::temp_dir().join(format!("probe-flasher-{}-blank.bin", std::process::id()));
+    std::fs::write(&path, image.values().copied().collect::<Vec<_>>()).unwrap();
+    let sim = SimulatedBootloader::new(SimConfig::default());
+    let mut session = BootloaderSession::from_port(Box::new(sim.clone()), &options()).unwrap();
+    let source = ImageSource::with_format(&path, ImageFormat::Binary, Some(FLASH_BASE));
+    let written = stm32_uart::flash_images(&mut session, &[source], &logger);
+    std::fs::remove_file(&path).unwrap();
+    // 只有包含 0x42 的 256 字节帧被发送，校验仍覆盖整个镜像
+    assert_eq!(written.unwrap(), 256);
+    assert_eq!(sim.flash().len(), 256);
+
+    // 单独的 write_image 不知道目标是否已擦除，即使刚擦除过也写入每一帧
+    let sim = SimulatedBootloader::new(SimConfig::default());
+    let mut session = BootloaderSession::from_port(Box::new(sim), &options()).unwrap();
+    session.erase_all(&logger).unwrap();
+    assert_eq!(session.write_image(&image, &logger).unwrap(), 1024);
+}
+
+/// 按 DfuSe 规范拼出容器：每个 target 一组 (地址, 数据) element
+fn build_dfu(targets: &[&[(u32, &[u8])]]) -> Vec<u8> {
+    let mut body = Vec::new();
+    for (alt, elements) in targets.iter().enumerate() {
+        let size: usize = elements.iter().map(|(_, d)| 8 + d.len()).sum();
+        body.extend_from_slice(b"Target");
+        body.push(alt as u8);
+        body.extend_from_slice(&0u32.to_le_bytes());
+        body.extend_from_slice(&[0; 255]);
+        body.extend_from_slice(&(size as u32).to_le_bytes());
+        body.extend_from_slice(&(elements.len() as u32).to_le_bytes());
+        for (address, data) in elements.iter() {
+            body.extend_from_slice(&address.to_le_bytes());
+            body.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            body.extend_from_slice(data);
+        }
+    }
+
+    let mut file = b"DfuSe\x01".to_vec();
+    file.extend_from_slice(&((11 + body.len()) as u32).to_le_bytes());
+    file.push(targets.len() as u8);
+    file.extend_from_slice(&body);
+    file.extend_from_slice(&[0xFF, 0xFF, 0x11, 0xDF, 0x83, 0x04, 0x1A, 0x01]);
+    file.extend_from_slice(b"UFD");
+    file.push(16);
+    file.extend_from_slice(&(!crc32fast::hash(&file)).to_le_bytes());
+    file
+}
+
+#[test]
+fn dfuse_suffix_crc_matches_known_file() {
+    // 单个 element（0x08000000 处 1 字节 0xAA）的 .dfu 文件后缀，CRC 为 zlib CRC-32 取反
+    const SUFFIX: [u8; 16] = [
+        0xFF, 0xFF, 0x11, 0xDF, 0x83, 0x04, 0x1A, 0x01, b'U', b'F', b'D', 16, 0xBE, 0x4D, 0x6D,
+        0xF6,
+    ];
+    let dfu = build_dfu(&[&[(FLASH_BASE, &[0xAA])]]);
+    assert_eq!(dfu[dfu.len() - 16..], SUFFIX);
+
+    let path = std::env::temp_dir().join(format!("probe-flasher-crc-{}.dfu", std::process::id()));
+    std::fs::write(&path, &dfu).unwrap();
+    let image = stm32_uart::parse_dfu_to_image(&path);
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(image.unwrap(), BTreeMap::from([(FLASH_BASE, 0xAA)]));
+}
+
+#[test]
+fn dfuse_elements_are_placed_at_their_addresses() {
+    let dfu = build_dfu(&[&[(FLASH_BASE, &[1, 2, 3, 4]), (FLASH_BASE + 0x800, &[5, 6])]]);
+    let path = std::env::temp_dir().join(format!("probe-flasher-{}.dfu", std::process::id()));
+    std::fs::write(&path, &dfu).unwrap();
+    let image = stm32_uart::FirmwareSource::from_path(&path, None).load();
+
+    let mut corrupt = dfu.clone();
+    corrupt[300] ^= 0xFF;
+    std::fs::write(&path, corrupt).unwrap();
+    let corrupt = stm32_uart::parse_dfu_to_image(&path);
+    std::fs::remove_file(&path).unwrap();
+
+    let image = image.unwrap();
+    assert_eq!(image.len(), 6);
+    assert_eq!(image[&(FLASH_BASE + 0x801)], 6);
+    assert!(matches!(corrupt, Err(Error::Dfu(_))));
+}
+
+#[test]
+fn write_plan_counts_blank_pages_and_erase_scope() {
+    // 第一页有数据，第二页全为 0xFF，第三页只写了一半 0xFF
+    let mut image: BTreeMap<u32, u8> = (0..1024)
+        .map(|i| (FLASH_BASE + i, (i % 255) as u8))
+        .collect();
+    image.extend((1024..2048).map(|i| (FLASH_BASE + i, 0xFF)));
+    image.extend((2048..2560).map(|i| (FLASH_BASE + i, 0xFF)));
+    let chip = probe_flasher::chipdb::lookup(0x410);
+
+    let plan = stm32_uart::WritePlan::analyze(
+        &image,
+        chip,
+        &FlashOptions {
+            erase_mode: EraseMode::Pages,
+            ..FlashOptions::default()
+        },
+        115200,
+    );
+    assert_eq!(plan.blank_bytes, 1536);
+    assert_eq!((plan.pages, plan.blank_pages), (Some(3), Some(2)));
+    assert_eq!(plan.erase_pages, Some(3));
+    assert!(plan.estimated_ms > 0);
+
+    let mass = stm32_uart::WritePlan::analyze(&image, chip, &FlashOptions::default(), 115200);
+    assert!(mass.mass_erase);
+    assert_eq!(mass.erase_pages, Some(128));
+
+    // 烧录时分析结果直接交给 ProgressSink，不经过日志
+    struct Plans(std::sync::Mutex<Vec<stm32_uart::WritePlan>>);
+    impl ProgressSink for Plans {
+        fn plan(&self, plan: &stm32_uart::WritePlan) {
+            self.0.lock().unwrap().push(plan.clone());
+        }
+    }
+    let path = std::env::temp_dir().join(format!("probe-flasher-{}-plan.bin", std::process::id()));
+    std::fs::write(&path, image.values().copied().collect::<Vec<_>>()).unwrap();
+    let sim = SimulatedBootloader::new(SimConfig::default());
+    let mut session = BootloaderSession::from_port(Box::new(sim), &options()).unwrap();
+    let plans = Plans(Default::default());
+    let source = ImageSource::with_format(&path, ImageFormat::Binary, Some(FLASH_BASE));
+    let written = stm32_uart::flash_images(&mut session, &[source], &plans);
+    std::fs::remove_file(&path).unwrap();
+    written.unwrap();
+    let plans = plans.0.into_inner().unwrap();
+    assert_eq!(plans.len(), 1);
+    assert_eq!(plans[0].bytes, image.len());
+}
+
+#[test]
+fn vector_table_check_flags_foreign_firmware() {
+    let image_with = |sp: u32, reset: u32| -> BTreeMap<u32, u8> {
+        let mut image: BTreeMap<u32, u8> = (0..0x200).map(|i| (FLASH_BASE + i, 0)).collect();
+        for (i, b) in sp
+            .to_le_bytes()
+            .into_iter()
+            .chain(reset.to_le_bytes())
+            .enumerate()
+        {
+            image.insert(FLASH_BASE + i as u32, b);
+        }
+        image
+    };
+    let f103 = probe_flasher::chipdb::chip_memory_map(0x410);
+
+    let good = image_with(0x2000_5000, FLASH_BASE + 0x101);
+    assert!(stm32_uart::check_vector_table(&good, f103.as_ref()).is_empty());
+
+    // 为 64 KB SRAM 的型号链接：通用范围内合法，但超出 F103C8 的 20 KB
+    let big_ram = image_with(0x2001_0000, FLASH_BASE + 0x101);
+    assert!(stm32_uart::check_vector_table(&big_ram, None).is_empty());
+    assert_eq!(
+        stm32_uart::check_vector_table(&big_ram, f103.as_ref()),
+        vec![stm32_uart::VectorTableIssue::StackOutsideRam(0x2001_0000)]
+    );
+
+    // 为 0x08004000 链接却放在 0x08000000
+    let relinked = image_with(0x2000_5000, 0x0800_4101);
+    assert_eq!(
+        stm32_uart::check_vector_table(&relinked, f103.as_ref()),
+        vec![stm32_uart::VectorTableIssue::ResetOutsideImage(0x0800_4101)]
+    );
+}
+
+#[test]
+fn chip_database_describes_product_lines() {
+    use probe_flasher::chipdb::{self, ChipQuirk};
+
+    let f103 = chipdb::lookup(0x410).unwrap();
+    assert_eq!(f103.describe(), "STM32F10xxx medium-density, 128 KB flash");
+    assert!(f103.quirks().is_empty());
+
+    let h7 = chipdb::lookup(0x450).unwrap();
+    assert_eq!(h7.flash_size(), 2 * 1024 * 1024);
+    assert_eq!(
+        h7.quirks(),
+        vec![
+            ChipQuirk::WriteUnit(32),
+            ChipQuirk::SlowMassErase,
+            ChipQuirk::DualBank
+        ]
+    );
+    // 第二个 Bank 的扇区号接着第一个 Bank 编号
+    let bank2 = h7.erase_units_covering(FLASH_BASE + 0x10_0000, 1);
+    assert_eq!(bank2[0].index, 8);
+}
+
+#[test]
+fn archive_links_entries_and_detects_tampering() {
+    use probe_flasher::archive::{ArchiveEntry, ImageArchive};
+
+    let dir = std::env::temp_dir().join(format!("probe-flasher-{}-archive", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let archive = ImageArchive::new(&dir);
+
+    let image: BTreeMap<u32, u8> = (FLASH_BASE..).zip([1u8, 2, 3, 4]).collect();
+    let stored = archive.store_image(&image).unwrap();
+    // 相同镜像只存一份
+    assert_eq!(archive.store_image(&image).unwrap(), stored);
+
+    let entry = |ok| ArchiveEntry {
+        finished_at: "2026-01-01T00:00:00+00:00".into(),
+        station: "line1".into(),
+        job: "app".into(),
+        ok,
+        bytes_written: 4,
+        error: None,
+        image_sha256: stored.sha256.clone(),
+        image_crc32: stored.crc32,
+        image_file: stored.file.clone(),
+        previous: None,
+    };
+    let first = archive.append(entry(true)).unwrap();
+    assert_eq!(first.previous, None);
+    let second = archive.append(entry(false)).unwrap();
+    assert!(second.previous.is_some());
+    assert!(archive.verify().unwrap().is_empty());
+    assert_eq!(
+        archive.load_image(&second).unwrap(),
+        stm32_uart::image_to_hex(&image).unwrap()
+    );
+
+    // 改动第一条记录后第二条的链接失效
+    let reports = dir.join("reports.jsonl");
+    let text = std::fs::read_to_string(&reports).unwrap();
+    std::fs::write(&reports, text.replacen("\"ok\":true", "\"ok\":false", 1)).unwrap();
+    let problems = archive.verify().unwrap();
+    assert_eq!(problems.len(), 1);
+    assert!(problems[0].starts_with("entry 2"));
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn wiring_observations_identify_every_boot_mode() {
+    use clap::ValueEnum;
+    use probe_flasher::stm32_uart::{BootLineConfig, BootMode, Level};
+    use probe_flasher::wiring::{self, Observation, WiringVerdict};
+
+    for &boot_mode in BootMode::value_variants() {
+        if boot_mode == BootMode::None {
+            continue;
+        }
+        let wired = WiringVerdict {
+            boot_mode,
+            lines: BootLineConfig::for_mode(boot_mode),
+        };
+        let seen: Vec<_> = wiring::STEPS.iter().map(|&s| wired.predict(s)).collect();
+        let verdict = wiring::infer(&seen).unwrap();
+        assert_eq!(verdict.overrides(), 0, "{boot_mode:?}");
+        assert_eq!(
+            wiring::STEPS.map(|s| verdict.predict(s)).to_vec(),
+            seen,
+            "{boot_mode:?}"
+        );
+    }
+
+    // DTR 低电平复位、RTS 低电平拉高 BOOT0：没有同名模式，需要覆盖一个电平
+    let seen = [
+        Observation {
+            in_reset: true,
+            boot0_high: true,
+        },
+        Observation {
+            in_reset: false,
+            boot0_high: true,
+        },
+        Observation {
+            in_reset: true,
+            boot0_high: false,
+        },
+    ];
+    let verdict = wiring::infer(&seen).unwrap();
+    assert_eq!(verdict.overrides(), 1);
+    assert_eq!(verdict.lines.reset_assert_level, Level::Low);
+    assert_eq!(verdict.lines.boot_level, Level::Low);
+
+    // 从未复位说明复位线没有接上
+    assert_eq!(wiring::infer(&[Observation::default(); 3]), None);
+}
+
+#[test]
+fn session_reads_flash_size_register() {
+    let sim = SimulatedBootloader::new(SimConfig {
+        flash_size: 32 * 1024,
+        ..SimConfig::default()
+    });
+    let session = BootloaderSession::from_port(Box::new(sim), &options()).unwrap();
+    // 0x410 在数据库中登记为 128 KB，寄存器给出实际的 32 KB
+    assert_eq!(session.flash_size(), Some(32 * 1024));
+
+    // 超出实际容量的镜像在擦除前被拒绝，而不是写到一半被 NACK
+    let image: BTreeMap<u32, u8> = (FLASH_BASE + 0x7F00..FLASH_BASE + 0x8100)
+        .map(|a| (a, 0x5A))
+        .collect();
+    assert!(matches!(
+        session.check_image(&image),
+        Err(Error::ImageExceedsFlash {
+            flash_end: 0x0800_8000,
+            size_kb: 32,
+            ..
+        })
+    ));
+
+    let protected = SimulatedBootloader::new(SimConfig {
+        read_protected: true,
+        ..SimConfig::default()
+    });
+    let session = BootloaderSession::from_port(Box::new(protected), &options()).unwrap();
+    assert_eq!(session.flash_size(), None);
+}
+
+#[test]
+fn buffered_port_reads_in_bulk_and_clears_queue() {
+    use std::io::{Read, Write};
+
+    use probe_flasher::{rxbuf::BufferedPort, transport::Transport};
+    use serialport::SerialPort;
+
+    let sim = SimulatedBootloader::new(SimConfig::default());
+    let mut port = BufferedPort::new(Box::new(sim.clone()));
+    port.write_all(&[0x7F]).unwrap();
+    // GET 命令
+    port.write_all(&[0x00, 0xFF]).unwrap();
+
+    // 同步 ACK 和 GET 的完整应答一次读入队列，之后逐字节取出
+    let mut byte = [0u8; 1];
+    port.read_exact(&mut byte).unwrap();
+    assert_eq!(byte[0], 0x79);
+    assert_eq!(sim.bytes_to_read().unwrap(), 0);
+    assert!(port.queued() > 3);
+
+    port.read_exact(&mut byte).unwrap();
+    assert_eq!(byte[0], 0x79);
+    port.clear().unwrap();
+    assert_eq!(port.queued(), 0);
+    port.set_timeout(Duration::from_millis(5)).unwrap();
+    assert_eq!(
+        port.read(&mut byte).unwrap_err().kind(),
+        std::io::ErrorKind::TimedOut
+    );
+}
+
+/// 在子进程中运行：注入的故障是进程全局的，会被并行测试中的其他写入取走
+#[test]
+#[ignore = "run by injected_nack_is_retried in a child process"]
+fn injected_nack_child() {
+    if std::env::var(probe_flasher::inject::ENV_VAR).is_err() {
+        return;
+    }
+    let sim = SimulatedBootloader::new(SimConfig::default());
+    let logger = NullLogger;
+    let image: BTreeMap<u32, u8> = (FLASH_BASE..FLASH_BASE + 512)
+        .map(|a| (a, a as u8))
+        .collect();
+    let mut session = BootloaderSession::from_port(Box::new(sim.clone()), &options()).unwrap();
+    session.erase_all(&logger).unwrap();
+    assert_eq!(session.write_image(&image, &logger).unwrap(), 512);
+    assert_eq!(sim.flash(), image);
+    assert_eq!(session.link_stats().command_retries, 1);
+}
+
+#[test]
+fn injected_nack_is_retried() {
+    let output = std::process::Command::new(std::env::current_exe().unwrap())
+        .args(["--exact", "injected_nack_child", "--ignored"])
+        .env(probe_flasher::inject::ENV_VAR, "nack@50")
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{stdout}");
+    assert!(stdout.contains("1 passed"), "{stdout}");
+}
+
+#[test]
+fn fault_injection_specs_parse() {
+    use probe_flasher::inject::{self, Fault};
+
+    assert_eq!(
+        inject::parse("sync-timeout, nack@50,port-lost@100").unwrap(),
+        vec![Fault::SyncTimeout, Fault::Nack(50), Fault::PortLost(100)]
+    );
+    assert_eq!(inject::parse("").unwrap(), vec![]);
+    assert!(inject::parse("nack@101").is_err());
+    assert!(inject::parse("drop-ack").is_err());
+    assert_eq!(Fault::PortLost(30).to_string(), "port-lost@30");
+}
+
+#[test]
+fn raised_baud_falls_back_when_resync_fails() {
+    use probe_flasher::stm32_uart::BootMode;
+
+    let sim = SimulatedBootloader::new(SimConfig {
+        max_baud: Some(460_800),
+        ..SimConfig::default()
+    });
+    let opts = FlashOptions {
+        boot_mode: BootMode::DtrLowRtsHigh,
+        ..options()
+    };
+    let logger = NullLogger;
+    let mut session = BootloaderSession::from_port(Box::new(sim), &opts).unwrap();
+
+    // 921600 下芯片收不到有效的 0x7F，回到原波特率后仍可继续操作
+    assert_eq!(session.raise_baud(921_600).unwrap(), 115_200);
+    assert_eq!(session.baud(), 115_200);
+    session.erase_all(&logger).unwrap();
+
+    assert_eq!(session.raise_baud(460_800).unwrap(), 460_800);
+    assert_eq!(session.baud(), 460_800);
+    let image: BTreeMap<u32, u8> = (FLASH_BASE..FLASH_BASE + 512)
+        .map(|a| (a, a as u8))
+        .collect();
+    session.erase_all(&logger).unwrap();
+    session.write_image(&image, &logger).unwrap();
+    session.verify_image(&image, &logger).unwrap();
+
+    // 不控制复位线时无法重新同步，保持原波特率
+    let mut session = BootloaderSession::from_port(
+        Box::new(SimulatedBootloader::new(SimConfig::default())),
+        &options(),
+    )
+    .unwrap();
+    assert_eq!(session.raise_baud(921_600).unwrap(), 115_200);
+}
+
+#[test]
+fn flash_options_render_as_command_line() {
+    use std::path::PathBuf;
+
+    use probe_flasher::cmdline;
+    use probe_flasher::stm32_uart::{BootMode, EraseMode, ImageFormat, ImageSource};
+
+    let opts = FlashOptions {
+        baud_rate: 57_600,
+        boot_mode: BootMode::RtsLowDtrHigh,
+        lines: stm32_uart::BootLineConfig::for_mode(BootMode::RtsLowDtrHigh),
+        reset_after: true,
+        verify: VerifyStrategy::ReadBackFull,
+        erase_mode: EraseMode::Pages,
+        timeouts: Timeouts {
+            ack: Some(Duration::from_millis(1500)),
+            ..Timeouts::default()
+        },
+        go_address: Some(0x0800_4000),
+        ..FlashOptions::default()
+    };
+    let sources = [ImageSource {
+        path: PathBuf::from("build/my app.hex"),
+        format: ImageFormat::IntelHex,
+        base_address: None,
+    }];
+    let command = cmdline::flash_command("/dev/ttyUSB0", &sources, &opts).unwrap();
+    assert_eq!(
+        command,
+        "probe-flasher flash --port /dev/ttyUSB0 --hex 'build/my app.hex' --baud 57600 \
+         --boot-mode rts-low-dtr-high --read-timeout-ms 1500 --no-remember \
+         --go-address 0x08004000 --verify --erase pages --no-track"
+    );
+
+    let bin = [ImageSource {
+        path: PathBuf::from("app.bin"),
+        format: ImageFormat::Binary,
+        base_address: Some(0x0800_8000),
+    }];
+    let args = cmdline::flash_args("COM5", &bin, &FlashOptions::default()).unwrap();
+    assert!(args.windows(2).any(|w| w == ["--address", "0x08008000"]));
+    assert!(args.contains(&"--no-reset".to_string()));
+
+    // 多个文件中有需要平移地址的，命令行无法表达
+    let shifted = [
+        sources[0].clone(),
+        ImageSource {
+            base_address: Some(0x0800_8000),
+            ..sources[0].clone()
+        },
+    ];
+    assert_eq!(cmdline::flash_args("COM5", &shifted, &opts), None);
+    assert_eq!(cmdline::quote("it's"), r"'it'\''s'");
+}
+
+#[test]
+fn provisioning_data_builds_a_flashable_image() {
+    use std::collections::HashMap;
+
+    use probe_flasher::provision::{Layout, Value};
+    use probe_flasher::stm32_uart::DumpFormat;
+
+    let layout: Layout = toml::from_str(
+        r#"
+        base = 0x0800F800
+        size = 32
+        crc_offset = 28
+        fields = [
+            { key = "serial", offset = 0, kind = "str", len = 8 },
+            { key = "gain", offset = 8, kind = "f32" },
+            { key = "trim", offset = 12, kind = "i32" },
+            { key = "mac", offset = 16, kind = "bytes", len = 6 },
+        ]
+        "#,
+    )
+    .unwrap();
+    let values: HashMap<String, Value> = [
+        ("serial", Value::from("SN42")),
+        ("gain", Value::from(1.5)),
+        ("trim", Value::from(-3i64)),
+        ("mac", Value::from(vec![0x02, 0, 0, 0, 0, 0x2A])),
+    ]
+    .into_iter()
+    .map(|(k, v)| (k.to_string(), v))
+    .collect();
+    let image = layout.build(&values).unwrap();
+    assert_eq!(&image.data[..8], b"SN42\0\0\0\0");
+    assert_eq!(&image.data[8..12], &1.5f32.to_le_bytes());
+    assert_eq!(&image.data[12..16], &(-3i32).to_le_bytes());
+    assert_eq!(&image.data[22..28], &[0xFF; 6]);
+    let crc = crc32fast::hash(&image.data[..28]);
+    assert_eq!(&image.data[28..], &crc.to_le_bytes());
+
+    let text: HashMap<String, String> = [
+        ("serial", "SN42"),
+        ("gain", "1.5"),
+        ("trim", "-3"),
+        ("mac", "02 00 00 00 00 2A"),
+    ]
+    .into_iter()
+    .map(|(k, v)| (k.to_string(), v.to_string()))
+    .collect();
+    assert_eq!(layout.build_from_text(&text).unwrap(), image);
+
+    let path = std::env::temp_dir().join(format!(
+        "probe-flasher-{}-provision.hex",
+        std::process::id()
+    ));
+    let source = image.save(&path, DumpFormat::Hex).unwrap();
+    let merged = stm32_uart::merge_sources(&[source], &options()).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(merged, image.to_image().unwrap());
+
+    let mut too_long = values.clone();
+    too_long.insert("serial".to_string(), Value::from("SN-123456789"));
+    assert!(matches!(layout.build(&too_long), Err(Error::Provision(_))));
+    let mut unknown = values;
+    unknown.insert("seriall".to_string(), Value::from("x"));
+    assert!(matches!(layout.build(&unknown), Err(Error::Provision(_))));
+}
+
+#[test]
+fn bench_scores_link_faults() {
+    let sim = SimulatedBootloader::new(SimConfig::default());
+    let logger = NullLogger;
+    let mut session = BootloaderSession::from_port(Box::new(sim.clone()), &options()).unwrap();
+    let clean = session.bench(5, &logger).unwrap();
+    assert_eq!(clean.score(), 100);
+    // from_port 中的 GET 和读 Flash 容量寄存器各一次，加上 5 轮
+    assert_eq!(clean.commands[&0x00].count, 6);
+    assert_eq!(clean.commands[&0x11].count, 6);
+
+    let mut session = BootloaderSession::from_port(Box::new(sim.clone()), &options()).unwrap();
+    sim.inject_faults([Some(Fault::DropAck), None, None, Some(Fault::Garbage(0x55))]);
+    let faulty = session.bench(5, &logger).unwrap();
+    let faults: u32 = faulty
+        .commands
+        .values()
+        .map(|c| c.timeouts + c.garbled)
+        .sum();
+    assert!(faults >= 1, "{faulty:?}");
+    assert!(faulty.score() < clean.score());
+    assert!(faulty.to_string().contains("/100"));
+}
+
+#[test]
+fn compare_reports_every_differing_region() {
+    let sim = SimulatedBootloader::new(SimConfig::default());
+    let logger = NullLogger;
+    let mut session = BootloaderSession::from_port(Box::new(sim.clone()), &options()).unwrap();
+    let mut image: BTreeMap<u32, u8> = (FLASH_BASE..FLASH_BASE + 600)
+        .map(|a| (a, a as u8))
+        .collect();
+    image.extend((FLASH_BASE + 0x1000..FLASH_BASE + 0x1100).map(|a| (a, 0x5A)));
+    session.write_image(&image, &logger).unwrap();
+
+    let mut expected = image.clone();
+    for addr in [FLASH_BASE + 300, FLASH_BASE + 301, FLASH_BASE + 590] {
+        *expected.get_mut(&addr).unwrap() ^= 0xFF;
+    }
+    let diffs = session.compare_image(&expected, &logger).unwrap();
+    assert_eq!(diffs.len(), 2);
+    assert_eq!(diffs[0].mismatched, 3);
+    assert_eq!(
+        diffs[0].first,
+        Some((FLASH_BASE + 300, 300u32 as u8 ^ 0xFF, 300u32 as u8))
+    );
+    assert!(diffs[1].matches());
+
+    let opts = FlashOptions {
+        verify: VerifyStrategy::CrcCommand,
+        ..options()
+    };
+    let mut session = BootloaderSession::from_port(Box::new(sim.clone()), &opts).unwrap();
+    let diffs = session.compare_image(&expected, &logger).unwrap();
+    assert_eq!(diffs[0].mismatched, 3);
+    assert!(diffs[1].matches());
+}
+
+const F4_DFU_LAYOUT: &str = "@Internal Flash  /0x08000000/04*016Kg,01*064Kg,07*128Kg";
+
+/// 模拟设备的 wTransferSize
+const MOCK_DFU_XFER: u16 = 256;
+
+/// DfuSe 模拟设备：DNLOAD 在随后的第一次 GETSTATUS 时执行并报 dnBUSY，未擦除的字节不能写
+#[derive(Default)]
+struct MockDfuSe {
+    state: u8,
+    status: u8,
+    pointer: u32,
+    /// 每次设置的地址指针
+    addresses: Vec<u32>,
+    pending: Option<(u16, Vec<u8>)>,
+    flash: BTreeMap<u32, u8>,
+    erased: Vec<u32>,
+    left_at: Option<u32>,
+}
+
+impl MockDfuSe {
+    fn fail(&mut self, status: u8) {
+        self.status = status;
+        self.state = 10;
+    }
+
+    fn execute(&mut self, block: u16, data: Vec<u8>, memory: &DfuMemory) {
+        match (block, data.as_slice()) {
+            (0, [0x21, a @ ..]) => {
+                self.pointer = u32::from_le_bytes(a.try_into().unwrap());
+                self.addresses.push(self.pointer);
+            }
+            (0, [0x41]) => self.flash.clear(),
+            (0, [0x41, a @ ..]) => {
+                let addr = u32::from_le_bytes(a.try_into().unwrap());
+                let Ok(&[page]) = memory.pages_covering(addr, 1).as_deref() else {
+                    return self.fail(0x08);
+                };
+                let segment = memory.segments.iter().rev().find(|s| s.start <= page);
+                let size = segment.unwrap().size;
+                self.flash
+                    .retain(|&addr, _| !(page..page + size).contains(&addr));
+                self.erased.push(page);
+            }
+            (2, []) => {
+                self.left_at = Some(self.pointer);
+                self.state = 7;
+                return;
+            }
+            (2.., bytes) => {
+                let start = self.pointer + u32::from(block - 2) * u32::from(MOCK_DFU_XFER);
+                for (i, &b) in bytes.iter().enumerate() {
+                    let addr = start + i as u32;
+                    if self.flash.contains_key(&addr) {
+                        return self.fail(0x03);
+                    }
+                    self.flash.insert(addr, b);
+                }
+            }
+            _ => return self.fail(0x0F),
+        }
+        self.state = 4;
+    }
+}
+
+struct MockDfuDevice(std::rc::Rc<std::cell::RefCell<MockDfuSe>>, DfuMemory);
+
+impl DfuDevice for MockDfuDevice {
+    fn control_out(&mut self, request: u8, value: u16, data: &[u8]) -> Result<(), DfuError> {
+        let mut dev = self.0.borrow_mut();
+        match request {
+            1 => {
+                dev.pending = Some((value, data.to_vec()));
+                dev.state = 3;
+            }
+            4 => {
+                dev.status = 0;
+                dev.state = 2;
+            }
+            6 => dev.state = 2,
+            _ => return Err(DfuError::Protocol(format!("unexpected request {request}"))),
+        }
+        Ok(())
+    }
+
+    fn control_in(&mut self, request: u8, value: u16, len: usize) -> Result<Vec<u8>, DfuError> {
+        let mut dev = self.0.borrow_mut();
+        match request {
+            3 => {
+                if let Some((block, data)) = dev.pending.take() {
+                    dev.execute(block, data, &self.1);
+                } else if dev.state == 4 {
+                    dev.state = 5;
+                }
+                Ok(vec![dev.status, 0, 0, 0, dev.state, 0])
+            }
+            2 if value == 2 && matches!(dev.state, 2 | 9) => {
+                dev.state = 9;
+                Ok((0..len as u32)
+                    .map(|i| dev.flash.get(&(dev.pointer + i)).copied().unwrap_or(0xFF))
+                    .collect())
+            }
+            _ => Err(DfuError::Protocol(format!("unexpected request {request}"))),
+        }
+    }
+}
+
+#[test]
+fn dfuse_memory_layout_maps_pages() {
+    let memory = DfuMemory::parse(F4_DFU_LAYOUT).unwrap();
+    assert_eq!(memory.name, "Internal Flash");
+    assert_eq!(
+        memory.segments[1],
+        DfuSegment {
+            start: 0x0801_0000,
+            count: 1,
+            size: 64 * 1024,
+            flags: 7,
+        }
+    );
+    assert_eq!(memory.segments[2].end(), 0x0810_0000);
+    assert_eq!(
+        memory.pages_covering(0x0800_FF00, 0x200).unwrap(),
+        vec![0x0800_C000, 0x0801_0000]
+    );
+    assert!(matches!(
+        memory.pages_covering(0x080F_FFFF, 2),
+        Err(Error::AddressOutOfRange {
+            address: 0x0810_0000,
+            ..
+        })
+    ));
+    assert!(
+        DfuMemory::parse("@Option Bytes  /0x1FFFC000/01*016 e")
+            .is_ok_and(|m| !m.segments[0].erasable())
+    );
+    assert!(DfuMemory::parse("Internal Flash /0x08000000/04*016Kg").is_err());
+    assert!(DfuMemory::parse("@Internal Flash /0x08000000/04*000Kg").is_err());
+}
+
+#[test]
+fn dfuse_session_erases_writes_verifies_and_leaves() {
+    struct Recorder(std::sync::Mutex<Vec<ProgressEvent>>);
+
+    impl ProgressSink for Recorder {
+        fn progress(&self, event: &ProgressEvent) {
+            self.0.lock().unwrap().push(*event);
+        }
+    }
+
+    let memory = DfuMemory::parse(F4_DFU_LAYOUT).unwrap();
+    let mock = std::rc::Rc::new(std::cell::RefCell::new(MockDfuSe {
+        // 上次会话留下的错误状态，打开时要先清除
+        state: 10,
+        status: 0x0E,
+        ..MockDfuSe::default()
+    }));
+    // 镜像覆盖的扇区里有旧数据，镜像之外的扇区必须保留
+    mock.borrow_mut().flash.insert(0x0800_C010, 0x00);
+    mock.borrow_mut().flash.insert(0x0802_0000, 0x42);
+
+    let mut image: BTreeMap<u32, u8> = (0x0800_FF00..0x0801_0300u32)
+        .map(|a| (a, a as u8))
+        .collect();
+    image.extend((0x0800_0000..0x0800_0010u32).map(|a| (a, 0xA5)));
+    let opts = FlashOptions {
+        erase_mode: EraseMode::Pages,
+        ..options()
+    };
+    let device = MockDfuDevice(mock.clone(), memory.clone());
+    let mut session = DfuSession::from_device(
+        Box::new(device),
+        memory,
+        MOCK_DFU_XFER,
+        "STM32 BOOTLOADER",
+        &opts,
+    )
+    .unwrap();
+    let mut timeline = stm32_uart::PhaseTimeline::default();
+    let recorder = Recorder(Default::default());
+    let written =
+        stm32_dfu::flash_session(&mut session, &image, &opts, &recorder, &mut timeline).unwrap();
+    assert_eq!(written, image.len() as u64);
+    assert_eq!(
+        mock.borrow().erased,
+        vec![0x0800_0000, 0x0800_C000, 0x0801_0000]
+    );
+    // 每段连续数据只设置一次地址，1 KB 的第二段按块号 2–5 写入
+    assert_eq!(mock.borrow().addresses, vec![0x0800_0000, 0x0800_FF00]);
+    // 擦除进度按页计
+    let events = recorder.0.into_inner().unwrap();
+    let erase: Vec<_> = events
+        .iter()
+        .filter(|e| e.phase() == ProgressPhase::ErasePages)
+        .collect();
+    assert_eq!(
+        erase.first(),
+        Some(&&ProgressEvent::ErasePage {
+            addr: 0x0800_0000,
+            done: 1,
+            total: 3,
+        })
+    );
+    assert_eq!(erase.last().unwrap().counts(), (3, 3));
+    session.verify(&image, &NullLogger).unwrap();
+    assert_eq!(mock.borrow().flash.get(&0x0802_0000), Some(&0x42));
+    assert_eq!(
+        session.read(0x0800_FFFE, 4).unwrap(),
+        vec![0xFE, 0xFF, 0x00, 0x01]
+    );
+
+    session.leave(0x0800_0000).unwrap();
+    assert_eq!(mock.borrow().left_at, Some(0x0800_0000));
+
+    // 写入未擦除的区域：设备报 errWRITE，错误带上状态码
+    let memory = DfuMemory::parse(F4_DFU_LAYOUT).unwrap();
+    let device = MockDfuDevice(mock.clone(), memory.clone());
+    let mut session =
+        DfuSession::from_device(Box::new(device), memory, 256, "STM32 BOOTLOADER", &opts).unwrap();
+    let err = session.write_image(&image, &NullLogger).unwrap_err();
+    assert!(
+        matches!(err, Error::UsbDfu(DfuError::Status { status: 0x03, .. })),
+        "{err:?}"
+    );
+    assert_eq!(err.code(), ErrorCode::Usb);
+}
@@ -0,0 +1,923 @@
+//! 虚拟串口对集成测试：一端由模拟 Bootloader 线程驱动，另一端交给真实的 serialport
+//! 代码打开，端到端跑通 打开 / Boot 模式 / 同步 / 烧录，无需硬件
+//!
+//! Unix 上使用 pty 对；Windows 上通过环境变量 `PROBE_FLASHER_VIRTUAL_PAIR=COM10,COM11`
+//! 指定 com0com 之类的虚拟串口对，未设置时跳过测试。pty 不支持 DTR/RTS，
+//! 只有能模拟控制线的虚拟串口对才会执行 Boot 模式序列。
+
+use std::{
+    io::{ErrorKind, Read, Write},
+    path::PathBuf,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread::JoinHandle,
+    time::Duration,
+};
+
+use probe_flasher::{
+    async_uart,
+    devicedb::{CycleTracking, DeviceDb},
+    job::{JobFile, JobImage, JobRunner, StationConfig, StationState},
+    monitor::{MonitorEvent, MonitorHub},
+    optbytes::{ObFamily, RdpLevel},
+    sim::{Fault, SimConfig, SimulatedBootloader},
+    stm32_uart::{
+        self, BootMode, DumpFormat, ErrorCode, ExternalMemory, FLASH_BASE, FirmwareSource,
+        FlashOptions, ImageFormat, ImageSource, ProgressSink, Timeouts, VerifyStrategy,
+    },
+    stub::{self, StubConfig},
+};
+use serialport::SerialPort;
+
+struct NullLogger;
+
+impl ProgressSink for NullLogger {}
+
+/// 虚拟串口对的两端
+struct PairEnds {
+    /// 被测代码打开的端口名
+    device: String,
+    /// 交给模拟器的另一端
+    wire: Box<dyn SerialPort>,
+    /// 是否支持 DTR/RTS 控制线
+    modem_lines: bool,
+}
+
+trait VirtualPair {
+    fn open() -> Option<PairEnds>;
+}
+
+#[cfg(unix)]
+struct PtyPair;
+
+#[cfg(unix)]
+impl VirtualPair for PtyPair {
+    fn open() -> Option<PairEnds> {
+        let (master, slave) = serialport::TTYPort::pair().ok()?;
+        let device = slave.name()?;
+        // 被测代码会以独占方式重新打开从端
+        drop(slave);
+        Some(PairEnds {
+            device,
+            wire: Box::new(master),
+            modem_lines: false,
+        })
+    }
+}
+
+/// 通过环境变量指定的虚拟串口对（如 com0com）
+struct EnvPair;
+
+impl VirtualPair for EnvPair {
+    fn open() -> Option<PairEnds> {
+        let spec = std::env::var("PROBE_FLASHER_VIRTUAL_PAIR").ok()?;
+        let (device, emulator) = spec.split_once(',')?;
+        let wire = serialport::new(emulator.trim(), 115_200)
+            .timeout(Duration::from_millis(5))
+            .open()
+            .ok()?;
+        Some(PairEnds {
+            device: device.trim().to_string(),
+            wire,
+            modem_lines: true,
+        })
+    }
+}
+
+fn open_pair() -> Option<PairEnds> {
+    #[cfg(unix)]
+    if let Some(pair) = PtyPair::open() {
+        return Some(pair);
+    }
+    EnvPair::open()
+}
+
+/// 在后台线程中把虚拟串口另一端的字节转发给模拟 Bootloader
+struct Emulator {
+    port_name: String,
+    boot_mode: BootMode,
+    sim: SimulatedBootloader,
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Emulator {
+    fn start(config: SimConfig) -> Option<Self> {
+        let PairEnds {
+            device: port_name,
+            mut wire,
+            modem_lines,
+        } = open_pair()?;
+        wire.set_timeout(Duration::from_millis(5)).ok()?;
+
+        let sim = SimulatedBootloader::new(config);
+        let stop = Arc::new(AtomicBool::new(false));
+        let handle = {
+            let mut sim = sim.clone();
+            let stop = stop.clone();
+            std::thread::spawn(move || {
+                let mut buf = [0u8; 512];
+                while !stop.load(Ordering::Relaxed) {
+                    match wire.read(&mut buf) {
+                        Ok(n) if n > 0 => sim.write_all(&buf[..n]).unwrap(),
+                        Ok(_) => {}
+                        Err(e) if e.kind() == ErrorKind::TimedOut => {}
+                        // 被测代码关闭从端时 pty 主端短暂返回 EIO
+                        Err(_) => std::thread::sleep(Duration::from_millis(5)),
+                    }
+                    while let Ok(n) = sim.read(&mut buf) {
+                        if wire.write_all(&buf[..n]).is_err() {
+                            break;
+                        }
+                    }
+                }
+            })
+        };
+
+        Some(Self {
+            port_name,
+            boot_mode: if modem_lines {
+                BootMode::RtsLowDtrHigh
+            } else {
+                BootMode::None
+            },
+            sim,
+            stop,
+            handle: Some(handle),
+        })
+    }
+}
+
+impl Emulator {
+    fn options(&self) -> FlashOptions {
+        FlashOptions {
+            boot_mode: self.boot_mode,
+            timeouts: Timeouts {
+                sync: Some(Duration::from_millis(200)),
+                ack: Some(Duration::from_millis(200)),
+                erase: Some(Duration::from_secs(1)),
+                read_chunk: Some(Duration::from_millis(200)),
+            },
+            ..FlashOptions::default()
+        }
+    }
+}
+
+impl Drop for Emulator {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+fn write_hex(name: &str, records: &[&str]) -> PathBuf {
+    let path =
+        std::env::temp_dir().join(format!("probe-flasher-{}-{name}.hex", std::process::id()));
+    std::fs::write(&path, records.join("\n")).unwrap();
+    path
+}
+
+#[test]
+fn identify_over_virtual_port() {
+    let Some(emu) = Emulator::start(SimConfig::default()) else {
+        eprintln!("no virtual serial pair available, skipping");
+        return;
+    };
+
+    let result = stm32_uart::identify(&emu.port_name, &emu.options());
+    assert!(result.ok, "{:?}", result.error);
+    assert_eq!(result.bootloader_version, Some(0x22));
+    assert_eq!(result.product_id, Some(0x0410));
+    assert_eq!(result.version_info.map(|v| v.version), Some(0x22));
+    assert_eq!(result.read_protected, Some(false));
+}
+
+#[test]
+fn identify_reports_read_protection() {
+    let Some(emu) = Emulator::start(SimConfig {
+        read_protected: true,
+        ..SimConfig::default()
+    }) else {
+        eprintln!("no virtual serial pair available, skipping");
+        return;
+    };
+
+    let result = stm32_uart::identify(&emu.port_name, &emu.options());
+    assert!(result.ok, "{:?}", result.error);
+    assert_eq!(result.read_protected, Some(true));
+}
+
+#[test]
+fn flash_hex_over_virtual_port() {
+    let Some(emu) = Emulator::start(SimConfig::default()) else {
+        eprintln!("no virtual serial pair available, skipping");
+        return;
+    };
+    let hex = write_hex(
+        "flash",
+        &[
+            ":020000040800F2",
+            ":10000000000102030405060708090A0B0C0D0E0F78",
+            ":04001000DEADBEEFB4",
+            ":00000001FF",
+        ],
+    );
+
+    let opts = FlashOptions {
+        reset_after: true,
+        ..emu.options()
+    };
+    let result = stm32_uart::flash_hex(&emu.port_name, &hex, &opts, &NullLogger);
+    std::fs::remove_file(&hex).unwrap();
+    let report = result.unwrap();
+    assert_eq!(report.bytes_written, 20);
+    assert!(report.timeline.total() > Duration::ZERO);
+
+    let flash = emu.sim.flash();
+    assert_eq!(flash.len(), 20);
+    assert_eq!(flash[&FLASH_BASE], 0x00);
+    assert_eq!(flash[&(FLASH_BASE + 0x13)], 0xEF);
+    assert_eq!(emu.sim.go_address(), Some(FLASH_BASE));
+}
+
+#[test]
+fn async_identify_and_flash_over_virtual_port() {
+    let Some(emu) = Emulator::start(SimConfig::default()) else {
+        eprintln!("no virtual serial pair available, skipping");
+        return;
+    };
+    let hex = write_hex(
+        "async",
+        &[
+            ":020000040800F2",
+            ":10000000000102030405060708090A0B0C0D0E0F78",
+            ":04001000DEADBEEFB4",
+            ":00000001FF",
+        ],
+    );
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+
+    // GUI 在多线程运行时中 await 这些 future，它们必须是 Send
+    fn send<F: std::future::Future + Send>(future: F) -> F {
+        future
+    }
+
+    let identified = runtime.block_on(send(async_uart::identify_async(
+        &emu.port_name,
+        &emu.options(),
+    )));
+    assert!(identified.ok, "{:?}", identified.error);
+    assert_eq!(identified.product_id, Some(0x0410));
+
+    // 留在 Bootloader 中，接着再烧录一次
+    let opts = FlashOptions {
+        reset_after: false,
+        verify: VerifyStrategy::ReadBackFull,
+        ..emu.options()
+    };
+    let result = runtime.block_on(send(async_uart::flash_hex_async(
+        &emu.port_name,
+        &hex,
+        &opts,
+        NullLogger,
+    )));
+    assert_eq!(result.unwrap().bytes_written, 20);
+    assert_eq!(emu.sim.flash()[&(FLASH_BASE + 0x13)], 0xEF);
+
+    // 异步接口与同步接口共用一份实现，同步接口的功能都可用
+    let locked = FlashOptions {
+        lock_after: true,
+        ..emu.options()
+    };
+    let result = runtime.block_on(send(async_uart::flash_hex_async(
+        &emu.port_name,
+        &hex,
+        &locked,
+        NullLogger,
+    )));
+    std::fs::remove_file(&hex).unwrap();
+    assert_eq!(result.unwrap().bytes_written, 20);
+    assert!(emu.sim.read_protected());
+}
+
+#[test]
+fn virgin_device_reloads_option_bytes_after_flash() {
+    let Some(emu) = Emulator::start(SimConfig {
+        product_id: 0x445,
+        flash_size: 32 * 1024,
+        ..SimConfig::default()
+    }) else {
+        eprintln!("no virtual serial pair available, skipping");
+        return;
+    };
+    let hex = write_hex(
+        "virgin",
+        &[
+            ":020000040800F2",
+            ":10000000000102030405060708090A0B0C0D0E0F78",
+            ":00000001FF",
+        ],
+    );
+    let original = emu.sim.option_bytes();
+    let opts = FlashOptions {
+        reset_after: true,
+        virgin_probe: true,
+        ..emu.options()
+    };
+
+    // Flash 为空：写回选项字节让芯片重新加载，而不是 GO
+    stm32_uart::flash_hex(&emu.port_name, &hex, &opts, &NullLogger).unwrap();
+    assert_eq!(emu.sim.flash()[&(FLASH_BASE + 0x0F)], 0x0F);
+    assert_eq!(emu.sim.go_address(), None);
+    assert_eq!(emu.sim.option_bytes(), original);
+
+    // 已有固件时按正常流程 GO
+    let result = stm32_uart::flash_hex(&emu.port_name, &hex, &opts, &NullLogger);
+    std::fs::remove_file(&hex).unwrap();
+    result.unwrap();
+    assert_eq!(emu.sim.go_address(), Some(FLASH_BASE));
+}
+
+#[test]
+fn flash_bin_at_base_address() {
+    let Some(emu) = Emulator::start(SimConfig::default()) else {
+        eprintln!("no virtual serial pair available, skipping");
+        return;
+    };
+    let bin = std::env::temp_dir().join(format!("probe-flasher-{}-app.bin", std::process::id()));
+    std::fs::write(&bin, [0x11, 0x22, 0x33, 0x44, 0x55]).unwrap();
+
+    let source = FirmwareSource::from_path(&bin, Some(FLASH_BASE + 0x2000));
+    let result = stm32_uart::flash_firmware(&emu.port_name, &source, &emu.options(), &NullLogger);
+    std::fs::remove_file(&bin).unwrap();
+    assert_eq!(result.unwrap().bytes_written, 5);
+
+    let flash = emu.sim.flash();
+    assert_eq!(flash[&(FLASH_BASE + 0x2000)], 0x11);
+    assert_eq!(flash[&(FLASH_BASE + 0x2004)], 0x55);
+}
+
+#[test]
+fn offset_shifts_zero_linked_image_into_flash() {
+    let Some(emu) = Emulator::start(SimConfig::default()) else {
+        eprintln!("no virtual serial pair available, skipping");
+        return;
+    };
+    // 按 0 地址链接的固件，平移到 0x08001000 之后
+    let hex = write_hex("offset", &[":0410000001020304E2", ":00000001FF"]);
+    let source = ImageSource::with_format(&hex, ImageFormat::IntelHex, None);
+
+    let options = FlashOptions {
+        offset: i64::from(FLASH_BASE),
+        ..emu.options()
+    };
+    let result = stm32_uart::flash_firmware(&emu.port_name, &source, &options, &NullLogger);
+    let out_of_range = stm32_uart::flash_firmware(
+        &emu.port_name,
+        &source,
+        &FlashOptions {
+            offset: -0x2000,
+            ..emu.options()
+        },
+        &NullLogger,
+    );
+    std::fs::remove_file(&hex).unwrap();
+
+    assert_eq!(result.unwrap().bytes_written, 4);
+    let flash = emu.sim.flash();
+    assert_eq!(flash[&(FLASH_BASE + 0x1000)], 0x01);
+    assert_eq!(flash[&(FLASH_BASE + 0x1003)], 0x04);
+    assert!(matches!(
+        out_of_range,
+        Err(stm32_uart::Error::ImageOffsetOutOfRange {
+            offset: -0x2000,
+            first: 0x1000,
+            last: 0x1003
+        })
+    ));
+}
+
+#[test]
+fn merged_hex_files_flash_in_one_session() {
+    let Some(emu) = Emulator::start(SimConfig::default()) else {
+        eprintln!("no virtual serial pair available, skipping");
+        return;
+    };
+    let boot = write_hex(
+        "merge-boot",
+        &[":020000040800F2", ":0400000001020304F2", ":00000001FF"],
+    );
+    let app = write_hex(
+        "merge-app",
+        &[":020000040800F2", ":04400000DEADBEEF84", ":00000001FF"],
+    );
+    let source = |path: &PathBuf| ImageSource {
+        path: path.clone(),
+        format: ImageFormat::IntelHex,
+        base_address: None,
+    };
+
+    let result = stm32_uart::flash_sources(
+        &emu.port_name,
+        &[source(&boot), source(&app)],
+        &emu.options(),
+        &NullLogger,
+    );
+    let overlap = stm32_uart::flash_sources(
+        &emu.port_name,
+        &[source(&boot), source(&boot)],
+        &emu.options(),
+        &NullLogger,
+    );
+    std::fs::remove_file(&boot).unwrap();
+    std::fs::remove_file(&app).unwrap();
+
+    assert_eq!(result.unwrap().bytes_written, 8);
+    let flash = emu.sim.flash();
+    assert_eq!(flash[&FLASH_BASE], 0x01);
+    assert_eq!(flash[&(FLASH_BASE + 0x4003)], 0xEF);
+    assert!(matches!(
+        overlap,
+        Err(stm32_uart::Error::ImageOverlap(FLASH_BASE))
+    ));
+}
+
+#[test]
+fn go_targets_vector_table_of_hex_entry_point() {
+    let Some(emu) = Emulator::start(SimConfig::default()) else {
+        eprintln!("no virtual serial pair available, skipping");
+        return;
+    };
+    // 应用链接在 0x08004000，Start Linear Address 为带 Thumb 位的复位处理函数
+    let hex = write_hex(
+        "entry",
+        &[
+            ":020000040800F2",
+            ":084000000050002001410008FE",
+            ":0400000508004101AD",
+            ":00000001FF",
+        ],
+    );
+    let source = FirmwareSource::from_path(&hex, None);
+    let opts = FlashOptions {
+        reset_after: true,
+        ..emu.options()
+    };
+
+    let result = stm32_uart::flash_firmware(&emu.port_name, &source, &opts, &NullLogger);
+    assert!(result.is_ok());
+    assert_eq!(emu.sim.go_address(), Some(FLASH_BASE + 0x4000));
+
+    // 显式的 --go-address 优先
+    let emu = Emulator::start(SimConfig::default()).unwrap();
+    let opts = FlashOptions {
+        go_address: Some(FLASH_BASE),
+        reset_after: true,
+        ..emu.options()
+    };
+    let result = stm32_uart::flash_firmware(&emu.port_name, &source, &opts, &NullLogger);
+    std::fs::remove_file(&hex).unwrap();
+    assert!(result.is_ok());
+    assert_eq!(emu.sim.go_address(), Some(FLASH_BASE));
+}
+
+#[test]
+fn external_memory_is_written_through_extension_commands() {
+    let external = ExternalMemory {
+        start: ExternalMemory::QSPI_BASE,
+        size: 0x10_0000,
+        write_command: 0xA0,
+        erase_command: Some(0xA1),
+    };
+    let config = || {
+        let mut config = SimConfig {
+            external: Some(external.clone()),
+            ..SimConfig::default()
+        };
+        config.commands.extend([0xA0, 0xA1]);
+        config
+    };
+    let Some(emu) = Emulator::start(config()) else {
+        eprintln!("no virtual serial pair available, skipping");
+        return;
+    };
+    let hex = write_hex(
+        "qspi",
+        &[
+            ":020000040800F2",
+            ":04000000005000208C",
+            ":0200000490006A",
+            ":080000000102030405060708D4",
+            ":00000001FF",
+        ],
+    );
+    let source = FirmwareSource::from_path(&hex, None);
+
+    // 未配置外部存储器时按芯片存储器映射拒绝
+    let result = stm32_uart::flash_firmware(&emu.port_name, &source, &emu.options(), &NullLogger);
+    assert!(matches!(
+        result,
+        Err(stm32_uart::Error::AddressOutOfRange {
+            address: 0x9000_0000,
+            ..
+        })
+    ));
+
+    let emu = Emulator::start(config()).unwrap();
+    let opts = FlashOptions {
+        external_memory: vec![external.clone()],
+        verify: VerifyStrategy::ReadBackFull,
+        ..emu.options()
+    };
+    let result = stm32_uart::flash_firmware(&emu.port_name, &source, &opts, &NullLogger);
+    std::fs::remove_file(&hex).unwrap();
+    assert!(result.is_ok(), "{result:?}");
+    let flash = emu.sim.flash();
+    let qspi: Vec<u8> = flash.range(0x9000_0000..).map(|(_, &b)| b).collect();
+    assert_eq!(qspi, [1, 2, 3, 4, 5, 6, 7, 8]);
+    assert_eq!(flash.get(&FLASH_BASE), Some(&0x00));
+}
+
+#[test]
+fn ram_stub_flashes_with_large_frames() {
+    let Some(emu) = Emulator::start(SimConfig {
+        stub: true,
+        ..SimConfig::default()
+    }) else {
+        eprintln!("no virtual serial pair available, skipping");
+        return;
+    };
+    // 超过 stub 单帧负载，需要分帧写入和回读
+    let mut firmware = vec![0x00, 0x50, 0x00, 0x20, 0x01, 0x01, 0x00, 0x08];
+    firmware.extend((0..3000u32).map(|i| (i % 251) as u8));
+    let path = std::env::temp_dir().join(format!("probe-flasher-{}-stub.bin", std::process::id()));
+    std::fs::write(&path, &firmware).unwrap();
+    let source = ImageSource::with_format(&path, ImageFormat::Binary, Some(FLASH_BASE));
+    let stub = StubConfig {
+        image: vec![0x00, 0x50, 0x00, 0x20, 0x09, 0x00, 0x00, 0x20],
+        address: StubConfig::SRAM_BASE,
+        baud: None,
+    };
+    let opts = FlashOptions {
+        verify: VerifyStrategy::ReadBackFull,
+        reset_after: true,
+        ..emu.options()
+    };
+
+    let result = stub::flash_with_stub(&emu.port_name, &[source], &stub, &opts, &NullLogger);
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(result.unwrap().bytes_written, firmware.len() as u64);
+    let flash: Vec<u8> = emu.sim.flash().into_values().collect();
+    assert_eq!(flash, firmware);
+    assert_eq!(emu.sim.go_address(), Some(FLASH_BASE));
+}
+
+#[test]
+fn lock_and_unlock_over_virtual_port() {
+    let Some(emu) = Emulator::start(SimConfig::default()) else {
+        eprintln!("no virtual serial pair available, skipping");
+        return;
+    };
+
+    stm32_uart::lock(&emu.port_name, &emu.options()).unwrap();
+    assert!(emu.sim.read_protected());
+
+    stm32_uart::unlock(&emu.port_name, &emu.options()).unwrap();
+    assert!(!emu.sim.read_protected());
+}
+
+#[test]
+fn flash_clears_and_reapplies_write_protection() {
+    let Some(emu) = Emulator::start(SimConfig {
+        write_protected: vec![0, 1],
+        ..SimConfig::default()
+    }) else {
+        eprintln!("no virtual serial pair available, skipping");
+        return;
+    };
+    let hex = write_hex(
+        "wrp",
+        &[":020000040800F2", ":04000000DEADBEEFC4", ":00000001FF"],
+    );
+
+    // 出厂写保护下写入被拒绝
+    let result = stm32_uart::flash_hex(&emu.port_name, &hex, &emu.options(), &NullLogger);
+    assert!(result.is_err());
+
+    let opts = FlashOptions {
+        unprotect_before: true,
+        protect_after: Some(vec![0]),
+        ..emu.options()
+    };
+    let result = stm32_uart::flash_hex(&emu.port_name, &hex, &opts, &NullLogger);
+    std::fs::remove_file(&hex).unwrap();
+    assert_eq!(result.unwrap().bytes_written, 4);
+    assert_eq!(emu.sim.flash()[&(FLASH_BASE + 3)], 0xEF);
+    assert_eq!(emu.sim.write_protected(), vec![0]);
+}
+
+#[test]
+fn job_runner_flashes_and_counts_results() {
+    let Some(emu) = Emulator::start(SimConfig::default()) else {
+        eprintln!("no virtual serial pair available, skipping");
+        return;
+    };
+    let hex = write_hex(
+        "job",
+        &[":020000040800F2", ":04000000DEADBEEFC4", ":00000001FF"],
+    );
+    let job = JobFile {
+        name: "app".into(),
+        images: vec![JobImage {
+            path: hex.clone(),
+            base_address: None,
+            format: None,
+        }],
+        ..JobFile::default()
+    };
+    let station = StationConfig {
+        name: "line1".into(),
+        port: emu.port_name.clone(),
+        boot_mode: emu.boot_mode,
+        ..StationConfig::default()
+    };
+    let runner = JobRunner::new(job, station);
+
+    let report = runner.run_once(&NullLogger);
+    std::fs::remove_file(&hex).unwrap();
+    assert_eq!(report.unwrap().bytes_written, 4);
+    assert!(runner.run_once(&NullLogger).is_err());
+
+    let status = runner.status().lock().unwrap().clone();
+    assert_eq!((status.passed, status.failed), (1, 1));
+    assert_eq!(status.state, StationState::Idle);
+    assert!(!status.last.unwrap().ok);
+    assert_eq!(emu.sim.flash()[&FLASH_BASE], 0xDE);
+}
+
+#[test]
+fn job_and_station_files_are_checked_before_running() {
+    let path = std::env::temp_dir().join(format!("probe-flasher-{}-job.json", std::process::id()));
+    std::fs::write(&path, r#"{ "name": "app", "images": [] }"#).unwrap();
+    let result = JobFile::load(&path);
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(result.unwrap_err().code(), ErrorCode::Config);
+
+    // 既没有触发也不等待换板时，连续运行会反复烧录同一块板
+    let station = StationConfig::default();
+    assert!(station.check_continuous().is_err());
+    let station = StationConfig {
+        wait_port: true,
+        ..StationConfig::default()
+    };
+    assert!(station.check_continuous().is_ok());
+}
+
+#[test]
+fn interrupted_dump_keeps_previous_file() {
+    let Some(emu) = Emulator::start(SimConfig::default()) else {
+        eprintln!("no virtual serial pair available, skipping");
+        return;
+    };
+    // 同步、GET 和 GET_ID 各两个 ACK、读 Flash 容量寄存器和第一块读取各三个 ACK 之后，
+    // 丢弃第二块的命令 ACK
+    let mut faults = vec![None; 11];
+    faults.push(Some(Fault::DropAck));
+    emu.sim.inject_faults(faults);
+    let path = std::env::temp_dir().join(format!("probe-flasher-{}-dump.bin", std::process::id()));
+    std::fs::write(&path, b"previous").unwrap();
+
+    let result = stm32_uart::dump_flash(
+        &emu.port_name,
+        FLASH_BASE,
+        1024,
+        &path,
+        DumpFormat::Bin,
+        &emu.options(),
+        &NullLogger,
+    );
+    let content = std::fs::read(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    assert!(matches!(result, Err(stm32_uart::Error::Timeout)));
+    assert_eq!(content, b"previous");
+}
+
+#[test]
+fn hex_dump_reflashes_to_same_image() {
+    let Some(emu) = Emulator::start(SimConfig::default()) else {
+        eprintln!("no virtual serial pair available, skipping");
+        return;
+    };
+    let hex = write_hex(
+        "source",
+        &[
+            ":020000040800F2",
+            ":10000000000102030405060708090A0B0C0D0E0F78",
+            ":00000001FF",
+        ],
+    );
+    stm32_uart::flash_hex(&emu.port_name, &hex, &emu.options(), &NullLogger).unwrap();
+    let source = stm32_uart::parse_hex_to_image(&hex).unwrap();
+    std::fs::remove_file(&hex).unwrap();
+
+    let dump = std::env::temp_dir().join(format!("probe-flasher-{}-dump.hex", std::process::id()));
+    stm32_uart::dump_flash(
+        &emu.port_name,
+        FLASH_BASE,
+        16,
+        &dump,
+        DumpFormat::from_path(&dump),
+        &emu.options(),
+        &NullLogger,
+    )
+    .unwrap();
+    let dumped = stm32_uart::parse_hex_to_image(&dump).unwrap();
+    std::fs::remove_file(&dump).unwrap();
+
+    assert_eq!(dumped, source);
+}
+
+#[test]
+fn option_bytes_backup_and_restore() {
+    let Some(emu) = Emulator::start(SimConfig::default()) else {
+        eprintln!("no virtual serial pair available, skipping");
+        return;
+    };
+    let original = emu.sim.option_bytes();
+    let path = std::env::temp_dir().join(format!("probe-flasher-{}-ob.hex", std::process::id()));
+
+    stm32_uart::backup_option_bytes(&emu.port_name, &path, &emu.options()).unwrap();
+
+    // 修改一个字节后从备份恢复
+    let mut hex = stm32_uart::parse_hex_to_image(&path).unwrap();
+    *hex.get_mut(&0x1FFF_F802).unwrap() = 0x00;
+    let modified = path.with_extension("modified.hex");
+    std::fs::write(&modified, stm32_uart::image_to_hex(&hex).unwrap()).unwrap();
+    stm32_uart::restore_option_bytes(&emu.port_name, &modified, &emu.options()).unwrap();
+    assert_eq!(emu.sim.option_bytes()[2], 0x00);
+
+    stm32_uart::restore_option_bytes(&emu.port_name, &path, &emu.options()).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    std::fs::remove_file(&modified).unwrap();
+
+    assert_eq!(emu.sim.option_bytes(), original);
+}
+
+#[test]
+fn option_bytes_decode_and_program_fields() {
+    let Some(emu) = Emulator::start(SimConfig::default()) else {
+        eprintln!("no virtual serial pair available, skipping");
+        return;
+    };
+
+    let ob = stm32_uart::read_option_bytes(&emu.port_name, &emu.options()).unwrap();
+    assert_eq!(ob.family, ObFamily::F1);
+    assert_eq!(ob.rdp(), RdpLevel::Level0);
+    assert_eq!(ob.get("nboot1").unwrap(), 1);
+
+    let changes = vec![("nboot1".to_string(), 0), ("data0".to_string(), 0x42)];
+    let ob = stm32_uart::program_option_bytes(&emu.port_name, &changes, &emu.options()).unwrap();
+    assert_eq!(ob.get("nboot1").unwrap(), 0);
+    assert_eq!(ob.get("data0").unwrap(), 0x42);
+    // 反码随字段一起更新
+    assert_eq!(&emu.sim.option_bytes()[2..6], &[0xEF, 0x10, 0x42, 0xBD]);
+
+    // 越界的值、未知字段和 RDP Level 2 在写入前被拒绝
+    let mut copy = ob.clone();
+    assert!(copy.set("nboot1", 2).is_err());
+    assert!(copy.set("bor_lev", 0).is_err());
+    assert!(copy.set("rdp", 0xCC).is_err());
+    assert_eq!(copy, ob);
+}
+
+#[test]
+fn flash_cycles_are_counted_per_device() {
+    let Some(emu) = Emulator::start(SimConfig::default()) else {
+        eprintln!("no virtual serial pair available, skipping");
+        return;
+    };
+    let hex = write_hex(
+        "cycles",
+        &[":020000040800F2", ":04000000DEADBEEFC4", ":00000001FF"],
+    );
+    let db_path =
+        std::env::temp_dir().join(format!("probe-flasher-{}-devices.json", std::process::id()));
+    let opts = FlashOptions {
+        cycle_tracking: Some(CycleTracking {
+            db_path: db_path.clone(),
+            warn_threshold: 1,
+        }),
+        ..emu.options()
+    };
+
+    for _ in 0..2 {
+        stm32_uart::flash_hex(&emu.port_name, &hex, &opts, &NullLogger).unwrap();
+    }
+    let db = DeviceDb::load(&db_path).unwrap();
+    std::fs::remove_file(&hex).unwrap();
+    std::fs::remove_file(&db_path).unwrap();
+
+    let record = &db.devices["30FFD6054E57343923711043"];
+    assert_eq!(record.product_id, Some(0x410));
+    assert_eq!((record.erase_count, record.flash_count), (2, 2));
+}
+
+#[cfg(unix)]
+#[test]
+fn power_hook_cycles_before_and_after_flash() {
+    use probe_flasher::power::{PowerCycle, PowerSwitch};
+
+    let Some(emu) = Emulator::start(SimConfig::default()) else {
+        eprintln!("no virtual serial pair available, skipping");
+        return;
+    };
+    let hex = write_hex(
+        "power",
+        &[":020000040800F2", ":04000000DEADBEEFC4", ":00000001FF"],
+    );
+    let trace =
+        std::env::temp_dir().join(format!("probe-flasher-{}-power.txt", std::process::id()));
+    let switch = PowerSwitch::Hook {
+        command: format!("echo $PROBE_FLASHER_POWER >> '{}'", trace.display()),
+    };
+    let opts = FlashOptions {
+        reset_after: true,
+        power_cycle: Some(PowerCycle {
+            off_time: Duration::from_millis(10),
+            after: true,
+            ..PowerCycle::new(switch)
+        }),
+        ..emu.options()
+    };
+
+    stm32_uart::flash_hex(&emu.port_name, &hex, &opts, &NullLogger).unwrap();
+    let log = std::fs::read_to_string(&trace).unwrap();
+    std::fs::remove_file(&hex).unwrap();
+    std::fs::remove_file(&trace).unwrap();
+
+    assert_eq!(log, "off\non\noff\non\n");
+    assert_eq!(emu.sim.flash()[&FLASH_BASE], 0xDE);
+    // 完成后断电重启代替了 GO
+    assert_eq!(emu.sim.go_address(), None);
+}
+
+#[test]
+fn monitors_route_data_by_port() {
+    let (Some(mut a), Some(mut b)) = (open_pair(), open_pair()) else {
+        return;
+    };
+    for wire in [&mut a.wire, &mut b.wire] {
+        wire.set_timeout(Duration::from_millis(500)).unwrap();
+    }
+    let (tx, rx) = std::sync::mpsc::channel();
+    let tx = std::sync::Mutex::new(tx);
+    let hub = MonitorHub::new(move |event| {
+        let _ = tx.lock().unwrap().send(event);
+    });
+    hub.open(&a.device, 115_200).unwrap();
+    hub.open(&b.device, 115_200).unwrap();
+    assert!(matches!(
+        hub.open(&a.device, 115_200),
+        Err(stm32_uart::Error::MonitorBusy(_))
+    ));
+
+    b.wire.write_all(b"from b").unwrap();
+    let mut received = Vec::new();
+    while received.len() < 6 {
+        match rx.recv_timeout(Duration::from_secs(2)).unwrap() {
+            MonitorEvent::Data { port, data } => {
+                assert_eq!(port, b.device);
+                received.extend(data);
+            }
+            other => panic!("unexpected {other:?}"),
+        }
+    }
+    assert_eq!(received, b"from b");
+
+    hub.send(&a.device, b"to a").unwrap();
+    let mut buf = [0u8; 4];
+    a.wire.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf, b"to a");
+
+    assert!(hub.close(&a.device));
+    assert_eq!(
+        rx.recv_timeout(Duration::from_secs(2)).unwrap(),
+        MonitorEvent::Closed {
+            port: a.device.clone(),
+            error: None
+        }
+    );
+    assert!(matches!(
+        hub.send(&a.device, b"x"),
+        Err(stm32_uart::Error::NotMonitored(_))
+    ));
+    assert_eq!(hub.ports(), vec![b.device.clone()]);
+}
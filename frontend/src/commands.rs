@@ -1,10 +1,29 @@
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
-use std::sync::{Arc, Mutex};
+use std::collections::{HashMap, VecDeque};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
 use std::time::Duration;
-use tauri::{AppHandle, Emitter};
+use tauri::{AppHandle, Emitter, Manager};
 
-use probe_flasher::stm32_uart::{self, BootLineConfig, BootMode, FlashOptions};
+use probe_flasher::async_uart;
+use probe_flasher::chipdb::{self, MemoryMap};
+use probe_flasher::cmdline;
+use probe_flasher::devicedb::{self, ConfigExport, CycleTracking};
+use probe_flasher::diag;
+use probe_flasher::i18n::{self, Lang, Msg, tr};
+use probe_flasher::inject;
+use probe_flasher::job::{JobFile, JobRunner, StationConfig};
+use probe_flasher::monitor::{MonitorEvent, MonitorHub};
+use probe_flasher::sim::{self, DemoPort, SimulatedBootloader};
+use probe_flasher::stm32_dfu::{self, DfuSelector, DfuTarget};
+use probe_flasher::stm32_uart::{
+    self, BootLineConfig, BootMode, BootTiming, BootloaderSession, BoundaryPolicy, CancelToken,
+    EraseMode, ErrorCode, FlashOptions, FlashReport, ImageSource, Level, ModemStatus, Padding,
+    PhaseTimeline, ProgressGranularity, RetryPolicy, Timeouts, VerifyStrategy, VersionLocator,
+    WritePlan,
+};
+use probe_flasher::targets::{self, TargetFamily};
+use probe_flasher::wiring::{self, Observation, WiringCheck, WiringStep, WiringVerdict};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PortInfo {
@@ -13,6 +32,13 @@ pub struct PortInfo {
     pub vid: Option<u16>,
     pub pid: Option<u16>,
     pub serial: Option<String>,
+    pub interface: Option<u8>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SupportedCommand {
+    pub code: u8,
+    pub name: String,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -20,8 +46,35 @@ pub struct IdentifyResult {
     pub ok: bool,
     pub bootloader_version: Option<u8>,
     pub product_id: Option<u16>,
-    pub supported_commands: Vec<u8>,
+    pub product_id_raw: Vec<u8>,
+    pub supported_commands: Vec<SupportedCommand>,
+    pub capabilities: Vec<String>,
+    /// GET VERSION 返回的读保护解除 / 启用计数
+    pub read_protection_disabled: Option<u8>,
+    pub read_protection_enabled: Option<u8>,
+    pub read_protected: Option<bool>,
+    /// 协议版本及其默认擦除超时（按界面语言），如 "v3.1（擦除超时 60 秒）"
+    pub protocol: Option<String>,
+    /// 芯片数据库中的产品线和 Flash 容量，如 "STM32F10xxx medium-density, 128 KB flash"
+    pub chip: Option<String>,
+    /// 该芯片影响烧录方式的特性说明
+    pub chip_quirks: Vec<String>,
+    /// Flash 容量寄存器读出的实际容量（KB），区分同一产品 ID 的不同容量型号
+    pub flash_size_kb: Option<u32>,
     pub error: Option<String>,
+    /// 失败类别的稳定编码，界面据此给出处理建议
+    pub error_code: Option<ErrorCode>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FirmwareInfo {
+    pub size: usize,
+    pub start: u32,
+    pub end: u32,
+    pub segments: Vec<(u32, usize)>,
+    pub version: Option<String>,
+    /// 向量表检查的告警（初始 SP / 复位向量不合理）
+    pub warnings: Vec<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -29,22 +82,91 @@ pub struct FlashResult {
     pub ok: bool,
     pub duration_ms: u64,
     pub bytes_written: Option<u64>,
+    /// 各阶段耗时（进入 Bootloader、同步、擦除、写入、校验、复位），失败时为空
+    pub timeline: Vec<PhaseTiming>,
+    /// 链路评分（0–100），失败时为空
+    pub link_score: Option<u8>,
     pub error: Option<String>,
+    pub error_code: Option<ErrorCode>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PhaseTiming {
+    pub phase: String,
+    pub ms: u64,
+}
+
+fn phase_timings(timeline: &PhaseTimeline) -> Vec<PhaseTiming> {
+    timeline
+        .entries()
+        .map(|(phase, elapsed)| PhaseTiming {
+            phase: phase.name().to_string(),
+            ms: elapsed.as_millis() as u64,
+        })
+        .collect()
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct LogEvent {
+    /// 日志缓冲区中的序号，单调递增
+    pub id: u64,
     pub level: String,
     pub message: String,
     pub timestamp: String,
 }
 
+/// 后端保存的日志上限，超出后丢弃最早的行
+const MAX_LOG_LINES: usize = 100_000;
+
+/// 完整的会话日志，界面只按页查询可见部分
+#[derive(Default)]
+pub struct LogStore {
+    entries: VecDeque<LogEvent>,
+    next_id: u64,
+}
+
+impl LogStore {
+    fn push(&mut self, level: &str, message: &str) -> LogEvent {
+        let event = LogEvent {
+            id: self.next_id,
+            level: level.to_string(),
+            message: message.to_string(),
+            timestamp: chrono::Local::now().format("%H:%M:%S").to_string(),
+        };
+        self.next_id += 1;
+        if self.entries.len() == MAX_LOG_LINES {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(event.clone());
+        event
+    }
+}
+
+/// 日志查询条件，字段为空时不过滤
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct LogFilter {
+    /// 不区分大小写的子串
+    pub text: Option<String>,
+    pub level: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LogPage {
+    /// 满足条件的总行数
+    pub total: usize,
+    pub offset: usize,
+    pub entries: Vec<LogEvent>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ProgressEvent {
     pub phase: String,
     pub percent: u8,
     pub done: usize,
     pub total: usize,
+    /// done / total 的单位：写入 / 读取为 "bytes"，擦除为已用 / 预计的 "ms"，USB DFU 逐页擦除为 "pages"，
+    /// 链路测试为 "rounds"
+    pub unit: String,
 }
 
 pub struct TauriLogger {
@@ -56,49 +178,262 @@ impl TauriLogger {
         Self { app }
     }
 
-    fn emit_log(&self, level: &str, message: &str) {
-        let event = LogEvent {
-            level: level.to_string(),
-            message: message.to_string(),
-            timestamp: chrono::Local::now().format("%H:%M:%S").to_string(),
+    /// 一行日志：写入后端日志缓冲区并通知界面；后端协议日志经 `logging::init` 转到这里
+    pub fn line(&self, level: &'static str, msg: &str) {
+        diag::record_log(level, msg);
+        emit_log(&self.app, level, msg);
+    }
+}
+
+impl stm32_uart::ProgressSink for TauriLogger {
+    fn progress(&self, event: &stm32_uart::ProgressEvent) {
+        let phase = event.phase();
+        let (done, total) = event.counts();
+        let event = ProgressEvent {
+            phase: phase.label().to_string(),
+            percent: event.percent(),
+            done: done as usize,
+            total: total as usize,
+            unit: phase.unit().to_string(),
         };
-        let _ = self.app.emit("log-line", &event);
-    }
-}
-
-impl stm32_uart::Logger for TauriLogger {
-    fn line(&self, level: &'static str, msg: &str) {
-        // 处理进度格式: "PROGRESS:phase:current:total"
-        if msg.starts_with("PROGRESS:") {
-            let parts: Vec<&str> = msg.split(':').collect();
-            if parts.len() >= 4
-                && let (Ok(current), Ok(total)) =
-                    (parts[2].parse::<usize>(), parts[3].parse::<usize>())
-            {
-                let percent = if total > 0 {
-                    ((current as f64 / total as f64) * 100.0) as u8
-                } else {
-                    0
-                };
-
-                let event = ProgressEvent {
-                    phase: parts[1].to_string(),
-                    percent,
-                    done: current,
-                    total,
-                };
-                let _ = self.app.emit("flash-progress", &event);
-                return;
-            }
+        let _ = self.app.emit("flash-progress", &event);
+    }
+
+    /// 写入计划：界面收到结构化事件，日志中记录摘要
+    fn plan(&self, plan: &WritePlan) {
+        let _ = self.app.emit("flash-plan", plan);
+        self.line("info", &plan.summary());
+    }
+}
+
+/// 写入后端日志缓冲区并通知界面
+fn emit_log(app: &AppHandle, level: &str, message: &str) {
+    let event = app
+        .state::<AppState>()
+        .logs
+        .lock()
+        .unwrap()
+        .push(level, message);
+    let _ = app.emit("log-line", &event);
+}
+
+/// GUI 高级设置面板对应的超时 / 重试参数
+///
+/// 缺少的字段取默认值，旧版本导出的配置文件也能导入。
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AdvancedSettings {
+    /// 应答 / 擦除 / 同步 / 读回超时，留空时连接后按协议版本和芯片取默认值
+    pub read_timeout_ms: Option<u64>,
+    pub erase_timeout_ms: Option<u64>,
+    pub sync_timeout_ms: Option<u64>,
+    pub read_chunk_timeout_ms: Option<u64>,
+    pub sync_attempts: u32,
+    pub sync_backoff_ms: u64,
+    /// 写入 / 擦除命令被 NACK 时的尝试次数（含第一次）和重试前的等待
+    pub command_attempts: u32,
+    pub command_backoff_ms: u64,
+    /// 同步无响应时重新执行 Boot 序列的总轮数，重试间隔从 boot_backoff_ms 开始翻倍
+    pub boot_cycles: u32,
+    pub boot_backoff_ms: u64,
+    pub boot_settle_ms: u64,
+    pub reset_pulse_ms: u64,
+    pub boot_delay_ms: u64,
+    /// 同步后切换到的更高波特率，0 表示不切换
+    pub high_baud: u32,
+    /// 进度事件粒度，大固件时避免刷爆事件总线
+    pub progress_every_bytes: usize,
+    pub progress_max_per_sec: u32,
+    pub flush_frames: bool,
+    /// 写入帧字节数（32–256），连续 NACK 时自动减半
+    pub write_chunk: usize,
+    /// 对齐填充：编程粒度、数据段起止是否补齐到粒度、末尾不足一个字时是否补齐
+    pub align_bytes: u32,
+    pub pad_boundaries: bool,
+    pub pad_tail: bool,
+    /// 只擦除固件覆盖的页 / 扇区，保留其余 Flash
+    pub erase_pages: bool,
+    /// 写入后的校验策略："none"、"crc"、"read-back"、"sampled:<百分比>"
+    pub verify: VerifyStrategy,
+    /// 在本地设备数据库中记录擦写次数，超过阈值时告警
+    pub track_cycles: bool,
+    pub cycle_warn_threshold: u64,
+    /// 记住每个设备上次成功的连接参数，下次优先尝试
+    pub remember_connection: bool,
+    /// 全新芯片：先不执行 Boot 序列直接同步，烧录后按系列处理空检查
+    pub virgin_probe: bool,
+    /// 演示模式：串口列表中出现模拟 Bootloader，无需硬件
+    pub demo_mode: bool,
+}
+
+impl Default for AdvancedSettings {
+    fn default() -> Self {
+        let opts = FlashOptions::default();
+        Self {
+            read_timeout_ms: None,
+            erase_timeout_ms: None,
+            sync_timeout_ms: None,
+            read_chunk_timeout_ms: None,
+            sync_attempts: opts.sync_retry.attempts,
+            sync_backoff_ms: opts.sync_retry.backoff.as_millis() as u64,
+            command_attempts: opts.command_retry.attempts,
+            command_backoff_ms: opts.command_retry.backoff.as_millis() as u64,
+            boot_cycles: opts.boot_cycles,
+            boot_backoff_ms: opts.boot_backoff.as_millis() as u64,
+            boot_settle_ms: opts.boot_timing.settle.as_millis() as u64,
+            reset_pulse_ms: opts.boot_timing.reset_pulse.as_millis() as u64,
+            boot_delay_ms: opts.boot_timing.boot_delay.as_millis() as u64,
+            high_baud: opts.high_baud.unwrap_or(0),
+            progress_every_bytes: opts.progress.every_bytes,
+            progress_max_per_sec: opts.progress.max_per_sec,
+            flush_frames: opts.flush_frames,
+            write_chunk: opts.write_chunk,
+            align_bytes: opts.padding.granularity,
+            pad_boundaries: opts.padding.boundaries == BoundaryPolicy::Pad,
+            pad_tail: opts.padding.tail_word,
+            erase_pages: opts.erase_mode == EraseMode::Pages,
+            verify: opts.verify,
+            track_cycles: true,
+            cycle_warn_threshold: devicedb::DEFAULT_CYCLE_WARN,
+            remember_connection: true,
+            virgin_probe: opts.virgin_probe,
+            demo_mode: false,
         }
+    }
+}
 
-        self.emit_log(level, msg);
+impl AdvancedSettings {
+    fn validate(&self) -> Result<(), String> {
+        if self.sync_attempts == 0 || self.command_attempts == 0 || self.boot_cycles == 0 {
+            return Err(
+                "sync_attempts, command_attempts and boot_cycles must be at least 1".to_string(),
+            );
+        }
+        if !(stm32_uart::MIN_WRITE_CHUNK..=stm32_uart::MAX_WRITE_CHUNK).contains(&self.write_chunk)
+        {
+            return Err("write_chunk must be between 32 and 256".to_string());
+        }
+        if !(1..=256).contains(&self.align_bytes) {
+            return Err("align_bytes must be between 1 and 256".to_string());
+        }
+        if [
+            self.read_timeout_ms,
+            self.erase_timeout_ms,
+            self.sync_timeout_ms,
+            self.read_chunk_timeout_ms,
+        ]
+        .contains(&Some(0))
+        {
+            return Err("timeouts must be greater than zero".to_string());
+        }
+        Ok(())
+    }
+
+    fn apply(&self, opts: &mut FlashOptions) {
+        opts.timeouts = Timeouts {
+            sync: self.sync_timeout_ms.map(Duration::from_millis),
+            ack: self.read_timeout_ms.map(Duration::from_millis),
+            erase: self.erase_timeout_ms.map(Duration::from_millis),
+            read_chunk: self.read_chunk_timeout_ms.map(Duration::from_millis),
+        };
+        opts.sync_retry = RetryPolicy::new(
+            self.sync_attempts,
+            Duration::from_millis(self.sync_backoff_ms),
+        );
+        opts.command_retry = RetryPolicy::new(
+            self.command_attempts,
+            Duration::from_millis(self.command_backoff_ms),
+        );
+        opts.boot_cycles = self.boot_cycles;
+        opts.boot_backoff = Duration::from_millis(self.boot_backoff_ms);
+        opts.boot_timing = BootTiming {
+            settle: Duration::from_millis(self.boot_settle_ms),
+            reset_pulse: Duration::from_millis(self.reset_pulse_ms),
+            boot_delay: Duration::from_millis(self.boot_delay_ms),
+        };
+        opts.high_baud = (self.high_baud > 0).then_some(self.high_baud);
+        opts.progress = ProgressGranularity {
+            every_bytes: self.progress_every_bytes,
+            max_per_sec: self.progress_max_per_sec,
+        };
+        opts.flush_frames = self.flush_frames;
+        opts.write_chunk = self.write_chunk;
+        opts.padding = Padding {
+            granularity: self.align_bytes,
+            boundaries: if self.pad_boundaries {
+                BoundaryPolicy::Pad
+            } else {
+                BoundaryPolicy::Split
+            },
+            tail_word: self.pad_tail,
+        };
+        opts.erase_mode = if self.erase_pages {
+            EraseMode::Pages
+        } else {
+            EraseMode::Mass
+        };
+        opts.verify = self.verify;
+        opts.virgin_probe = self.virgin_probe;
+        opts.cycle_tracking = self.track_cycles.then(|| CycleTracking {
+            warn_threshold: self.cycle_warn_threshold,
+            ..CycleTracking::default()
+        });
+        opts.remember_connection = self
+            .remember_connection
+            .then(devicedb::default_connections_path);
     }
 }
 
 #[derive(Default)]
 pub struct AppState {
     pub is_flashing: Arc<Mutex<bool>>,
+    pub settings: Arc<Mutex<AdvancedSettings>>,
+    pub logs: Arc<Mutex<LogStore>>,
+    /// 高级页逐步操作时保持打开的 Bootloader 会话，打开期间视为正在烧录
+    pub session: Arc<Mutex<Option<BootloaderSession>>>,
+    /// 进行中的接线检查，串口保持打开，期间同样视为正在烧录
+    pub wiring: Arc<Mutex<Option<WiringCheck>>>,
+    /// 上一次烧录的等价 CLI 命令行
+    pub last_command: Arc<Mutex<Option<String>>>,
+    /// 串口监视器，第一次打开时创建
+    pub monitors: Arc<OnceLock<MonitorHub>>,
+    /// 烧录和会话操作共用的取消标志，见 `cancel_flash`
+    pub cancel: CancelToken,
+    /// 演示模式注册的模拟端口，开启期间在各次连接之间保留设备状态
+    pub demo: Arc<Mutex<Option<DemoPort>>>,
+}
+
+impl AppState {
+    /// 开关演示模式；已开启时保留现有设备状态
+    pub fn set_demo_mode(&self, enabled: bool) {
+        let mut demo = self.demo.lock().unwrap();
+        if !enabled {
+            *demo = None;
+        } else if demo.is_none() {
+            *demo = Some(DemoPort::attach(
+                sim::DEMO_PORT,
+                SimulatedBootloader::demo(),
+            ));
+        }
+        self.settings.lock().unwrap().demo_mode = enabled;
+    }
+
+    fn monitors(&self, app: &AppHandle) -> &MonitorHub {
+        self.monitors.get_or_init(|| {
+            let app = app.clone();
+            MonitorHub::new(move |event: MonitorEvent| {
+                let _ = app.emit("monitor-event", &event);
+            })
+        })
+    }
+
+    /// 监视器占用的串口不能用于烧录
+    fn ensure_not_monitored(&self, port: &str) -> Result<(), String> {
+        if self.monitors.get().is_some_and(|hub| hub.is_open(port)) {
+            return Err(format!("Port {port} is open in the monitor"));
+        }
+        Ok(())
+    }
 }
 
 #[tauri::command]
@@ -113,6 +448,7 @@ pub fn list_ports() -> Result<Vec<PortInfo>, String> {
                     vid: p.vid,
                     pid: p.pid,
                     serial: p.serial,
+                    interface: p.interface,
                 })
                 .collect()
         })
@@ -120,32 +456,65 @@ pub fn list_ports() -> Result<Vec<PortInfo>, String> {
 }
 
 #[tauri::command]
-pub fn identify_port(
-    app: AppHandle,
+pub async fn identify_port(
+    state: tauri::State<'_, AppState>,
     port: String,
     baud: u32,
     boot_mode: String,
+    boot_level: Option<String>,
+    reset_level: Option<String>,
+    auto_baud: bool,
 ) -> Result<IdentifyResult, String> {
     let boot_mode = parse_boot_mode(&boot_mode)?;
+    let lines = resolve_lines(boot_mode, boot_level.as_deref(), reset_level.as_deref())?;
 
-    let opts = FlashOptions {
+    let mut opts = FlashOptions {
         baud_rate: baud,
         boot_mode,
-        lines: BootLineConfig::default(),
-        verify: false,
-        reset_after: false,
-        read_timeout: Duration::from_millis(800),
+        lines,
+        auto_baud,
+        ..FlashOptions::default()
     };
+    state.settings.lock().unwrap().apply(&mut opts);
 
-    let logger = TauriLogger::new(app);
-    let result = stm32_uart::identify(&port, &opts, &logger);
+    let result = async_uart::identify_async(&port, &opts).await;
+    let chip = result.product_id.and_then(chipdb::lookup);
 
     Ok(IdentifyResult {
         ok: result.ok,
         bootloader_version: result.bootloader_version,
         product_id: result.product_id,
-        supported_commands: result.supported_commands,
+        product_id_raw: result.product_id_raw,
+        supported_commands: result
+            .supported_commands
+            .iter()
+            .map(|&code| SupportedCommand {
+                code,
+                name: stm32_uart::command_name(code).to_string(),
+            })
+            .collect(),
+        capabilities: result
+            .capabilities
+            .summary()
+            .into_iter()
+            .map(str::to_string)
+            .collect(),
+        read_protection_disabled: result.version_info.map(|v| v.read_protection_disabled),
+        read_protection_enabled: result.version_info.map(|v| v.read_protection_enabled),
+        read_protected: result.read_protected,
+        protocol: result.protocol.map(|p| {
+            tr(
+                Msg::ProtocolEraseTimeout,
+                &[&p.label(), &p.timing(chip).erase().as_secs()],
+            )
+        }),
+        chip: chip.map(|c| c.describe()),
+        chip_quirks: chip
+            .map(|c| c.quirks().iter().map(|q| q.describe()).collect())
+            .unwrap_or_default(),
+        flash_size_kb: result.flash_size.map(|size| size / 1024),
         error: result.error,
+        error_code: result.error_code,
     })
 }
 
@@ -155,10 +524,29 @@ pub async fn flash_firmware(
     state: tauri::State<'_, AppState>,
     port: String,
     hex_path: String,
+    address: Option<String>,
+    extra_paths: Option<Vec<String>>,
     baud: u32,
     boot_mode: String,
     reset_after: bool,
+    boot_level: Option<String>,
+    reset_level: Option<String>,
+    auto_baud: bool,
+    rebase_alias: bool,
+    version_pattern: Option<String>,
+    go_address: Option<String>,
 ) -> Result<FlashResult, String> {
+    let boot_mode = parse_boot_mode(&boot_mode)?;
+    let lines = resolve_lines(boot_mode, boot_level.as_deref(), reset_level.as_deref())?;
+    let go_address = go_address
+        .filter(|s| !s.trim().is_empty())
+        .map(|s| parse_go_address(&s))
+        .transpose()?;
+    let bin_address = parse_bin_address(address)?;
+    let extra_paths = extra_paths.unwrap_or_default();
+    let sources = image_sources(hex_path, bin_address, &extra_paths);
+
+    state.ensure_not_monitored(&port)?;
     {
         let mut is_flashing = state.is_flashing.lock().unwrap();
         if *is_flashing {
@@ -167,22 +555,26 @@ pub async fn flash_firmware(
         *is_flashing = true;
     }
 
-    let boot_mode = parse_boot_mode(&boot_mode)?;
-    let hex_path = PathBuf::from(hex_path);
-
-    let opts = FlashOptions {
+    let mut opts = FlashOptions {
         baud_rate: baud,
         boot_mode,
-        lines: BootLineConfig::default(),
-        verify: false,
+        lines,
         reset_after,
-        read_timeout: Duration::from_millis(800),
+        auto_baud,
+        rebase_alias,
+        version_locator: version_locator(version_pattern),
+        go_address,
+        cancel: state.cancel.clone(),
+        ..FlashOptions::default()
     };
+    state.settings.lock().unwrap().apply(&mut opts);
+    state.cancel.reset();
+    *state.last_command.lock().unwrap() = cmdline::flash_command(&port, &sources, &opts);
 
     let logger = TauriLogger::new(app.clone());
     let start = std::time::Instant::now();
 
-    let result = stm32_uart::flash_hex(&port, &hex_path, &opts, &logger);
+    let result = async_uart::flash_sources_async(&port, &sources, &opts, logger).await;
 
     {
         let mut is_flashing = state.is_flashing.lock().unwrap();
@@ -190,22 +582,40 @@ pub async fn flash_firmware(
     }
 
     let duration_ms = start.elapsed().as_millis() as u64;
+    Ok(flash_result(&app, result, duration_ms))
+}
 
+/// 把烧录结果发给界面（`flash-done` 事件）并转换为命令返回值
+fn flash_result(
+    app: &AppHandle,
+    result: Result<FlashReport, stm32_uart::Error>,
+    duration_ms: u64,
+) -> FlashResult {
     match result {
-        Ok(()) => {
+        Ok(report) => {
+            let timeline = phase_timings(&report.timeline);
             let _ = app.emit(
                 "flash-done",
                 serde_json::json!({
                     "ok": true,
-                    "message": "烧录完成！"
+                    "code": Msg::FlashDone.code(),
+                    "message": tr(Msg::FlashDone, &[]),
+                    "bytes_written": report.bytes_written,
+                    "duration_ms": duration_ms,
+                    "timeline": timeline,
+                    "link": report.link.as_ref().map(ToString::to_string),
                 }),
             );
-            Ok(FlashResult {
+            FlashResult {
                 ok: true,
                 duration_ms,
-                bytes_written: None,
+                bytes_written: Some(report.bytes_written),
+                timeline,
+                // USB DFU 没有串口命令往返，不给链路评分
+                link_score: report.link.as_ref().map(|link| link.score()),
                 error: None,
-            })
+                error_code: None,
+            }
         }
         Err(e) => {
             let error_msg = e.to_string();
@@ -213,19 +623,793 @@ pub async fn flash_firmware(
                 "flash-done",
                 serde_json::json!({
                     "ok": false,
-                    "message": format!("烧录失败: {}", error_msg)
+                    "code": Msg::FlashFailed.code(),
+                    "message": tr(Msg::FlashFailed, &[&error_msg]),
+                    "error_code": e.code(),
                 }),
             );
-            Ok(FlashResult {
+            FlashResult {
                 ok: false,
                 duration_ms,
                 bytes_written: None,
+                timeline: Vec::new(),
+                link_score: None,
                 error: Some(error_msg),
+                error_code: Some(e.code()),
+            }
+        }
+    }
+}
+
+#[tauri::command]
+pub fn list_dfu_devices() -> Result<Vec<DfuTarget>, String> {
+    stm32_dfu::list_devices().map_err(|e| e.to_string())
+}
+
+/// 经 USB DFU 烧录单个固件文件，擦除、校验、进度等沿用高级设置，串口相关的设置不起作用
+#[tauri::command]
+pub async fn flash_dfu(
+    app: AppHandle,
+    state: tauri::State<'_, AppState>,
+    serial: Option<String>,
+    alt: Option<u8>,
+    hex_path: String,
+    address: Option<String>,
+    reset_after: bool,
+    go_address: Option<String>,
+) -> Result<FlashResult, String> {
+    let go_address = go_address
+        .filter(|s| !s.trim().is_empty())
+        .map(|s| parse_go_address(&s))
+        .transpose()?;
+    let source = ImageSource::from_path(&hex_path, parse_bin_address(address)?);
+    let selector = DfuSelector {
+        serial,
+        alt: alt.unwrap_or(0),
+        ..DfuSelector::default()
+    };
+
+    {
+        let mut is_flashing = state.is_flashing.lock().unwrap();
+        if *is_flashing {
+            return Err("Already flashing".to_string());
+        }
+        *is_flashing = true;
+    }
+
+    let mut opts = FlashOptions {
+        reset_after,
+        go_address,
+        cancel: state.cancel.clone(),
+        ..FlashOptions::default()
+    };
+    state.settings.lock().unwrap().apply(&mut opts);
+    state.cancel.reset();
+
+    let logger = TauriLogger::new(app.clone());
+    let start = std::time::Instant::now();
+    // USB 传输是阻塞的，放到阻塞线程池中执行
+    let result = tauri::async_runtime::spawn_blocking(move || {
+        stm32_dfu::flash_dfu(&selector, &[source], &opts, &logger)
+    })
+    .await;
+    *state.is_flashing.lock().unwrap() = false;
+    let result = result.map_err(|e| e.to_string())?;
+
+    let duration_ms = start.elapsed().as_millis() as u64;
+    Ok(flash_result(&app, result, duration_ms))
+}
+
+/// 任务队列：依次运行任务文件，与 probe-flasher-agent 共用 `JobRunner`；
+/// 串口和 Boot 模式取界面当前选择，其余参数取高级设置。取消后不再开始下一个任务
+#[tauri::command]
+pub async fn run_job_queue(
+    app: AppHandle,
+    state: tauri::State<'_, AppState>,
+    port: String,
+    jobs: Vec<String>,
+    baud: u32,
+    boot_mode: String,
+) -> Result<Vec<FlashResult>, String> {
+    let boot_mode = parse_boot_mode(&boot_mode)?;
+    state.ensure_not_monitored(&port)?;
+    {
+        let mut is_flashing = state.is_flashing.lock().unwrap();
+        if *is_flashing {
+            return Err("Already flashing".to_string());
+        }
+        *is_flashing = true;
+    }
+
+    let mut base = FlashOptions {
+        cancel: state.cancel.clone(),
+        ..FlashOptions::default()
+    };
+    state.settings.lock().unwrap().apply(&mut base);
+    state.cancel.reset();
+    let station = StationConfig {
+        name: "GUI".to_string(),
+        port,
+        baud_rate: baud,
+        boot_mode,
+        ..StationConfig::default()
+    };
+
+    let mut results = Vec::new();
+    for job in jobs {
+        if state.cancel.is_cancelled() {
+            break;
+        }
+        let logger = TauriLogger::new(app.clone());
+        let (station, base) = (station.clone(), base.clone());
+        let start = std::time::Instant::now();
+        let result = tauri::async_runtime::spawn_blocking(move || {
+            let job = JobFile::load(Path::new(&job))?;
+            JobRunner::new(job, station)
+                .with_options(base)
+                .run_once(&logger)
+        })
+        .await
+        .map_err(|e| e.to_string());
+        let result = match result {
+            Ok(result) => result,
+            Err(e) => {
+                *state.is_flashing.lock().unwrap() = false;
+                return Err(e);
+            }
+        };
+        let duration_ms = start.elapsed().as_millis() as u64;
+        results.push(flash_result(&app, result, duration_ms));
+    }
+    *state.is_flashing.lock().unwrap() = false;
+    Ok(results)
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionInfo {
+    pub port: String,
+    pub baud: u32,
+    pub product_id: Option<u16>,
+    pub supported_commands: Vec<SupportedCommand>,
+}
+
+/// 高级页：连接并保持 Bootloader 会话，之后可单独执行擦除 / 写入 / 校验
+#[tauri::command]
+pub async fn open_session(
+    state: tauri::State<'_, AppState>,
+    port: String,
+    baud: u32,
+    boot_mode: String,
+    reset_after: bool,
+    boot_level: Option<String>,
+    reset_level: Option<String>,
+    auto_baud: bool,
+    rebase_alias: bool,
+    go_address: Option<String>,
+) -> Result<SessionInfo, String> {
+    let boot_mode = parse_boot_mode(&boot_mode)?;
+    let lines = resolve_lines(boot_mode, boot_level.as_deref(), reset_level.as_deref())?;
+    let go_address = go_address
+        .filter(|s| !s.trim().is_empty())
+        .map(|s| parse_go_address(&s))
+        .transpose()?;
+
+    state.ensure_not_monitored(&port)?;
+    {
+        let mut is_flashing = state.is_flashing.lock().unwrap();
+        if *is_flashing {
+            return Err("Already flashing".to_string());
+        }
+        *is_flashing = true;
+    }
+
+    let mut opts = FlashOptions {
+        baud_rate: baud,
+        boot_mode,
+        lines,
+        reset_after,
+        auto_baud,
+        rebase_alias,
+        go_address,
+        cancel: state.cancel.clone(),
+        ..FlashOptions::default()
+    };
+    state.settings.lock().unwrap().apply(&mut opts);
+    state.cancel.reset();
+
+    let session = match async_uart::open_session_async(&port, &opts).await {
+        Ok(session) => session,
+        Err(e) => {
+            *state.is_flashing.lock().unwrap() = false;
+            return Err(e.to_string());
+        }
+    };
+
+    let info = SessionInfo {
+        port,
+        baud: session.baud(),
+        product_id: session.product_id(),
+        supported_commands: session
+            .commands()
+            .iter()
+            .map(|&code| SupportedCommand {
+                code,
+                name: stm32_uart::command_name(code).to_string(),
             })
+            .collect(),
+    };
+    *state.session.lock().unwrap() = Some(session);
+    Ok(info)
+}
+
+/// 在已打开的会话上执行一步操作；串口 I/O 是阻塞的，连同会话锁一起放到阻塞线程池中
+async fn with_session<T: Send + 'static>(
+    state: &AppState,
+    step: impl FnOnce(&mut BootloaderSession) -> stm32_uart::Result<T> + Send + 'static,
+) -> Result<T, String> {
+    let session = state.session.clone();
+    let cancel = state.cancel.clone();
+    tauri::async_runtime::spawn_blocking(move || {
+        let mut session = session.lock().unwrap();
+        let session = session.as_mut().ok_or("No open session")?;
+        cancel.reset();
+        step(session).map_err(|e| e.to_string())
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+/// 给出固件时按擦除设置只擦除其覆盖的部分，否则全片擦除
+#[tauri::command]
+pub async fn session_erase(
+    app: AppHandle,
+    state: tauri::State<'_, AppState>,
+    hex_path: Option<String>,
+    address: Option<String>,
+) -> Result<(), String> {
+    let logger = TauriLogger::new(app);
+    let source = hex_path
+        .map(|path| parse_bin_address(address).map(|addr| ImageSource::from_path(path, addr)))
+        .transpose()?;
+    with_session(&state, move |session| match &source {
+        Some(source) => {
+            let image = stm32_uart::load_image(source, session.options())?;
+            session.check_image(&image)?;
+            session.erase_before_write(&image, &logger)
+        }
+        None => session.erase_all(&logger),
+    })
+    .await
+}
+
+/// 写入固件（不擦除），返回写入的字节数
+#[tauri::command]
+pub async fn session_write(
+    app: AppHandle,
+    state: tauri::State<'_, AppState>,
+    hex_path: String,
+    address: Option<String>,
+) -> Result<u64, String> {
+    let logger = TauriLogger::new(app);
+    let source = ImageSource::from_path(hex_path, parse_bin_address(address)?);
+    with_session(&state, move |session| {
+        let image = stm32_uart::load_image(&source, session.options())?;
+        session.check_image(&image)?;
+        session.write_image(&image, &logger)
+    })
+    .await
+}
+
+/// 按高级设置的校验方式比较目标 Flash 与固件，不一致时返回首个差异
+#[tauri::command]
+pub async fn session_verify(
+    app: AppHandle,
+    state: tauri::State<'_, AppState>,
+    hex_path: String,
+    address: Option<String>,
+) -> Result<(), String> {
+    let logger = TauriLogger::new(app);
+    let source = ImageSource::from_path(hex_path, parse_bin_address(address)?);
+    with_session(&state, move |session| {
+        let image = stm32_uart::load_image(&source, session.options())?;
+        session.verify_image(&image, &logger)
+    })
+    .await
+}
+
+/// 结束会话：按打开时的参数复位运行或留在 Bootloader，返回各阶段耗时
+#[tauri::command]
+pub async fn close_session(state: tauri::State<'_, AppState>) -> Result<Vec<PhaseTiming>, String> {
+    let session = state.session.lock().unwrap().take();
+    *state.is_flashing.lock().unwrap() = false;
+    let session = session.ok_or("No open session")?;
+
+    let timeline = phase_timings(session.timeline());
+    state.cancel.reset();
+    tauri::async_runtime::spawn_blocking(move || session.finish())
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| e.to_string())?;
+    Ok(timeline)
+}
+
+/// 取消进行中的烧录或会话操作：在下一个协议步骤之间中止，会话保持打开，一次性烧录则关闭串口
+#[tauri::command]
+pub fn cancel_flash(state: tauri::State<'_, AppState>) {
+    state.cancel.cancel();
+}
+
+/// 主固件和附加文件（如应用之外的 Bootloader）合并后一次擦除、写入；只有主固件可以是 .bin
+fn image_sources(
+    hex_path: String,
+    bin_address: Option<u32>,
+    extra_paths: &[String],
+) -> Vec<ImageSource> {
+    std::iter::once((hex_path, bin_address))
+        .chain(extra_paths.iter().map(|path| (path.clone(), None)))
+        .map(|(path, base_address)| ImageSource::from_path(path, base_address))
+        .collect()
+}
+
+#[tauri::command]
+pub fn reset_device(
+    state: tauri::State<'_, AppState>,
+    port: String,
+    baud: u32,
+    boot_mode: String,
+    boot_level: Option<String>,
+    reset_level: Option<String>,
+) -> Result<(), String> {
+    let boot_mode = parse_boot_mode(&boot_mode)?;
+    let lines = resolve_lines(boot_mode, boot_level.as_deref(), reset_level.as_deref())?;
+
+    if *state.is_flashing.lock().unwrap() {
+        return Err("Already flashing".to_string());
+    }
+
+    let mut opts = FlashOptions {
+        baud_rate: baud,
+        boot_mode,
+        lines,
+        ..FlashOptions::default()
+    };
+    state.settings.lock().unwrap().apply(&mut opts);
+
+    stm32_uart::reset(&port, &opts).map_err(|e| e.to_string())
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct WiringPrompt {
+    /// 从 0 开始的步骤序号
+    pub index: usize,
+    pub total: usize,
+    pub step: WiringStep,
+    pub prompt: String,
+}
+
+impl WiringPrompt {
+    fn new(index: usize, step: WiringStep) -> Self {
+        Self {
+            index,
+            total: wiring::STEPS.len(),
+            step,
+            prompt: step.prompt(),
         }
     }
 }
 
+/// 接线检查的一步结果：还有下一步时 `next` 非空，完成后 `verdict` 为推断出的接法
+#[derive(Debug, Clone, Serialize)]
+pub struct WiringOutcome {
+    pub next: Option<WiringPrompt>,
+    pub verdict: Option<WiringVerdict>,
+    /// 推断结果与 Boot 模式名称的电平不同时，需要填入高级设置的覆盖项
+    pub boot_level: Option<Level>,
+    pub reset_level: Option<Level>,
+}
+
+/// 开始接线检查：打开串口并设置第一步的 DTR / RTS 电平，返回需要用户观察的内容
+#[tauri::command]
+pub fn wiring_check_start(
+    state: tauri::State<'_, AppState>,
+    port: String,
+    baud: u32,
+) -> Result<WiringPrompt, String> {
+    {
+        let mut is_flashing = state.is_flashing.lock().unwrap();
+        if *is_flashing {
+            return Err("Already flashing".to_string());
+        }
+        *is_flashing = true;
+    }
+
+    let mut opts = FlashOptions {
+        baud_rate: baud,
+        ..FlashOptions::default()
+    };
+    state.settings.lock().unwrap().apply(&mut opts);
+    let check = match WiringCheck::start(&port, &opts) {
+        Ok(check) => check,
+        Err(e) => {
+            *state.is_flashing.lock().unwrap() = false;
+            return Err(e.to_string());
+        }
+    };
+    *state.wiring.lock().unwrap() = Some(check);
+    Ok(WiringPrompt::new(0, wiring::STEPS[0]))
+}
+
+/// 报告当前一步看到的现象；最后一步后推断接法，并保存为该设备的连接参数
+#[tauri::command]
+pub fn wiring_check_answer(
+    app: AppHandle,
+    state: tauri::State<'_, AppState>,
+    port: String,
+    baud: u32,
+    observation: Observation,
+) -> Result<WiringOutcome, String> {
+    let mut running = state.wiring.lock().unwrap();
+    let check = running.as_mut().ok_or("No wiring check in progress")?;
+    match check.observe(observation) {
+        Ok(Some(step)) => {
+            return Ok(WiringOutcome {
+                next: Some(WiringPrompt::new(check.index(), step)),
+                verdict: None,
+                boot_level: None,
+                reset_level: None,
+            });
+        }
+        Ok(None) => {}
+        Err(e) => {
+            running.take();
+            *state.is_flashing.lock().unwrap() = false;
+            return Err(e.to_string());
+        }
+    }
+
+    let verdict = running.take().and_then(WiringCheck::finish);
+    drop(running);
+    *state.is_flashing.lock().unwrap() = false;
+    let logger = TauriLogger::new(app);
+    let Some(verdict) = verdict else {
+        return Err(tr(Msg::WiringInconclusive, &[]));
+    };
+
+    logger.line("info", &verdict.describe());
+    let mut opts = FlashOptions {
+        baud_rate: baud,
+        ..FlashOptions::default()
+    };
+    state.settings.lock().unwrap().apply(&mut opts);
+    match verdict.remember(&port, &opts) {
+        Ok(true) => logger.line("info", &tr(Msg::WiringRemembered, &[])),
+        Ok(false) => {}
+        Err(e) => logger.line("warn", &tr(Msg::ConnectionSaveFailed, &[&e])),
+    }
+
+    let overridden = verdict.overrides() > 0;
+    Ok(WiringOutcome {
+        next: None,
+        verdict: Some(verdict),
+        boot_level: overridden.then_some(verdict.lines.boot_level),
+        reset_level: overridden.then_some(verdict.lines.reset_assert_level),
+    })
+}
+
+/// 中途取消接线检查，恢复 DTR / RTS 并关闭串口
+#[tauri::command]
+pub fn wiring_check_cancel(state: tauri::State<'_, AppState>) {
+    if let Some(check) = state.wiring.lock().unwrap().take() {
+        check.finish();
+        *state.is_flashing.lock().unwrap() = false;
+    }
+}
+
+/// 读取 CTS / DSR / CD / RI 状态输入线，治具可据此在烧录前检测目标上电
+#[tauri::command]
+pub fn read_modem_status(
+    state: tauri::State<'_, AppState>,
+    port: String,
+) -> Result<ModemStatus, String> {
+    if *state.is_flashing.lock().unwrap() {
+        return Err("Already flashing".to_string());
+    }
+    stm32_uart::modem_status(&port, &FlashOptions::default()).map_err(|e| e.to_string())
+}
+
+/// 打开串口监视器，收到的数据以 `monitor-event` 事件发出，事件中带端口名
+#[tauri::command]
+pub fn monitor_open(
+    app: AppHandle,
+    state: tauri::State<'_, AppState>,
+    port: String,
+    baud: u32,
+) -> Result<(), String> {
+    if state.session.lock().unwrap().is_some() {
+        return Err("Close the bootloader session first".to_string());
+    }
+    state
+        .monitors(&app)
+        .open(&port, baud)
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub fn monitor_send(
+    app: AppHandle,
+    state: tauri::State<'_, AppState>,
+    port: String,
+    data: String,
+) -> Result<(), String> {
+    state
+        .monitors(&app)
+        .send(&port, data.as_bytes())
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub fn monitor_close(app: AppHandle, state: tauri::State<'_, AppState>, port: String) -> bool {
+    state.monitors(&app).close(&port)
+}
+
+/// 已打开监视器的串口
+#[tauri::command]
+pub fn monitor_ports(state: tauri::State<'_, AppState>) -> Vec<String> {
+    state
+        .monitors
+        .get()
+        .map(MonitorHub::ports)
+        .unwrap_or_default()
+}
+
+/// 上一次烧录的等价 `probe-flasher flash` 命令行，用于把 GUI 中调通的设置搬进脚本；
+/// 尚未烧录或参数无法用命令行表达时返回错误
+#[tauri::command]
+pub fn last_flash_command(state: tauri::State<'_, AppState>) -> Result<String, String> {
+    state
+        .last_command
+        .lock()
+        .unwrap()
+        .clone()
+        .ok_or_else(|| "No flash with a command-line equivalent yet".to_string())
+}
+
+/// 调试用的隐藏命令：设置待触发的故障（格式同 `PROBE_FLASHER_INJECT`，空串清除），
+/// 在开发者工具中调用以检查错误提示和重试流程；返回当前待触发的故障
+#[tauri::command]
+pub fn inject_faults(spec: String) -> Result<Vec<String>, String> {
+    if !inject::arm(inject::parse(&spec)?) {
+        return Err("Fault injection is only available in debug builds".to_string());
+    }
+    Ok(inject::armed().iter().map(ToString::to_string).collect())
+}
+
+/// 已注册的目标芯片族及其选项描述，界面据此生成目标选择器和表单
+#[tauri::command]
+pub fn list_target_families() -> Vec<TargetFamily> {
+    targets::target_families()
+}
+
+/// 按产品 ID 返回芯片数据库中的存储器映射，供界面绘制；给出实际 Flash 容量时按其截短
+#[tauri::command]
+pub fn chip_memory_map(pid: u16, flash_size_kb: Option<u32>) -> Option<MemoryMap> {
+    let mut map = chipdb::chip_memory_map(pid)?;
+    if let Some(kb) = flash_size_kb {
+        map.limit_flash(kb * 1024);
+    }
+    Some(map)
+}
+
+#[tauri::command]
+pub fn inspect_firmware(
+    hex_path: String,
+    address: Option<String>,
+    version_pattern: Option<String>,
+) -> Result<FirmwareInfo, String> {
+    let image = ImageSource::from_path(hex_path, parse_bin_address(address)?)
+        .load()
+        .map_err(|e| e.to_string())?;
+    let info = stm32_uart::inspect_image(&image, version_locator(version_pattern).as_ref());
+
+    Ok(FirmwareInfo {
+        size: info.size,
+        start: info.start,
+        end: info.end,
+        segments: info.segments,
+        version: info.version,
+        warnings: info
+            .vector_issues
+            .iter()
+            .map(|issue| issue.message())
+            .collect(),
+    })
+}
+
+/// 烧录前的确认对话框：按已识别的芯片和当前设置分析固件，芯片未识别时不含页信息和擦除耗时
+#[tauri::command]
+pub fn plan_firmware(
+    state: tauri::State<'_, AppState>,
+    hex_path: String,
+    address: Option<String>,
+    extra_paths: Option<Vec<String>>,
+    pid: Option<u16>,
+    baud: u32,
+    rebase_alias: bool,
+) -> Result<WritePlan, String> {
+    let mut opts = FlashOptions {
+        baud_rate: baud,
+        rebase_alias,
+        ..FlashOptions::default()
+    };
+    state.settings.lock().unwrap().apply(&mut opts);
+
+    let bin_address = parse_bin_address(address)?;
+    let extra_paths = extra_paths.unwrap_or_default();
+    let sources = image_sources(hex_path, bin_address, &extra_paths);
+    let image = stm32_uart::merge_sources(&sources, &opts).map_err(|e| e.to_string())?;
+    Ok(WritePlan::analyze(
+        &image,
+        pid.and_then(chipdb::lookup),
+        &opts,
+        baud,
+    ))
+}
+
+#[tauri::command]
+pub fn create_diagnostic_bundle(
+    app: AppHandle,
+    state: tauri::State<'_, AppState>,
+    path: String,
+) -> Result<String, String> {
+    let version = app.package_info().version.to_string();
+    let settings = serde_json::to_string_pretty(&*state.settings.lock().unwrap())
+        .map_err(|e| e.to_string())?;
+
+    diag::create_bundle(&PathBuf::from(&path), &version, &settings).map_err(|e| e.to_string())?;
+    Ok(path)
+}
+
+/// 界面自身产生的日志也写入后端缓冲区，保证查询结果完整
+#[tauri::command]
+pub fn append_log(app: AppHandle, level: String, message: String) {
+    emit_log(&app, &level, &message);
+}
+
+/// 按条件查询日志，`offset` / `limit` 作用于过滤后的结果，`offset` 为空时返回最后一页
+#[tauri::command]
+pub fn query_logs(
+    state: tauri::State<'_, AppState>,
+    filter: LogFilter,
+    offset: Option<usize>,
+    limit: usize,
+) -> LogPage {
+    let store = state.logs.lock().unwrap();
+    let text = filter
+        .text
+        .filter(|t| !t.is_empty())
+        .map(|t| t.to_lowercase());
+    let level = filter.level.filter(|l| !l.is_empty());
+    let matches = |e: &&LogEvent| {
+        level.as_ref().is_none_or(|l| &e.level == l)
+            && text
+                .as_ref()
+                .is_none_or(|t| e.message.to_lowercase().contains(t))
+    };
+
+    let total = store.entries.iter().filter(matches).count();
+    let offset = offset.unwrap_or(total.saturating_sub(limit));
+    let entries = store
+        .entries
+        .iter()
+        .filter(matches)
+        .skip(offset)
+        .take(limit)
+        .cloned()
+        .collect();
+    LogPage {
+        total,
+        offset,
+        entries,
+    }
+}
+
+#[tauri::command]
+pub fn clear_logs(state: tauri::State<'_, AppState>) {
+    state.logs.lock().unwrap().entries.clear();
+}
+
+/// 与 CLI 共用的消息目录，按消息码返回当前语言的模板
+#[tauri::command]
+pub fn get_message_catalog(lang: Lang) -> HashMap<&'static str, &'static str> {
+    i18n::catalog(lang).into_iter().collect()
+}
+
+#[tauri::command]
+pub fn set_language(lang: Lang) {
+    i18n::set_lang(lang);
+}
+
+#[tauri::command]
+pub fn get_advanced_settings(state: tauri::State<'_, AppState>) -> AdvancedSettings {
+    state.settings.lock().unwrap().clone()
+}
+
+#[tauri::command]
+pub fn set_advanced_settings(
+    state: tauri::State<'_, AppState>,
+    settings: AdvancedSettings,
+) -> Result<(), String> {
+    settings.validate()?;
+    let demo_mode = settings.demo_mode;
+    *state.settings.lock().unwrap() = settings;
+    state.set_demo_mode(demo_mode);
+    Ok(())
+}
+
+/// 把高级设置、界面上的连接参数（波特率、Boot 序列等）和按设备记住的连接参数导出到一个 JSON 文件
+#[tauri::command]
+pub fn export_config(
+    state: tauri::State<'_, AppState>,
+    path: String,
+    ui: serde_json::Value,
+) -> Result<(), String> {
+    let settings = serde_json::json!({
+        "advanced": &*state.settings.lock().unwrap(),
+        "ui": ui,
+    });
+    ConfigExport::collect(settings, &devicedb::default_connections_path())
+        .and_then(|export| export.save(Path::new(&path)))
+        .map_err(|e| e.to_string())
+}
+
+/// 导入配置文件：应用高级设置、替换本机的连接参数记录，返回界面参数由前端恢复
+#[tauri::command]
+pub fn import_config(
+    state: tauri::State<'_, AppState>,
+    path: String,
+) -> Result<serde_json::Value, String> {
+    let export = ConfigExport::load(Path::new(&path)).map_err(|e| e.to_string())?;
+    let advanced: AdvancedSettings = match export.settings.get("advanced") {
+        Some(value) => serde_json::from_value(value.clone()).map_err(|e| e.to_string())?,
+        None => AdvancedSettings::default(),
+    };
+    advanced.validate()?;
+    export
+        .install_connections(&devicedb::default_connections_path())
+        .map_err(|e| e.to_string())?;
+
+    let demo_mode = advanced.demo_mode;
+    *state.settings.lock().unwrap() = advanced;
+    state.set_demo_mode(demo_mode);
+    Ok(export.settings.get("ui").cloned().unwrap_or_default())
+}
+
+fn version_locator(pattern: Option<String>) -> Option<VersionLocator> {
+    pattern
+        .filter(|p| !p.is_empty())
+        .map(VersionLocator::Pattern)
+}
+
+fn parse_address(s: &str) -> Option<u32> {
+    let s = s.trim();
+    match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => s.parse().ok(),
+    }
+}
+
+fn parse_go_address(s: &str) -> Result<u32, String> {
+    let address = parse_address(s).ok_or_else(|| format!("Invalid go address: {}", s.trim()))?;
+    stm32_uart::validate_go_address(address).map_err(|e| e.to_string())
+}
+
+/// .bin 固件的写入地址，空值表示默认的 Flash 起始地址
+fn parse_bin_address(s: Option<String>) -> Result<Option<u32>, String> {
+    s.filter(|s| !s.trim().is_empty())
+        .map(|s| parse_address(&s).ok_or_else(|| format!("Invalid address: {}", s.trim())))
+        .transpose()
+}
+
 fn parse_boot_mode(mode: &str) -> Result<BootMode, String> {
     match mode {
         "none" => Ok(BootMode::None),
@@ -240,3 +1424,27 @@ fn parse_boot_mode(mode: &str) -> Result<BootMode, String> {
         _ => Err(format!("Unknown boot mode: {}", mode)),
     }
 }
+
+fn parse_level(level: &str) -> Result<Level, String> {
+    match level {
+        "low" => Ok(Level::Low),
+        "high" => Ok(Level::High),
+        _ => Err(format!("Unknown level: {}", level)),
+    }
+}
+
+/// 以 Boot 模式隐含的电平为基础，应用高级设置中的覆盖项
+fn resolve_lines(
+    boot_mode: BootMode,
+    boot_level: Option<&str>,
+    reset_level: Option<&str>,
+) -> Result<BootLineConfig, String> {
+    let mut lines = BootLineConfig::for_mode(boot_mode);
+    if let Some(level) = boot_level {
+        lines.boot_level = parse_level(level)?;
+    }
+    if let Some(level) = reset_level {
+        lines.reset_assert_level = parse_level(level)?;
+    }
+    Ok(lines)
+}
@@ -4,13 +4,60 @@ mod commands;
 use commands::AppState;
 
 fn main() {
+    // --demo：所有命令连接模拟 Bootloader，用于界面开发、截图和培训
+    let state = AppState::default();
+    if std::env::args().any(|arg| arg == "--demo") {
+        state.set_demo_mode(true);
+    }
+
     tauri::Builder::default()
-        .manage(AppState::default())
+        .manage(state)
+        .setup(|app| {
+            let handle = app.handle().clone();
+            probe_flasher::logging::init(move |level, msg| {
+                commands::TauriLogger::new(handle.clone()).line(level, msg)
+            });
+            Ok(())
+        })
         .plugin(tauri_plugin_dialog::init())
         .invoke_handler(tauri::generate_handler![
             commands::list_ports,
             commands::identify_port,
             commands::flash_firmware,
+            commands::list_dfu_devices,
+            commands::flash_dfu,
+            commands::run_job_queue,
+            commands::open_session,
+            commands::session_erase,
+            commands::session_write,
+            commands::session_verify,
+            commands::close_session,
+            commands::cancel_flash,
+            commands::reset_device,
+            commands::wiring_check_start,
+            commands::wiring_check_answer,
+            commands::wiring_check_cancel,
+            commands::read_modem_status,
+            commands::monitor_open,
+            commands::monitor_send,
+            commands::monitor_close,
+            commands::monitor_ports,
+            commands::last_flash_command,
+            commands::inject_faults,
+            commands::list_target_families,
+            commands::chip_memory_map,
+            commands::inspect_firmware,
+            commands::plan_firmware,
+            commands::create_diagnostic_bundle,
+            commands::append_log,
+            commands::query_logs,
+            commands::clear_logs,
+            commands::get_message_catalog,
+            commands::set_language,
+            commands::get_advanced_settings,
+            commands::set_advanced_settings,
+            commands::export_config,
+            commands::import_config,
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");